@@ -0,0 +1,22 @@
+//! Overcommits an address space against a swap space on a RAM disk, and
+//! checks that its pages are read back intact.
+//!
+//! It runs on ArceOS only:
+//!
+//! ```sh
+//! make A=examples/swap NO_AXSTD=y run
+//! ```
+//!
+//! and prints `Swap test OK!` on success.
+
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+#[cfg(target_os = "none")]
+extern crate alloc;
+
+#[cfg(target_os = "none")]
+mod overcommit;
+
+#[cfg(not(target_os = "none"))]
+fn main() {}
@@ -0,0 +1,98 @@
+//! The test, run as the main task of ArceOS.
+
+use alloc::sync::Arc;
+
+use axalloc::{UsageKind, global_allocator};
+use axdriver_block::ramdisk::RamDisk;
+use axhal::{
+    paging::{MappingFlags, PageSize},
+    trap::PageFaultFlags,
+};
+use axlog::ax_println;
+use axmm::{
+    AddrSpace,
+    backend::Backend,
+    swap::{self, SwapSpace},
+};
+use axsync::Mutex;
+use memory_addr::{PAGE_SIZE_4K, VirtAddr, va};
+
+/// The base of the address space under test, which is never loaded.
+const ASPACE_BASE: usize = 0x1000_0000;
+
+/// Fills `page` with the index `i` of the page.
+fn fill_page(page: &mut [u8], i: usize) {
+    for word in page.chunks_exact_mut(8) {
+        word.copy_from_slice(&(i as u64).to_le_bytes());
+    }
+}
+
+fn page_addr(i: usize) -> VirtAddr {
+    va!(ASPACE_BASE + i * PAGE_SIZE_4K)
+}
+
+#[unsafe(no_mangle)]
+fn main() {
+    let allocator = global_allocator();
+
+    // The RAM disk takes a quarter of the free memory.
+    let disk_pages = allocator.available_pages() / 4;
+    let disk = allocator
+        .alloc_pages(disk_pages, PAGE_SIZE_4K, UsageKind::Global)
+        .expect("failed to allocate the RAM disk");
+    let disk = unsafe { RamDisk::new(disk, disk_pages * PAGE_SIZE_4K) };
+    swap::swap_on(SwapSpace::new_device(disk)).unwrap();
+
+    // Map more pages than the remaining memory can hold, by half of the swap
+    // space.
+    let num_pages = allocator.available_pages() + disk_pages / 2;
+    let size = num_pages * PAGE_SIZE_4K;
+    let aspace = Arc::new(Mutex::new(
+        AddrSpace::new_empty(va!(ASPACE_BASE), size).unwrap(),
+    ));
+    swap::register(&aspace);
+    let mut aspace = aspace.lock();
+    aspace
+        .map(
+            va!(ASPACE_BASE),
+            size,
+            MappingFlags::READ | MappingFlags::WRITE,
+            false,
+            Backend::new_alloc(va!(ASPACE_BASE), PageSize::Size4K),
+        )
+        .unwrap();
+    ax_println!(
+        "Writing {} pages, swap space of {} pages",
+        num_pages,
+        disk_pages
+    );
+
+    let mut page = [0; PAGE_SIZE_4K];
+    for i in 0..num_pages {
+        assert!(aspace.handle_page_fault(page_addr(i), PageFaultFlags::WRITE));
+        fill_page(&mut page, i);
+        aspace.write(page_addr(i), &page).unwrap();
+    }
+    assert!(swap::stats().pages_out > 0);
+
+    let mut expected = [0; PAGE_SIZE_4K];
+    for i in 0..num_pages {
+        // Pages swapped out are not mapped, and are read back on faults.
+        if aspace.read(page_addr(i), &mut page).is_err() {
+            assert!(aspace.handle_page_fault(page_addr(i), PageFaultFlags::READ));
+            aspace.read(page_addr(i), &mut page).unwrap();
+        }
+        fill_page(&mut expected, i);
+        assert!(page == expected, "page {i} corrupted");
+    }
+    let stats = swap::stats();
+    assert!(stats.pages_in > 0);
+    ax_println!(
+        "Swapped {} pages out, {} pages in",
+        stats.pages_out,
+        stats.pages_in
+    );
+
+    aspace.clear();
+    ax_println!("Swap test OK!");
+}
@@ -0,0 +1,192 @@
+//! Per-frame metadata.
+//!
+//! Every page managed by the page allocator has a [`FrameDesc`], stored in an
+//! array allocated from the page allocator itself when it is initialized.
+//! Descriptors are looked up by the virtual address of their page, without
+//! locking, and are reset whenever their page is allocated or freed.
+
+use core::{
+    fmt, mem,
+    sync::atomic::{AtomicU8, AtomicU16, AtomicU32, Ordering},
+};
+
+use crate::{ALL_KINDS, PAGE_SIZE, UsageKind};
+
+bitflags::bitflags! {
+    /// The state of a frame.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FrameFlags: u8 {
+        /// The frame has been modified and not written back yet.
+        const DIRTY = 1 << 0;
+        /// The frame must stay resident, see [`FrameDesc::pin`].
+        const PINNED = 1 << 1;
+        /// The frame is mapped copy-on-write.
+        const COW = 1 << 2;
+        /// The frame caches the content of a file.
+        const PAGE_CACHE = 1 << 3;
+        /// The frame has been faulted in recently.
+        const REFERENCED = 1 << 4;
+    }
+}
+
+/// The owner kind of a free frame.
+const KIND_FREE: u8 = u8::MAX;
+
+/// The metadata of a physical frame.
+pub struct FrameDesc {
+    ref_count: AtomicU32,
+    flags: AtomicU8,
+    kind: AtomicU8,
+    pin_count: AtomicU16,
+}
+
+impl FrameDesc {
+    const fn new() -> Self {
+        Self {
+            ref_count: AtomicU32::new(0),
+            flags: AtomicU8::new(0),
+            kind: AtomicU8::new(KIND_FREE),
+            pin_count: AtomicU16::new(0),
+        }
+    }
+
+    fn reset(&self, kind: u8) {
+        self.ref_count.store(0, Ordering::Relaxed);
+        self.flags.store(0, Ordering::Relaxed);
+        self.pin_count.store(0, Ordering::Relaxed);
+        self.kind.store(kind, Ordering::Release);
+    }
+
+    /// Returns the number of references to the frame.
+    ///
+    /// Its meaning is up to the owner of the frame, e.g. the number of page
+    /// tables mapping it. It is zero when the frame is allocated.
+    pub fn ref_count(&self) -> u32 {
+        self.ref_count.load(Ordering::Acquire)
+    }
+
+    /// Increments the reference count, and returns its previous value.
+    pub fn inc_ref(&self) -> u32 {
+        let prev = self.ref_count.fetch_add(1, Ordering::AcqRel);
+        assert_ne!(prev, u32::MAX, "frame reference count overflow");
+        prev
+    }
+
+    /// Decrements the reference count, and returns its previous value.
+    pub fn dec_ref(&self) -> u32 {
+        let prev = self.ref_count.fetch_sub(1, Ordering::AcqRel);
+        assert_ne!(prev, 0, "frame reference count underflow");
+        prev
+    }
+
+    /// Returns the flags of the frame.
+    ///
+    /// [`FrameFlags::PINNED`] is also set while the frame is pinned.
+    pub fn flags(&self) -> FrameFlags {
+        let mut flags = FrameFlags::from_bits_retain(self.flags.load(Ordering::Acquire));
+        if self.pin_count() != 0 {
+            flags |= FrameFlags::PINNED;
+        }
+        flags
+    }
+
+    /// Sets or clears `flags`.
+    pub fn set_flags(&self, flags: FrameFlags, value: bool) {
+        if value {
+            self.flags.fetch_or(flags.bits(), Ordering::AcqRel);
+        } else {
+            self.flags.fetch_and(!flags.bits(), Ordering::AcqRel);
+        }
+    }
+
+    /// Returns the number of times the frame is pinned.
+    pub fn pin_count(&self) -> u16 {
+        self.pin_count.load(Ordering::Acquire)
+    }
+
+    /// Pins the frame, so that it is neither evicted nor swapped out until it
+    /// is unpinned as many times.
+    pub fn pin(&self) {
+        let prev = self.pin_count.fetch_add(1, Ordering::AcqRel);
+        assert_ne!(prev, u16::MAX, "frame pin count overflow");
+    }
+
+    /// Unpins the frame.
+    pub fn unpin(&self) {
+        let prev = self.pin_count.fetch_sub(1, Ordering::AcqRel);
+        assert_ne!(prev, 0, "frame pin count underflow");
+    }
+
+    /// Returns what the frame is allocated for, or `None` if it is free.
+    pub fn kind(&self) -> Option<UsageKind> {
+        ALL_KINDS
+            .get(self.kind.load(Ordering::Acquire) as usize)
+            .copied()
+    }
+}
+
+impl fmt::Debug for FrameDesc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameDesc")
+            .field("ref_count", &self.ref_count())
+            .field("flags", &self.flags())
+            .field("pin_count", &self.pin_count())
+            .field("kind", &self.kind())
+            .finish()
+    }
+}
+
+/// The descriptors of the frames of the page allocator.
+pub(crate) struct FrameTable {
+    base: usize,
+    descs: &'static [FrameDesc],
+}
+
+impl FrameTable {
+    /// Returns the number of bytes needed for the descriptors of
+    /// `num_frames` frames.
+    pub(crate) const fn size_for(num_frames: usize) -> usize {
+        num_frames * mem::size_of::<FrameDesc>()
+    }
+
+    /// Creates the descriptors of the `num_frames` frames starting from
+    /// `base`, in the memory at `storage`.
+    ///
+    /// # Safety
+    ///
+    /// `storage` must be valid for [`FrameTable::size_for`] bytes, suitably
+    /// aligned, and never be used for anything else.
+    pub(crate) unsafe fn init(base: usize, num_frames: usize, storage: *mut FrameDesc) -> Self {
+        for i in 0..num_frames {
+            unsafe { storage.add(i).write(FrameDesc::new()) };
+        }
+        Self {
+            base,
+            descs: unsafe { core::slice::from_raw_parts(storage, num_frames) },
+        }
+    }
+
+    pub(crate) fn get(&self, vaddr: usize) -> Option<&'static FrameDesc> {
+        let index = vaddr.checked_sub(self.base)? / PAGE_SIZE;
+        self.descs.get(index)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (usize, &'static FrameDesc)> + use<> {
+        let base = self.base;
+        self.descs
+            .iter()
+            .enumerate()
+            .map(move |(i, desc)| (base + i * PAGE_SIZE, desc))
+    }
+
+    /// Resets the descriptors of the `num_pages` pages starting from `vaddr`,
+    /// which have just been allocated for `kind`, or freed if it is `None`.
+    pub(crate) fn reset(&self, vaddr: usize, num_pages: usize, kind: Option<UsageKind>) {
+        let kind = kind.map_or(KIND_FREE, |kind| kind as u8);
+        for i in 0..num_pages {
+            if let Some(desc) = self.get(vaddr + i * PAGE_SIZE) {
+                desc.reset(kind);
+            }
+        }
+    }
+}
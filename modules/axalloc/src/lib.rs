@@ -14,6 +14,7 @@ extern crate alloc;
 
 #[cfg(feature = "dice")]
 mod ffi;
+mod frame;
 mod page;
 
 use core::{
@@ -24,11 +25,15 @@ use core::{
 
 use allocator::{AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
 use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
+
+use self::frame::FrameTable;
 
 const PAGE_SIZE: usize = 0x1000;
 
 const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
 
+pub use frame::{FrameDesc, FrameFlags};
 pub use page::GlobalPage;
 
 cfg_if::cfg_if! {
@@ -45,7 +50,7 @@ cfg_if::cfg_if! {
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UsageKind {
     RustHeap,
     UserMem,
@@ -111,11 +116,14 @@ impl fmt::Debug for UsageStats {
 /// Currently, [`TlsfByteAllocator`] is used as the byte allocator, while
 /// [`BitmapPageAllocator`] is used as the page allocator.
 ///
+/// Each page of the page allocator is described by a [`FrameDesc`].
+///
 /// [`TlsfByteAllocator`]: allocator::TlsfByteAllocator
 pub struct GlobalAllocator {
     balloc: SpinNoIrq<DefaultByteAllocator>,
     palloc: SpinNoIrq<BitmapPageAllocator<PAGE_SIZE>>,
     stats: SpinNoIrq<UsageStats>,
+    frames: LazyInit<FrameTable>,
 }
 
 impl GlobalAllocator {
@@ -125,6 +133,7 @@ impl GlobalAllocator {
             balloc: SpinNoIrq::new(DefaultByteAllocator::new()),
             palloc: SpinNoIrq::new(BitmapPageAllocator::new()),
             stats: SpinNoIrq::new(UsageStats::new()),
+            frames: LazyInit::new(),
         }
     }
 
@@ -143,13 +152,25 @@ impl GlobalAllocator {
 
     /// Initializes the allocator with the given region.
     ///
-    /// It firstly adds the whole region to the page allocator, then allocates
-    /// a small region (32 KB) to initialize the byte allocator. Therefore,
-    /// the given region must be larger than 32 KB.
+    /// It firstly adds the whole region to the page allocator, and allocates
+    /// the frame descriptors of its pages from it. Then it allocates a small
+    /// region (32 KB) to initialize the byte allocator. Therefore, the given
+    /// region must be larger than 32 KB.
     pub fn init(&self, start_vaddr: usize, size: usize) {
         assert!(size > MIN_HEAP_SIZE);
         let init_heap_size = MIN_HEAP_SIZE;
         self.palloc.lock().init(start_vaddr, size);
+
+        let base = start_vaddr.next_multiple_of(PAGE_SIZE);
+        let num_frames = (start_vaddr + size).saturating_sub(base) / PAGE_SIZE;
+        let table_pages = FrameTable::size_for(num_frames).div_ceil(PAGE_SIZE);
+        let table_ptr = self
+            .alloc_pages(table_pages, PAGE_SIZE, UsageKind::Global)
+            .unwrap();
+        let frames = unsafe { FrameTable::init(base, num_frames, table_ptr as *mut _) };
+        frames.reset(table_ptr, table_pages, Some(UsageKind::Global));
+        self.frames.init_once(frames);
+
         let heap_ptr = self
             .alloc_pages(init_heap_size / PAGE_SIZE, PAGE_SIZE, UsageKind::RustHeap)
             .unwrap();
@@ -242,7 +263,11 @@ impl GlobalAllocator {
         if !matches!(kind, UsageKind::RustHeap) {
             self.stats.lock().alloc(kind, num_pages * PAGE_SIZE);
         }
-        self.palloc.lock().alloc_pages(num_pages, align_pow2)
+        let vaddr = self.palloc.lock().alloc_pages(num_pages, align_pow2)?;
+        if let Some(frames) = self.frames.get() {
+            frames.reset(vaddr, num_pages, Some(kind));
+        }
+        Ok(vaddr)
     }
 
     /// Allocates contiguous pages starting from the given address.
@@ -258,9 +283,14 @@ impl GlobalAllocator {
         num_pages: usize,
         align_pow2: usize,
     ) -> AllocResult<usize> {
-        self.palloc
+        let vaddr = self
+            .palloc
             .lock()
-            .alloc_pages_at(start, num_pages, align_pow2)
+            .alloc_pages_at(start, num_pages, align_pow2)?;
+        if let Some(frames) = self.frames.get() {
+            frames.reset(vaddr, num_pages, Some(UsageKind::Global));
+        }
+        Ok(vaddr)
     }
 
     /// Gives back the allocated pages starts from `pos` to the page allocator.
@@ -272,9 +302,24 @@ impl GlobalAllocator {
     /// [`alloc_pages`]: GlobalAllocator::alloc_pages
     pub fn dealloc_pages(&self, pos: usize, num_pages: usize, kind: UsageKind) {
         self.stats.lock().dealloc(kind, num_pages * PAGE_SIZE);
+        if let Some(frames) = self.frames.get() {
+            frames.reset(pos, num_pages, None);
+        }
         self.palloc.lock().dealloc_pages(pos, num_pages)
     }
 
+    /// Returns the descriptor of the page at `vaddr`, or `None` if the page
+    /// is not managed by the page allocator.
+    pub fn frame(&self, vaddr: usize) -> Option<&'static FrameDesc> {
+        self.frames.get()?.get(vaddr)
+    }
+
+    /// Returns the descriptors of all pages of the page allocator, with their
+    /// virtual addresses, e.g. to dump them for debugging.
+    pub fn frames(&self) -> impl Iterator<Item = (usize, &'static FrameDesc)> {
+        self.frames.get().into_iter().flat_map(FrameTable::iter)
+    }
+
     /// Returns the number of allocated bytes in the byte allocator.
     pub fn used_bytes(&self) -> usize {
         self.balloc.lock().used_bytes()
@@ -96,13 +96,16 @@ impl DmaAllocator {
         flags: MappingFlags,
     ) -> AllocResult<()> {
         let expand_size = num_pages * PAGE_SIZE_4K;
-        axmm::kernel_aspace()
-            .lock()
-            .protect(vaddr, expand_size, flags)
-            .map_err(|e| {
-                error!("change table flag fail: {e:?}");
-                AllocError::NoMemory
-            })
+        let mut flush = axmm::TlbFlush::new();
+        let mut aspace = axmm::kernel_aspace().lock();
+        let result = aspace.protect_deferred(vaddr, expand_size, flags, &mut flush);
+        // The TLBs are flushed once the kernel address space is unlocked.
+        drop(aspace);
+        flush.flush();
+        result.map_err(|e| {
+            error!("change table flag fail: {e:?}");
+            AllocError::NoMemory
+        })
     }
 
     /// Gives back the allocated region to the byte allocator.
@@ -7,7 +7,7 @@ use alloc::{
 use core::sync::atomic::{AtomicU8, Ordering};
 use core::{num::NonZeroUsize, ops::Range, task::Context};
 
-use axalloc::{UsageKind, global_allocator};
+use axalloc::{FrameDesc, FrameFlags, UsageKind, global_allocator};
 use axfs_ng_vfs::{
     FileNode, Location, NodeFlags, NodePermission, NodeType, VfsError, VfsResult, path::Path,
 };
@@ -315,18 +315,40 @@ impl PageCache {
             .inspect_err(|err| {
                 warn!("Failed to allocate page cache: {:?}", err);
             })?;
-        Ok(Self {
+        let page = Self {
             addr: addr.into(),
             dirty: false,
-        })
+        };
+        if let Some(frame) = page.frame() {
+            frame.set_flags(FrameFlags::PAGE_CACHE, true);
+        }
+        Ok(page)
+    }
+
+    /// Returns the descriptor of the frame of the page.
+    pub fn frame(&self) -> Option<&'static FrameDesc> {
+        global_allocator().frame(self.addr.as_usize())
     }
 
     pub fn paddr(&self) -> PhysAddr {
         virt_to_phys(self.addr)
     }
 
+    /// Returns whether the page is pinned, and must not be evicted.
+    pub fn is_pinned(&self) -> bool {
+        self.frame()
+            .is_some_and(|frame| frame.flags().contains(FrameFlags::PINNED))
+    }
+
     pub fn mark_dirty(&mut self) {
-        self.dirty = true;
+        self.set_dirty(true);
+    }
+
+    fn set_dirty(&mut self, dirty: bool) {
+        self.dirty = dirty;
+        if let Some(frame) = self.frame() {
+            frame.set_flags(FrameFlags::DIRTY, dirty);
+        }
     }
 
     pub fn data(&mut self) -> &mut [u8] {
@@ -465,11 +487,15 @@ impl CachedFile {
         for listener in self.shared.evict_listeners.lock().iter() {
             (listener.listener)(pn, &page);
         }
+        self.write_back(file, pn, page)
+    }
+
+    fn write_back(&self, file: &FileNode, pn: u32, page: &mut PageCache) -> VfsResult<()> {
         if page.dirty {
             let page_start = pn as u64 * PAGE_SIZE as u64;
             let len = (file.len()? - page_start).min(PAGE_SIZE as u64) as usize;
             file.write_at(&page.data()[..len], page_start)?;
-            page.dirty = false;
+            page.set_dirty(false);
         }
         Ok(())
     }
@@ -487,11 +513,20 @@ impl CachedFile {
         }
         let mut evicted = None;
         if cache.len() == cache.cap().get() {
-            // Cache is full, remove the least recently used page
-            if let Some((pn, mut page)) = cache.pop_lru() {
-                self.evict_cache(file, pn, &mut page)?;
-                evicted = Some((pn, page));
-            }
+            // Cache is full, remove the least recently used page which is not
+            // pinned
+            let Some(lru_pn) = cache
+                .iter()
+                .rev()
+                .find(|(_, page)| !page.is_pinned())
+                .map(|(pn, _)| *pn)
+            else {
+                warn!("Page cache full of pinned pages");
+                return Err(VfsError::NoMemory);
+            };
+            let mut page = cache.pop(&lru_pn).unwrap();
+            self.evict_cache(file, lru_pn, &mut page)?;
+            evicted = Some((lru_pn, page));
         }
 
         // Page not in cache, read it
@@ -578,7 +613,7 @@ impl CachedFile {
                 let len = range.end - range.start;
                 buf.read(&mut page.data()[range.start..range.end])?;
                 if !self.in_memory {
-                    page.dirty = true;
+                    page.set_dirty(true);
                 }
                 Ok(written + len)
             },
@@ -627,7 +662,7 @@ impl CachedFile {
                 if let Some(mut page) = guard.pop(&pn) {
                     if !self.in_memory {
                         // Don't write back pages since they're discarded
-                        page.dirty = false;
+                        page.set_dirty(false);
                         self.evict_cache(file, pn, &mut page)?;
                     }
                 }
@@ -642,8 +677,14 @@ impl CachedFile {
         }
         let file = self.inner.entry().as_file()?;
         let mut guard = self.shared.page_cache.lock();
-        while let Some((pn, mut page)) = guard.pop_lru() {
-            self.evict_cache(file, pn, &mut page)?;
+        let pns = guard.iter().rev().map(|(pn, _)| *pn).collect::<Vec<_>>();
+        for pn in pns {
+            // Pinned pages stay in the cache.
+            if guard.peek(&pn).is_some_and(PageCache::is_pinned) {
+                self.write_back(file, pn, guard.peek_mut(&pn).unwrap())?;
+            } else if let Some(mut page) = guard.pop(&pn) {
+                self.evict_cache(file, pn, &mut page)?;
+            }
         }
         file.sync(data_only)?;
         Ok(())
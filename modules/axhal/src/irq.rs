@@ -21,6 +21,27 @@ pub fn register_irq_waker(irq: u32, waker: &Waker) {
     axplat::irq::register(irq as usize, poll_handler);
 }
 
+/// Enables IRQs and waits for one, with no window in between where an IRQ
+/// could be handled before the CPU waits.
+///
+/// It must be called with IRQs disabled, after checking that there is
+/// nothing to do. The IRQ is handled before this function returns, and IRQs
+/// are left enabled.
+pub fn enable_and_wait_for_irqs() {
+    #[cfg(all(target_arch = "x86_64", target_os = "none"))]
+    // `hlt` runs in the interrupt shadow of `sti`.
+    unsafe {
+        core::arch::asm!("sti; hlt", options(nomem, nostack));
+    }
+    #[cfg(not(all(target_arch = "x86_64", target_os = "none")))]
+    {
+        // Other architectures leave `wfi`/`idle` when an IRQ is pending,
+        // even if IRQs are masked.
+        axcpu::asm::wait_for_irqs();
+        axcpu::asm::enable_irqs();
+    }
+}
+
 /// IRQ handler.
 ///
 /// # Warn
@@ -1,9 +1,42 @@
 //! Time-related operations.
 
+use core::sync::atomic::{AtomicI64, Ordering};
+
 #[cfg(feature = "irq")]
 pub use axplat::time::set_oneshot_timer;
 pub use axplat::time::{
     Duration, MICROS_PER_SEC, MILLIS_PER_SEC, NANOS_PER_MICROS, NANOS_PER_MILLIS, NANOS_PER_SEC,
-    TimeValue, busy_wait, busy_wait_until, current_ticks, epochoffset_nanos, monotonic_time,
-    monotonic_time_nanos, nanos_to_ticks, ticks_to_nanos, wall_time, wall_time_nanos,
+    TimeValue, busy_wait, busy_wait_until, current_ticks, monotonic_time, monotonic_time_nanos,
+    nanos_to_ticks, ticks_to_nanos,
 };
+
+/// Adjustment of the wall clock made by [`set_wall_time`], in nanoseconds.
+static WALL_TIME_ADJUSTMENT: AtomicI64 = AtomicI64::new(0);
+
+/// Returns the offset of the wall clock from the monotonic clock, in
+/// nanoseconds.
+pub fn epochoffset_nanos() -> u64 {
+    axplat::time::epochoffset_nanos()
+        .wrapping_add_signed(WALL_TIME_ADJUSTMENT.load(Ordering::Acquire))
+}
+
+/// Returns the current wall time in nanoseconds.
+pub fn wall_time_nanos() -> u64 {
+    monotonic_time_nanos() + epochoffset_nanos()
+}
+
+/// Returns the current wall time as [`TimeValue`].
+pub fn wall_time() -> TimeValue {
+    TimeValue::from_nanos(wall_time_nanos())
+}
+
+/// Sets the wall clock to `now`.
+///
+/// Tasks sleeping until a wall-clock deadline are not aware of the change,
+/// use `axtask::set_wall_time` to set the clock in a multitask environment.
+pub fn set_wall_time(now: TimeValue) {
+    let offset = (now.as_nanos() as i128
+        - monotonic_time_nanos() as i128
+        - axplat::time::epochoffset_nanos() as i128) as i64;
+    WALL_TIME_ADJUSTMENT.store(offset, Ordering::Release);
+}
@@ -1,10 +1,11 @@
-use alloc::sync::Arc;
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
 use core::{fmt, ops::DerefMut};
 
+use axalloc::{FrameDesc, global_allocator};
 use axerrno::{AxError, AxResult, ax_bail};
 use axhal::{
     mem::phys_to_virt,
-    paging::{MappingFlags, PageTable},
+    paging::{MappingFlags, PageSize, PageTable},
     trap::PageFaultFlags,
 };
 use axsync::Mutex;
@@ -13,13 +14,31 @@ use memory_addr::{
 };
 use memory_set::{MemoryArea, MemorySet};
 
-use crate::backend::{Backend, BackendOps};
+#[cfg(feature = "swap")]
+use crate::swap;
+use crate::{
+    backend::{Advice, Backend, BackendOps},
+    tlb::{self, ActiveCpus, TlbFlush, TlbGather},
+};
+
+/// The number of pages swapped out at once when a page fault runs out of
+/// memory.
+#[cfg(feature = "swap")]
+const SWAP_CLUSTER: usize = 32;
 
 /// The virtual memory address space.
 pub struct AddrSpace {
     va_range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pt: PageTable,
+    active_cpus: Arc<ActiveCpus>,
+    #[cfg(feature = "swap")]
+    swap_enabled: bool,
+    /// Where the next scan for pages to swap out starts.
+    #[cfg(feature = "swap")]
+    swap_hand: VirtAddr,
+    /// The locked ranges, whose pages are pinned, from start to end.
+    locked: BTreeMap<VirtAddr, VirtAddr>,
 }
 
 impl AddrSpace {
@@ -53,6 +72,52 @@ impl AddrSpace {
         self.pt.root_paddr()
     }
 
+    /// Returns the set of CPUs the address space is loaded on.
+    ///
+    /// Whoever loads the page table of the address space on a CPU without
+    /// [`activate`](Self::activate) must keep it up to date, so that TLB
+    /// entries are invalidated on that CPU when mappings are removed.
+    pub fn active_cpus(&self) -> &Arc<ActiveCpus> {
+        &self.active_cpus
+    }
+
+    /// Loads the page table of the address space as the user page table of
+    /// the current CPU, and records it in the CPUs the address space is
+    /// loaded on, in place of the address space previously activated there.
+    ///
+    /// # Safety
+    ///
+    /// The address space must contain the kernel mappings where the
+    /// architecture has a single page table for both, and must outlive its
+    /// use on the current CPU.
+    pub unsafe fn activate(&self) {
+        let _guard = kernel_guard::NoPreempt::new();
+        self.active_cpus.mark_loaded();
+        unsafe { axhal::asm::write_user_page_table(self.page_table_root()) };
+        axhal::asm::flush_tlb(None);
+        tlb::switch_loaded(&self.active_cpus);
+    }
+
+    /// Invalidates the TLB entries of `range` on all CPUs the address space
+    /// is loaded on.
+    pub(crate) fn flush_tlb(&self, range: VirtAddrRange) {
+        tlb::shootdown(&self.active_cpus, range);
+    }
+
+    /// Invalidates the TLB entries of `range` everywhere, then frees the
+    /// frames unmapped meanwhile.
+    fn flush_tlb_and_free(&self, range: VirtAddrRange) {
+        let mut flush = TlbFlush::new();
+        self.defer_flush(range, &mut flush);
+        flush.flush();
+    }
+
+    /// Adds the invalidation of the TLB entries of `range` everywhere, and
+    /// the freeing of the frames unmapped meanwhile, to `flush`.
+    fn defer_flush(&self, range: VirtAddrRange, flush: &mut TlbFlush) {
+        flush.push(&self.active_cpus, self.page_table_root(), range);
+    }
+
     /// Checks if the address space contains the given address range.
     pub fn contains_range(&self, start: VirtAddr, size: usize) -> bool {
         self.va_range.contains(start) && (self.va_range.end - start) >= size
@@ -64,6 +129,12 @@ impl AddrSpace {
             va_range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pt: PageTable::try_new().map_err(|_| AxError::NoMemory)?,
+            active_cpus: Arc::new(ActiveCpus::new()),
+            #[cfg(feature = "swap")]
+            swap_enabled: false,
+            #[cfg(feature = "swap")]
+            swap_hand: base,
+            locked: BTreeMap::new(),
         })
     }
 
@@ -193,9 +264,32 @@ impl AddrSpace {
     /// Returns an error if the address range is out of the address space or not
     /// aligned.
     pub fn unmap(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        let mut flush = TlbFlush::new();
+        let result = self.unmap_deferred(start, size, &mut flush);
+        flush.flush();
+        result
+    }
+
+    /// Removes mappings within the specified virtual address range, like
+    /// [`unmap`](Self::unmap), but leaves the TLB shootdown to `flush`.
+    ///
+    /// It is meant for address spaces locked with IRQs disabled, such as the
+    /// kernel one, which must be unlocked before `flush` is done.
+    pub fn unmap_deferred(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flush: &mut TlbFlush,
+    ) -> AxResult {
         self.validate_region(start, size)?;
 
-        self.areas.unmap(start, size, &mut self.pt)?;
+        let range = VirtAddrRange::from_start_size(start, size);
+        self.remove_locked(range);
+        let result = self.repin(&[range.start, range.end], |this| {
+            this.areas.unmap(start, size, &mut this.pt)
+        });
+        self.defer_flush(range, flush);
+        result?;
         Ok(())
     }
 
@@ -215,7 +309,12 @@ impl AddrSpace {
         for vaddr in PageIter4K::new(start.align_down_4k(), end_align_up)
             .expect("Failed to create page iterator")
         {
-            let (mut paddr, ..) = self.pt.query(vaddr).map_err(|_| AxError::BadAddress)?;
+            let (mut paddr, _flags, _) = self.pt.query(vaddr).map_err(|_| AxError::BadAddress)?;
+            // The page has been swapped out.
+            #[cfg(feature = "swap")]
+            if swap::slot_of(paddr, _flags).is_some() {
+                ax_bail!(BadAddress);
+            }
 
             let mut copy_size = (size - cnt).min(PAGE_SIZE_4K);
 
@@ -262,17 +361,290 @@ impl AddrSpace {
     /// Returns an error if the address range is out of the address space or not
     /// aligned.
     pub fn protect(&mut self, start: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
+        let mut flush = TlbFlush::new();
+        let result = self.protect_deferred(start, size, flags, &mut flush);
+        flush.flush();
+        result
+    }
+
+    /// Updates mapping within the specified virtual address range, like
+    /// [`protect`](Self::protect), but leaves the TLB shootdown to `flush`.
+    ///
+    /// It is meant for address spaces locked with IRQs disabled, such as the
+    /// kernel one, which must be unlocked before `flush` is done.
+    pub fn protect_deferred(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        flush: &mut TlbFlush,
+    ) -> AxResult {
         self.validate_region(start, size)?;
 
-        self.areas
-            .protect(start, size, |_| Some(flags), &mut self.pt)?;
+        // Huge pages crossing the bounds may be split.
+        let result = self.repin(&[start, start + size], |this| {
+            this.areas
+                .protect(start, size, |_| Some(flags), &mut this.pt)
+        });
+        self.defer_flush(VirtAddrRange::from_start_size(start, size), flush);
+        result?;
+
+        Ok(())
+    }
+
+    /// Resizes the mapping of `old_size` bytes at `old_start` to `new_size`
+    /// bytes, and returns its new start address.
+    ///
+    /// The old range must be covered by contiguous memory areas. If it cannot
+    /// grow in place, it is moved elsewhere when `may_move` is `true`. Its
+    /// pages are moved with it, without being copied, and stay locked if they
+    /// were. The part grown is locked if the end of the old range was.
+    ///
+    /// The mapping is left as it was if it cannot grow.
+    ///
+    /// Returns [`AxError::NoMemory`] if there is no room for the new size.
+    pub fn remap(
+        &mut self,
+        old_start: VirtAddr,
+        old_size: usize,
+        new_size: usize,
+        may_move: bool,
+    ) -> AxResult<VirtAddr> {
+        self.validate_region(old_start, old_size)?;
+        if old_size == 0 || new_size == 0 || !is_aligned_4k(new_size) {
+            ax_bail!(InvalidInput, "invalid size");
+        }
+        let old_range = VirtAddrRange::from_start_size(old_start, old_size);
+        if !self.can_access_range(old_start, old_size, MappingFlags::empty()) {
+            ax_bail!(BadAddress, "range not mapped");
+        }
+        if new_size <= old_size {
+            if new_size < old_size {
+                self.unmap(old_start + new_size, old_size - new_size)?;
+            }
+            return Ok(old_start);
+        }
+
+        let grow_size = new_size - old_size;
+        // The part grown uses the backend of the last area.
+        let last = self.areas.find(old_range.end - PAGE_SIZE_4K).unwrap();
+        let (flags, last_backend) = (last.flags(), last.backend().clone());
+        let last_range = VirtAddrRange::new(last.start().max(old_start), old_range.end);
+        last_backend.check_grow(last_range, grow_size)?;
+        let lock_grown = self.is_locked(old_range.end - PAGE_SIZE_4K);
+
+        if old_range.end == last.end() && self.is_free(old_range.end, grow_size) {
+            let area = MemoryArea::new(old_range.end, grow_size, flags, last_backend);
+            self.areas.map(area, &mut self.pt, false)?;
+            if lock_grown {
+                if let Err(err) = self.lock(old_range.end, grow_size) {
+                    self.unmap(old_range.end, grow_size)?;
+                    return Err(err);
+                }
+            }
+            Ok(old_start)
+        } else if may_move {
+            let new_start = self
+                .find_free_area(self.base(), new_size, self.va_range)
+                .ok_or(AxError::NoMemory)?;
+            self.move_range(old_range, new_start, grow_size, lock_grown)?;
+            Ok(new_start)
+        } else {
+            ax_bail!(NoMemory, "no room to grow in place");
+        }
+    }
+
+    /// Moves the mappings of `range` to `new_start`, and extends the last one
+    /// by `grow_size` bytes there, which are locked if `lock_grown` is `true`.
+    ///
+    /// If it fails, the mappings are moved back and locked again as they were.
+    fn move_range(
+        &mut self,
+        range: VirtAddrRange,
+        new_start: VirtAddr,
+        grow_size: usize,
+        lock_grown: bool,
+    ) -> AxResult {
+        // The frames of moved pages may change when huge pages are split.
+        let locked = self.locked_in(range);
+        self.remove_locked(range);
+
+        // The parts whose pages may have been moved, with their backends.
+        let mut parts = Vec::new();
+        let mut moved = Vec::new();
+        let mut result = Ok(());
+        let mut modify = self.pt.modify();
+        for area in self.areas.iter() {
+            let Some(part) = intersect(area.va_range(), range) else {
+                continue;
+            };
+            let part_start = new_start + (part.start - range.start);
+            parts.push((part, area.backend().clone()));
+            match area.backend().relocate(part, part_start, &mut modify) {
+                Ok(backend) => moved.push(MemoryArea::new(
+                    part_start,
+                    part.size(),
+                    area.flags(),
+                    backend,
+                )),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        drop(modify);
+        if result.is_ok() {
+            if let Some(last) = moved.last() {
+                let grown =
+                    MemoryArea::new(last.end(), grow_size, last.flags(), last.backend().clone());
+                moved.push(grown);
+            }
+            for area in moved {
+                if let Err(err) = self.areas.map(area, &mut self.pt, false) {
+                    result = Err(err.into());
+                    break;
+                }
+            }
+        }
+        if result.is_ok() && lock_grown {
+            result = self.lock(new_start + range.size(), grow_size);
+        }
 
+        if let Err(err) = result {
+            self.move_back(range, new_start, grow_size, &parts);
+            for part in locked {
+                self.set_pinned(part, true);
+                self.insert_locked(part);
+            }
+            return Err(err);
+        }
+        self.unmap(range.start, range.size())?;
+        for part in locked {
+            let part =
+                VirtAddrRange::from_start_size(new_start + (part.start - range.start), part.size());
+            self.set_pinned(part, true);
+            self.insert_locked(part);
+        }
+        Ok(())
+    }
+
+    /// Undoes a failed [`move_range`](Self::move_range), moving the pages of
+    /// `parts` back into `range` and unmapping what was mapped at
+    /// `new_start`.
+    fn move_back(
+        &mut self,
+        range: VirtAddrRange,
+        new_start: VirtAddr,
+        grow_size: usize,
+        parts: &[(VirtAddrRange, Backend)],
+    ) {
+        let mut modify = self.pt.modify();
+        for (part, backend) in parts {
+            let part_start = new_start + (part.start - range.start);
+            let moved = VirtAddrRange::from_start_size(part_start, part.size());
+            if let Err(err) = backend.relocate(moved, part.start, &mut modify) {
+                warn!("Failed to move {moved:?} back: {err:?}");
+            }
+        }
+        drop(modify);
+        // Frees the pages populated in the part grown.
+        if let Err(err) = self.unmap(new_start, range.size() + grow_size) {
+            warn!("Failed to unmap {new_start:?} after a failed move: {err:?}");
+        }
+    }
+
+    /// Gives advice about the use of the memory region at `start`, see
+    /// [`Advice`].
+    ///
+    /// Returns [`AxError::InvalidInput`] if pages are dropped from a locked
+    /// range, or the advice does not apply to a mapping, and
+    /// [`AxError::NoMemory`] if the region is not fully mapped.
+    pub fn advise(&mut self, start: VirtAddr, size: usize, advice: Advice) -> AxResult {
+        self.validate_region(start, size)?;
+        let range = VirtAddrRange::from_start_size(start, size);
+        let drops_pages = matches!(advice, Advice::DontNeed | Advice::Free);
+        if drops_pages && !self.locked_in(range).is_empty() {
+            ax_bail!(InvalidInput, "range locked");
+        }
+
+        let mut callbacks = Vec::new();
+        let result = self.repin(&[range.start, range.end], |this| {
+            let mut gather = TlbGather::new(this.pt.root_paddr());
+            let mut modify = this.pt.modify();
+            for area in this.areas.iter() {
+                let Some(part) = intersect(area.va_range(), range) else {
+                    continue;
+                };
+                let callback =
+                    area.backend()
+                        .advise(part, area.flags(), advice, &mut modify, &mut gather)?;
+                callbacks.extend(callback);
+            }
+            AxResult::Ok(())
+        });
+        if drops_pages {
+            self.flush_tlb_and_free(range);
+        }
+        for cb in callbacks {
+            cb(self);
+        }
+        result?;
+
+        if !self.can_access_range(start, size, MappingFlags::empty()) {
+            ax_bail!(NoMemory, "range not fully mapped");
+        }
+        Ok(())
+    }
+
+    /// Locks the memory region at `start`: populates its pages, and pins them
+    /// so that they are never evicted from the page cache or swapped out,
+    /// until they are unlocked.
+    ///
+    /// Locking pages again has no effect.
+    ///
+    /// Returns [`AxError::NoMemory`] if the region is not fully mapped or its
+    /// pages cannot be populated.
+    pub fn lock(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.validate_region(start, size)?;
+        if !self.can_access_range(start, size, MappingFlags::empty()) {
+            ax_bail!(NoMemory, "range not fully mapped");
+        }
+        let range = VirtAddrRange::from_start_size(start, size);
+        let locked = self.locked_in(range);
+
+        // Pages are pinned as soon as they are populated, so that they are
+        // not evicted by the following ones.
+        let mut pinned = Vec::new();
+        for part in subtract(range, &locked) {
+            for vaddr in PageIter4K::new(part.start, part.end).unwrap() {
+                let page = VirtAddrRange::from_start_size(vaddr, PAGE_SIZE_4K);
+                if let Err(err) = self.repin(&[vaddr], |this| this.fault_in(vaddr)) {
+                    for page in pinned {
+                        self.set_pinned(page, false);
+                    }
+                    return Err(err);
+                }
+                self.set_pinned(page, true);
+                pinned.push(page);
+            }
+        }
+        self.insert_locked(range);
+        Ok(())
+    }
+
+    /// Unlocks the memory region at `start`, unpinning its pages.
+    pub fn unlock(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.validate_region(start, size)?;
+        self.remove_locked(VirtAddrRange::from_start_size(start, size));
         Ok(())
     }
 
     /// Removes all mappings in the address space.
     pub fn clear(&mut self) {
+        self.remove_locked(self.va_range);
         self.areas.clear(&mut self.pt).unwrap();
+        self.flush_tlb_and_free(self.va_range);
     }
 
     /// Checks whether an access to the specified memory region is valid.
@@ -323,15 +695,48 @@ impl AddrSpace {
         if let Some(area) = self.areas.find(vaddr) {
             let flags = area.flags();
             if flags.contains(access_flags) {
-                let page_size = area.backend().page_size();
-                let populate_result = area.backend().populate(
-                    VirtAddrRange::from_start_size(vaddr.align_down(page_size), page_size as _),
-                    flags,
-                    access_flags,
-                    &mut self.pt.modify(),
-                );
+                // The size of the page mapped at `vaddr`, if any.
+                let mapped_size = self.pt.query(vaddr).ok().map(|(_, _, size)| size);
+                if mapped_size.is_none() {
+                    let huge = VirtAddrRange::from_start_size(
+                        vaddr.align_down(PageSize::Size2M),
+                        PageSize::Size2M as _,
+                    );
+                    if area.start() <= huge.start
+                        && huge.end <= area.end()
+                        && area
+                            .backend()
+                            .populate_huge(huge, flags, &mut self.pt.modify())
+                    {
+                        return true;
+                    }
+                }
+                let backend = area.backend().clone();
+                let page_size = backend.page_size();
+                let range =
+                    VirtAddrRange::from_start_size(vaddr.align_down(page_size), page_size as _);
+                // Copy-on-write replaces the frames of locked pages.
+                let populate = |this: &mut Self| {
+                    this.repin(&[vaddr], |this| {
+                        backend.populate(range, flags, access_flags, &mut this.pt.modify())
+                    })
+                };
+                let populate_result = match populate(self) {
+                    #[cfg(feature = "swap")]
+                    Err(AxError::NoMemory) if self.swap_out(SWAP_CLUSTER) > 0 => populate(self),
+                    result => result,
+                };
                 return match populate_result {
                     Ok((n, callback)) => {
+                        // A mapped page is replaced or made writable
+                        // (copy-on-write), so other CPUs must drop their
+                        // entries of the old one.
+                        if let Some(size) = mapped_size.filter(|_| n > 0) {
+                            self.flush_tlb(VirtAddrRange::from_start_size(
+                                vaddr.align_down(size),
+                                size as _,
+                            ));
+                        }
                         if let Some(cb) = callback {
                             cb(self);
                         }
@@ -378,9 +783,243 @@ impl AddrSpace {
             aspace.areas.map(new_area, &mut aspace.pt, false)?;
         }
         drop(guard);
+        // Copy-on-write pages are now read-only in this address space.
+        drop(self_modify);
+        self.flush_tlb(self.va_range);
+        #[cfg(feature = "swap")]
+        if self.swap_enabled {
+            swap::register(&new_aspace);
+        }
 
         Ok(new_aspace)
     }
+
+    /// Enables swapping out the pages of the address space.
+    ///
+    /// It is called by [`swap::register`].
+    #[cfg(feature = "swap")]
+    pub(crate) fn enable_swap(&mut self) {
+        self.swap_enabled = true;
+    }
+
+    /// Swaps out up to `max` cold pages, and returns the number of pages
+    /// freed.
+    ///
+    /// Does nothing if swapping is not enabled for the address space or no
+    /// swap space is in use.
+    #[cfg(feature = "swap")]
+    pub fn swap_out(&mut self, max: usize) -> usize {
+        if !self.swap_enabled || max == 0 {
+            return 0;
+        }
+        let hand = self.swap_hand;
+        let mut victims = Vec::new();
+        let mut stop = None;
+        let mut pt = self.pt.modify();
+        // The first round may only clear the referenced flags.
+        'scan: for _ in 0..2 {
+            let after_hand = self
+                .areas
+                .iter()
+                .filter(|area| area.end() > hand)
+                .map(|area| (area, VirtAddrRange::new(area.start().max(hand), area.end())));
+            let before_hand = self
+                .areas
+                .iter()
+                .filter(|area| area.start() < hand)
+                .map(|area| (area, VirtAddrRange::new(area.start(), area.end().min(hand))));
+            for (area, range) in after_hand.chain(before_hand) {
+                stop = area.backend().scan_cold(range, &mut pt, &mut victims, max);
+                if stop.is_some() {
+                    break 'scan;
+                }
+            }
+        }
+        if let Some(stop) = stop {
+            self.swap_hand = stop;
+        }
+
+        let (Some(first), Some(last)) = (
+            victims.iter().map(|it| it.vaddr()).min(),
+            victims.iter().map(|it| it.vaddr()).max(),
+        ) else {
+            return 0;
+        };
+        tlb::shootdown(
+            &self.active_cpus,
+            VirtAddrRange::new(first, last + PAGE_SIZE_4K),
+        );
+        swap::write_out(victims, &mut pt)
+    }
+
+    /// Checks whether the given address range is in the address space and not
+    /// mapped.
+    fn is_free(&self, start: VirtAddr, size: usize) -> bool {
+        self.contains_range(start, size)
+            && self
+                .areas
+                .iter()
+                .all(|area| area.end() <= start || area.start() >= start + size)
+    }
+
+    /// Populates the page at `vaddr` as if it was accessed with the
+    /// permissions of its area.
+    fn fault_in(&mut self, vaddr: VirtAddr) -> AxResult {
+        let area = self.areas.find(vaddr).ok_or(AxError::NoMemory)?;
+        let flags = area.flags();
+        let page_size = area.backend().page_size();
+        let range = VirtAddrRange::from_start_size(vaddr.align_down(page_size), page_size as _);
+        let access_flags = flags & (MappingFlags::READ | MappingFlags::WRITE);
+        let mapped_size = self.pt.query(vaddr).ok().map(|(_, _, size)| size);
+        let (n, callback) =
+            area.backend()
+                .populate(range, flags, access_flags, &mut self.pt.modify())?;
+        if let Some(size) = mapped_size.filter(|_| n > 0) {
+            self.flush_tlb(VirtAddrRange::from_start_size(
+                vaddr.align_down(size),
+                size as _,
+            ));
+        }
+        if let Some(cb) = callback {
+            cb(self);
+        }
+        Ok(())
+    }
+
+    /// Returns the descriptor of the frame mapped at `vaddr`, if it is
+    /// allocated from the page allocator.
+    fn frame_at(&self, vaddr: VirtAddr) -> Option<&'static FrameDesc> {
+        let (paddr, _flags, _) = self.pt.query(vaddr).ok()?;
+        #[cfg(feature = "swap")]
+        if swap::slot_of(paddr, _flags).is_some() {
+            return None;
+        }
+        global_allocator().frame(phys_to_virt(paddr).as_usize())
+    }
+
+    /// Pins or unpins the frames mapped in `range`.
+    fn set_pinned(&self, range: VirtAddrRange, pinned: bool) {
+        for vaddr in PageIter4K::new(range.start, range.end).unwrap() {
+            let Some(frame) = self.frame_at(vaddr) else {
+                continue;
+            };
+            if pinned {
+                frame.pin();
+            } else if frame.pin_count() != 0 {
+                // Otherwise, the page has been replaced, e.g. when its file
+                // was truncated.
+                frame.unpin();
+            }
+        }
+    }
+
+    /// Returns whether the page at `vaddr` is locked.
+    fn is_locked(&self, vaddr: VirtAddr) -> bool {
+        self.locked
+            .range(..=vaddr)
+            .next_back()
+            .is_some_and(|(_, &end)| vaddr < end)
+    }
+
+    /// Returns the parts of `range` which are locked, in order.
+    fn locked_in(&self, range: VirtAddrRange) -> Vec<VirtAddrRange> {
+        self.locked
+            .iter()
+            .filter_map(|(&start, &end)| intersect(VirtAddrRange::new(start, end), range))
+            .collect()
+    }
+
+    /// Records `range`, whose pages are pinned, as locked.
+    fn insert_locked(&mut self, mut range: VirtAddrRange) {
+        let merged = self
+            .locked
+            .iter()
+            .filter(|&(&start, &end)| start <= range.end && end >= range.start)
+            .map(|(&start, _)| start)
+            .collect::<Vec<_>>();
+        for start in merged {
+            let end = self.locked.remove(&start).unwrap();
+            range.start = range.start.min(start);
+            range.end = range.end.max(end);
+        }
+        self.locked.insert(range.start, range.end);
+    }
+
+    /// Unpins the pages of the locked parts of `range`, and records them as
+    /// unlocked.
+    fn remove_locked(&mut self, range: VirtAddrRange) {
+        for part in self.locked_in(range) {
+            self.set_pinned(part, false);
+        }
+        let overlapping = self
+            .locked
+            .iter()
+            .filter(|&(&start, &end)| start < range.end && end > range.start)
+            .map(|(&start, _)| start)
+            .collect::<Vec<_>>();
+        for start in overlapping {
+            let end = self.locked.remove(&start).unwrap();
+            if start < range.start {
+                self.locked.insert(start, range.start);
+            }
+            if end > range.end {
+                self.locked.insert(range.end, end);
+            }
+        }
+    }
+
+    /// Runs `f`, which may replace the frames of the huge pages containing
+    /// `addrs`, and keeps the locked pages in them pinned.
+    fn repin<R>(&mut self, addrs: &[VirtAddr], f: impl FnOnce(&mut Self) -> R) -> R {
+        if self.locked.is_empty() {
+            return f(self);
+        }
+        let mut blocks = addrs
+            .iter()
+            .map(|vaddr| vaddr.align_down(PageSize::Size2M))
+            .collect::<Vec<_>>();
+        blocks.dedup();
+        let locked = blocks
+            .into_iter()
+            .flat_map(|start| {
+                let end = start.as_usize().saturating_add(PageSize::Size2M as _);
+                self.locked_in(VirtAddrRange::new(start, end.into()))
+            })
+            .collect::<Vec<_>>();
+
+        for &part in &locked {
+            self.set_pinned(part, false);
+        }
+        let result = f(self);
+        for &part in &locked {
+            self.set_pinned(part, true);
+        }
+        result
+    }
+}
+
+/// Returns the intersection of two ranges, if it is not empty.
+fn intersect(a: VirtAddrRange, b: VirtAddrRange) -> Option<VirtAddrRange> {
+    let start = a.start.max(b.start);
+    let end = a.end.min(b.end);
+    (start < end).then(|| VirtAddrRange::new(start, end))
+}
+
+/// Returns the parts of `range` outside of `holes`, which are in order and
+/// inside `range`.
+fn subtract(range: VirtAddrRange, holes: &[VirtAddrRange]) -> Vec<VirtAddrRange> {
+    let mut parts = Vec::new();
+    let mut start = range.start;
+    for hole in holes {
+        if hole.start > start {
+            parts.push(VirtAddrRange::new(start, hole.start));
+        }
+        start = start.max(hole.end);
+    }
+    if start < range.end {
+        parts.push(VirtAddrRange::new(start, range.end));
+    }
+    parts
 }
 
 impl fmt::Debug for AddrSpace {
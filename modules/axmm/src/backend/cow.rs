@@ -1,46 +1,44 @@
-use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::slice;
 
-use axerrno::{AxError, AxResult};
+use axalloc::FrameFlags;
+use axerrno::{AxError, AxResult, ax_bail};
 use axfs_ng::FileBackend;
 use axhal::{
     mem::phys_to_virt,
     paging::{MappingFlags, PageSize, PageTableMut, PagingError},
 };
 use axsync::Mutex;
-use kspin::SpinNoIrq;
-use memory_addr::{PhysAddr, VirtAddr, VirtAddrRange};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr, VirtAddrRange};
 
+#[cfg(feature = "swap")]
+use crate::swap::{self, SwapSlot, SwapVictim};
 use crate::{
     AddrSpace,
-    backend::{Backend, BackendOps, alloc_frame, dealloc_frame, pages_in},
+    backend::{
+        Advice, Backend, BackendOps, alloc_frame, dealloc_frame, frame_desc, pages_in, relocated,
+    },
+    thp,
+    tlb::TlbGather,
 };
 
-static FRAME_TABLE: SpinNoIrq<BTreeMap<PhysAddr, u8>> = SpinNoIrq::new(BTreeMap::new());
-
-fn inc_frame_ref(paddr: PhysAddr) {
-    let mut table = FRAME_TABLE.lock();
-    *table.entry(paddr).or_insert(0) += 1;
-}
-
-fn dec_frame_ref(paddr: PhysAddr) -> usize {
-    let mut table = FRAME_TABLE.lock();
-    if let Some(count) = table.get_mut(&paddr) {
-        let prev = *count;
-        if prev == 1 {
-            table.remove(&paddr);
-        } else {
-            *count -= 1;
-        }
-        prev as usize
-    } else {
-        0
+fn copy_frame(src: PhysAddr, dst: PhysAddr, size: usize) {
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            phys_to_virt(src).as_ptr(),
+            phys_to_virt(dst).as_mut_ptr(),
+            size,
+        );
     }
 }
 
 /// Copy-on-write mapping backend.
 ///
 /// This corresponds to the `MAP_PRIVATE` flag.
+///
+/// Anonymous mappings of 4K pages may be backed by transparent huge pages,
+/// see [`thp`]. With the `swap` feature, their base pages may be swapped out,
+/// see [`swap`].
 #[derive(Clone)]
 pub struct CowBackend {
     start: VirtAddr,
@@ -56,7 +54,7 @@ impl CowBackend {
         pt: &mut PageTableMut,
     ) -> AxResult {
         let frame = alloc_frame(true, self.size)?;
-        inc_frame_ref(frame);
+        frame_desc(frame).inc_ref();
 
         if let Some((file, file_start, file_end)) = &self.file {
             let buf = unsafe {
@@ -75,43 +73,181 @@ impl CowBackend {
 
             file.read_at(&mut &mut buf[start..start + max_read], file_start)?;
         }
+        frame_desc(frame).set_flags(FrameFlags::REFERENCED, true);
         pt.map(vaddr, frame, self.size, flags)?;
         Ok(())
     }
 
+    /// Reads the page swapped out to `slot` back, and maps it at `vaddr` in
+    /// place of its swap entry.
+    #[cfg(feature = "swap")]
+    fn swap_in_at(
+        &self,
+        vaddr: VirtAddr,
+        slot: SwapSlot,
+        flags: MappingFlags,
+        pt: &mut PageTableMut,
+    ) -> AxResult {
+        let frame = alloc_frame(false, PageSize::Size4K)?;
+        if let Err(err) = swap::swap_in(slot, frame) {
+            dealloc_frame(frame, PageSize::Size4K);
+            return Err(err);
+        }
+        swap::take_entry(pt, vaddr);
+        if let Err(err) = pt.map(vaddr, frame, PageSize::Size4K, flags) {
+            dealloc_frame(frame, PageSize::Size4K);
+            swap::set_entry(pt, vaddr, slot)?;
+            return Err(err.into());
+        }
+        let desc = frame_desc(frame);
+        desc.inc_ref();
+        desc.set_flags(FrameFlags::REFERENCED, true);
+        swap::free_slot(slot);
+        Ok(())
+    }
+
+    /// Returns whether the mapping may be backed by transparent huge pages.
+    fn thp_eligible(&self) -> bool {
+        self.size == PageSize::Size4K && self.file.is_none() && thp::is_enabled()
+    }
+
+    /// Tries to map a zeroed huge page at `vaddr`, which must be aligned to it.
+    fn alloc_huge_at(&self, vaddr: VirtAddr, flags: MappingFlags, pt: &mut PageTableMut) -> bool {
+        let Ok(frame) = alloc_frame(true, PageSize::Size2M) else {
+            thp::record_fallback();
+            return false;
+        };
+        // Fails if base pages or swap entries are in the range.
+        if pt.map(vaddr, frame, PageSize::Size2M, flags).is_err() {
+            dealloc_frame(frame, PageSize::Size2M);
+            thp::record_fallback();
+            return false;
+        }
+        frame_desc(frame).inc_ref();
+        true
+    }
+
+    /// Splits the huge page mapped at `vaddr` into base pages with the same
+    /// flags.
+    ///
+    /// The frames of the huge page are reused if no other address space maps
+    /// it. Otherwise, its content is copied to new frames.
+    fn split_huge_page(&self, vaddr: VirtAddr, pt: &mut PageTableMut) -> AxResult {
+        let (paddr, flags, page_size) = pt.query(vaddr)?;
+        let start = vaddr.align_down(page_size);
+        let frame = paddr.align_down(page_size);
+        let num_pages = page_size as usize / PAGE_SIZE_4K;
+
+        let desc = frame_desc(frame);
+        let frames = match desc.dec_ref() {
+            0 => unreachable!(),
+            1 => {
+                desc.inc_ref();
+                thp::huge_page_freed();
+                let frames = (0..num_pages)
+                    .map(|i| frame + i * PAGE_SIZE_4K)
+                    .collect::<Vec<_>>();
+                for &it in &frames[1..] {
+                    frame_desc(it).inc_ref();
+                }
+                frames
+            }
+            2.. => {
+                let mut frames = Vec::with_capacity(num_pages);
+                for i in 0..num_pages {
+                    match alloc_frame(false, PageSize::Size4K) {
+                        Ok(new_frame) => {
+                            copy_frame(frame + i * PAGE_SIZE_4K, new_frame, PAGE_SIZE_4K);
+                            frame_desc(new_frame).inc_ref();
+                            frames.push(new_frame);
+                        }
+                        Err(err) => {
+                            desc.inc_ref();
+                            for it in frames {
+                                dealloc_frame(it, PageSize::Size4K);
+                            }
+                            return Err(err);
+                        }
+                    }
+                }
+                frames
+            }
+        };
+
+        pt.unmap(start)?;
+        for (i, frame) in frames.into_iter().enumerate() {
+            pt.map(start + i * PAGE_SIZE_4K, frame, PageSize::Size4K, flags)?;
+        }
+        thp::record_split();
+        Ok(())
+    }
+
+    /// Splits the huge pages crossing the bounds of `range`, so that the
+    /// range can be unmapped or protected on its own.
+    fn split_huge_bounds(&self, range: VirtAddrRange, pt: &mut PageTableMut) -> AxResult {
+        if self.size != PageSize::Size4K {
+            return Ok(());
+        }
+        for addr in [range.start, range.end] {
+            if let Ok((_, _, page_size)) = pt.query(addr) {
+                if page_size != self.size && !addr.is_aligned(page_size) {
+                    self.split_huge_page(addr, pt)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a write to the page mapped at `vaddr`, and returns the size of
+    /// the page mapped there afterwards.
     fn handle_cow_fault(
         &self,
         vaddr: VirtAddr,
         paddr: PhysAddr,
+        page_size: PageSize,
         flags: MappingFlags,
         pt: &mut PageTableMut,
-    ) -> AxResult {
-        match dec_frame_ref(paddr) {
+    ) -> AxResult<PageSize> {
+        let vaddr = vaddr.align_down(page_size);
+        let frame = paddr.align_down(page_size);
+        let desc = frame_desc(frame);
+        match desc.dec_ref() {
             0 => unreachable!(),
             // There is only one AddrSpace reference to the page,
             // so there is no need to copy it.
             1 => {
-                inc_frame_ref(paddr);
+                desc.inc_ref();
+                desc.set_flags(FrameFlags::COW, false);
+                desc.set_flags(FrameFlags::REFERENCED, true);
                 pt.protect(vaddr, flags)?;
             }
             // Allocates the new page and copies the contents of the original page,
             // remapping the virtual address to the physical address of the new page.
             2.. => {
-                let new_frame = alloc_frame(false, self.size)?;
-                inc_frame_ref(new_frame);
-                unsafe {
-                    core::ptr::copy_nonoverlapping(
-                        phys_to_virt(paddr).as_ptr(),
-                        phys_to_virt(new_frame).as_mut_ptr(),
-                        self.size as _,
-                    );
-                }
+                let new_frame = match alloc_frame(false, page_size) {
+                    Ok(new_frame) => new_frame,
+                    Err(err) => {
+                        desc.inc_ref();
+                        if page_size == self.size {
+                            return Err(err);
+                        }
+                        // Only copy the base page written to.
+                        thp::record_fallback();
+                        self.split_huge_page(vaddr, pt)?;
+                        let (paddr, _, page_size) = pt.query(vaddr)?;
+                        return self.handle_cow_fault(vaddr, paddr, page_size, flags, pt);
+                    }
+                };
+                let new_desc = frame_desc(new_frame);
+                new_desc.inc_ref();
+                new_desc.set_flags(FrameFlags::REFERENCED, true);
+                copy_frame(frame, new_frame, page_size as _);
 
                 pt.remap(vaddr, new_frame, flags)?;
             }
         }
 
-        Ok(())
+        Ok(page_size)
     }
 }
 
@@ -125,13 +261,31 @@ impl BackendOps for CowBackend {
         Ok(())
     }
 
-    fn unmap(&self, range: VirtAddrRange, pt: &mut PageTableMut) -> AxResult {
+    fn unmap(
+        &self,
+        range: VirtAddrRange,
+        pt: &mut PageTableMut,
+        gather: &mut TlbGather,
+    ) -> AxResult {
         debug!("Cow::unmap: {range:?}");
+        self.split_huge_bounds(range, pt)?;
+        // The end of the last huge page unmapped.
+        let mut huge_end = range.start;
         for addr in pages_in(range, self.size)? {
+            if addr < huge_end {
+                continue;
+            }
+            #[cfg(feature = "swap")]
+            if let Some(slot) = swap::take_entry(pt, addr) {
+                swap::free_slot(slot);
+                continue;
+            }
             if let Ok((frame, _flags, page_size)) = pt.unmap(addr) {
-                assert_eq!(page_size, self.size);
-                if dec_frame_ref(frame) == 1 {
-                    dealloc_frame(frame, self.size);
+                if page_size != self.size {
+                    huge_end = addr + page_size as usize;
+                }
+                if frame_desc(frame).dec_ref() == 1 {
+                    gather.free_frame(frame, page_size);
                 }
             } else {
                 // Deallocation is needn't if the page is not allocated.
@@ -140,6 +294,35 @@ impl BackendOps for CowBackend {
         Ok(())
     }
 
+    fn split_huge(&self, range: VirtAddrRange, pt: &mut PageTableMut) -> AxResult {
+        self.split_huge_bounds(range, pt)
+    }
+
+    #[cfg(feature = "swap")]
+    fn protect(
+        &self,
+        range: VirtAddrRange,
+        new_flags: MappingFlags,
+        pt: &mut PageTableMut,
+    ) -> AxResult {
+        self.split_huge_bounds(range, pt)?;
+        let mut addr = range.start;
+        while addr < range.end {
+            match pt.query(addr) {
+                Ok((paddr, flags, page_size)) => {
+                    // Swap entries must stay non-present.
+                    if swap::slot_of(paddr, flags).is_none() {
+                        pt.protect(addr, new_flags)?;
+                    }
+                    addr += page_size as usize;
+                }
+                Err(PagingError::NotMapped) => addr += self.size as usize,
+                Err(_) => return Err(AxError::BadAddress),
+            }
+        }
+        Ok(())
+    }
+
     fn populate(
         &self,
         range: VirtAddrRange,
@@ -148,20 +331,42 @@ impl BackendOps for CowBackend {
         pt: &mut PageTableMut,
     ) -> AxResult<(usize, Option<Box<dyn FnOnce(&mut AddrSpace)>>)> {
         let mut pages = 0;
+        // The end of the last huge page populated.
+        let mut huge_end = range.start;
         for addr in pages_in(range, self.size)? {
+            if addr < huge_end {
+                continue;
+            }
             match pt.query(addr) {
-                Ok((paddr, page_flags, page_size)) => {
-                    assert_eq!(self.size, page_size);
+                Ok((paddr, page_flags, mut page_size)) => {
+                    #[cfg(feature = "swap")]
+                    if let Some(slot) = swap::slot_of(paddr, page_flags) {
+                        self.swap_in_at(addr, slot, flags, pt)?;
+                        pages += 1;
+                        continue;
+                    }
                     if access_flags.contains(MappingFlags::WRITE)
                         && !page_flags.contains(MappingFlags::WRITE)
                     {
-                        self.handle_cow_fault(addr, paddr, flags, pt)?;
+                        page_size = self.handle_cow_fault(addr, paddr, page_size, flags, pt)?;
                         pages += 1;
                     }
+                    if page_size != self.size {
+                        huge_end = addr.align_down(page_size) + page_size as usize;
+                    }
                 }
-                // If the page is not mapped, try map it.
+                // If the page is not mapped, try map it, with a huge page if
+                // the range covers one.
                 Err(PagingError::NotMapped) => {
-                    self.alloc_new_at(addr, flags, pt)?;
+                    if self.thp_eligible()
+                        && addr.is_aligned(PageSize::Size2M)
+                        && range.end - addr >= PageSize::Size2M as usize
+                        && self.alloc_huge_at(addr, flags, pt)
+                    {
+                        huge_end = addr + PageSize::Size2M as usize;
+                    } else {
+                        self.alloc_new_at(addr, flags, pt)?;
+                    }
                     pages += 1;
                 }
                 Err(_) => return Err(AxError::BadAddress),
@@ -170,6 +375,104 @@ impl BackendOps for CowBackend {
         Ok((pages, None))
     }
 
+    fn populate_huge(
+        &self,
+        range: VirtAddrRange,
+        flags: MappingFlags,
+        pt: &mut PageTableMut,
+    ) -> bool {
+        self.thp_eligible() && self.alloc_huge_at(range.start, flags, pt)
+    }
+
+    #[cfg(feature = "swap")]
+    fn scan_cold(
+        &self,
+        range: VirtAddrRange,
+        pt: &mut PageTableMut,
+        victims: &mut Vec<SwapVictim>,
+        max: usize,
+    ) -> Option<VirtAddr> {
+        if self.size != PageSize::Size4K {
+            return None;
+        }
+        swap::scan_cold(range, pt, victims, max)
+    }
+
+    #[cfg_attr(not(feature = "swap"), allow(unused_variables))]
+    fn advise(
+        &self,
+        range: VirtAddrRange,
+        flags: MappingFlags,
+        advice: Advice,
+        pt: &mut PageTableMut,
+        gather: &mut TlbGather,
+    ) -> AxResult<Option<Box<dyn FnOnce(&mut AddrSpace)>>> {
+        match advice {
+            Advice::DontNeed => self.unmap(range, pt, gather)?,
+            Advice::Free => {
+                if self.file.is_some() {
+                    ax_bail!(InvalidInput, "not a private anonymous mapping");
+                }
+                // The pages would be zeroed on the next access anyway.
+                self.unmap(range, pt, gather)?;
+            }
+            #[cfg(feature = "swap")]
+            Advice::WillNeed => {
+                for vaddr in pages_in(range, self.size)? {
+                    if let Some(slot) = swap::entry_at(pt, vaddr) {
+                        self.swap_in_at(vaddr, slot, flags, pt)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn relocate(
+        &self,
+        range: VirtAddrRange,
+        new_start: VirtAddr,
+        pt: &mut PageTableMut,
+    ) -> AxResult<Backend> {
+        self.split_huge_bounds(range, pt)?;
+        let mut addr = range.start;
+        while addr < range.end {
+            let (paddr, flags, page_size) = match pt.query(addr) {
+                Ok(it) => it,
+                Err(PagingError::NotMapped) => {
+                    addr += self.size as usize;
+                    continue;
+                }
+                Err(_) => return Err(AxError::BadAddress),
+            };
+            let new_addr = relocated(addr, range, new_start);
+            #[cfg(feature = "swap")]
+            if let Some(slot) = swap::slot_of(paddr, flags) {
+                swap::set_entry(pt, new_addr, slot)?;
+                swap::take_entry(pt, addr);
+                addr += PAGE_SIZE_4K;
+                continue;
+            }
+            if page_size == self.size {
+                pt.map(new_addr, paddr, page_size, flags)?;
+            } else if !(new_addr.is_aligned(page_size)
+                && pt.map(new_addr, paddr, page_size, flags).is_ok())
+            {
+                // Huge pages can only be moved to addresses aligned to them,
+                // where no page table of base pages is in the way.
+                self.split_huge_page(addr, pt)?;
+                continue;
+            }
+            pt.unmap(addr)?;
+            addr += page_size as usize;
+        }
+        Ok(Backend::Cow(CowBackend {
+            start: relocated(self.start, range, new_start),
+            ..self.clone()
+        }))
+    }
+
     fn clone_map(
         &self,
         range: VirtAddrRange,
@@ -180,19 +483,36 @@ impl BackendOps for CowBackend {
     ) -> AxResult<Backend> {
         let cow_flags = flags - MappingFlags::WRITE;
 
+        // The end of the last huge page shared.
+        let mut huge_end = range.start;
         for vaddr in pages_in(range, self.size)? {
+            if vaddr < huge_end {
+                continue;
+            }
             // Copy data from old memory area to new memory area.
             match old_pt.query(vaddr) {
-                Ok((paddr, _, page_size)) => {
-                    assert_eq!(page_size, self.size);
+                #[cfg_attr(not(feature = "swap"), allow(unused_variables))]
+                Ok((paddr, page_flags, page_size)) => {
+                    // Swap entries are shared like the pages.
+                    #[cfg(feature = "swap")]
+                    if let Some(slot) = swap::slot_of(paddr, page_flags) {
+                        swap::dup_slot(slot);
+                        swap::set_entry(new_pt, vaddr, slot)?;
+                        continue;
+                    }
+                    if page_size != self.size {
+                        huge_end = vaddr + page_size as usize;
+                    }
                     // If the page is mapped in the old page table:
                     // - Update its permissions in the old page table using `flags`.
                     // - Map the same physical page into the new page table at the same
                     // virtual address, with the same page size and `flags`.
-                    inc_frame_ref(paddr);
+                    let desc = frame_desc(paddr);
+                    desc.inc_ref();
+                    desc.set_flags(FrameFlags::COW, true);
 
                     old_pt.protect(vaddr, cow_flags)?;
-                    new_pt.map(vaddr, paddr, self.size, cow_flags)?;
+                    new_pt.map(vaddr, paddr, page_size, cow_flags)?;
                 }
                 // If the page is not mapped, skip it.
                 Err(PagingError::NotMapped) => {}
@@ -3,7 +3,10 @@ use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
 };
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use axerrno::{AxError, AxResult};
 use axfs_ng::{CachedFile, FileFlags};
@@ -13,9 +16,14 @@ use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 
 use crate::{
     AddrSpace,
-    backend::{Backend, BackendOps, pages_in},
+    backend::{Advice, Backend, BackendOps, pages_in, relocated},
+    tlb::TlbGather,
 };
 
+/// The number of pages read ahead after a page fault in a mapping accessed
+/// sequentially.
+const READ_AHEAD_PAGES: usize = 16;
+
 #[doc(hidden)]
 pub struct FileBackendInner {
     start: VirtAddr,
@@ -24,6 +32,9 @@ pub struct FileBackendInner {
     offset_page: u32,
     handle: AtomicUsize,
     futex_handle: Arc<()>,
+    /// The number of pages to read ahead after a page fault.
+    read_ahead: AtomicUsize,
+    aspace: Weak<Mutex<AddrSpace>>,
 }
 impl Drop for FileBackendInner {
     fn drop(&mut self) {
@@ -59,6 +70,44 @@ impl FileBackendInner {
         })
     }
 
+    fn page_number(&self, vaddr: VirtAddr) -> u32 {
+        ((vaddr - self.start) / PAGE_SIZE_4K) as u32 + self.offset_page
+    }
+
+    /// Reads the pages `pns` into the page cache, as far as the file goes,
+    /// and records the pages evicted meanwhile in `evicted`.
+    fn prefetch(&self, pns: Range<u32>, evicted: &mut Vec<u32>) {
+        let Ok(len) = self.cache.location().len() else {
+            return;
+        };
+        let end = pns.end.min(len.div_ceil(PAGE_SIZE_4K as u64) as u32);
+        for pn in pns.start..end {
+            let result = self.cache.with_page_or_insert(pn, |_, evicted_page| {
+                if let Some((pn, _)) = evicted_page {
+                    evicted.push(pn);
+                }
+                Ok(())
+            });
+            if let Err(err) = result {
+                debug!("Stopped reading ahead at page {pn}: {err:?}");
+                break;
+            }
+        }
+    }
+
+    /// Returns a callback unmapping the pages `evicted` from the page cache.
+    fn evict_later(self: &Arc<Self>, evicted: Vec<u32>) -> Option<Box<dyn FnOnce(&mut AddrSpace)>> {
+        if evicted.is_empty() {
+            return None;
+        }
+        let inner = self.clone();
+        Some(Box::new(move |aspace: &mut AddrSpace| {
+            for pn in evicted {
+                inner.on_evict(pn, aspace);
+            }
+        }))
+    }
+
     fn on_evict(self: &Arc<Self>, pn: u32, aspace: &mut AddrSpace) {
         let Some(pn) = pn.checked_sub(self.offset_page) else {
             return;
@@ -73,7 +122,9 @@ impl FileBackendInner {
 
         let pt = aspace.page_table_mut();
         match pt.modify().unmap(vaddr) {
-            Ok(_) | Err(PagingError::NotMapped) => {}
+            // The page is freed by the cache once we return.
+            Ok(_) => aspace.flush_tlb(VirtAddrRange::from_start_size(vaddr, PAGE_SIZE_4K)),
+            Err(PagingError::NotMapped) => {}
             Err(err) => {
                 warn!("Failed to unmap page {:?}: {:?}", vaddr, err);
             }
@@ -114,7 +165,12 @@ impl BackendOps for FileBackend {
         self.check_flags(flags)
     }
 
-    fn unmap(&self, range: VirtAddrRange, pt: &mut PageTableMut) -> AxResult {
+    fn unmap(
+        &self,
+        range: VirtAddrRange,
+        pt: &mut PageTableMut,
+        _gather: &mut TlbGather,
+    ) -> AxResult {
         for addr in pages_in(range, PageSize::Size4K)? {
             match pt.unmap(addr) {
                 Ok(_) | Err(PagingError::NotMapped) => {}
@@ -145,7 +201,8 @@ impl BackendOps for FileBackend {
     ) -> AxResult<(usize, Option<Box<dyn FnOnce(&mut AddrSpace)>>)> {
         let mut pages = 0;
         let mut to_be_evicted = Vec::new();
-        let start_page = ((range.start - self.0.start) / PAGE_SIZE_4K) as u32 + self.0.offset_page;
+        let start_page = self.0.page_number(range.start);
+        let mut end_page = None;
         for (i, addr) in pages_in(range, PageSize::Size4K)?.enumerate() {
             let pn = start_page + i as u32;
             match pt.query(addr) {
@@ -182,23 +239,62 @@ impl BackendOps for FileBackend {
                         pages += 1;
                         Ok(())
                     })?;
+                    end_page = Some(pn + 1);
                 }
                 Err(_) => return Err(AxError::BadAddress),
             }
         }
-        Ok((
-            pages,
-            if to_be_evicted.is_empty() {
-                None
-            } else {
-                let inner = self.0.clone();
-                Some(Box::new(move |aspace: &mut AddrSpace| {
-                    for pn in to_be_evicted {
-                        inner.on_evict(pn, aspace);
-                    }
-                }))
-            },
-        ))
+        let read_ahead = self.0.read_ahead.load(Ordering::Relaxed) as u32;
+        if let Some(end_page) = end_page.filter(|_| read_ahead > 0) {
+            self.0
+                .prefetch(end_page..end_page + read_ahead, &mut to_be_evicted);
+        }
+        Ok((pages, self.0.evict_later(to_be_evicted)))
+    }
+
+    fn advise(
+        &self,
+        range: VirtAddrRange,
+        _flags: MappingFlags,
+        advice: Advice,
+        pt: &mut PageTableMut,
+        gather: &mut TlbGather,
+    ) -> AxResult<Option<Box<dyn FnOnce(&mut AddrSpace)>>> {
+        match advice {
+            Advice::Normal | Advice::Random => self.0.read_ahead.store(0, Ordering::Relaxed),
+            Advice::Sequential => self.0.read_ahead.store(READ_AHEAD_PAGES, Ordering::Relaxed),
+            Advice::WillNeed => {
+                let mut evicted = Vec::new();
+                let pns = self.0.page_number(range.start)..self.0.page_number(range.end);
+                self.0.prefetch(pns, &mut evicted);
+                return Ok(self.0.evict_later(evicted));
+            }
+            // The pages stay in the page cache.
+            Advice::DontNeed => self.unmap(range, pt, gather)?,
+            Advice::Free => return Err(AxError::InvalidInput),
+        }
+        Ok(None)
+    }
+
+    fn relocate(
+        &self,
+        range: VirtAddrRange,
+        new_start: VirtAddr,
+        _pt: &mut PageTableMut,
+    ) -> AxResult<Backend> {
+        let aspace = self.0.aspace.upgrade().ok_or(AxError::BadState)?;
+        let inner = Arc::new(FileBackendInner {
+            start: relocated(self.0.start, range, new_start),
+            cache: self.0.cache.clone(),
+            flags: self.0.flags,
+            offset_page: self.0.offset_page,
+            handle: AtomicUsize::new(0),
+            futex_handle: self.0.futex_handle.clone(),
+            read_ahead: AtomicUsize::new(self.0.read_ahead.load(Ordering::Relaxed)),
+            aspace: Arc::downgrade(&aspace),
+        });
+        inner.register_listener(&aspace);
+        Ok(Backend::File(FileBackend(inner)))
     }
 
     fn clone_map(
@@ -216,6 +312,8 @@ impl BackendOps for FileBackend {
             offset_page: self.0.offset_page,
             handle: AtomicUsize::new(0),
             futex_handle: self.0.futex_handle.clone(),
+            read_ahead: AtomicUsize::new(self.0.read_ahead.load(Ordering::Relaxed)),
+            aspace: Arc::downgrade(new_aspace),
         });
         inner.register_listener(new_aspace);
         Ok(Backend::File(FileBackend(inner)))
@@ -238,6 +336,8 @@ impl Backend {
             offset_page,
             handle: AtomicUsize::new(0),
             futex_handle: Arc::new(()),
+            read_ahead: AtomicUsize::new(0),
+            aspace: Arc::downgrade(aspace),
         });
         inner.register_listener(aspace);
         Self::File(FileBackend(inner))
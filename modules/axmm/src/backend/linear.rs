@@ -8,6 +8,7 @@ use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr, VirtAddrRange};
 use crate::{
     AddrSpace,
     backend::{Backend, BackendOps},
+    tlb::TlbGather,
 };
 
 /// Linear mapping backend.
@@ -38,13 +39,29 @@ impl BackendOps for LinearBackend {
         Ok(())
     }
 
-    fn unmap(&self, range: VirtAddrRange, pt: &mut PageTableMut) -> AxResult {
+    fn unmap(
+        &self,
+        range: VirtAddrRange,
+        pt: &mut PageTableMut,
+        _gather: &mut TlbGather,
+    ) -> AxResult {
         let pa_range = PhysAddrRange::from_start_size(self.pa(range.start), range.size());
         debug!("Linear::unmap: {range:?} -> {pa_range:?}");
         pt.unmap_region(range.start, range.size())?;
         Ok(())
     }
 
+    fn relocate(
+        &self,
+        range: VirtAddrRange,
+        new_start: VirtAddr,
+        _pt: &mut PageTableMut,
+    ) -> AxResult<Backend> {
+        // The physical addresses stay the same.
+        let delta = new_start.as_usize().wrapping_sub(range.start.as_usize()) as isize;
+        Ok(Backend::new_linear(self.offset.wrapping_add(delta)))
+    }
+
     fn clone_map(
         &self,
         _range: VirtAddrRange,
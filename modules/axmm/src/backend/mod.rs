@@ -1,7 +1,9 @@
 //! Memory mapping backends.
+#[cfg(feature = "swap")]
+use alloc::vec::Vec;
 use alloc::{boxed::Box, sync::Arc};
 
-use axalloc::{UsageKind, global_allocator};
+use axalloc::{FrameDesc, UsageKind, global_allocator};
 use axerrno::{AxError, AxResult};
 use axhal::{
     mem::{phys_to_virt, virt_to_phys},
@@ -19,7 +21,9 @@ pub mod shared;
 
 pub use shared::SharedPages;
 
-use crate::{AddrSpace, page_iter::PageIterWrapper};
+#[cfg(feature = "swap")]
+use crate::swap::{self, SwapVictim};
+use crate::{AddrSpace, page_iter::PageIterWrapper, thp, tlb::TlbGather};
 
 fn divide_page(size: usize, page_size: PageSize) -> usize {
     assert!(page_size.is_aligned(size), "unaligned");
@@ -29,27 +33,79 @@ fn divide_page(size: usize, page_size: PageSize) -> usize {
 fn alloc_frame(zeroed: bool, size: PageSize) -> AxResult<PhysAddr> {
     let page_size = size as usize;
     let num_pages = page_size / PAGE_SIZE_4K;
-    let vaddr =
-        VirtAddr::from(global_allocator().alloc_pages(num_pages, page_size, UsageKind::UserMem)?);
+    let alloc = || global_allocator().alloc_pages(num_pages, page_size, UsageKind::UserMem);
+    let vaddr = VirtAddr::from(match alloc() {
+        Ok(vaddr) => vaddr,
+        // Huge pages fall back to base pages rather than reclaiming memory.
+        #[cfg(feature = "swap")]
+        Err(_) if size == PageSize::Size4K && swap::reclaim(num_pages) > 0 => alloc()?,
+        Err(err) => return Err(err.into()),
+    });
     if zeroed {
         unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, page_size) };
     }
     let paddr = virt_to_phys(vaddr);
+    if size != PageSize::Size4K {
+        thp::huge_page_allocated();
+    }
 
     Ok(paddr)
 }
 
-fn dealloc_frame(frame: PhysAddr, align: PageSize) {
+pub(crate) fn dealloc_frame(frame: PhysAddr, align: PageSize) {
     let vaddr = phys_to_virt(frame);
     let page_size: usize = align.into();
     let num_pages = page_size / PAGE_SIZE_4K;
     global_allocator().dealloc_pages(vaddr.as_usize(), num_pages, UsageKind::UserMem);
+    if align != PageSize::Size4K {
+        thp::huge_page_freed();
+    }
+}
+
+/// Returns the descriptor of `frame`, which must have been allocated from the
+/// page allocator.
+pub(crate) fn frame_desc(frame: PhysAddr) -> &'static FrameDesc {
+    global_allocator()
+        .frame(phys_to_virt(frame).as_usize())
+        .expect("frame not allocated from the page allocator")
 }
 
 fn pages_in(range: VirtAddrRange, align: PageSize) -> AxResult<PageIterWrapper> {
     PageIterWrapper::new(range.start, range.end, align).ok_or(AxError::InvalidInput)
 }
 
+/// Returns where `addr` goes when the mapping of `range` is moved to
+/// `new_start`.
+fn relocated(addr: VirtAddr, range: VirtAddrRange, new_start: VirtAddr) -> VirtAddr {
+    VirtAddr::from(
+        addr.as_usize()
+            .wrapping_sub(range.start.as_usize())
+            .wrapping_add(new_start.as_usize()),
+    )
+}
+
+/// Advice about the use of a memory region, given to
+/// [`AddrSpace::advise`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// No special treatment.
+    Normal,
+    /// The pages will be accessed in random order, so reading ahead is
+    /// useless.
+    Random,
+    /// The pages will be accessed in sequential order, so they may be read
+    /// ahead aggressively.
+    Sequential,
+    /// The pages will be accessed soon, and may be read ahead.
+    WillNeed,
+    /// The pages will not be accessed soon, and are dropped. Private pages
+    /// are zero-filled, or read from the file again, on the next access.
+    DontNeed,
+    /// The pages of a private anonymous mapping are not needed anymore, and
+    /// may be freed.
+    Free,
+}
+
 #[enum_dispatch]
 pub trait BackendOps {
     /// Returns the page size of the backend.
@@ -59,7 +115,33 @@ pub trait BackendOps {
     fn map(&self, range: VirtAddrRange, flags: MappingFlags, pt: &mut PageTableMut) -> AxResult;
 
     /// Unmap a memory region.
-    fn unmap(&self, range: VirtAddrRange, pt: &mut PageTableMut) -> AxResult;
+    ///
+    /// The frames to free are handed to `gather`, which frees them once they
+    /// are flushed from the TLBs.
+    fn unmap(
+        &self,
+        range: VirtAddrRange,
+        pt: &mut PageTableMut,
+        gather: &mut TlbGather,
+    ) -> AxResult;
+
+    /// Splits the huge pages crossing the bounds of a memory region into
+    /// base pages, before the region is protected on its own.
+    fn split_huge(&self, _range: VirtAddrRange, _pt: &mut PageTableMut) -> AxResult {
+        Ok(())
+    }
+
+    /// Changes the flags of the pages mapped in a memory region.
+    fn protect(
+        &self,
+        range: VirtAddrRange,
+        new_flags: MappingFlags,
+        pt: &mut PageTableMut,
+    ) -> AxResult {
+        self.split_huge(range, pt)?;
+        pt.protect_region(range.start, range.size(), new_flags)?;
+        Ok(())
+    }
 
     /// Called before a memory region is protected.
     fn on_protect(
@@ -82,6 +164,70 @@ pub trait BackendOps {
         Ok((0, None))
     }
 
+    /// Tries to populate a memory region of the size of a huge page, aligned
+    /// to it, with a single huge page. Returns `false` if it is not possible.
+    fn populate_huge(
+        &self,
+        _range: VirtAddrRange,
+        _flags: MappingFlags,
+        _pt: &mut PageTableMut,
+    ) -> bool {
+        false
+    }
+
+    /// Scans `range` for cold pages to swap out, and unmaps them into
+    /// `victims` until it holds `max` pages.
+    ///
+    /// Returns the address where the scan stopped, if it did before the end
+    /// of the range.
+    #[cfg(feature = "swap")]
+    fn scan_cold(
+        &self,
+        _range: VirtAddrRange,
+        _pt: &mut PageTableMut,
+        _victims: &mut Vec<SwapVictim>,
+        _max: usize,
+    ) -> Option<VirtAddr> {
+        None
+    }
+
+    /// Applies `advice` to a memory region.
+    ///
+    /// The frames to free are handed to `gather`, like in
+    /// [`BackendOps::unmap`].
+    fn advise(
+        &self,
+        _range: VirtAddrRange,
+        _flags: MappingFlags,
+        advice: Advice,
+        _pt: &mut PageTableMut,
+        _gather: &mut TlbGather,
+    ) -> AxResult<Option<Box<dyn FnOnce(&mut AddrSpace)>>> {
+        match advice {
+            Advice::DontNeed | Advice::Free => Err(AxError::InvalidInput),
+            _ => Ok(None),
+        }
+    }
+
+    /// Checks whether a memory region can be extended by `size` bytes at its
+    /// end, with the same backend.
+    fn check_grow(&self, _range: VirtAddrRange, _size: usize) -> AxResult {
+        Ok(())
+    }
+
+    /// Moves a memory region to `new_start`, and returns the backend of the
+    /// moved region.
+    ///
+    /// The pages that can only be reached through the page table are moved
+    /// there, without copying. The region is then mapped at `new_start` with
+    /// the returned backend, and unmapped from its old place.
+    fn relocate(
+        &self,
+        range: VirtAddrRange,
+        new_start: VirtAddr,
+        pt: &mut PageTableMut,
+    ) -> AxResult<Backend>;
+
     /// Duplicates this mapping for use in a different page table.
     ///
     /// This differs from `clone`, which is designed for splitting a mapping
@@ -125,7 +271,8 @@ impl MappingBackend for Backend {
 
     fn unmap(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
         let range = VirtAddrRange::from_start_size(start, size);
-        if let Err(err) = BackendOps::unmap(self, range, &mut pt.modify()) {
+        let mut gather = TlbGather::new(pt.root_paddr());
+        if let Err(err) = BackendOps::unmap(self, range, &mut pt.modify(), &mut gather) {
             warn!("Failed to unmap area: {:?}", err);
             false
         } else {
@@ -140,6 +287,12 @@ impl MappingBackend for Backend {
         new_flags: Self::Flags,
         pt: &mut Self::PageTable,
     ) -> bool {
-        pt.modify().protect_region(start, size, new_flags).is_ok()
+        let range = VirtAddrRange::from_start_size(start, size);
+        if let Err(err) = BackendOps::protect(self, range, new_flags, &mut pt.modify()) {
+            warn!("Failed to protect area: {:?}", err);
+            false
+        } else {
+            true
+        }
     }
 }
@@ -1,15 +1,16 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::ops::Deref;
 
-use axerrno::AxResult;
+use axerrno::{AxResult, ax_bail};
 use axhal::paging::{MappingFlags, PageSize, PageTableMut};
 use axsync::Mutex;
 use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, VirtAddrRange};
 
-use super::{alloc_frame, dealloc_frame};
+use super::{alloc_frame, dealloc_frame, frame_desc};
 use crate::{
     AddrSpace,
-    backend::{Backend, BackendOps, divide_page, pages_in},
+    backend::{Advice, Backend, BackendOps, divide_page, pages_in, relocated},
+    tlb::TlbGather,
 };
 
 pub struct SharedPages {
@@ -80,18 +81,62 @@ impl BackendOps for SharedBackend {
             pages_in(range, self.pages.size)?.zip(self.pages_starting_from(range.start))
         {
             pt.map(vaddr, *paddr, self.pages.size, flags)?;
+            frame_desc(*paddr).inc_ref();
         }
         Ok(())
     }
 
-    fn unmap(&self, range: VirtAddrRange, pt: &mut PageTableMut) -> AxResult {
+    fn unmap(
+        &self,
+        range: VirtAddrRange,
+        pt: &mut PageTableMut,
+        gather: &mut TlbGather,
+    ) -> AxResult {
         debug!("Shared::unmap: {:?}", range);
+        // The pages may be freed with this mapping.
+        gather.keep_shared(self.pages.clone());
         for vaddr in pages_in(range, self.pages.size)? {
-            pt.unmap(vaddr)?;
+            let (frame, ..) = pt.unmap(vaddr)?;
+            frame_desc(frame).dec_ref();
         }
         Ok(())
     }
 
+    fn advise(
+        &self,
+        _range: VirtAddrRange,
+        _flags: MappingFlags,
+        advice: Advice,
+        _pt: &mut PageTableMut,
+        _gather: &mut TlbGather,
+    ) -> AxResult<Option<Box<dyn FnOnce(&mut AddrSpace)>>> {
+        // The pages are kept even if they are dropped from the page table,
+        // so there is nothing to do.
+        if advice == Advice::Free {
+            ax_bail!(InvalidInput, "not a private anonymous mapping");
+        }
+        Ok(None)
+    }
+
+    fn check_grow(&self, range: VirtAddrRange, size: usize) -> AxResult {
+        if range.end + size - self.start > self.pages.len() * self.pages.size as usize {
+            ax_bail!(InvalidInput, "beyond the shared pages");
+        }
+        Ok(())
+    }
+
+    fn relocate(
+        &self,
+        range: VirtAddrRange,
+        new_start: VirtAddr,
+        _pt: &mut PageTableMut,
+    ) -> AxResult<Backend> {
+        Ok(Backend::new_shared(
+            relocated(self.start, range, new_start),
+            self.pages.clone(),
+        ))
+    }
+
     fn clone_map(
         &self,
         _range: VirtAddrRange,
@@ -0,0 +1,78 @@
+//! Kernel stacks of tasks, mapped with guard pages.
+//!
+//! Stacks live in a dedicated region at the top of the kernel address space.
+//! Each one is mapped with an unmapped guard page on both sides, so that an
+//! overflow faults instead of running into another stack.
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::{MappingFlags, PageSize};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr, VirtAddrRange, align_up_4k, va};
+
+use crate::{AddrSpace, TlbFlush, backend::Backend, kernel_aspace};
+
+/// The size of the kernel stack region, which is the span of a top-level
+/// page table entry on all architectures or within one.
+const STACK_REGION_SIZE: usize = 1 << 30;
+
+/// The size of the guard page on each side of a stack.
+const GUARD_SIZE: usize = PAGE_SIZE_4K;
+
+/// Returns the kernel stack region, the last aligned [`STACK_REGION_SIZE`]
+/// bytes of the kernel address space.
+fn stack_region() -> VirtAddrRange {
+    let end = va!(axconfig::plat::KERNEL_ASPACE_BASE + axconfig::plat::KERNEL_ASPACE_SIZE)
+        .align_down(STACK_REGION_SIZE);
+    VirtAddrRange::from_start_size(end - STACK_REGION_SIZE, STACK_REGION_SIZE)
+}
+
+/// Creates the page tables covering the kernel stack region.
+///
+/// It makes the page table entries covering the region exist from the start,
+/// so that they are shared by the copies of the kernel mappings made for user
+/// address spaces. A page is mapped to create them and unmapped right away,
+/// which leaves the page tables in place. The address space is not loaded
+/// yet, so no TLB may hold the page.
+pub(crate) fn init(aspace: &mut AddrSpace) -> AxResult {
+    let start = stack_region().start;
+    let mut pt = aspace.page_table_mut().modify();
+    pt.map(
+        start,
+        PhysAddr::from(0),
+        PageSize::Size4K,
+        MappingFlags::READ,
+    )?;
+    pt.unmap(start)?;
+    Ok(())
+}
+
+/// Maps a kernel stack of `size` bytes, with an unmapped guard page on both
+/// sides, and returns its lowest address.
+pub fn alloc_kernel_stack(size: usize) -> AxResult<VirtAddr> {
+    let size = align_up_4k(size);
+    let region = stack_region();
+    let mut aspace = kernel_aspace().lock();
+    let start = aspace
+        .find_free_area(region.start, size + 2 * GUARD_SIZE, region)
+        .ok_or(AxError::NoMemory)?;
+    let bottom = start + GUARD_SIZE;
+    aspace.map(
+        bottom,
+        size,
+        MappingFlags::READ | MappingFlags::WRITE,
+        true,
+        Backend::new_alloc(bottom, PageSize::Size4K),
+    )?;
+    Ok(bottom)
+}
+
+/// Unmaps a kernel stack returned by [`alloc_kernel_stack`].
+pub fn dealloc_kernel_stack(bottom: VirtAddr, size: usize) -> AxResult {
+    let mut flush = TlbFlush::new();
+    let result = kernel_aspace()
+        .lock()
+        .unmap_deferred(bottom, align_up_4k(size), &mut flush);
+    // The other CPUs may spin on the kernel address space lock with IRQs
+    // disabled, so they are only asked to flush their TLBs once it is free.
+    flush.flush();
+    result
+}
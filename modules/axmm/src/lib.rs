@@ -6,6 +6,14 @@
 // This file has been modified by KylinSoft on 2025.
 
 //! [ArceOS](https://github.com/arceos-org/arceos) memory management module.
+//!
+//! # Cargo Features
+//!
+//! - `ipi`: Invalidate the TLB entries of unmapped or protected memory on all
+//!   CPUs an address space is loaded on, through IPIs. Otherwise only the
+//!   current CPU is flushed.
+//! - `swap`: Swap cold anonymous pages out to a block device or a file when
+//!   memory runs out, see [`swap`].
 
 #![no_std]
 
@@ -16,7 +24,12 @@ extern crate alloc;
 
 mod aspace;
 pub mod backend;
+mod kstack;
 mod page_iter;
+#[cfg(feature = "swap")]
+pub mod swap;
+pub mod thp;
+mod tlb;
 
 use axerrno::LinuxResult;
 use axhal::{
@@ -28,6 +41,8 @@ use lazyinit::LazyInit;
 use memory_addr::{MemoryAddr, PhysAddr, va};
 
 pub use self::aspace::AddrSpace;
+pub use self::kstack::{alloc_kernel_stack, dealloc_kernel_stack};
+pub use self::tlb::{ActiveCpus, TlbFlush, TlbGather};
 
 static KERNEL_ASPACE: LazyInit<SpinNoIrq<AddrSpace>> = LazyInit::new();
 
@@ -88,8 +103,12 @@ pub fn kernel_page_table_root() -> PhysAddr {
 pub fn init_memory_management() {
     info!("Initialize virtual memory management...");
 
-    let kernel_aspace = new_kernel_aspace().expect("failed to initialize kernel address space");
+    let mut kernel_aspace =
+        new_kernel_aspace().expect("failed to initialize kernel address space");
+    kstack::init(&mut kernel_aspace).expect("failed to initialize kernel stack region");
     debug!("kernel address space init OK: {:#x?}", kernel_aspace);
+    // The kernel address space stays loaded on all CPUs.
+    kernel_aspace.active_cpus().mark_loaded();
     KERNEL_ASPACE.init_once(SpinNoIrq::new(kernel_aspace));
     unsafe { axhal::asm::write_kernel_page_table(kernel_page_table_root()) };
     // flush all TLB
@@ -98,6 +117,7 @@ pub fn init_memory_management() {
 
 /// Initializes kernel paging for secondary CPUs.
 pub fn init_memory_management_secondary() {
+    KERNEL_ASPACE.lock().active_cpus().mark_loaded();
     unsafe { axhal::asm::write_kernel_page_table(kernel_page_table_root()) };
     // flush all TLB
     axhal::asm::flush_tlb(None);
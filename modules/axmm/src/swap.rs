@@ -0,0 +1,448 @@
+//! Swapping of anonymous memory.
+//!
+//! When frames run out, cold pages of copy-on-write (anonymous or private
+//! file) mappings are written to a swap space, on a block device or in a
+//! file, and unmapped. A swap entry is left in the page table entry of each
+//! page, which is read back when it is faulted in.
+//!
+//! Cold pages are found by a clock scan over the resident pages of an address
+//! space. Without access to the accessed bits of page table entries, a page
+//! counts as referenced when it has been faulted in since the hand last
+//! passed it, which gives it a second chance. Pages shared with other address
+//! spaces, huge pages and pinned pages are never swapped out.
+//!
+//! An address space swaps its own pages out when a page fault runs out of
+//! memory. Allocations of user memory reclaim pages from the other address
+//! spaces. Only the address spaces [registered](register) are swapped.
+//!
+//! The app in `examples/swap` overcommits an address space against a swap
+//! space on a RAM disk, and checks that its pages are read back intact.
+
+use alloc::{
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::{
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use axalloc::FrameFlags;
+use axdriver::{AxBlockDevice, prelude::BlockDriverOps};
+use axerrno::{AxError, AxResult, ax_bail};
+use axfs_ng::FileBackend;
+use axhal::{
+    mem::phys_to_virt,
+    paging::{MappingFlags, PageSize, PageTableMut},
+};
+use axsync::Mutex;
+use kspin::SpinNoIrq;
+use memory_addr::{PAGE_SIZE_4K, PhysAddr, VirtAddr, VirtAddrRange};
+
+use crate::{
+    AddrSpace,
+    backend::{dealloc_frame, frame_desc},
+};
+
+/// A page of the swap space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SwapSlot(usize);
+
+impl SwapSlot {
+    /// Returns the index of the slot in the swap space.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+enum SwapTarget {
+    Device(AxBlockDevice),
+    File(FileBackend),
+}
+
+impl SwapTarget {
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> AxResult {
+        match self {
+            Self::Device(dev) => {
+                let block_id = offset / dev.block_size() as u64;
+                dev.read_block(block_id, buf).map_err(|err| {
+                    warn!("Failed to read swap device: {:?}", err);
+                    AxError::Io
+                })
+            }
+            Self::File(file) => {
+                let len = buf.len();
+                if file.read_at(&mut &mut buf[..], offset)? != len {
+                    ax_bail!(Io, "swap file too short");
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> AxResult {
+        match self {
+            Self::Device(dev) => {
+                let block_id = offset / dev.block_size() as u64;
+                dev.write_block(block_id, buf).map_err(|err| {
+                    warn!("Failed to write swap device: {:?}", err);
+                    AxError::Io
+                })
+            }
+            Self::File(file) => {
+                if file.write_at(&mut &buf[..], offset)? != buf.len() {
+                    ax_bail!(Io, "swap file too short");
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A swap space, where pages are written to when swapped out.
+pub struct SwapSpace {
+    target: SwapTarget,
+    /// The number of swap entries referring to each slot.
+    refs: Vec<u32>,
+    used: usize,
+    /// Where to look for a free slot first.
+    hint: usize,
+}
+
+impl SwapSpace {
+    fn new(target: SwapTarget, size: u64) -> Self {
+        let num_slots = (size / PAGE_SIZE_4K as u64) as usize;
+        Self {
+            target,
+            refs: vec![0; num_slots],
+            used: 0,
+            hint: 0,
+        }
+    }
+
+    /// Creates a swap space using a whole block device.
+    pub fn new_device(dev: AxBlockDevice) -> Self {
+        assert_eq!(PAGE_SIZE_4K % dev.block_size(), 0);
+        let size = dev.num_blocks() * dev.block_size() as u64;
+        Self::new(SwapTarget::Device(dev), size)
+    }
+
+    /// Creates a swap space using the first `size` bytes of a file.
+    ///
+    /// The file should bypass the page cache, which would need memory to
+    /// swap pages out.
+    pub fn new_file(file: FileBackend, size: u64) -> Self {
+        Self::new(SwapTarget::File(file), size)
+    }
+
+    /// Returns the number of slots of the swap space.
+    pub fn num_slots(&self) -> usize {
+        self.refs.len()
+    }
+
+    /// Returns the number of slots in use.
+    pub fn used_slots(&self) -> usize {
+        self.used
+    }
+
+    /// Allocates a slot, referred to by one swap entry.
+    pub fn alloc_slot(&mut self) -> Option<SwapSlot> {
+        let num_slots = self.num_slots();
+        let index = (self.hint..num_slots)
+            .chain(0..self.hint)
+            .find(|&i| self.refs[i] == 0)?;
+        self.refs[index] = 1;
+        self.used += 1;
+        self.hint = (index + 1) % num_slots;
+        Some(SwapSlot(index))
+    }
+
+    /// Adds a swap entry referring to `slot`.
+    pub fn dup_slot(&mut self, slot: SwapSlot) {
+        let refs = &mut self.refs[slot.0];
+        assert_ne!(*refs, 0, "duplicating a free swap slot");
+        *refs = refs
+            .checked_add(1)
+            .expect("swap slot reference count overflow");
+    }
+
+    /// Removes a swap entry referring to `slot`, and frees the slot if it was
+    /// the last one.
+    pub fn free_slot(&mut self, slot: SwapSlot) {
+        let refs = &mut self.refs[slot.0];
+        assert_ne!(*refs, 0, "freeing a free swap slot");
+        *refs -= 1;
+        if *refs == 0 {
+            self.used -= 1;
+        }
+    }
+
+    /// Reads the page stored in `slot` into `buf`.
+    pub fn read(&mut self, slot: SwapSlot, buf: &mut [u8]) -> AxResult {
+        assert_eq!(buf.len(), PAGE_SIZE_4K);
+        self.target.read((slot.0 * PAGE_SIZE_4K) as u64, buf)
+    }
+
+    /// Writes the page in `buf` to `slot`.
+    pub fn write(&mut self, slot: SwapSlot, buf: &[u8]) -> AxResult {
+        assert_eq!(buf.len(), PAGE_SIZE_4K);
+        self.target.write((slot.0 * PAGE_SIZE_4K) as u64, buf)
+    }
+}
+
+static SWAP_SPACE: Mutex<Option<SwapSpace>> = Mutex::new(None);
+
+static PAGES_OUT: AtomicUsize = AtomicUsize::new(0);
+static PAGES_IN: AtomicUsize = AtomicUsize::new(0);
+
+/// Starts swapping to `space`.
+///
+/// Returns [`AxError::ResourceBusy`] if a swap space is in use already.
+pub fn swap_on(space: SwapSpace) -> AxResult {
+    let mut swap = SWAP_SPACE.lock();
+    if swap.is_some() {
+        ax_bail!(ResourceBusy, "swap space in use");
+    }
+    info!("Swap on: {} pages", space.num_slots());
+    *swap = Some(space);
+    Ok(())
+}
+
+/// Counters of swapping.
+#[derive(Debug, Clone, Copy)]
+pub struct SwapStats {
+    /// The number of slots of the swap space.
+    pub total_slots: usize,
+    /// The number of slots in use.
+    pub used_slots: usize,
+    /// The number of pages swapped out.
+    pub pages_out: usize,
+    /// The number of pages swapped in.
+    pub pages_in: usize,
+}
+
+/// Returns the counters of swapping.
+pub fn stats() -> SwapStats {
+    let swap = SWAP_SPACE.lock();
+    SwapStats {
+        total_slots: swap.as_ref().map_or(0, SwapSpace::num_slots),
+        used_slots: swap.as_ref().map_or(0, SwapSpace::used_slots),
+        pages_out: PAGES_OUT.load(Ordering::Relaxed),
+        pages_in: PAGES_IN.load(Ordering::Relaxed),
+    }
+}
+
+fn alloc_slot() -> Option<SwapSlot> {
+    SWAP_SPACE.lock().as_mut()?.alloc_slot()
+}
+
+/// Removes a swap entry referring to `slot`.
+pub(crate) fn free_slot(slot: SwapSlot) {
+    if let Some(swap) = SWAP_SPACE.lock().as_mut() {
+        swap.free_slot(slot);
+    }
+}
+
+fn frame_buf<'a>(frame: PhysAddr) -> &'a mut [u8] {
+    unsafe { slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) }
+}
+
+/// Reads the page stored in `slot` into `frame`.
+pub(crate) fn swap_in(slot: SwapSlot, frame: PhysAddr) -> AxResult {
+    let mut swap = SWAP_SPACE.lock();
+    let swap = swap.as_mut().ok_or(AxError::BadState)?;
+    swap.read(slot, frame_buf(frame))?;
+    PAGES_IN.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Marks the page table entries holding swap entries.
+///
+/// A swap entry is a non-present page table entry, whose physical address is
+/// this bit, above any memory, with the index of the slot as page number.
+const SWAP_ENTRY_MARK: usize = 1 << 47;
+
+/// Returns the slot of the swap entry queried from a page table as `paddr`
+/// and `flags`, if it is one.
+pub(crate) fn slot_of(paddr: PhysAddr, flags: MappingFlags) -> Option<SwapSlot> {
+    let paddr = paddr.as_usize();
+    (flags.is_empty() && paddr & SWAP_ENTRY_MARK != 0)
+        .then(|| SwapSlot((paddr & !SWAP_ENTRY_MARK) / PAGE_SIZE_4K))
+}
+
+/// Returns the slot of the swap entry at `vaddr`, if there is one.
+pub(crate) fn entry_at(pt: &PageTableMut, vaddr: VirtAddr) -> Option<SwapSlot> {
+    let (paddr, flags, _) = pt.query(vaddr).ok()?;
+    slot_of(paddr, flags)
+}
+
+/// Puts a swap entry referring to `slot` at `vaddr`, where no page is mapped.
+pub(crate) fn set_entry(pt: &mut PageTableMut, vaddr: VirtAddr, slot: SwapSlot) -> AxResult {
+    let paddr = PhysAddr::from(SWAP_ENTRY_MARK | (slot.0 * PAGE_SIZE_4K));
+    pt.map(vaddr, paddr, PageSize::Size4K, MappingFlags::empty())?;
+    Ok(())
+}
+
+/// Removes the swap entry at `vaddr`, if there is one, and returns its slot.
+///
+/// The slot is not freed.
+pub(crate) fn take_entry(pt: &mut PageTableMut, vaddr: VirtAddr) -> Option<SwapSlot> {
+    let slot = entry_at(pt, vaddr)?;
+    // Unmapping clears non-present entries too.
+    let _ = pt.unmap(vaddr);
+    Some(slot)
+}
+
+/// Adds a swap entry referring to `slot`, for a copy of a mapping.
+pub(crate) fn dup_slot(slot: SwapSlot) {
+    SWAP_SPACE
+        .lock()
+        .as_mut()
+        .expect("swap entries without swap space")
+        .dup_slot(slot);
+}
+
+/// A page unmapped to be swapped out.
+pub(crate) struct SwapVictim {
+    vaddr: VirtAddr,
+    frame: PhysAddr,
+    flags: MappingFlags,
+    slot: SwapSlot,
+}
+
+impl SwapVictim {
+    pub(crate) fn vaddr(&self) -> VirtAddr {
+        self.vaddr
+    }
+}
+
+/// Scans the pages of `range` for cold ones, and replaces them with swap
+/// entries to swap them out until `victims` holds `max` pages.
+///
+/// Returns the address where the scan stopped, if it did before the end of
+/// the range.
+pub(crate) fn scan_cold(
+    range: VirtAddrRange,
+    pt: &mut PageTableMut,
+    victims: &mut Vec<SwapVictim>,
+    max: usize,
+) -> Option<VirtAddr> {
+    let mut vaddr = range.start;
+    while vaddr < range.end {
+        if victims.len() >= max {
+            return Some(vaddr);
+        }
+        let Ok((frame, flags, page_size)) = pt.query(vaddr) else {
+            vaddr += PAGE_SIZE_4K;
+            continue;
+        };
+        if page_size != PageSize::Size4K {
+            vaddr += page_size as usize;
+            continue;
+        }
+        if slot_of(frame, flags).is_some() {
+            vaddr += PAGE_SIZE_4K;
+            continue;
+        }
+        let desc = frame_desc(frame);
+        if desc.ref_count() == 1 && !desc.flags().contains(FrameFlags::PINNED) {
+            if desc.flags().contains(FrameFlags::REFERENCED) {
+                // Second chance.
+                desc.set_flags(FrameFlags::REFERENCED, false);
+            } else {
+                let Some(slot) = alloc_slot() else {
+                    // The swap space is full.
+                    return Some(vaddr);
+                };
+                if pt.unmap(vaddr).is_ok() && set_entry(pt, vaddr, slot).is_ok() {
+                    victims.push(SwapVictim {
+                        vaddr,
+                        frame,
+                        flags,
+                        slot,
+                    });
+                } else {
+                    free_slot(slot);
+                    if let Err(err) = pt.map(vaddr, frame, PageSize::Size4K, flags) {
+                        warn!("Failed to map back page {:?}: {:?}", vaddr, err);
+                    }
+                }
+            }
+        }
+        vaddr += PAGE_SIZE_4K;
+    }
+    None
+}
+
+/// Writes the pages unmapped by [`scan_cold`] to the swap space and frees
+/// them, once they are flushed from the TLBs. The pages that fail to be
+/// written are mapped back.
+///
+/// Returns the number of pages freed.
+pub(crate) fn write_out(victims: Vec<SwapVictim>, pt: &mut PageTableMut) -> usize {
+    let mut freed = 0;
+    for victim in victims {
+        let result = match SWAP_SPACE.lock().as_mut() {
+            Some(swap) => swap.write(victim.slot, frame_buf(victim.frame)),
+            None => Err(AxError::BadState),
+        };
+        match result {
+            Ok(()) => {
+                dealloc_frame(victim.frame, PageSize::Size4K);
+                PAGES_OUT.fetch_add(1, Ordering::Relaxed);
+                freed += 1;
+            }
+            Err(err) => {
+                warn!("Failed to swap out page {:?}: {:?}", victim.vaddr, err);
+                take_entry(pt, victim.vaddr);
+                free_slot(victim.slot);
+                if let Err(err) = pt.map(victim.vaddr, victim.frame, PageSize::Size4K, victim.flags)
+                {
+                    warn!("Failed to map back page {:?}: {:?}", victim.vaddr, err);
+                }
+            }
+        }
+    }
+    freed
+}
+
+static ASPACES: SpinNoIrq<Vec<Weak<Mutex<AddrSpace>>>> = SpinNoIrq::new(Vec::new());
+
+/// Enables swapping for an address space, and registers it to reclaim pages
+/// from when memory runs out.
+///
+/// The copies made by [`AddrSpace::try_clone`] are registered automatically.
+pub fn register(aspace: &Arc<Mutex<AddrSpace>>) {
+    aspace.lock().enable_swap();
+    ASPACES.lock().push(Arc::downgrade(aspace));
+}
+
+/// Swaps out up to `max` pages of the registered address spaces which are
+/// not locked, and returns the number of pages freed.
+pub(crate) fn reclaim(max: usize) -> usize {
+    // Swapping may sleep.
+    if !axhal::asm::irqs_enabled() {
+        return 0;
+    }
+    let aspaces = {
+        let mut aspaces = ASPACES.lock();
+        aspaces.retain(|it| it.strong_count() > 0);
+        aspaces.clone()
+    };
+    let mut freed = 0;
+    for aspace in aspaces {
+        if freed >= max {
+            break;
+        }
+        let Some(aspace) = aspace.upgrade() else {
+            continue;
+        };
+        // The address space of the caller may be locked, as well as others
+        // which may wait for it.
+        if let Some(mut aspace) = aspace.try_lock() {
+            freed += aspace.swap_out(max - freed);
+        }
+    }
+    freed
+}
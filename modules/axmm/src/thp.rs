@@ -0,0 +1,66 @@
+//! Transparent huge pages.
+//!
+//! Anonymous mappings of 4K pages are backed by 2M pages where they cover a
+//! whole aligned huge page, when such a page is faulted in or populated and
+//! contiguous frames are available, and by 4K pages otherwise. A huge page is
+//! split into 4K pages when only a part of it is unmapped or protected, or
+//! copied on write without a free huge page to copy it to.
+//!
+//! They are disabled by default, see [`set_enabled`].
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static HUGE_PAGES: AtomicUsize = AtomicUsize::new(0);
+static FALLBACKS: AtomicUsize = AtomicUsize::new(0);
+static SPLITS: AtomicUsize = AtomicUsize::new(0);
+
+/// Enables or disables transparent huge pages.
+///
+/// It only affects the pages mapped afterwards. It is disabled by default.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether transparent huge pages are enabled.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Counters of the huge pages of user memory.
+#[derive(Debug, Clone, Copy)]
+pub struct ThpStats {
+    /// The number of huge pages in use.
+    pub huge_pages: usize,
+    /// The number of times 4K pages were mapped instead of a huge page, or a
+    /// huge page was split, because no huge page could be mapped.
+    pub fallbacks: usize,
+    /// The number of huge pages split into 4K pages.
+    pub splits: usize,
+}
+
+/// Returns the counters of the huge pages of user memory.
+pub fn stats() -> ThpStats {
+    ThpStats {
+        huge_pages: HUGE_PAGES.load(Ordering::Relaxed),
+        fallbacks: FALLBACKS.load(Ordering::Relaxed),
+        splits: SPLITS.load(Ordering::Relaxed),
+    }
+}
+
+pub(crate) fn huge_page_allocated() {
+    HUGE_PAGES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn huge_page_freed() {
+    HUGE_PAGES.fetch_sub(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_fallback() {
+    FALLBACKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_split() {
+    SPLITS.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,219 @@
+//! TLB shootdown.
+//!
+//! Each [`AddrSpace`](crate::AddrSpace) records the CPUs it is loaded on. When
+//! its mappings are removed or restricted, the TLB entries of the affected
+//! range are invalidated on all of those CPUs, through IPIs for the other
+//! ones, and the caller waits for every CPU to be done. The frames unmapped
+//! meanwhile are only freed after that, so that no CPU can still access them
+//! through a stale entry once they are reused.
+//!
+//! The kernel address space is locked with IRQs disabled, so CPUs waiting for
+//! it cannot serve IPIs: its mappings are removed with a [`TlbFlush`], which
+//! does the shootdown once the lock is released.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+#[cfg(feature = "ipi")]
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axconfig::plat::CPU_NUM;
+use axhal::{paging::PageSize, percpu::this_cpu_id};
+use kspin::SpinNoIrq;
+use memory_addr::{PAGE_SIZE_4K, PageIter4K, PhysAddr, VirtAddrRange};
+
+use crate::backend::{SharedPages, dealloc_frame};
+
+/// Ranges of more pages than this are invalidated by flushing the whole TLB.
+const FLUSH_ALL_THRESHOLD: usize = 32;
+
+/// The set of CPUs an address space is loaded on.
+///
+/// It is shared through an [`Arc`] so that the context switch code can update
+/// it without locking the address space. User address spaces are kept up to
+/// date by [`AddrSpace::activate`](crate::AddrSpace::activate).
+pub struct ActiveCpus([AtomicBool; CPU_NUM]);
+
+impl ActiveCpus {
+    pub(crate) const fn new() -> Self {
+        Self([const { AtomicBool::new(false) }; CPU_NUM])
+    }
+
+    /// Records that the address space is loaded on the current CPU.
+    ///
+    /// It must be called, with preemption disabled, right before the page
+    /// table of the address space is loaded.
+    pub fn mark_loaded(&self) {
+        self.0[this_cpu_id()].store(true, Ordering::SeqCst);
+    }
+
+    /// Records that the address space is not loaded on the current CPU anymore.
+    ///
+    /// It must be called right after another page table is loaded, which is
+    /// expected to flush the TLB entries of the address space.
+    pub fn mark_unloaded(&self) {
+        self.0[this_cpu_id()].store(false, Ordering::SeqCst);
+    }
+
+    /// Returns whether the address space is loaded on the CPU `cpu_id`.
+    pub fn contains(&self, cpu_id: usize) -> bool {
+        self.0[cpu_id].load(Ordering::SeqCst)
+    }
+}
+
+/// Invalidates the TLB entries of `range` on the current CPU.
+fn flush_local(range: VirtAddrRange) {
+    if range.size() > FLUSH_ALL_THRESHOLD * PAGE_SIZE_4K {
+        axhal::asm::flush_tlb(None);
+    } else if let Some(pages) = PageIter4K::new(range.start, range.end) {
+        for vaddr in pages {
+            axhal::asm::flush_tlb(Some(vaddr));
+        }
+    }
+}
+
+/// Invalidates the TLB entries of `range` on the current CPU and all CPUs in
+/// `cpus`, and waits for them to be done.
+///
+/// The current CPU is always flushed, as the address space may be loaded on
+/// it without being recorded. Without the `ipi` feature, only the current CPU
+/// is flushed.
+#[cfg_attr(not(feature = "ipi"), allow(unused_variables))]
+pub(crate) fn shootdown(cpus: &ActiveCpus, range: VirtAddrRange) {
+    if range.is_empty() {
+        return;
+    }
+    // Stay on this CPU, which is flushed directly.
+    let _guard = kernel_guard::NoPreempt::new();
+    flush_local(range);
+
+    #[cfg(feature = "ipi")]
+    {
+        let this_cpu = this_cpu_id();
+        let pending = Arc::new(AtomicUsize::new(0));
+        for cpu_id in (0..CPU_NUM).filter(|&it| it != this_cpu && cpus.contains(it)) {
+            pending.fetch_add(1, Ordering::AcqRel);
+            let pending = pending.clone();
+            axipi::run_on_cpu(cpu_id, move || {
+                flush_local(range);
+                pending.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+        while pending.load(Ordering::Acquire) != 0 {
+            // Another CPU may be waiting for us the same way with IRQs
+            // disabled, so serve its requests meanwhile.
+            axipi::ipi_handler();
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// The CPUs of the user address spaces loaded by
+/// [`AddrSpace::activate`](crate::AddrSpace::activate), by CPU.
+static LOADED: [SpinNoIrq<Option<Arc<ActiveCpus>>>; CPU_NUM] =
+    [const { SpinNoIrq::new(None) }; CPU_NUM];
+
+/// Records that `cpus` are now the CPUs of the user address space loaded on
+/// the current CPU, right after its page table is loaded, and removes the
+/// current CPU from those of the previous one.
+pub(crate) fn switch_loaded(cpus: &Arc<ActiveCpus>) {
+    let prev = LOADED[this_cpu_id()].lock().replace(cpus.clone());
+    if let Some(prev) = prev.filter(|it| !Arc::ptr_eq(it, cpus)) {
+        prev.mark_unloaded();
+    }
+}
+
+/// TLB shootdowns to do later, once the address spaces they are for are
+/// unlocked.
+///
+/// Other CPUs may spin on an address space lock with IRQs disabled, so no
+/// shootdown, which waits for them, can be done while holding it.
+#[must_use = "the TLBs are only flushed by `TlbFlush::flush`"]
+pub struct TlbFlush {
+    pending: Vec<(Arc<ActiveCpus>, VirtAddrRange, Vec<Deferred>)>,
+}
+
+impl TlbFlush {
+    /// Creates an empty set of TLB shootdowns.
+    pub const fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+        }
+    }
+
+    /// Adds the shootdown of `range` on `cpus`, taking the frames gathered
+    /// for the page table `root` so far, to be freed after it.
+    ///
+    /// It must be called with the address space still locked.
+    pub(crate) fn push(&mut self, cpus: &Arc<ActiveCpus>, root: PhysAddr, range: VirtAddrRange) {
+        let deferred = DEFERRED.lock().remove(&root).unwrap_or_default();
+        self.pending.push((cpus.clone(), range, deferred));
+    }
+
+    /// Invalidates the TLB entries of the ranges on all CPUs, then frees the
+    /// frames unmapped from them.
+    pub fn flush(self) {
+        for (cpus, range, deferred) in self.pending {
+            shootdown(&cpus, range);
+            for it in deferred {
+                match it {
+                    Deferred::Frame(frame, size) => dealloc_frame(frame, size),
+                    Deferred::Shared(pages) => drop(pages),
+                }
+            }
+        }
+    }
+}
+
+impl Default for TlbFlush {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Something to free once no CPU can access it through the TLB anymore.
+enum Deferred {
+    Frame(PhysAddr, PageSize),
+    Shared(Arc<SharedPages>),
+}
+
+/// Frames unmapped from page tables and not flushed from the TLBs yet, by the
+/// root of their page table.
+static DEFERRED: SpinNoIrq<BTreeMap<PhysAddr, Vec<Deferred>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Collects the frames unmapped from a page table, to be freed by a
+/// [`TlbFlush`] after the TLB shootdown.
+pub struct TlbGather {
+    root: PhysAddr,
+    deferred: Vec<Deferred>,
+}
+
+impl TlbGather {
+    pub(crate) fn new(root: PhysAddr) -> Self {
+        Self {
+            root,
+            deferred: Vec::new(),
+        }
+    }
+
+    /// Frees `frame` once the TLBs are flushed.
+    pub fn free_frame(&mut self, frame: PhysAddr, size: PageSize) {
+        self.deferred.push(Deferred::Frame(frame, size));
+    }
+
+    /// Keeps `pages` alive until the TLBs are flushed.
+    pub fn keep_shared(&mut self, pages: Arc<SharedPages>) {
+        self.deferred.push(Deferred::Shared(pages));
+    }
+}
+
+impl Drop for TlbGather {
+    fn drop(&mut self) {
+        if !self.deferred.is_empty() {
+            DEFERRED
+                .lock()
+                .entry(self.root)
+                .or_default()
+                .append(&mut self.deferred);
+        }
+    }
+}
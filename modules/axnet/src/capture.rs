@@ -0,0 +1,415 @@
+//! Packet capture on network interfaces.
+//!
+//! When capturing is started, the frames sent and received by the devices are
+//! recorded into a ring buffer, which can be exported in [pcap] or [pcapng]
+//! format to a file or over a stream socket.
+//!
+//! This module is only available with the `pcap` feature. Without it, the
+//! hooks in the devices compile to nothing.
+//!
+//! [pcap]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcap-04.html
+//! [pcapng]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html
+
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use axerrno::AxResult;
+use axfs_ng::FS_CONTEXT;
+use axhal::time::wall_time;
+use axio::Buf;
+use smoltcp::wire::{
+    EthernetFrame, EthernetProtocol, IpProtocol, IpVersion, Ipv4Packet, Ipv6Packet,
+};
+use spin::Mutex;
+
+pub use crate::device::{Direction, LinkType};
+use crate::{
+    SendOptions, SocketOps,
+    consts::{CAPTURE_BUFFER_SIZE, CAPTURE_SNAPLEN},
+};
+
+/// A protocol to capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Arp,
+    /// ICMP or ICMPv6.
+    Icmp,
+    Tcp,
+    Udp,
+    /// Another IP protocol, by number.
+    Ip(u8),
+}
+
+/// Selects the frames to capture.
+#[derive(Debug, Clone)]
+pub struct CaptureFilter {
+    /// Names of the interfaces to capture on, all of them if empty.
+    pub interfaces: Vec<String>,
+    /// Protocols to capture, all of them if empty.
+    pub protocols: Vec<Protocol>,
+    /// Maximum number of bytes recorded per frame.
+    pub snaplen: usize,
+}
+
+impl Default for CaptureFilter {
+    fn default() -> Self {
+        Self {
+            interfaces: Vec::new(),
+            protocols: Vec::new(),
+            snaplen: CAPTURE_SNAPLEN,
+        }
+    }
+}
+
+/// File format of exported captures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// Classic pcap. Frames of IP-only devices such as `lo` are given a dummy
+    /// Ethernet header, as a pcap file has a single link type.
+    Pcap,
+    /// pcapng, which records the interface and direction of each frame.
+    PcapNg,
+}
+
+/// Counters of the current capture.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CaptureStats {
+    /// Number of frames in the buffer.
+    pub packets: usize,
+    /// Number of bytes in the buffer.
+    pub bytes: usize,
+    /// Number of frames evicted from the buffer because it was full.
+    pub dropped: u64,
+}
+
+struct Record {
+    iface: usize,
+    direction: Direction,
+    timestamp: Duration,
+    orig_len: usize,
+    data: Vec<u8>,
+}
+
+struct Capture {
+    filter: CaptureFilter,
+    /// Interfaces seen so far, indexed by [`Record::iface`].
+    ifaces: Vec<(String, LinkType)>,
+    records: VecDeque<Record>,
+    bytes: usize,
+    dropped: u64,
+}
+
+impl Capture {
+    fn new(filter: CaptureFilter) -> Self {
+        Self {
+            filter,
+            ifaces: Vec::new(),
+            records: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    fn matches(&self, iface: &str, link: LinkType, frame: &[u8]) -> bool {
+        if !self.filter.interfaces.is_empty()
+            && !self.filter.interfaces.iter().any(|it| it == iface)
+        {
+            return false;
+        }
+        if self.filter.protocols.is_empty() {
+            return true;
+        }
+        classify(link, frame).is_some_and(|proto| self.filter.protocols.contains(&proto))
+    }
+
+    fn iface_index(&mut self, iface: &str, link: LinkType) -> usize {
+        match self.ifaces.iter().position(|(name, _)| name == iface) {
+            Some(index) => index,
+            None => {
+                self.ifaces.push((iface.into(), link));
+                self.ifaces.len() - 1
+            }
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        self.bytes += record.data.len();
+        self.records.push_back(record);
+        while self.bytes > CAPTURE_BUFFER_SIZE {
+            let Some(oldest) = self.records.pop_front() else {
+                break;
+            };
+            self.bytes -= oldest.data.len();
+            self.dropped += 1;
+        }
+    }
+
+    fn export(&self, format: CaptureFormat) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.bytes + self.records.len() * 32 + 64);
+        match format {
+            CaptureFormat::Pcap => self.export_pcap(&mut out),
+            CaptureFormat::PcapNg => self.export_pcapng(&mut out),
+        }
+        out
+    }
+
+    fn export_pcap(&self, out: &mut Vec<u8>) {
+        // Global header, with nanosecond timestamps.
+        put_u32(out, 0xa1b2_3c4d);
+        put_u16(out, 2);
+        put_u16(out, 4);
+        put_u32(out, 0);
+        put_u32(out, 0);
+        put_u32(out, self.filter.snaplen as u32 + ETHERNET_HEADER_LEN as u32);
+        put_u32(out, LINKTYPE_ETHERNET as u32);
+
+        for record in &self.records {
+            let header = match self.ifaces[record.iface].1 {
+                LinkType::Ethernet => None,
+                LinkType::Ip => Some(dummy_ethernet_header(&record.data)),
+            };
+            let header_len = header.map_or(0, |it| it.len());
+            put_u32(out, record.timestamp.as_secs() as u32);
+            put_u32(out, record.timestamp.subsec_nanos());
+            put_u32(out, (header_len + record.data.len()) as u32);
+            put_u32(out, (header_len + record.orig_len) as u32);
+            if let Some(header) = header {
+                out.extend_from_slice(&header);
+            }
+            out.extend_from_slice(&record.data);
+        }
+    }
+
+    fn export_pcapng(&self, out: &mut Vec<u8>) {
+        // Section header block.
+        put_u32(out, 0x0a0d_0d0a);
+        put_u32(out, 28);
+        put_u32(out, 0x1a2b_3c4d);
+        put_u16(out, 1);
+        put_u16(out, 0);
+        out.extend_from_slice(&(-1i64).to_le_bytes());
+        put_u32(out, 28);
+
+        // Interface description blocks.
+        for (name, link) in &self.ifaces {
+            let start = begin_block(out, 1);
+            put_u16(out, link_type_number(*link));
+            put_u16(out, 0);
+            put_u32(out, self.filter.snaplen as u32);
+            // if_name
+            put_option(out, 2, name.as_bytes());
+            // if_tsresol: nanoseconds
+            put_option(out, 9, &[9]);
+            put_option(out, 0, &[]);
+            end_block(out, start);
+        }
+
+        // Enhanced packet blocks.
+        for record in &self.records {
+            let start = begin_block(out, 6);
+            let nanos = record.timestamp.as_nanos() as u64;
+            put_u32(out, record.iface as u32);
+            put_u32(out, (nanos >> 32) as u32);
+            put_u32(out, nanos as u32);
+            put_u32(out, record.data.len() as u32);
+            put_u32(out, record.orig_len as u32);
+            out.extend_from_slice(&record.data);
+            pad(out);
+            // epb_flags: inbound or outbound
+            let flags: u32 = match record.direction {
+                Direction::Rx => 1,
+                Direction::Tx => 2,
+            };
+            put_option(out, 2, &flags.to_le_bytes());
+            put_option(out, 0, &[]);
+            end_block(out, start);
+        }
+    }
+}
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+
+fn link_type_number(link: LinkType) -> u16 {
+    match link {
+        LinkType::Ethernet => LINKTYPE_ETHERNET,
+        LinkType::Ip => LINKTYPE_RAW,
+    }
+}
+
+fn classify(link: LinkType, frame: &[u8]) -> Option<Protocol> {
+    let packet = match link {
+        LinkType::Ethernet => {
+            let frame = EthernetFrame::new_checked(frame).ok()?;
+            match frame.ethertype() {
+                EthernetProtocol::Arp => return Some(Protocol::Arp),
+                EthernetProtocol::Ipv4 | EthernetProtocol::Ipv6 => {}
+                _ => return None,
+            }
+            &frame.into_inner()[ETHERNET_HEADER_LEN..]
+        }
+        LinkType::Ip => frame,
+    };
+    let protocol = match IpVersion::of_packet(packet).ok()? {
+        IpVersion::Ipv4 => Ipv4Packet::new_checked(packet).ok()?.next_header(),
+        IpVersion::Ipv6 => Ipv6Packet::new_checked(packet).ok()?.next_header(),
+    };
+    Some(match protocol {
+        IpProtocol::Icmp | IpProtocol::Icmpv6 => Protocol::Icmp,
+        IpProtocol::Tcp => Protocol::Tcp,
+        IpProtocol::Udp => Protocol::Udp,
+        other => Protocol::Ip(other.into()),
+    })
+}
+
+fn dummy_ethernet_header(packet: &[u8]) -> [u8; ETHERNET_HEADER_LEN] {
+    let ethertype = match IpVersion::of_packet(packet) {
+        Ok(IpVersion::Ipv6) => EthernetProtocol::Ipv6,
+        _ => EthernetProtocol::Ipv4,
+    };
+    let mut header = [0; ETHERNET_HEADER_LEN];
+    header[12..].copy_from_slice(&u16::from(ethertype).to_be_bytes());
+    header
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn pad(out: &mut Vec<u8>) {
+    out.resize(out.len().next_multiple_of(4), 0);
+}
+
+fn put_option(out: &mut Vec<u8>, code: u16, value: &[u8]) {
+    put_u16(out, code);
+    put_u16(out, value.len() as u16);
+    out.extend_from_slice(value);
+    pad(out);
+}
+
+/// Writes the header of a pcapng block, returning its start offset.
+fn begin_block(out: &mut Vec<u8>, block_type: u32) -> usize {
+    let start = out.len();
+    put_u32(out, block_type);
+    // Total length, patched by `end_block`.
+    put_u32(out, 0);
+    start
+}
+
+fn end_block(out: &mut Vec<u8>, start: usize) {
+    let len = (out.len() + 4 - start) as u32;
+    out[start + 4..start + 8].copy_from_slice(&len.to_le_bytes());
+    put_u32(out, len);
+}
+
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+
+/// Records a frame passing through the device named `iface`.
+///
+/// Called by the devices for every frame they send or receive.
+pub(crate) fn record(iface: &str, link: LinkType, direction: Direction, frame: &[u8]) {
+    if !CAPTURING.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut guard = CAPTURE.lock();
+    let Some(capture) = guard.as_mut() else {
+        return;
+    };
+    if !capture.matches(iface, link, frame) {
+        return;
+    }
+    let iface = capture.iface_index(iface, link);
+    let len = frame.len().min(capture.filter.snaplen);
+    capture.push(Record {
+        iface,
+        direction,
+        timestamp: wall_time(),
+        orig_len: frame.len(),
+        data: frame[..len].to_vec(),
+    });
+}
+
+/// Starts capturing the frames matching `filter`, discarding any previous
+/// capture.
+pub fn start(filter: CaptureFilter) {
+    *CAPTURE.lock() = Some(Capture::new(filter));
+    CAPTURING.store(true, Ordering::Relaxed);
+}
+
+/// Stops capturing. The captured frames are kept until the next [`start`]
+/// or [`clear`].
+pub fn stop() {
+    CAPTURING.store(false, Ordering::Relaxed);
+}
+
+/// Returns whether a capture is running.
+pub fn is_capturing() -> bool {
+    CAPTURING.load(Ordering::Relaxed)
+}
+
+/// Discards the captured frames.
+pub fn clear() {
+    if let Some(capture) = CAPTURE.lock().as_mut() {
+        capture.records.clear();
+        capture.bytes = 0;
+        capture.dropped = 0;
+    }
+}
+
+/// Returns the counters of the current capture.
+pub fn stats() -> CaptureStats {
+    CAPTURE
+        .lock()
+        .as_ref()
+        .map_or(CaptureStats::default(), |capture| CaptureStats {
+            packets: capture.records.len(),
+            bytes: capture.bytes,
+            dropped: capture.dropped,
+        })
+}
+
+/// Exports the captured frames in the given format.
+pub fn export(format: CaptureFormat) -> Vec<u8> {
+    // Export an empty capture rather than nothing, so that the result is
+    // always a valid file.
+    let empty;
+    let guard = CAPTURE.lock();
+    let capture = match guard.as_ref() {
+        Some(capture) => capture,
+        None => {
+            empty = Capture::new(CaptureFilter::default());
+            &empty
+        }
+    };
+    capture.export(format)
+}
+
+/// Exports the captured frames to the file at `path`, replacing its
+/// contents.
+pub fn save(path: &str, format: CaptureFormat) -> AxResult {
+    let data = export(format);
+    FS_CONTEXT.lock().write(path, data)?;
+    Ok(())
+}
+
+/// Exports the captured frames over a connected stream socket, such as a
+/// TCP or vsock stream.
+///
+/// Returns the number of bytes sent.
+pub fn send(socket: &impl SocketOps, format: CaptureFormat) -> AxResult<usize> {
+    let data = export(format);
+    let mut src = &data[..];
+    while src.remaining() > 0 {
+        socket.send(&mut src, SendOptions::default())?;
+    }
+    Ok(data.len())
+}
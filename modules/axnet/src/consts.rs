@@ -1,3 +1,5 @@
+use core::time::Duration;
+
 macro_rules! env_or_default {
     ($key:literal) => {
         match option_env!($key) {
@@ -10,6 +12,32 @@ macro_rules! env_or_default {
 pub const IP: &str = env_or_default!("AX_IP");
 pub const GATEWAY: &str = env_or_default!("AX_GW");
 pub const IP_PREFIX: u8 = 24;
+pub const IP6: &str = env_or_default!("AX_IP6");
+pub const GATEWAY6: &str = env_or_default!("AX_GW6");
+pub const IP6_PREFIX: u8 = 64;
+/// Comma-separated list of the default DNS name servers.
+pub const DNS_SERVERS: &str = env_or_default!("AX_DNS");
+
+pub const DNS_PORT: u16 = 53;
+/// How long to wait for a reply from a single name server.
+pub const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DNS_MAX_CNAME_DEPTH: usize = 8;
+pub const DNS_CACHE_SIZE: usize = 64;
+
+/// How long to wait for a DHCP lease before falling back to [`IP`].
+#[cfg(feature = "dhcp")]
+pub const DHCP_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(feature = "dhcp")]
+pub const DHCP_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(feature = "dhcp")]
+pub const DHCP_REQUEST_RETRIES: u32 = 3;
+#[cfg(feature = "dhcp")]
+pub const DHCP_MIN_RENEW_INTERVAL: Duration = Duration::from_secs(60);
+#[cfg(feature = "dhcp")]
+pub const DHCP_DEFAULT_LEASE: Duration = Duration::from_secs(120);
+
+/// Maximum number of devices, limited by the width of socket device masks.
+pub const MAX_DEVICES: usize = 32;
 
 pub const STANDARD_MTU: usize = 1500;
 
@@ -17,7 +45,19 @@ pub const TCP_RX_BUF_LEN: usize = 64 * 1024;
 pub const TCP_TX_BUF_LEN: usize = 64 * 1024;
 pub const UDP_RX_BUF_LEN: usize = 64 * 1024;
 pub const UDP_TX_BUF_LEN: usize = 64 * 1024;
+pub const ICMP_RX_BUF_LEN: usize = 16 * 1024;
+pub const ICMP_TX_BUF_LEN: usize = 16 * 1024;
+pub const RAW_RX_BUF_LEN: usize = 64 * 1024;
+pub const RAW_TX_BUF_LEN: usize = 64 * 1024;
 pub const LISTEN_QUEUE_SIZE: usize = 512;
+/// Maximum number of TCP connections tracked for statistics.
+pub const TCP_CONN_TABLE_SIZE: usize = 1024;
+
+/// Maximum number of captured bytes kept, older frames being dropped first.
+#[cfg(feature = "pcap")]
+pub const CAPTURE_BUFFER_SIZE: usize = 1024 * 1024;
+#[cfg(feature = "pcap")]
+pub const CAPTURE_SNAPLEN: usize = 65535;
 
 pub const SOCKET_BUFFER_SIZE: usize = 64;
 pub const ETHERNET_MAX_PENDING_PACKETS: usize = 32;
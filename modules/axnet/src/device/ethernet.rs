@@ -1,22 +1,26 @@
 use core::task::Waker;
 
-use alloc::{string::String, vec};
+use alloc::{collections::VecDeque, string::String, vec, vec::Vec};
 
 use axdriver::prelude::*;
 use axhal::irq::register_irq_waker;
 use hashbrown::HashMap;
 use smoltcp::{
+    phy::ChecksumCapabilities,
     storage::{PacketBuffer, PacketMetadata},
     time::{Duration, Instant},
     wire::{
         ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
-        EthernetRepr, IpAddress, Ipv4Cidr,
+        EthernetRepr, HardwareAddress, IPV6_LINK_LOCAL_ALL_NODES, IPV6_LINK_LOCAL_ALL_ROUTERS,
+        Icmpv6Packet, Icmpv6Repr, IpAddress, IpCidr, IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr,
+        Ipv6Address, Ipv6Cidr, Ipv6Packet, Ipv6Repr, NdiscNeighborFlags, NdiscPrefixInfoFlags,
+        NdiscRepr, RawHardwareAddress,
     },
 };
 
 use crate::{
     consts::{ETHERNET_MAX_PENDING_PACKETS, STANDARD_MTU},
-    device::Device,
+    device::{Autoconf, Device, Direction, LinkStats, LinkType, NeighborEntry, capture},
 };
 
 const EMPTY_MAC: EthernetAddress = EthernetAddress([0; 6]);
@@ -26,19 +30,73 @@ struct Neighbor {
     expires_at: Instant,
 }
 
+/// Returns the EUI-64 based link-local address for `mac` (RFC 4291).
+pub fn link_local_address(mac: EthernetAddress) -> Ipv6Address {
+    Ipv6Address::from(interface_address([0xfe, 0x80, 0, 0, 0, 0, 0, 0], mac))
+}
+
+fn interface_address(prefix: [u8; 8], mac: EthernetAddress) -> [u8; 16] {
+    let mac = mac.0;
+    let mut octets = [0u8; 16];
+    octets[..8].copy_from_slice(&prefix);
+    octets[8..].copy_from_slice(&[
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]);
+    octets
+}
+
+fn solicited_node(addr: Ipv6Address) -> Ipv6Address {
+    let o = addr.octets();
+    Ipv6Address::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | o[13] as u16,
+        u16::from_be_bytes([o[14], o[15]]),
+    )
+}
+
+fn multicast_mac(addr: Ipv6Address) -> EthernetAddress {
+    let o = addr.octets();
+    EthernetAddress([0x33, 0x33, o[12], o[13], o[14], o[15]])
+}
+
+fn ethertype_of(packet: &[u8]) -> EthernetProtocol {
+    match IpVersion::of_packet(packet) {
+        Ok(IpVersion::Ipv6) => EthernetProtocol::Ipv6,
+        _ => EthernetProtocol::Ipv4,
+    }
+}
+
 pub struct EthernetDevice {
     #[allow(dead_code)]
     name: String,
     inner: AxNetDevice,
     neighbors: HashMap<IpAddress, Option<Neighbor>>,
-    ip: Ipv4Cidr,
+    /// IPv4 addresses of this device, the primary one first.
+    ipv4: Vec<Ipv4Cidr>,
+    /// IPv6 addresses of this device, the link-local one first.
+    ipv6: Vec<Ipv6Cidr>,
+    /// Configuration learnt from router advertisements, not yet installed.
+    autoconf: VecDeque<Autoconf>,
 
     pending_packets: PacketBuffer<'static, IpAddress>,
+    stats: LinkStats,
 }
 impl EthernetDevice {
     const NEIGHBOR_TTL: Duration = Duration::from_secs(60);
 
-    pub fn new(name: String, inner: AxNetDevice, ip: Ipv4Cidr) -> Self {
+    pub fn new(name: String, inner: AxNetDevice) -> Self {
         let pending_packets = PacketBuffer::new(
             vec![PacketMetadata::EMPTY; ETHERNET_MAX_PENDING_PACKETS],
             vec![
@@ -51,9 +109,12 @@ impl EthernetDevice {
             name,
             inner,
             neighbors: HashMap::new(),
-            ip,
+            ipv4: Vec::new(),
+            ipv6: Vec::new(),
+            autoconf: VecDeque::new(),
 
             pending_packets,
+            stats: LinkStats::default(),
         }
     }
 
@@ -63,7 +124,9 @@ impl EthernetDevice {
     }
 
     fn send_to<F>(
+        name: &str,
         inner: &mut AxNetDevice,
+        stats: &mut LinkStats,
         dst: EthernetAddress,
         size: usize,
         f: F,
@@ -73,6 +136,7 @@ impl EthernetDevice {
     {
         if let Err(err) = inner.recycle_tx_buffers() {
             warn!("recycle_tx_buffers failed: {:?}", err);
+            stats.tx_errors += 1;
             return;
         }
 
@@ -86,6 +150,7 @@ impl EthernetDevice {
             Ok(buf) => buf,
             Err(err) => {
                 warn!("alloc_tx_buffer failed: {:?}", err);
+                stats.tx_errors += 1;
                 return;
             }
         };
@@ -97,8 +162,14 @@ impl EthernetDevice {
             tx_buf.packet_len(),
             tx_buf.packet()
         );
+        let len = tx_buf.packet_len();
+        capture(name, LinkType::Ethernet, Direction::Tx, tx_buf.packet());
         if let Err(err) = inner.transmit(tx_buf) {
             warn!("transmit failed: {:?}", err);
+            stats.tx_errors += 1;
+        } else {
+            stats.tx_packets += 1;
+            stats.tx_bytes += len as u64;
         }
     }
 
@@ -111,10 +182,11 @@ impl EthernetDevice {
         let frame = EthernetFrame::new_unchecked(frame);
         let Ok(repr) = EthernetRepr::parse(&frame) else {
             warn!("Dropping malformed Ethernet frame");
+            self.stats.rx_errors += 1;
             return false;
         };
 
-        if !repr.dst_addr.is_broadcast()
+        if !repr.dst_addr.is_multicast()
             && repr.dst_addr != EMPTY_MAC
             && repr.dst_addr != self.hardware_address()
         {
@@ -129,16 +201,41 @@ impl EthernetDevice {
                     .copy_from_slice(frame.payload());
                 return true;
             }
+            EthernetProtocol::Ipv6 => {
+                if self.process_ndisc(frame.payload(), timestamp) {
+                    return false;
+                }
+                buffer
+                    .enqueue(frame.payload().len(), ())
+                    .unwrap()
+                    .copy_from_slice(frame.payload());
+                return true;
+            }
             EthernetProtocol::Arp => self.process_arp(frame.payload(), timestamp),
-            _ => {}
+            _ => self.stats.rx_dropped += 1,
         }
 
         false
     }
 
+    fn request_neighbor(&mut self, target_ip: IpAddress) {
+        match target_ip {
+            IpAddress::Ipv4(_) => self.request_arp(target_ip),
+            IpAddress::Ipv6(target_ipv6) => self.request_ndisc(target_ipv6),
+        }
+    }
+
+    /// Picks the source address for ARP requests about `target`.
+    fn ipv4_source_for(&self, target: &Ipv4Address) -> Ipv4Address {
+        self.ipv4
+            .iter()
+            .find(|cidr| cidr.contains_addr(target))
+            .or(self.ipv4.first())
+            .map_or(Ipv4Address::UNSPECIFIED, |cidr| cidr.address())
+    }
+
     fn request_arp(&mut self, target_ip: IpAddress) {
         let IpAddress::Ipv4(target_ipv4) = target_ip else {
-            warn!("IPv6 address ARP is not supported: {}", target_ip);
             return;
         };
         debug!("Requesting ARP for {}", target_ipv4);
@@ -146,13 +243,15 @@ impl EthernetDevice {
         let arp_repr = ArpRepr::EthernetIpv4 {
             operation: ArpOperation::Request,
             source_hardware_addr: self.hardware_address(),
-            source_protocol_addr: self.ip.address(),
+            source_protocol_addr: self.ipv4_source_for(&target_ipv4),
             target_hardware_addr: EthernetAddress::BROADCAST,
             target_protocol_addr: target_ipv4,
         };
 
         Self::send_to(
+            &self.name,
             &mut self.inner,
+            &mut self.stats,
             EthernetAddress::BROADCAST,
             arp_repr.buffer_len(),
             |buf| arp_repr.emit(&mut ArpPacket::new_unchecked(buf)),
@@ -195,7 +294,11 @@ impl EthernetDevice {
             {
                 return;
             }
-            if self.ip.address() != target_protocol_addr {
+            if !self
+                .ipv4
+                .iter()
+                .any(|ip| ip.address() == target_protocol_addr)
+            {
                 return;
             }
 
@@ -212,13 +315,15 @@ impl EthernetDevice {
                 let response = ArpRepr::EthernetIpv4 {
                     operation: ArpOperation::Reply,
                     source_hardware_addr: self.hardware_address(),
-                    source_protocol_addr: self.ip.address(),
+                    source_protocol_addr: target_protocol_addr,
                     target_hardware_addr: source_hardware_addr,
                     target_protocol_addr: source_protocol_addr,
                 };
 
                 Self::send_to(
+                    &self.name,
                     &mut self.inner,
+                    &mut self.stats,
                     source_hardware_addr,
                     response.buffer_len(),
                     |buf| response.emit(&mut ArpPacket::new_unchecked(buf)),
@@ -226,34 +331,256 @@ impl EthernetDevice {
                 );
             }
 
-            if self
-                .pending_packets
-                .peek()
-                .is_ok_and(|it| it.0 == &IpAddress::Ipv4(source_protocol_addr))
-            {
-                while let Ok((&next_hop, buf)) = self.pending_packets.peek() {
-                    // TODO: optimize logic such that one long-pending ARP
-                    // request does not block all other packets
-
-                    let Some(Some(neighbor)) = self.neighbors.get(&next_hop) else {
-                        break;
-                    };
-                    if neighbor.expires_at <= now {
-                        // Neighbor is expired, we need to request ARP again
-                        self.request_arp(next_hop);
-                        break;
-                    }
+            self.flush_pending(IpAddress::Ipv4(source_protocol_addr), now);
+        }
+    }
 
-                    Self::send_to(
-                        &mut self.inner,
+    /// Sends the pending packets that were waiting for `resolved`.
+    fn flush_pending(&mut self, resolved: IpAddress, now: Instant) {
+        if self
+            .pending_packets
+            .peek()
+            .is_ok_and(|it| it.0 == &resolved)
+        {
+            while let Ok((&next_hop, buf)) = self.pending_packets.peek() {
+                // TODO: optimize logic such that one long-pending ARP
+                // request does not block all other packets
+
+                let Some(Some(neighbor)) = self.neighbors.get(&next_hop) else {
+                    break;
+                };
+                if neighbor.expires_at <= now {
+                    // Neighbor is expired, we need to request it again
+                    self.request_neighbor(next_hop);
+                    break;
+                }
+
+                Self::send_to(
+                    &self.name,
+                    &mut self.inner,
+                    &mut self.stats,
+                    neighbor.hardware_address,
+                    buf.len(),
+                    |b| b.copy_from_slice(buf),
+                    ethertype_of(buf),
+                );
+                let _ = self.pending_packets.dequeue();
+            }
+        }
+    }
+
+    /// Picks the source address for NDP messages about `target`.
+    fn ipv6_source_for(&self, target: &Ipv6Address) -> Option<Ipv6Address> {
+        self.ipv6
+            .iter()
+            .find(|cidr| cidr.contains_addr(target))
+            .or(self.ipv6.first())
+            .map(|cidr| cidr.address())
+    }
+
+    fn has_ipv6_addr(&self, addr: &Ipv6Address) -> bool {
+        self.ipv6.iter().any(|cidr| cidr.address() == *addr)
+    }
+
+    fn send_ndisc(
+        &mut self,
+        src_addr: Ipv6Address,
+        dst_addr: Ipv6Address,
+        dst_mac: EthernetAddress,
+        repr: NdiscRepr,
+    ) {
+        let icmp_repr = Icmpv6Repr::Ndisc(repr);
+        let ip_repr = Ipv6Repr {
+            src_addr,
+            dst_addr,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: icmp_repr.buffer_len(),
+            hop_limit: 255,
+        };
+        let header_len = ip_repr.buffer_len();
+
+        Self::send_to(
+            &self.name,
+            &mut self.inner,
+            &mut self.stats,
+            dst_mac,
+            header_len + icmp_repr.buffer_len(),
+            |buf| {
+                ip_repr.emit(&mut Ipv6Packet::new_unchecked(&mut *buf));
+                icmp_repr.emit(
+                    &src_addr,
+                    &dst_addr,
+                    &mut Icmpv6Packet::new_unchecked(&mut buf[header_len..]),
+                    &ChecksumCapabilities::default(),
+                );
+            },
+            EthernetProtocol::Ipv6,
+        );
+    }
+
+    fn request_ndisc(&mut self, target: Ipv6Address) {
+        let Some(src_addr) = self.ipv6_source_for(&target) else {
+            warn!("No IPv6 address to solicit {} from", target);
+            return;
+        };
+        debug!("Requesting NDP for {}", target);
+
+        let dst_addr = solicited_node(target);
+        self.send_ndisc(
+            src_addr,
+            dst_addr,
+            multicast_mac(dst_addr),
+            NdiscRepr::NeighborSolicit {
+                target_addr: target,
+                lladdr: Some(RawHardwareAddress::from(self.hardware_address())),
+            },
+        );
+
+        self.neighbors.insert(IpAddress::Ipv6(target), None);
+    }
+
+    fn solicit_routers(&mut self) {
+        let Some(src_addr) = self.ipv6.first().map(|cidr| cidr.address()) else {
+            return;
+        };
+        self.send_ndisc(
+            src_addr,
+            IPV6_LINK_LOCAL_ALL_ROUTERS,
+            multicast_mac(IPV6_LINK_LOCAL_ALL_ROUTERS),
+            NdiscRepr::RouterSolicit {
+                lladdr: Some(RawHardwareAddress::from(self.hardware_address())),
+            },
+        );
+    }
+
+    fn learn_neighbor(&mut self, ip: Ipv6Address, lladdr: RawHardwareAddress, now: Instant) {
+        let Ok(HardwareAddress::Ethernet(mac)) = lladdr.parse(smoltcp::phy::Medium::Ethernet)
+        else {
+            return;
+        };
+        if !mac.is_unicast() {
+            return;
+        }
+        debug!("NDP: {} -> {}", ip, mac);
+        self.neighbors.insert(
+            IpAddress::Ipv6(ip),
+            Some(Neighbor {
+                hardware_address: mac,
+                expires_at: now + Self::NEIGHBOR_TTL,
+            }),
+        );
+        self.flush_pending(IpAddress::Ipv6(ip), now);
+    }
+
+    /// Handles Neighbor Discovery messages, returning `true` if the packet
+    /// was consumed.
+    ///
+    /// Messages that are not for this device are left to the interface.
+    fn process_ndisc(&mut self, payload: &[u8], now: Instant) -> bool {
+        let Ok(packet) = Ipv6Packet::new_checked(payload) else {
+            return false;
+        };
+        if packet.next_header() != IpProtocol::Icmpv6 {
+            return false;
+        }
+        let (src_addr, dst_addr) = (packet.src_addr(), packet.dst_addr());
+        let Ok(icmp_packet) = Icmpv6Packet::new_checked(packet.payload()) else {
+            return false;
+        };
+        let Ok(Icmpv6Repr::Ndisc(repr)) = Icmpv6Repr::parse(
+            &src_addr,
+            &dst_addr,
+            &icmp_packet,
+            &ChecksumCapabilities::default(),
+        ) else {
+            return false;
+        };
+        // NDP messages must not have been forwarded (RFC 4861 section 7.1).
+        if packet.hop_limit() != 255 {
+            return true;
+        }
+
+        match repr {
+            NdiscRepr::NeighborSolicit {
+                target_addr,
+                lladdr,
+            } => {
+                if !self.has_ipv6_addr(&target_addr) {
+                    return false;
+                }
+                let solicited = !src_addr.is_unspecified();
+                if let Some(lladdr) = lladdr.filter(|_| solicited) {
+                    self.learn_neighbor(src_addr, lladdr, now);
+                }
+
+                let (dst_addr, dst_mac, flags) = match self.neighbors.get(&src_addr.into()) {
+                    Some(Some(neighbor)) if solicited => (
+                        src_addr,
                         neighbor.hardware_address,
-                        buf.len(),
-                        |b| b.copy_from_slice(buf),
-                        EthernetProtocol::Ipv4,
-                    );
-                    let _ = self.pending_packets.dequeue();
+                        NdiscNeighborFlags::SOLICITED | NdiscNeighborFlags::OVERRIDE,
+                    ),
+                    _ => (
+                        IPV6_LINK_LOCAL_ALL_NODES,
+                        multicast_mac(IPV6_LINK_LOCAL_ALL_NODES),
+                        NdiscNeighborFlags::OVERRIDE,
+                    ),
+                };
+                self.send_ndisc(
+                    target_addr,
+                    dst_addr,
+                    dst_mac,
+                    NdiscRepr::NeighborAdvert {
+                        flags,
+                        target_addr,
+                        lladdr: Some(RawHardwareAddress::from(self.hardware_address())),
+                    },
+                );
+                true
+            }
+            NdiscRepr::NeighborAdvert {
+                target_addr,
+                lladdr: Some(lladdr),
+                ..
+            } if self.neighbors.contains_key(&target_addr.into()) => {
+                self.learn_neighbor(target_addr, lladdr, now);
+                true
+            }
+            NdiscRepr::RouterAdvert {
+                router_lifetime,
+                lladdr,
+                prefix_info,
+                ..
+            } => {
+                if let Some(lladdr) = lladdr {
+                    self.learn_neighbor(src_addr, lladdr, now);
                 }
+                let ip = prefix_info
+                    .filter(|info| {
+                        info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF)
+                            && info.prefix_len == 64
+                            && info.valid_lifetime != Duration::ZERO
+                    })
+                    .map(|info| {
+                        let mut prefix = [0u8; 8];
+                        prefix.copy_from_slice(&info.prefix.octets()[..8]);
+                        Ipv6Cidr::new(
+                            Ipv6Address::from(interface_address(prefix, self.hardware_address())),
+                            64,
+                        )
+                    });
+                debug!("Router advertisement from {}, SLAAC: {:?}", src_addr, ip);
+                // Later advertisements supersede pending ones, so that router
+                // lifetimes are refreshed in order.
+                self.autoconf
+                    .retain(|it| it.router != src_addr || it.ip != ip);
+                self.autoconf.push_back(Autoconf {
+                    ip,
+                    router: src_addr,
+                    router_lifetime,
+                });
+                true
             }
+            _ => false,
         }
     }
 }
@@ -263,6 +590,14 @@ impl Device for EthernetDevice {
         &self.name
     }
 
+    fn mac_address(&self) -> Option<EthernetAddress> {
+        Some(self.hardware_address())
+    }
+
+    fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
     fn recv(&mut self, buffer: &mut PacketBuffer<()>, timestamp: Instant) -> bool {
         loop {
             let rx_buf = match self.inner.receive() {
@@ -270,10 +605,19 @@ impl Device for EthernetDevice {
                 Err(err) => {
                     if !matches!(err, DevError::Again) {
                         warn!("receive failed: {:?}", err);
+                        self.stats.rx_errors += 1;
                     }
                     return false;
                 }
             };
+            self.stats.rx_packets += 1;
+            self.stats.rx_bytes += rx_buf.packet_len() as u64;
+            capture(
+                &self.name,
+                LinkType::Ethernet,
+                Direction::Rx,
+                rx_buf.packet(),
+            );
             trace!(
                 "RECV {} bytes: {:02X?}",
                 rx_buf.packet_len(),
@@ -289,9 +633,32 @@ impl Device for EthernetDevice {
     }
 
     fn send(&mut self, next_hop: IpAddress, packet: &[u8], timestamp: Instant) -> bool {
-        if next_hop.is_broadcast() || self.ip.broadcast().map(IpAddress::Ipv4) == Some(next_hop) {
+        let multicast = match next_hop {
+            IpAddress::Ipv6(addr) if addr.is_multicast() => Some(addr),
+            _ => None,
+        };
+        if let Some(addr) = multicast {
             Self::send_to(
+                &self.name,
                 &mut self.inner,
+                &mut self.stats,
+                multicast_mac(addr),
+                packet.len(),
+                |buf| buf.copy_from_slice(packet),
+                EthernetProtocol::Ipv6,
+            );
+            return false;
+        }
+        if next_hop.is_broadcast()
+            || self
+                .ipv4
+                .iter()
+                .any(|ip| ip.broadcast().map(IpAddress::Ipv4) == Some(next_hop))
+        {
+            Self::send_to(
+                &self.name,
+                &mut self.inner,
+                &mut self.stats,
                 EthernetAddress::BROADCAST,
                 packet.len(),
                 |buf| buf.copy_from_slice(packet),
@@ -304,11 +671,13 @@ impl Device for EthernetDevice {
             Some(Some(neighbor)) => {
                 if neighbor.expires_at > timestamp {
                     Self::send_to(
+                        &self.name,
                         &mut self.inner,
+                        &mut self.stats,
                         neighbor.hardware_address,
                         packet.len(),
                         |buf| buf.copy_from_slice(packet),
-                        EthernetProtocol::Ipv4,
+                        ethertype_of(packet),
                     );
                     return false;
                 } else {
@@ -319,16 +688,18 @@ impl Device for EthernetDevice {
             Some(None) => false,
             None => true,
         };
-        // Only send ARP request if we haven't already requested it
+        // Only send ARP/NDP request if we haven't already requested it
         if need_request {
-            self.request_arp(next_hop);
+            self.request_neighbor(next_hop);
         }
         if self.pending_packets.is_full() {
             warn!("Pending packets buffer is full, dropping packet");
+            self.stats.tx_dropped += 1;
             return false;
         }
         let Ok(dst_buffer) = self.pending_packets.enqueue(packet.len(), next_hop) else {
             warn!("Failed to enqueue packet in pending packets buffer");
+            self.stats.tx_dropped += 1;
             return false;
         };
         dst_buffer.copy_from_slice(packet);
@@ -340,4 +711,86 @@ impl Device for EthernetDevice {
             register_irq_waker(irq, waker);
         }
     }
+
+    fn add_ip_addr(&mut self, ip: IpCidr) {
+        match ip {
+            IpCidr::Ipv4(ip) => {
+                if !self.ipv4.contains(&ip) {
+                    self.ipv4.push(ip);
+                }
+            }
+            IpCidr::Ipv6(ip) => {
+                if self.has_ipv6_addr(&ip.address()) {
+                    return;
+                }
+                if ip.address().is_unicast_link_local() {
+                    self.ipv6.insert(0, ip);
+                    // Ask routers on the link for prefixes to autoconfigure.
+                    self.solicit_routers();
+                } else {
+                    self.ipv6.push(ip);
+                }
+            }
+        }
+    }
+
+    fn remove_ip_addr(&mut self, ip: IpCidr) {
+        match ip {
+            IpCidr::Ipv4(ip) => {
+                self.ipv4.retain(|it| *it != ip);
+                // Cached neighbors may belong to the old subnet.
+                self.neighbors
+                    .retain(|addr, _| !matches!(addr, IpAddress::Ipv4(_)));
+            }
+            IpCidr::Ipv6(ip) => self.ipv6.retain(|it| *it != ip),
+        }
+    }
+
+    fn neighbors(&self) -> Vec<NeighborEntry> {
+        self.neighbors
+            .iter()
+            .map(|(ip, neighbor)| NeighborEntry {
+                ip: *ip,
+                mac: neighbor.as_ref().map(|it| it.hardware_address),
+                expires_at: neighbor.as_ref().map(|it| it.expires_at),
+            })
+            .collect()
+    }
+
+    fn poll_autoconf(&mut self) -> Option<Autoconf> {
+        self.autoconf.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: EthernetAddress = EthernetAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+
+    #[test]
+    fn eui64_addresses() {
+        assert_eq!(
+            link_local_address(MAC),
+            "fe80::5054:ff:fe12:3456".parse::<Ipv6Address>().unwrap()
+        );
+        let prefix = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 1];
+        assert_eq!(
+            Ipv6Address::from(interface_address(prefix, MAC)),
+            "2001:db8:0:1:5054:ff:fe12:3456"
+                .parse::<Ipv6Address>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn solicited_node_multicast() {
+        let addr = link_local_address(MAC);
+        let group = solicited_node(addr);
+        assert_eq!(group, "ff02::1:ff12:3456".parse::<Ipv6Address>().unwrap());
+        assert_eq!(
+            multicast_mac(group),
+            EthernetAddress([0x33, 0x33, 0xff, 0x12, 0x34, 0x56])
+        );
+    }
 }
@@ -10,12 +10,13 @@ use smoltcp::{
 
 use crate::{
     consts::{SOCKET_BUFFER_SIZE, STANDARD_MTU},
-    device::Device,
+    device::{Device, Direction, LinkStats, LinkType, capture},
 };
 
 pub struct LoopbackDevice {
     buffer: PacketBuffer<'static, ()>,
     poll: PollSet,
+    stats: LinkStats,
 }
 impl LoopbackDevice {
     pub fn new() -> Self {
@@ -26,6 +27,7 @@ impl LoopbackDevice {
         Self {
             buffer,
             poll: PollSet::new(),
+            stats: LinkStats::default(),
         }
     }
 }
@@ -41,6 +43,9 @@ impl Device for LoopbackDevice {
                 .enqueue(rx_buf.len(), ())
                 .unwrap()
                 .copy_from_slice(rx_buf);
+            self.stats.rx_packets += 1;
+            self.stats.rx_bytes += rx_buf.len() as u64;
+            capture("lo", LinkType::Ip, Direction::Rx, rx_buf);
             true
         })
     }
@@ -50,9 +55,13 @@ impl Device for LoopbackDevice {
             Ok(tx_buf) => {
                 tx_buf.copy_from_slice(packet);
                 self.poll.wake();
+                self.stats.tx_packets += 1;
+                self.stats.tx_bytes += packet.len() as u64;
+                capture("lo", LinkType::Ip, Direction::Tx, packet);
                 true
             }
             Err(_) => {
+                self.stats.tx_dropped += 1;
                 warn!(
                     "Loopback device buffer is full, dropping packet to {}",
                     next_hop
@@ -65,4 +74,8 @@ impl Device for LoopbackDevice {
     fn register_waker(&self, waker: &Waker) {
         self.poll.register(waker);
     }
+
+    fn stats(&self) -> LinkStats {
+        self.stats
+    }
 }
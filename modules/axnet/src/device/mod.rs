@@ -1,6 +1,11 @@
+use alloc::vec::Vec;
 use core::task::Waker;
 
-use smoltcp::{storage::PacketBuffer, time::Instant, wire::IpAddress};
+use smoltcp::{
+    storage::PacketBuffer,
+    time::{Duration, Instant},
+    wire::{EthernetAddress, IpAddress, IpCidr, Ipv6Address, Ipv6Cidr},
+};
 
 mod ethernet;
 mod loopback;
@@ -12,10 +17,73 @@ pub use loopback::*;
 #[cfg(feature = "vsock")]
 pub use vsock::*;
 
+/// Link-layer type of the frames of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    Ethernet,
+    /// Bare IP packets, without link-layer header.
+    Ip,
+}
+
+/// Direction of a frame relative to the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// Records a frame for packet capture, if enabled.
+#[cfg(feature = "pcap")]
+pub(crate) use crate::capture::record as capture;
+
+#[cfg(not(feature = "pcap"))]
+#[inline(always)]
+pub(crate) fn capture(_iface: &str, _link: LinkType, _direction: Direction, _frame: &[u8]) {}
+
+/// Configuration learnt from a router advertisement.
+pub struct Autoconf {
+    /// The address from stateless address autoconfiguration, if any.
+    pub ip: Option<Ipv6Cidr>,
+    /// The advertising router.
+    pub router: Ipv6Address,
+    /// How long the router may be used as a default router, zero if it is
+    /// not one.
+    pub router_lifetime: Duration,
+}
+
+/// Link-level traffic counters of a device.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LinkStats {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub rx_errors: u64,
+    /// Frames received but not delivered, e.g. of unknown protocol.
+    pub rx_dropped: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_errors: u64,
+    /// Packets not sent for lack of buffer space.
+    pub tx_dropped: u64,
+}
+
+/// An entry of the neighbor (ARP/NDP) cache of a device.
+pub struct NeighborEntry {
+    pub ip: IpAddress,
+    /// The resolved hardware address, or `None` while resolution is pending.
+    pub mac: Option<EthernetAddress>,
+    pub expires_at: Option<Instant>,
+}
+
 pub trait Device: Send + Sync {
-    #[allow(unused)]
     fn name(&self) -> &str;
 
+    /// Returns the hardware address, if the device has one.
+    fn mac_address(&self) -> Option<EthernetAddress> {
+        None
+    }
+
+    fn stats(&self) -> LinkStats;
+
     fn recv(&mut self, buffer: &mut PacketBuffer<()>, timestamp: Instant) -> bool;
     /// Sends a packet to the next hop.
     ///
@@ -25,4 +93,23 @@ pub trait Device: Send + Sync {
     fn send(&mut self, next_hop: IpAddress, packet: &[u8], timestamp: Instant) -> bool;
 
     fn register_waker(&self, waker: &Waker);
+
+    /// Assigns an address to this device.
+    ///
+    /// Devices that do not resolve link-layer addresses themselves can ignore
+    /// this.
+    fn add_ip_addr(&mut self, _ip: IpCidr) {}
+
+    /// Removes an address previously added with [`Device::add_ip_addr`].
+    fn remove_ip_addr(&mut self, _ip: IpCidr) {}
+
+    /// Returns the entries of the neighbor cache.
+    fn neighbors(&self) -> Vec<NeighborEntry> {
+        Vec::new()
+    }
+
+    /// Takes the configuration learnt from a router advertisement, if any.
+    fn poll_autoconf(&mut self) -> Option<Autoconf> {
+        None
+    }
 }
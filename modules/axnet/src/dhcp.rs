@@ -0,0 +1,529 @@
+//! DHCPv4 client used to configure Ethernet interfaces at boot.
+//!
+//! smoltcp's own DHCPv4 socket only works on an Ethernet-medium interface,
+//! while [`Service`](crate::service::Service) drives a single IP-medium
+//! interface shared by all devices. The client therefore speaks DHCP over a
+//! plain UDP socket registered in [`SOCKET_SET`], asking the server to
+//! broadcast its replies until an address has been leased.
+
+use alloc::{string::ToString, vec, vec::Vec};
+use core::{net::IpAddr, task::Context, time::Duration};
+
+use axerrno::{AxError, AxResult};
+use axhal::time::{TimeValue, monotonic_time, wall_time_nanos};
+use axpoll::{IoEvents, Pollable};
+use axtask::future::{Poller, block_on, sleep_until_monotonic};
+use smoltcp::{
+    iface::SocketHandle,
+    phy::PacketMeta,
+    socket::udp::{self as smol, UdpMetadata},
+    storage::PacketMetadata,
+    wire::{
+        DHCP_CLIENT_PORT, DHCP_SERVER_PORT, DhcpMessageType, DhcpPacket, DhcpRepr, EthernetAddress,
+        IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr,
+    },
+};
+
+use crate::{
+    SERVICE, SOCKET_SET,
+    consts::{
+        DHCP_DEFAULT_LEASE, DHCP_MIN_RENEW_INTERVAL, DHCP_REQUEST_RETRIES, DHCP_RETRY_INTERVAL,
+        DHCP_TIMEOUT, STANDARD_MTU,
+    },
+    dns,
+    general::GeneralOptions,
+    poll_interfaces,
+};
+
+/// Parameters requested from the server: subnet mask, router and DNS servers.
+const PARAMETER_REQUEST_LIST: &[u8] = &[1, 3, 6];
+
+/// A static IPv4 configuration, used when no DHCP server answers.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StaticConfig {
+    pub ip: Ipv4Cidr,
+    pub gateway: Option<Ipv4Address>,
+}
+
+#[derive(Debug, Clone)]
+struct Lease {
+    ip: Ipv4Cidr,
+    gateway: Option<Ipv4Address>,
+    server: Ipv4Address,
+    dns_servers: Vec<Ipv4Address>,
+    renew_at: TimeValue,
+    rebind_at: TimeValue,
+    expires_at: TimeValue,
+}
+
+impl Lease {
+    fn from_ack(repr: &DhcpRepr, server: Ipv4Address, now: TimeValue) -> Option<Self> {
+        let prefix_len = IpAddress::Ipv4(repr.subnet_mask?).prefix_len()?;
+        if !IpAddress::Ipv4(repr.your_ip).is_unicast() {
+            return None;
+        }
+
+        let lease = repr
+            .lease_duration
+            .map_or(DHCP_DEFAULT_LEASE, |secs| Duration::from_secs(secs as _));
+        // RFC 2131: T1 defaults to 0.5 and T2 to 0.875 times the lease.
+        let renew = repr
+            .renew_duration
+            .map_or(lease / 2, |secs| Duration::from_secs(secs as _));
+        let rebind = repr
+            .rebind_duration
+            .map_or(lease * 7 / 8, |secs| Duration::from_secs(secs as _))
+            .max(renew);
+
+        Some(Self {
+            ip: Ipv4Cidr::new(repr.your_ip, prefix_len),
+            gateway: repr.router,
+            server,
+            dns_servers: repr
+                .dns_servers
+                .iter()
+                .flatten()
+                .filter(|addr| IpAddress::Ipv4(**addr).is_unicast())
+                .copied()
+                .collect(),
+            renew_at: now + renew,
+            rebind_at: now + rebind,
+            expires_at: now + lease,
+        })
+    }
+
+    /// Returns when to try again after renewing, or rebinding if `rebinding`
+    /// is `true`, failed at `now`.
+    fn retry_at(&self, now: TimeValue, rebinding: bool) -> TimeValue {
+        // RFC 2131: wait one-half of the remaining time until T2 (or the
+        // lease expiry when rebinding), down to a minimum.
+        let until = if rebinding {
+            self.expires_at
+        } else {
+            self.rebind_at
+        };
+        let wait = (until.saturating_sub(now) / 2).max(DHCP_MIN_RENEW_INTERVAL);
+        (now + wait).min(until)
+    }
+}
+
+/// The parts of a server reply the client cares about.
+struct ServerReply {
+    message_type: DhcpMessageType,
+    your_ip: Ipv4Address,
+    server: Ipv4Address,
+    lease: Option<Lease>,
+}
+
+struct DhcpClient {
+    handle: SocketHandle,
+    dev: usize,
+    mac: EthernetAddress,
+    xid: u32,
+    general: GeneralOptions,
+    /// The address currently installed on the device.
+    current: Option<Ipv4Cidr>,
+}
+
+impl DhcpClient {
+    fn new(dev: usize, mac: EthernetAddress) -> AxResult<Self> {
+        let mut socket = smol::Socket::new(
+            smol::PacketBuffer::new(vec![PacketMetadata::EMPTY; 8], vec![0; STANDARD_MTU * 4]),
+            smol::PacketBuffer::new(vec![PacketMetadata::EMPTY; 8], vec![0; STANDARD_MTU * 4]),
+        );
+        socket
+            .bind(DHCP_CLIENT_PORT)
+            .map_err(|_| AxError::AddrInUse)?;
+        let handle = SOCKET_SET.add(socket);
+
+        let general = GeneralOptions::new();
+        general.set_device_mask(1 << dev);
+
+        let mac_bits = u32::from_be_bytes([mac.0[2], mac.0[3], mac.0[4], mac.0[5]]);
+        Ok(Self {
+            handle,
+            dev,
+            mac,
+            xid: mac_bits ^ wall_time_nanos() as u32,
+            general,
+            current: None,
+        })
+    }
+
+    fn with_smol_socket<R>(&self, f: impl FnOnce(&mut smol::Socket) -> R) -> R {
+        SOCKET_SET.with_socket_mut::<smol::Socket, _, _>(self.handle, f)
+    }
+
+    fn next_xid(&mut self) -> u32 {
+        // Cheap xorshift; transaction IDs only need to differ between rounds.
+        self.xid ^= self.xid << 13;
+        self.xid ^= self.xid >> 17;
+        self.xid ^= self.xid << 5;
+        self.xid
+    }
+
+    fn new_repr(&self, message_type: DhcpMessageType, xid: u32) -> DhcpRepr<'static> {
+        DhcpRepr {
+            message_type,
+            transaction_id: xid,
+            secs: 0,
+            client_hardware_address: self.mac,
+            client_ip: Ipv4Address::UNSPECIFIED,
+            your_ip: Ipv4Address::UNSPECIFIED,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: None,
+            subnet_mask: None,
+            relay_agent_ip: Ipv4Address::UNSPECIFIED,
+            // Our interface drops unicast packets to addresses it does not
+            // own yet, so ask for broadcast replies until we are configured.
+            broadcast: self.current.is_none(),
+            requested_ip: None,
+            client_identifier: Some(self.mac),
+            server_identifier: None,
+            parameter_request_list: Some(PARAMETER_REQUEST_LIST),
+            max_size: Some(STANDARD_MTU as u16),
+            lease_duration: None,
+            renew_duration: None,
+            rebind_duration: None,
+            dns_servers: None,
+            additional_options: &[],
+        }
+    }
+
+    fn send(&self, repr: &DhcpRepr, dst: Ipv4Address) -> AxResult {
+        let src = self
+            .current
+            .map_or(Ipv4Address::UNSPECIFIED, |ip| ip.address());
+        let meta = UdpMetadata {
+            endpoint: IpEndpoint::new(dst.into(), DHCP_SERVER_PORT),
+            local_address: Some(src.into()),
+            meta: PacketMeta::default(),
+        };
+        self.with_smol_socket(|socket| {
+            let buf = socket
+                .send(repr.buffer_len(), meta)
+                .map_err(|_| AxError::WouldBlock)?;
+            repr.emit(&mut DhcpPacket::new_unchecked(buf))
+                .map_err(|_| AxError::InvalidInput)
+        })?;
+        debug!("DHCP: send {:?} to {}", repr.message_type, dst);
+        poll_interfaces();
+        Ok(())
+    }
+
+    /// Receives the next reply matching `xid` and `expected`, or returns
+    /// [`AxError::WouldBlock`] if none is queued.
+    fn try_recv(&self, xid: u32, expected: DhcpMessageType) -> AxResult<ServerReply> {
+        self.with_smol_socket(|socket| {
+            while let Ok((data, meta)) = socket.recv() {
+                let Ok(packet) = DhcpPacket::new_checked(data) else {
+                    continue;
+                };
+                let Ok(repr) = DhcpRepr::parse(&packet) else {
+                    continue;
+                };
+                if repr.transaction_id != xid || repr.client_hardware_address != self.mac {
+                    continue;
+                }
+                if repr.message_type != expected && repr.message_type != DhcpMessageType::Nak {
+                    continue;
+                }
+                let IpAddress::Ipv4(src) = meta.endpoint.addr else {
+                    continue;
+                };
+
+                let server = repr.server_identifier.unwrap_or(src);
+                let lease = if repr.message_type == DhcpMessageType::Ack {
+                    let lease = Lease::from_ack(&repr, server, monotonic_time());
+                    if lease.is_none() {
+                        warn!("DHCP: ignoring malformed ACK from {}", server);
+                        continue;
+                    }
+                    lease
+                } else {
+                    None
+                };
+                return Ok(ServerReply {
+                    message_type: repr.message_type,
+                    your_ip: repr.your_ip,
+                    server,
+                    lease,
+                });
+            }
+            Err(AxError::WouldBlock)
+        })
+    }
+
+    fn recv(
+        &self,
+        xid: u32,
+        expected: DhcpMessageType,
+        timeout: Duration,
+    ) -> AxResult<ServerReply> {
+        Poller::new(self, IoEvents::IN)
+            .timeout(Some(timeout))
+            .poll(|| {
+                poll_interfaces();
+                self.try_recv(xid, expected)
+            })
+    }
+
+    /// Sends a DHCPREQUEST and waits for the server's verdict.
+    ///
+    /// Returns `None` if the server answered with a NAK.
+    fn request(&self, repr: DhcpRepr, dst: Ipv4Address) -> AxResult<Option<Lease>> {
+        for retry in 0..DHCP_REQUEST_RETRIES {
+            self.send(&repr, dst)?;
+            match self.recv(
+                repr.transaction_id,
+                DhcpMessageType::Ack,
+                DHCP_RETRY_INTERVAL * (1 << retry),
+            ) {
+                Ok(reply) => return Ok(reply.lease),
+                Err(AxError::TimedOut) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(AxError::TimedOut)
+    }
+
+    /// Runs the DISCOVER/OFFER/REQUEST/ACK exchange until `deadline`.
+    fn acquire(&mut self, deadline: TimeValue) -> AxResult<Lease> {
+        let mut retry = 0;
+        while monotonic_time() < deadline {
+            let xid = self.next_xid();
+            self.send(
+                &self.new_repr(DhcpMessageType::Discover, xid),
+                Ipv4Address::BROADCAST,
+            )?;
+
+            let timeout = (DHCP_RETRY_INTERVAL * (1 << retry.min(4)))
+                .min(deadline.saturating_sub(monotonic_time()));
+            retry += 1;
+            let offer = match self.recv(xid, DhcpMessageType::Offer, timeout) {
+                Ok(offer) if offer.message_type == DhcpMessageType::Offer => offer,
+                Ok(_) | Err(AxError::TimedOut) => continue,
+                Err(err) => return Err(err),
+            };
+            debug!("DHCP: offered {} by {}", offer.your_ip, offer.server);
+
+            let mut request = self.new_repr(DhcpMessageType::Request, xid);
+            request.requested_ip = Some(offer.your_ip);
+            request.server_identifier = Some(offer.server);
+            match self.request(request, Ipv4Address::BROADCAST) {
+                Ok(Some(lease)) => return Ok(lease),
+                Ok(None) | Err(AxError::TimedOut) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(AxError::TimedOut)
+    }
+
+    /// Keeps `lease` alive, returning once it is lost.
+    fn maintain(&mut self, mut lease: Lease) {
+        loop {
+            block_on(sleep_until_monotonic(lease.renew_at));
+
+            let now = monotonic_time();
+            if now >= lease.expires_at {
+                warn!("DHCP: lease for {} expired", lease.ip);
+                return;
+            }
+
+            // Renewing is unicast to the leasing server, rebinding is broadcast.
+            let rebinding = now >= lease.rebind_at;
+            let dst = if rebinding {
+                Ipv4Address::BROADCAST
+            } else {
+                lease.server
+            };
+            let xid = self.next_xid();
+            let mut request = self.new_repr(DhcpMessageType::Request, xid);
+            request.client_ip = lease.ip.address();
+
+            match self.request(request, dst) {
+                Ok(Some(new_lease)) => {
+                    debug!("DHCP: renewed {}", new_lease.ip);
+                    self.apply(Some(&new_lease));
+                    lease = new_lease;
+                }
+                Ok(None) => {
+                    warn!("DHCP: server refused to renew {}", lease.ip);
+                    return;
+                }
+                Err(err) => {
+                    debug!("DHCP: renew failed: {:?}", err);
+                    lease.renew_at = lease.retry_at(monotonic_time(), rebinding);
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, lease: Option<&Lease>) {
+        let ip = lease.map(|lease| lease.ip);
+        if let Some(lease) = lease {
+            info!("DHCP: leased {} via {:?}", lease.ip, lease.gateway);
+            if !lease.dns_servers.is_empty() {
+                info!("DHCP: DNS servers {:?}", lease.dns_servers);
+                dns::set_nameservers(lease.dns_servers.iter().copied().map(IpAddr::V4));
+            }
+        }
+        SERVICE.lock().set_ipv4_config(
+            self.dev,
+            self.current,
+            ip,
+            lease.and_then(|lease| lease.gateway),
+        );
+        self.current = ip;
+    }
+
+    fn run(mut self, fallback: Option<StaticConfig>) {
+        loop {
+            match self.acquire(monotonic_time() + DHCP_TIMEOUT) {
+                Ok(lease) => {
+                    self.apply(Some(&lease));
+                    self.maintain(lease);
+                    self.apply(None);
+                }
+                Err(err) => {
+                    warn!("DHCP: no lease obtained: {:?}", err);
+                    if let Some(config) = fallback {
+                        info!("DHCP: falling back to static address {}", config.ip);
+                        SERVICE.lock().set_ipv4_config(
+                            self.dev,
+                            self.current,
+                            Some(config.ip),
+                            config.gateway,
+                        );
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Pollable for DhcpClient {
+    fn poll(&self) -> IoEvents {
+        poll_interfaces();
+        let mut events = IoEvents::empty();
+        self.with_smol_socket(|socket| {
+            events.set(IoEvents::IN, socket.can_recv());
+            events.set(IoEvents::OUT, socket.can_send());
+        });
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.intersects(IoEvents::IN | IoEvents::OUT) {
+            self.general.register_waker(context.waker());
+        }
+    }
+}
+
+impl Drop for DhcpClient {
+    fn drop(&mut self) {
+        SOCKET_SET.remove(self.handle);
+    }
+}
+
+/// Starts a background DHCP client on the device at index `dev`.
+///
+/// If no server answers within [`DHCP_TIMEOUT`], `fallback` is installed
+/// instead and the client exits.
+pub(crate) fn start(dev: usize, mac: EthernetAddress, fallback: Option<StaticConfig>) {
+    let client = match DhcpClient::new(dev, mac) {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("DHCP: failed to create client: {:?}", err);
+            return;
+        }
+    };
+    axtask::spawn(move || client.run(fallback), "dhcp-client".to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
+
+    fn ack(lease: Option<u32>, renew: Option<u32>, rebind: Option<u32>) -> DhcpRepr<'static> {
+        DhcpRepr {
+            message_type: DhcpMessageType::Ack,
+            transaction_id: 1,
+            secs: 0,
+            client_hardware_address: EthernetAddress([2, 0, 0, 0, 0, 1]),
+            client_ip: Ipv4Address::UNSPECIFIED,
+            your_ip: Ipv4Address::new(10, 0, 2, 15),
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: Some(SERVER),
+            subnet_mask: Some(Ipv4Address::new(255, 255, 255, 0)),
+            relay_agent_ip: Ipv4Address::UNSPECIFIED,
+            broadcast: false,
+            requested_ip: None,
+            client_identifier: None,
+            server_identifier: Some(SERVER),
+            parameter_request_list: None,
+            max_size: None,
+            lease_duration: lease,
+            renew_duration: renew,
+            rebind_duration: rebind,
+            dns_servers: None,
+            additional_options: &[],
+        }
+    }
+
+    #[test]
+    fn lease_default_timers() {
+        let now = Duration::from_secs(100);
+        let lease = Lease::from_ack(&ack(Some(3600), None, None), SERVER, now).unwrap();
+        assert_eq!(lease.ip, Ipv4Cidr::new(Ipv4Address::new(10, 0, 2, 15), 24));
+        assert_eq!(lease.renew_at, now + Duration::from_secs(1800));
+        assert_eq!(lease.rebind_at, now + Duration::from_secs(3150));
+        assert_eq!(lease.expires_at, now + Duration::from_secs(3600));
+
+        // Without a lease time, the default one is used.
+        let lease = Lease::from_ack(&ack(None, None, None), SERVER, now).unwrap();
+        assert_eq!(lease.renew_at, now + DHCP_DEFAULT_LEASE / 2);
+        assert_eq!(lease.rebind_at, now + DHCP_DEFAULT_LEASE * 7 / 8);
+        assert_eq!(lease.expires_at, now + DHCP_DEFAULT_LEASE);
+
+        // T2 is never before T1.
+        let lease = Lease::from_ack(&ack(Some(3600), Some(3000), Some(2000)), SERVER, now).unwrap();
+        assert_eq!(lease.renew_at, now + Duration::from_secs(3000));
+        assert_eq!(lease.rebind_at, now + Duration::from_secs(3000));
+    }
+
+    #[test]
+    fn malformed_ack() {
+        let now = Duration::from_secs(100);
+        let mut repr = ack(Some(3600), None, None);
+        repr.subnet_mask = None;
+        assert!(Lease::from_ack(&repr, SERVER, now).is_none());
+
+        let mut repr = ack(Some(3600), None, None);
+        repr.your_ip = Ipv4Address::BROADCAST;
+        assert!(Lease::from_ack(&repr, SERVER, now).is_none());
+    }
+
+    #[test]
+    fn renew_backoff() {
+        let lease = Lease::from_ack(&ack(Some(3600), None, None), SERVER, Duration::ZERO).unwrap();
+
+        // Half of the time left until T2.
+        let now = Duration::from_secs(1800);
+        assert_eq!(lease.retry_at(now, false), Duration::from_secs(2475));
+        // Half of the time left until the lease expires when rebinding.
+        let now = Duration::from_secs(3150);
+        assert_eq!(lease.retry_at(now, true), Duration::from_secs(3375));
+
+        // Down to the minimum interval, but never past the deadline.
+        let now = Duration::from_secs(3100);
+        assert_eq!(lease.retry_at(now, false), Duration::from_secs(3150));
+        let now = Duration::from_secs(3500);
+        assert_eq!(lease.retry_at(now, true), now + DHCP_MIN_RENEW_INTERVAL);
+        let now = Duration::from_secs(3590);
+        assert_eq!(lease.retry_at(now, true), Duration::from_secs(3600));
+    }
+}
@@ -0,0 +1,368 @@
+//! A DNS stub resolver.
+//!
+//! Queries are sent over [`UdpSocket`]s to the configured name servers, one
+//! after another, until one of them answers. `A` and `AAAA` records are
+//! looked up for every name, and `CNAME` chains are followed either within a
+//! reply or by issuing further queries. Successful lookups are cached for as
+//! long as the shortest TTL of the records involved.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    future::poll_fn,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicU16, Ordering},
+    task::Poll,
+    time::Duration,
+};
+
+use axerrno::{AxError, AxResult, LinuxError, ax_bail, ax_err_type};
+use axhal::time::{TimeValue, wall_time, wall_time_nanos};
+use axpoll::{IoEvents, Pollable};
+use axsync::Mutex;
+use axtask::future::{block_on, interruptible, timeout};
+use hashbrown::HashMap;
+use smoltcp::wire::{
+    DnsFlags, DnsOpcode, DnsPacket, DnsQueryType, DnsQuestion, DnsRcode, DnsRecord, DnsRecordData,
+    DnsRepr,
+};
+
+use crate::{
+    RecvOptions, SendOptions, SocketAddrEx, SocketOps,
+    consts::{DNS_CACHE_SIZE, DNS_MAX_CNAME_DEPTH, DNS_PORT, DNS_SERVERS, DNS_TIMEOUT},
+    options::{Configurable, SetSocketOption},
+    udp::UdpSocket,
+};
+
+const MAX_NAME_LEN: usize = 255;
+const MAX_LABEL_LEN: usize = 63;
+const MAX_MESSAGE_LEN: usize = 512;
+
+static NAMESERVERS: Mutex<Vec<IpAddr>> = Mutex::new(Vec::new());
+
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires_at: TimeValue,
+}
+
+static CACHE: Mutex<Option<HashMap<String, CacheEntry>>> = Mutex::new(None);
+
+/// Sets the default name servers from the build configuration.
+pub(crate) fn init() {
+    let servers = DNS_SERVERS
+        .split(',')
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+        .filter_map(|it| match it.parse() {
+            Ok(addr) => Some(addr),
+            Err(_) => {
+                warn!("Invalid DNS server address: {}", it);
+                None
+            }
+        });
+    set_nameservers(servers);
+}
+
+/// Replaces the list of name servers, which are tried in order.
+pub fn set_nameservers(servers: impl IntoIterator<Item = IpAddr>) {
+    let mut guard = NAMESERVERS.lock();
+    guard.clear();
+    guard.extend(servers.into_iter().map(IpAddr::to_canonical));
+    debug!("DNS servers: {:?}", *guard);
+}
+
+/// Returns the current list of name servers.
+pub fn nameservers() -> Vec<IpAddr> {
+    NAMESERVERS.lock().clone()
+}
+
+/// Drops all cached lookup results.
+pub fn clear_cache() {
+    *CACHE.lock() = None;
+}
+
+fn cache_lookup(name: &str) -> Option<Vec<IpAddr>> {
+    let mut guard = CACHE.lock();
+    let cache = guard.as_mut()?;
+    let entry = cache.get(name)?;
+    if entry.expires_at <= wall_time() {
+        cache.remove(name);
+        return None;
+    }
+    Some(entry.addrs.clone())
+}
+
+fn cache_insert(name: String, addrs: Vec<IpAddr>, ttl: u32) {
+    if ttl == 0 {
+        return;
+    }
+    let now = wall_time();
+    let mut guard = CACHE.lock();
+    let cache = guard.get_or_insert_with(HashMap::new);
+    if cache.len() >= DNS_CACHE_SIZE && !cache.contains_key(&name) {
+        cache.retain(|_, entry| entry.expires_at > now);
+        if cache.len() >= DNS_CACHE_SIZE {
+            // Evict the entry that would expire first.
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+    }
+    cache.insert(
+        name,
+        CacheEntry {
+            addrs,
+            expires_at: now + Duration::from_secs(ttl as u64),
+        },
+    );
+}
+
+/// Lowercases `name` and strips its trailing dot, checking that it is a
+/// valid domain name.
+fn normalize(name: &str) -> AxResult<String> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || name
+            .split('.')
+            .any(|label| label.is_empty() || label.len() > MAX_LABEL_LEN)
+    {
+        ax_bail!(InvalidInput, "invalid domain name");
+    }
+    Ok(name.to_ascii_lowercase())
+}
+
+/// Encodes `name` into the label sequence used on the wire.
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(name.len() + 2);
+    for label in name.split('.') {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    buf
+}
+
+fn decode_name(packet: &DnsPacket<&[u8]>, bytes: &[u8]) -> Option<String> {
+    let mut name = String::new();
+    for label in packet.parse_name(bytes) {
+        let label = core::str::from_utf8(label.ok()?).ok()?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(label);
+    }
+    name.make_ascii_lowercase();
+    Some(name)
+}
+
+fn next_transaction_id() -> u16 {
+    static COUNTER: AtomicU16 = AtomicU16::new(0);
+    (wall_time_nanos() as u16) ^ COUNTER.fetch_add(0x9e37, Ordering::Relaxed)
+}
+
+enum RecordValue {
+    Addr(IpAddr),
+    Cname(String),
+}
+
+/// The useful part of a reply to a single query.
+#[derive(Default)]
+struct Answer {
+    addrs: Vec<IpAddr>,
+    /// The end of the alias chain, if it was not resolved in this reply.
+    cname: Option<String>,
+    ttl: u32,
+}
+
+/// Parses a reply, returning `None` if it does not belong to the query.
+fn parse_reply(buf: &[u8], id: u16, name: &str) -> Option<AxResult<Answer>> {
+    let packet = DnsPacket::new_checked(buf).ok()?;
+    if packet.transaction_id() != id || !packet.flags().contains(DnsFlags::RESPONSE) {
+        return None;
+    }
+    match packet.rcode() {
+        DnsRcode::NoError => {}
+        DnsRcode::NXDomain => return Some(Err(ax_err_type!(NotFound, "no such domain"))),
+        rcode => {
+            warn!("DNS server error for {}: {:?}", name, rcode);
+            return Some(Err(AxError::Other(LinuxError::EAGAIN)));
+        }
+    }
+
+    let mut payload = packet.payload();
+    for _ in 0..packet.question_count() {
+        payload = DnsQuestion::parse(payload).ok()?.0;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..packet.answer_record_count() {
+        let (rest, record) = DnsRecord::parse(payload).ok()?;
+        payload = rest;
+        let value = match record.data {
+            DnsRecordData::A(addr) => RecordValue::Addr(IpAddr::V4(addr)),
+            DnsRecordData::Aaaa(addr) => RecordValue::Addr(IpAddr::V6(addr)),
+            DnsRecordData::Cname(target) => RecordValue::Cname(decode_name(&packet, target)?),
+            DnsRecordData::Other(..) => continue,
+        };
+        records.push((decode_name(&packet, record.name)?, record.ttl, value));
+    }
+
+    let mut answer = Answer {
+        ttl: u32::MAX,
+        ..Default::default()
+    };
+    let mut target = name.to_string();
+    for _ in 0..DNS_MAX_CNAME_DEPTH {
+        let mut next = None;
+        for (owner, ttl, value) in &records {
+            if *owner != target {
+                continue;
+            }
+            answer.ttl = answer.ttl.min(*ttl);
+            match value {
+                RecordValue::Addr(addr) => answer.addrs.push(*addr),
+                RecordValue::Cname(alias) => next = Some(alias.clone()),
+            }
+        }
+        match next {
+            Some(alias) if answer.addrs.is_empty() => target = alias,
+            _ => break,
+        }
+    }
+    if answer.addrs.is_empty() && target != name {
+        answer.cname = Some(target);
+    }
+    Some(Ok(answer))
+}
+
+/// Sends a single query to `server` and waits for the matching reply.
+async fn query(server: IpAddr, name: &str, type_: DnsQueryType) -> AxResult<Answer> {
+    let id = next_transaction_id();
+    let encoded = encode_name(name);
+    let repr = DnsRepr {
+        transaction_id: id,
+        opcode: DnsOpcode::Query,
+        flags: DnsFlags::RECURSION_DESIRED,
+        question: DnsQuestion {
+            name: &encoded,
+            type_,
+        },
+    };
+    let mut request = alloc::vec![0u8; repr.buffer_len()];
+    repr.emit(&mut DnsPacket::new_unchecked(&mut request[..]));
+
+    let socket = UdpSocket::new();
+    socket.set_option(SetSocketOption::NonBlocking(&true))?;
+    socket.connect(SocketAddrEx::Ip(SocketAddr::new(server, DNS_PORT)))?;
+    socket.send(&mut &request[..], SendOptions::default())?;
+
+    let mut buf = [0u8; MAX_MESSAGE_LEN];
+    loop {
+        let len = poll_fn(|cx| {
+            match socket.recv(&mut &mut buf[..], RecvOptions::default()) {
+                Err(AxError::WouldBlock) => {}
+                result => return Poll::Ready(result),
+            }
+            socket.register(cx, IoEvents::IN);
+            match socket.recv(&mut &mut buf[..], RecvOptions::default()) {
+                Err(AxError::WouldBlock) => Poll::Pending,
+                result => Poll::Ready(result),
+            }
+        })
+        .await?;
+        match parse_reply(&buf[..len], id, name) {
+            Some(answer) => return answer,
+            None => debug!("DNS: ignoring unexpected reply from {}", server),
+        }
+    }
+}
+
+/// Queries the name servers in order until one of them answers.
+async fn query_any(name: &str, type_: DnsQueryType) -> AxResult<Answer> {
+    let servers = nameservers();
+    if servers.is_empty() {
+        ax_bail!(NotFound, "no DNS server configured");
+    }
+    let mut error = AxError::TimedOut;
+    for server in servers {
+        match timeout(Some(DNS_TIMEOUT), query(server, name, type_)).await {
+            Ok(Ok(answer)) => return Ok(answer),
+            // The domain does not exist, asking elsewhere will not help.
+            Ok(Err(AxError::NotFound)) => return Err(AxError::NotFound),
+            Ok(Err(err)) => error = err,
+            Err(elapsed) => error = elapsed.into(),
+        }
+        debug!("DNS server {} failed for {}: {:?}", server, name, error);
+    }
+    Err(error)
+}
+
+/// Looks up records of `type_`, following aliases across queries.
+async fn lookup(name: &str, type_: DnsQueryType) -> AxResult<(Vec<IpAddr>, u32)> {
+    let mut name = name.to_string();
+    let mut ttl = u32::MAX;
+    for _ in 0..DNS_MAX_CNAME_DEPTH {
+        let answer = query_any(&name, type_).await?;
+        ttl = ttl.min(answer.ttl);
+        match answer.cname {
+            Some(alias) => name = alias,
+            None => return Ok((answer.addrs, ttl)),
+        }
+    }
+    Err(ax_err_type!(NotFound, "CNAME chain too long"))
+}
+
+/// Resolves `name` to its IPv4 and IPv6 addresses.
+///
+/// IP address literals are returned as is, and `localhost` resolves to the
+/// loopback addresses without any query.
+pub async fn resolve(name: &str) -> AxResult<Vec<IpAddr>> {
+    if let Ok(addr) = name.parse::<IpAddr>() {
+        return Ok(alloc::vec![addr]);
+    }
+    let name = normalize(name)?;
+    if name == "localhost" {
+        return Ok(alloc::vec![
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ]);
+    }
+    if let Some(addrs) = cache_lookup(&name) {
+        return Ok(addrs);
+    }
+
+    let mut addrs = Vec::new();
+    let mut ttl = u32::MAX;
+    let mut error = AxError::NotFound;
+    for type_ in [DnsQueryType::A, DnsQueryType::Aaaa] {
+        match lookup(&name, type_).await {
+            Ok((found, found_ttl)) if !found.is_empty() => {
+                addrs.extend(found);
+                ttl = ttl.min(found_ttl);
+            }
+            Ok(_) => {}
+            Err(err) => error = err,
+        }
+    }
+    if addrs.is_empty() {
+        return Err(error);
+    }
+
+    debug!("DNS: {} -> {:?} (ttl {})", name, addrs, ttl);
+    cache_insert(name, addrs.clone(), ttl);
+    Ok(addrs)
+}
+
+/// Resolves `name` to its IP addresses, blocking until done.
+///
+/// See [`resolve`].
+pub fn dns_query(name: &str) -> AxResult<Vec<IpAddr>> {
+    block_on(interruptible(resolve(name)))?
+}
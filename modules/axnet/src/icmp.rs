@@ -0,0 +1,306 @@
+use alloc::vec;
+use core::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult, ax_bail, ax_err_type};
+use axio::{Buf, BufMut};
+use axpoll::{IoEvents, Pollable};
+use axsync::Mutex;
+use smoltcp::{
+    iface::SocketHandle,
+    socket::icmp::{self as smol, Endpoint},
+    wire::IpAddress,
+};
+use spin::RwLock;
+
+use crate::{
+    RecvFlags, RecvOptions, SOCKET_SET, SendOptions, Shutdown, SocketAddrEx, SocketOps,
+    consts::{ICMP_RX_BUF_LEN, ICMP_TX_BUF_LEN},
+    general::GeneralOptions,
+    options::{Configurable, GetSocketOption, SetSocketOption},
+    poll_interfaces,
+    socket::routable_addr,
+};
+
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+
+pub(crate) fn new_icmp_socket() -> smol::Socket<'static> {
+    smol::Socket::new(
+        smol::PacketBuffer::new(
+            vec![smol::PacketMetadata::EMPTY; 64],
+            vec![0; ICMP_RX_BUF_LEN],
+        ),
+        smol::PacketBuffer::new(
+            vec![smol::PacketMetadata::EMPTY; 64],
+            vec![0; ICMP_TX_BUF_LEN],
+        ),
+    )
+}
+
+/// An ICMP "ping" socket that provides POSIX-like APIs.
+///
+/// Like Linux's `SOCK_DGRAM` ICMP sockets, messages are sent and received
+/// including their ICMP header, and the identifier of outgoing echo requests
+/// is replaced with the one the socket is bound to, so that only the matching
+/// echo replies are received.
+pub struct IcmpSocket {
+    handle: SocketHandle,
+    local_addr: RwLock<Option<SocketAddr>>,
+    peer_addr: RwLock<Option<IpAddress>>,
+
+    general: GeneralOptions,
+}
+
+impl IcmpSocket {
+    /// Creates a new ICMP socket.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let socket = new_icmp_socket();
+        let handle = SOCKET_SET.add(socket);
+
+        Self {
+            handle,
+            local_addr: RwLock::new(None),
+            peer_addr: RwLock::new(None),
+
+            general: GeneralOptions::new(),
+        }
+    }
+
+    fn with_smol_socket<R>(&self, f: impl FnOnce(&mut smol::Socket) -> R) -> R {
+        SOCKET_SET.with_socket_mut::<smol::Socket, _, _>(self.handle, f)
+    }
+
+    fn ident(&self) -> Option<u16> {
+        self.local_addr.read().map(|addr| addr.port())
+    }
+
+    /// Binds to a fresh identifier on the unspecified address of the same
+    /// family as `remote`, if not bound yet.
+    fn bind_implicitly(&self, remote: &IpAddress) -> AxResult {
+        if self.local_addr.read().is_some() {
+            return Ok(());
+        }
+        let ip = match remote {
+            IpAddress::Ipv4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddress::Ipv6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        self.bind(SocketAddrEx::Ip(SocketAddr::new(ip, 0)))
+    }
+}
+
+impl Configurable for IcmpSocket {
+    fn get_option_inner(&self, option: &mut GetSocketOption) -> AxResult<bool> {
+        use GetSocketOption as O;
+
+        if self.general.get_option_inner(option)? {
+            return Ok(true);
+        }
+        match option {
+            O::Ttl(ttl) => {
+                self.with_smol_socket(|socket| {
+                    **ttl = socket.hop_limit().unwrap_or(64);
+                });
+            }
+            O::SendBuffer(size) => {
+                **size = ICMP_TX_BUF_LEN;
+            }
+            O::ReceiveBuffer(size) => {
+                **size = ICMP_RX_BUF_LEN;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn set_option_inner(&self, option: SetSocketOption) -> AxResult<bool> {
+        use SetSocketOption as O;
+
+        if self.general.set_option_inner(option)? {
+            return Ok(true);
+        }
+        match option {
+            O::Ttl(ttl) => {
+                self.with_smol_socket(|socket| {
+                    socket.set_hop_limit(Some(*ttl));
+                });
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl SocketOps for IcmpSocket {
+    fn bind(&self, local_addr: SocketAddrEx) -> AxResult {
+        let mut local_addr = local_addr.into_ip()?;
+        let mut guard = self.local_addr.write();
+        if guard.is_some() {
+            ax_bail!(InvalidInput, "already bound");
+        }
+
+        if local_addr.port() == 0 {
+            local_addr.set_port(get_ephemeral_ident());
+        }
+        self.with_smol_socket(|socket| {
+            socket
+                .bind(Endpoint::Ident(local_addr.port()))
+                .map_err(|e| match e {
+                    smol::BindError::InvalidState => ax_err_type!(InvalidInput, "already bound"),
+                    smol::BindError::Unaddressable => {
+                        ax_err_type!(InvalidInput, "unaddressable")
+                    }
+                })
+        })?;
+
+        *guard = Some(local_addr);
+        info!("ICMP socket {}: bound on {}", self.handle, local_addr);
+        Ok(())
+    }
+
+    fn connect(&self, remote_addr: SocketAddrEx) -> AxResult {
+        let remote_addr = IpAddress::from(routable_addr(remote_addr.into_ip()?).ip());
+        let mut guard = self.peer_addr.write();
+        self.bind_implicitly(&remote_addr)?;
+
+        *guard = Some(remote_addr);
+        debug!("ICMP socket {}: connected to {}", self.handle, remote_addr);
+        Ok(())
+    }
+
+    fn send(&self, src: &mut impl Buf, options: SendOptions) -> AxResult<usize> {
+        let remote_addr = match options.to {
+            Some(addr) => IpAddress::from(routable_addr(addr.into_ip()?).ip()),
+            None => self.peer_addr.read().ok_or(AxError::NotConnected)?,
+        };
+        if remote_addr.is_unspecified() {
+            ax_bail!(InvalidInput, "invalid address");
+        }
+        if src.remaining() < 8 {
+            ax_bail!(InvalidInput, "ICMP message too short");
+        }
+
+        self.bind_implicitly(&remote_addr)?;
+        let ident = self.ident().ok_or(AxError::NotConnected)?;
+        self.general.send_poller(self).poll(|| {
+            poll_interfaces();
+            self.with_smol_socket(|socket| {
+                if !socket.can_send() {
+                    return Err(AxError::WouldBlock);
+                }
+                let buf = socket
+                    .send(src.remaining(), remote_addr)
+                    .map_err(|e| match e {
+                        smol::SendError::BufferFull => AxError::WouldBlock,
+                        smol::SendError::Unaddressable => {
+                            ax_err_type!(ConnectionRefused, "unaddressable")
+                        }
+                    })?;
+                let read = src.read(buf)?;
+                assert_eq!(read, buf.len());
+
+                let echo_request = match remote_addr {
+                    IpAddress::Ipv4(_) => ICMPV4_ECHO_REQUEST,
+                    IpAddress::Ipv6(_) => ICMPV6_ECHO_REQUEST,
+                };
+                if buf[0] == echo_request {
+                    buf[4..6].copy_from_slice(&ident.to_be_bytes());
+                }
+                Ok(read)
+            })
+        })
+    }
+
+    fn recv(&self, dst: &mut impl BufMut, mut options: RecvOptions) -> AxResult<usize> {
+        if self.local_addr.read().is_none() {
+            ax_bail!(NotConnected);
+        }
+        if options.flags.contains(RecvFlags::PEEK) {
+            ax_bail!(OperationNotSupported, "ICMP sockets cannot peek");
+        }
+        let expected = *self.peer_addr.read();
+
+        self.general.recv_poller(self).poll(|| {
+            poll_interfaces();
+            self.with_smol_socket(|socket| match socket.recv() {
+                Ok((src, addr)) => {
+                    if expected.is_some_and(|expected| expected != addr) {
+                        return Err(AxError::WouldBlock);
+                    }
+                    if let Some(from) = options.from.as_deref_mut() {
+                        *from = SocketAddrEx::Ip(SocketAddr::new(addr.into(), 0));
+                    }
+
+                    let read = dst.write(src)?;
+                    if read < src.len() {
+                        warn!("ICMP message truncated: {} -> {} bytes", src.len(), read);
+                    }
+                    Ok(if options.flags.contains(RecvFlags::TRUNCATE) {
+                        src.len()
+                    } else {
+                        read
+                    })
+                }
+                Err(smol::RecvError::Exhausted) => Err(AxError::WouldBlock),
+                Err(smol::RecvError::Truncated) => {
+                    unreachable!("ICMP socket recv never returns Err(Truncated)")
+                }
+            })
+        })
+    }
+
+    fn local_addr(&self) -> AxResult<SocketAddrEx> {
+        self.local_addr
+            .read()
+            .map(SocketAddrEx::Ip)
+            .ok_or(AxError::NotConnected)
+    }
+
+    fn peer_addr(&self) -> AxResult<SocketAddrEx> {
+        self.peer_addr
+            .read()
+            .map(|addr| SocketAddrEx::Ip(SocketAddr::new(addr.into(), 0)))
+            .ok_or(AxError::NotConnected)
+    }
+
+    fn shutdown(&self, _how: Shutdown) -> AxResult {
+        poll_interfaces();
+        Ok(())
+    }
+}
+
+impl Pollable for IcmpSocket {
+    fn poll(&self) -> IoEvents {
+        poll_interfaces();
+        let mut events = IoEvents::empty();
+        self.with_smol_socket(|socket| {
+            events.set(IoEvents::IN, socket.can_recv());
+            events.set(IoEvents::OUT, socket.can_send());
+        });
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.intersects(IoEvents::IN | IoEvents::OUT) {
+            self.general.register_waker(context.waker());
+        }
+    }
+}
+
+impl Drop for IcmpSocket {
+    fn drop(&mut self) {
+        SOCKET_SET.remove(self.handle);
+    }
+}
+
+fn get_ephemeral_ident() -> u16 {
+    static CURR: Mutex<u16> = Mutex::new(1);
+    let mut curr = CURR.lock();
+
+    let ident = *curr;
+    *curr = curr.checked_add(1).unwrap_or(1);
+    ident
+}
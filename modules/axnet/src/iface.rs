@@ -0,0 +1,265 @@
+//! Network interface configuration and information.
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use axerrno::{AxResult, ax_bail, ax_err_type};
+use axsync::Mutex;
+use smoltcp::wire::{IpAddress, IpCidr};
+
+pub use crate::device::LinkStats;
+use crate::{
+    SERVICE,
+    consts::{GATEWAY, GATEWAY6, IP, IP_PREFIX, IP6, IP6_PREFIX},
+    router::Rule,
+    service::{Service, network_of, now},
+};
+
+/// An IP address together with the prefix length of its network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpPrefix {
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl From<IpCidr> for IpPrefix {
+    fn from(cidr: IpCidr) -> Self {
+        Self {
+            addr: cidr.address().into(),
+            prefix_len: cidr.prefix_len(),
+        }
+    }
+}
+
+impl From<IpPrefix> for IpCidr {
+    fn from(prefix: IpPrefix) -> Self {
+        IpCidr::new(prefix.addr.into(), prefix.prefix_len)
+    }
+}
+
+/// Static configuration of a network interface.
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceConfig {
+    /// IPv4 address and prefix length.
+    pub ipv4: Option<(Ipv4Addr, u8)>,
+    /// Default IPv4 gateway.
+    pub gateway: Option<Ipv4Addr>,
+    /// Global IPv6 address and prefix length, in addition to the link-local
+    /// and autoconfigured ones.
+    pub ipv6: Option<(Ipv6Addr, u8)>,
+    /// Default IPv6 gateway.
+    pub gateway6: Option<Ipv6Addr>,
+    /// Whether to obtain the IPv4 configuration with DHCP, in which case
+    /// `ipv4` and `gateway` are only used as a fallback.
+    pub dhcp: bool,
+}
+
+static CONFIGS: Mutex<Vec<(String, InterfaceConfig)>> = Mutex::new(Vec::new());
+
+/// Sets the configuration of the interface named `name` (e.g. `eth1`).
+///
+/// This must be called before [`init_network`](crate::init_network) to take
+/// effect. Interfaces without a configuration are left unconfigured, except
+/// for `eth0` which defaults to the build-time `AX_IP`/`AX_GW` and
+/// `AX_IP6`/`AX_GW6` settings.
+pub fn configure_interface(name: &str, config: InterfaceConfig) {
+    let mut configs = CONFIGS.lock();
+    match configs.iter_mut().find(|(it, _)| it == name) {
+        Some((_, it)) => *it = config,
+        None => configs.push((name.into(), config)),
+    }
+}
+
+fn parse_or_none<T: core::str::FromStr>(s: &str, what: &str) -> Option<T> {
+    if s.is_empty() {
+        return None;
+    }
+    match s.parse() {
+        Ok(value) => Some(value),
+        Err(_) => panic!("Invalid {what}: {s}"),
+    }
+}
+
+pub(crate) fn config_for(name: &str) -> InterfaceConfig {
+    if let Some((_, config)) = CONFIGS.lock().iter().find(|(it, _)| it == name) {
+        return config.clone();
+    }
+    if name != "eth0" {
+        return InterfaceConfig::default();
+    }
+    InterfaceConfig {
+        ipv4: parse_or_none(IP, "IPv4 address").map(|ip| (ip, IP_PREFIX)),
+        gateway: parse_or_none(GATEWAY, "gateway address"),
+        ipv6: parse_or_none(IP6, "IPv6 address").map(|ip| (ip, IP6_PREFIX)),
+        gateway6: parse_or_none(GATEWAY6, "IPv6 gateway address"),
+        dhcp: cfg!(feature = "dhcp"),
+    }
+}
+
+/// Information about a network interface.
+#[derive(Debug, Clone)]
+pub struct InterfaceInfo {
+    /// Index of the interface, `0` being the loopback interface.
+    pub index: usize,
+    pub name: String,
+    pub mac: Option<[u8; 6]>,
+    pub addrs: Vec<IpPrefix>,
+    pub up: bool,
+    pub stats: LinkStats,
+}
+
+/// Lists all network interfaces.
+pub fn interfaces() -> Vec<InterfaceInfo> {
+    SERVICE.lock().interfaces()
+}
+
+fn device_index(service: &Service, iface: &str) -> AxResult<usize> {
+    service
+        .device_index(iface)
+        .ok_or_else(|| ax_err_type!(NotFound, "no such interface"))
+}
+
+/// Brings the interface named `iface` up or down.
+///
+/// A down interface neither sends nor receives packets, and its routes are
+/// ignored.
+pub fn set_interface_up(iface: &str, up: bool) -> AxResult {
+    let mut service = SERVICE.lock();
+    let dev = device_index(&service, iface)?;
+    service.set_device_up(dev, up);
+    info!("{}: link {}", iface, if up { "up" } else { "down" });
+    Ok(())
+}
+
+/// Adds an address to the interface named `iface`, together with an on-link
+/// route for its subnet.
+pub fn add_address(iface: &str, addr: IpPrefix) -> AxResult {
+    let mut service = SERVICE.lock();
+    let dev = device_index(&service, iface)?;
+    service.add_address(dev, addr.into())
+}
+
+/// Removes an address from the interface named `iface`, together with all
+/// the routes using it as source address.
+pub fn remove_address(iface: &str, addr: IpPrefix) -> AxResult {
+    let mut service = SERVICE.lock();
+    let dev = device_index(&service, iface)?;
+    service.remove_address(dev, addr.into())
+}
+
+/// An entry of the routing table.
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// The destination network.
+    pub dst: IpPrefix,
+    /// The next hop, or `None` if the destination is on-link.
+    pub gateway: Option<IpAddr>,
+    pub iface: String,
+    /// The source address of outgoing packets.
+    pub src: IpAddr,
+    /// Preference among routes with the same prefix length, lower first.
+    pub metric: u32,
+}
+
+/// Lists the routing table, in lookup order.
+pub fn routes() -> Vec<Route> {
+    let service = SERVICE.lock();
+    service
+        .rules()
+        .iter()
+        .map(|rule| Route {
+            dst: rule.filter.into(),
+            gateway: rule.via.map(Into::into),
+            iface: service.device_name(rule.dev).into(),
+            src: rule.src.into(),
+            metric: rule.metric,
+        })
+        .collect()
+}
+
+/// Adds a route through the interface named `route.iface`.
+///
+/// The source address must be assigned to that interface, and the gateway,
+/// if any, must be of the same family as the destination.
+pub fn add_route(route: &Route) -> AxResult {
+    let mut service = SERVICE.lock();
+    let dev = device_index(&service, &route.iface)?;
+    let dst = network_of(route.dst.into());
+    let src = IpAddress::from(route.src);
+    if dst.address().version() != src.version()
+        || route
+            .gateway
+            .is_some_and(|gateway| IpAddress::from(gateway).version() != src.version())
+    {
+        ax_bail!(InvalidInput, "address family mismatch");
+    }
+    if !service.has_address(dev, &src) {
+        ax_bail!(InvalidInput, "source address not assigned to interface");
+    }
+    let via = route.gateway.map(IpAddress::from);
+    if service.rules().iter().any(|rule| {
+        rule.filter == dst && rule.via == via && rule.dev == dev && rule.metric == route.metric
+    }) {
+        ax_bail!(AlreadyExists, "route already exists");
+    }
+
+    service.add_rule(Rule::new(dst, via, dev, src).with_metric(route.metric));
+    Ok(())
+}
+
+/// Removes the routes to `dst` through the interface named `iface`.
+///
+/// If `gateway` or `metric` is given, only the matching routes are removed.
+pub fn remove_route(
+    dst: IpPrefix,
+    gateway: Option<IpAddr>,
+    iface: &str,
+    metric: Option<u32>,
+) -> AxResult {
+    let mut service = SERVICE.lock();
+    let dev = device_index(&service, iface)?;
+    let dst = network_of(dst.into());
+    let via = gateway.map(IpAddress::from);
+    let removed = service.remove_rules(|rule| {
+        rule.dev == dev
+            && rule.filter == dst
+            && (via.is_none() || rule.via == via)
+            && metric.is_none_or(|metric| rule.metric == metric)
+    });
+    if !removed {
+        ax_bail!(NotFound, "no such route");
+    }
+    Ok(())
+}
+
+/// An entry of the neighbor (ARP/NDP) cache of an interface.
+#[derive(Debug, Clone)]
+pub struct Neighbor {
+    pub addr: IpAddr,
+    /// The hardware address, or `None` while resolution is pending.
+    pub mac: Option<[u8; 6]>,
+    /// The time left before the entry expires, if it does.
+    pub expires_in: Option<Duration>,
+}
+
+/// Lists the neighbor cache of the interface named `iface`.
+pub fn neighbors(iface: &str) -> AxResult<Vec<Neighbor>> {
+    let service = SERVICE.lock();
+    let dev = device_index(&service, iface)?;
+    let now = now();
+    Ok(service
+        .neighbors(dev)
+        .into_iter()
+        .map(|entry| Neighbor {
+            addr: entry.ip.into(),
+            mac: entry.mac.map(|mac| mac.0),
+            expires_in: entry.expires_at.map(|expires_at| {
+                let micros = expires_at.total_micros() - now.total_micros();
+                Duration::from_micros(micros.max(0) as u64)
+            }),
+        })
+        .collect())
+}
@@ -8,25 +8,36 @@
 //!
 //! - [`TcpSocket`]: A TCP socket that provides POSIX-like APIs.
 //! - [`UdpSocket`]: A UDP socket that provides POSIX-like APIs.
-//! - [`dns_query`]: Function for DNS query.
+//! - [`IcmpSocket`]: An ICMP echo ("ping") socket.
+//! - [`RawSocket`]: A raw IP socket.
+//! - [`dns_query`]: Function for DNS query, see the [`dns`] module.
 //!
 //! [smoltcp]: https://github.com/smoltcp-rs/smoltcp
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(maybe_uninit_slice)]
 
 #[macro_use]
 extern crate log;
 extern crate alloc;
 
+#[cfg(feature = "pcap")]
+pub mod capture;
 mod consts;
 mod device;
+#[cfg(feature = "dhcp")]
+mod dhcp;
+pub mod dns;
 mod general;
+pub mod icmp;
+pub mod iface;
 mod listen_table;
 pub mod options;
+pub mod raw;
 mod router;
 mod service;
 mod socket;
+pub mod stats;
 pub(crate) mod state;
 pub mod tcp;
 pub mod udp;
@@ -35,19 +46,20 @@ pub mod unix;
 pub mod vsock;
 mod wrapper;
 
-use alloc::{borrow::ToOwned, boxed::Box};
+use alloc::{boxed::Box, format, vec::Vec};
 
 use axdriver::{AxDeviceContainer, prelude::*};
 use axsync::Mutex;
 use lazyinit::LazyInit;
-use smoltcp::wire::{EthernetAddress, Ipv4Address, Ipv4Cidr};
+use smoltcp::wire::{EthernetAddress, Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr};
+pub use dns::dns_query;
 pub use socket::*;
 
 use crate::{
-    consts::{GATEWAY, IP, IP_PREFIX},
-    device::{EthernetDevice, LoopbackDevice},
+    consts::MAX_DEVICES,
+    device::{EthernetDevice, LoopbackDevice, link_local_address},
     listen_table::ListenTable,
-    router::{Router, Rule},
+    router::Router,
     service::Service,
     wrapper::SocketSetWrapper,
 };
@@ -59,60 +71,85 @@ static SOCKET_SET: LazyInit<SocketSetWrapper> = LazyInit::new();
 static SERVICE: LazyInit<Mutex<Service>> = LazyInit::new();
 
 /// Initializes the network subsystem by NIC devices.
+///
+/// Every NIC becomes an interface named `eth0`, `eth1`, ... configured as
+/// set by [`iface::configure_interface`].
 pub fn init_network(mut net_devs: AxDeviceContainer<AxNetDevice>) {
     info!("Initialize network subsystem...");
 
     let mut router = Router::new();
     let lo_dev = router.add_device(Box::new(LoopbackDevice::new()));
 
-    let lo_ip = Ipv4Cidr::new(Ipv4Address::new(127, 0, 0, 1), 8);
-    router.add_rule(Rule::new(
-        lo_ip.into(),
-        None,
-        lo_dev,
-        lo_ip.address().into(),
-    ));
-
-    let eth0_ip = if let Some(dev) = net_devs.take_one() {
-        info!("  use NIC 0: {:?}", dev.device_name());
-
-        let eth0_address = EthernetAddress(dev.mac_address().0);
-        let eth0_ip = Ipv4Cidr::new(IP.parse().expect("Invalid IPv4 address"), IP_PREFIX);
-
-        let eth0_dev = router.add_device(Box::new(EthernetDevice::new(
-            "eth0".to_owned(),
-            dev,
-            eth0_ip,
-        )));
-
-        router.add_rule(Rule::new(
-            Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0).into(),
-            Some(GATEWAY.parse().expect("Invalid gateway address")),
-            eth0_dev,
-            eth0_ip.address().into(),
-        ));
-
-        info!("eth0:");
-        info!("  mac:  {}", eth0_address);
-        info!("  ip:   {}", eth0_ip);
-
-        Some(eth0_ip)
-    } else {
+    let mut nics = Vec::new();
+    while let Some(dev) = net_devs.take_one() {
+        if router.devices.len() >= MAX_DEVICES {
+            warn!("Too many NIC devices, ignoring {:?}", dev.device_name());
+            continue;
+        }
+        info!("  use NIC {}: {:?}", nics.len(), dev.device_name());
+
+        let name = format!("eth{}", nics.len());
+        let mac = EthernetAddress(dev.mac_address().0);
+        let index = router.add_device(Box::new(EthernetDevice::new(name.clone(), dev)));
+        nics.push((index, name, mac));
+    }
+    if nics.is_empty() {
         warn!("No NIC device found!");
-        None
-    };
+    }
 
     let mut service = Service::new(router);
-    service.iface.update_ip_addrs(|ip_addrs| {
-        ip_addrs.push(lo_ip.into()).unwrap();
-        if let Some(eth0_ip) = eth0_ip {
-            ip_addrs.push(eth0_ip.into()).unwrap();
+    let lo_ip = Ipv4Cidr::new(Ipv4Address::new(127, 0, 0, 1), 8);
+    service.set_ipv4_config(lo_dev, None, Some(lo_ip), None);
+    service.add_ipv6_addr(lo_dev, Ipv6Cidr::new(Ipv6Address::LOCALHOST, 128), None);
+
+    #[cfg(feature = "dhcp")]
+    let mut dhcp_devs = Vec::new();
+    for (dev, name, mac) in &nics {
+        let config = iface::config_for(name);
+        info!("{}:", name);
+        info!("  mac:  {}", mac);
+
+        let link_local = Ipv6Cidr::new(link_local_address(*mac), 64);
+        service.add_ipv6_addr(*dev, link_local, None);
+        info!("  ip6:  {}", link_local);
+        if let Some((ip6, prefix_len)) = config.ipv6 {
+            let ip6 = Ipv6Cidr::new(ip6, prefix_len);
+            service.add_ipv6_addr(*dev, ip6, config.gateway6);
+            info!("  ip6:  {}", ip6);
+        }
+
+        let ip = config
+            .ipv4
+            .map(|(ip, prefix_len)| Ipv4Cidr::new(ip, prefix_len));
+        if config.dhcp && cfg!(feature = "dhcp") {
+            // The static configuration is only a fallback in DHCP mode.
+            #[cfg(feature = "dhcp")]
+            dhcp_devs.push((
+                *dev,
+                *mac,
+                ip.map(|ip| dhcp::StaticConfig {
+                    ip,
+                    gateway: config.gateway,
+                }),
+            ));
+        } else if let Some(ip) = ip {
+            if config.dhcp {
+                warn!("{}: DHCP is not enabled, using static address", name);
+            }
+            service.set_ipv4_config(*dev, None, Some(ip), config.gateway);
+            info!("  ip:   {}", ip);
         }
-    });
+    }
     SERVICE.init_once(Mutex::new(service));
 
     SOCKET_SET.init_once(SocketSetWrapper::new());
     LISTEN_TABLE.init_once(ListenTable::new());
+    dns::init();
+
+    #[cfg(feature = "dhcp")]
+    for (dev, mac, fallback) in dhcp_devs {
+        dhcp::start(dev, mac, fallback);
+    }
 }
 
 /// Init vsock subsystem by vsock devices.
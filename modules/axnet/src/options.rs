@@ -44,6 +44,33 @@ impl UnixCredentials {
     }
 }
 
+/// Information about a TCP connection, like `struct tcp_info` in Linux.
+#[derive(Default, Debug, Clone)]
+pub struct TcpInfo {
+    /// Connection state, numbered as Linux's `TCP_*` states.
+    pub state: u8,
+    /// Smoothed round-trip time.
+    pub rtt: Duration,
+    /// Round-trip time variation.
+    pub rttvar: Duration,
+    /// Congestion window, in bytes.
+    pub snd_cwnd: u32,
+    /// Window advertised by the peer, in bytes.
+    pub snd_wnd: u32,
+    /// Number of retransmitted segments.
+    pub total_retrans: u32,
+    /// Payload bytes sent, excluding retransmissions.
+    pub bytes_sent: u64,
+    /// Payload bytes received.
+    pub bytes_received: u64,
+    pub segs_out: u64,
+    pub segs_in: u64,
+    /// Bytes in the send queue, not yet acknowledged.
+    pub unacked: usize,
+    /// Bytes in the receive queue, not yet read.
+    pub unread: usize,
+}
+
 define_options! {
     // ---- Socket level options (SO_*) ----
     ReuseAddress(bool),
@@ -61,7 +88,7 @@ define_options! {
     // --- TCP level options (TCP_*) ----
     NoDelay(bool),
     MaxSegment(usize),
-    TcpInfo(()),
+    TcpInfo(TcpInfo),
 
     // ---- IP level options (IP_*) ----
     Ttl(u8),
@@ -0,0 +1,290 @@
+use alloc::vec;
+use core::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicU8, Ordering},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult, ax_bail};
+use axio::{Buf, BufMut};
+use axpoll::{IoEvents, Pollable};
+use smoltcp::{
+    iface::SocketHandle,
+    phy::ChecksumCapabilities,
+    socket::raw as smol,
+    wire::{IpAddress, IpListenEndpoint, IpProtocol, IpRepr, IpVersion, Ipv4Packet, Ipv6Packet},
+};
+use spin::RwLock;
+
+use crate::{
+    RecvFlags, RecvOptions, SERVICE, SOCKET_SET, SendOptions, Shutdown, SocketAddrEx, SocketOps,
+    consts::{RAW_RX_BUF_LEN, RAW_TX_BUF_LEN},
+    general::GeneralOptions,
+    options::{Configurable, GetSocketOption, SetSocketOption},
+    poll_interfaces,
+    socket::routable_addr,
+};
+
+pub(crate) fn new_raw_socket(version: IpVersion, protocol: IpProtocol) -> smol::Socket<'static> {
+    smol::Socket::new(
+        version,
+        protocol,
+        smol::PacketBuffer::new(
+            vec![smol::PacketMetadata::EMPTY; 64],
+            vec![0; RAW_RX_BUF_LEN],
+        ),
+        smol::PacketBuffer::new(
+            vec![smol::PacketMetadata::EMPTY; 64],
+            vec![0; RAW_TX_BUF_LEN],
+        ),
+    )
+}
+
+/// A raw IP socket that provides POSIX-like APIs.
+///
+/// The socket receives every packet of its IP protocol. Sent data is the
+/// payload, for which the IP header is generated. Received IPv4 packets
+/// include their IP header while IPv6 ones do not, as on Linux.
+pub struct RawSocket {
+    handle: SocketHandle,
+    version: IpVersion,
+    protocol: IpProtocol,
+    local_addr: RwLock<Option<IpAddress>>,
+    peer_addr: RwLock<Option<IpAddress>>,
+    hop_limit: AtomicU8,
+
+    general: GeneralOptions,
+}
+
+impl RawSocket {
+    /// Creates a new raw socket for the given IP protocol number.
+    pub fn new(ipv6: bool, protocol: u8) -> Self {
+        let version = if ipv6 {
+            IpVersion::Ipv6
+        } else {
+            IpVersion::Ipv4
+        };
+        let protocol = IpProtocol::from(protocol);
+        let handle = SOCKET_SET.add(new_raw_socket(version, protocol));
+
+        Self {
+            handle,
+            version,
+            protocol,
+            local_addr: RwLock::new(None),
+            peer_addr: RwLock::new(None),
+            hop_limit: AtomicU8::new(64),
+
+            general: GeneralOptions::new(),
+        }
+    }
+
+    fn with_smol_socket<R>(&self, f: impl FnOnce(&mut smol::Socket) -> R) -> R {
+        SOCKET_SET.with_socket_mut::<smol::Socket, _, _>(self.handle, f)
+    }
+
+    fn to_ip_address(&self, addr: SocketAddrEx) -> AxResult<IpAddress> {
+        let addr = IpAddress::from(routable_addr(addr.into_ip()?).ip());
+        if addr.version() != self.version {
+            ax_bail!(InvalidInput, "address family mismatch");
+        }
+        Ok(addr)
+    }
+}
+
+impl Configurable for RawSocket {
+    fn get_option_inner(&self, option: &mut GetSocketOption) -> AxResult<bool> {
+        use GetSocketOption as O;
+
+        if self.general.get_option_inner(option)? {
+            return Ok(true);
+        }
+        match option {
+            O::Ttl(ttl) => {
+                **ttl = self.hop_limit.load(Ordering::Relaxed);
+            }
+            O::SendBuffer(size) => {
+                **size = RAW_TX_BUF_LEN;
+            }
+            O::ReceiveBuffer(size) => {
+                **size = RAW_RX_BUF_LEN;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn set_option_inner(&self, option: SetSocketOption) -> AxResult<bool> {
+        use SetSocketOption as O;
+
+        if self.general.set_option_inner(option)? {
+            return Ok(true);
+        }
+        match option {
+            O::Ttl(ttl) => {
+                self.hop_limit.store(*ttl, Ordering::Relaxed);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl SocketOps for RawSocket {
+    fn bind(&self, local_addr: SocketAddrEx) -> AxResult {
+        let local_addr = self.to_ip_address(local_addr)?;
+        let mut guard = self.local_addr.write();
+        if guard.is_some() {
+            ax_bail!(InvalidInput, "already bound");
+        }
+        *guard = (!local_addr.is_unspecified()).then_some(local_addr);
+        let endpoint = IpListenEndpoint {
+            addr: *guard,
+            port: 0,
+        };
+        self.general
+            .set_device_mask(SERVICE.lock().device_mask_for(&endpoint));
+        info!("Raw socket {}: bound on {}", self.handle, local_addr);
+        Ok(())
+    }
+
+    fn connect(&self, remote_addr: SocketAddrEx) -> AxResult {
+        let remote_addr = self.to_ip_address(remote_addr)?;
+        *self.peer_addr.write() = Some(remote_addr);
+        debug!("Raw socket {}: connected to {}", self.handle, remote_addr);
+        Ok(())
+    }
+
+    fn send(&self, src: &mut impl Buf, options: SendOptions) -> AxResult<usize> {
+        let remote_addr = match options.to {
+            Some(addr) => self.to_ip_address(addr)?,
+            None => self.peer_addr.read().ok_or(AxError::NotConnected)?,
+        };
+        if remote_addr.is_unspecified() {
+            ax_bail!(InvalidInput, "invalid address");
+        }
+        let source_addr = match *self.local_addr.read() {
+            Some(addr) => addr,
+            None => SERVICE.lock().get_source_address(&remote_addr)?,
+        };
+        let ip_repr = IpRepr::new(
+            source_addr,
+            remote_addr,
+            self.protocol,
+            src.remaining(),
+            self.hop_limit.load(Ordering::Relaxed),
+        );
+        let header_len = ip_repr.header_len();
+
+        self.general.send_poller(self).poll(|| {
+            poll_interfaces();
+            self.with_smol_socket(|socket| {
+                if !socket.can_send() {
+                    return Err(AxError::WouldBlock);
+                }
+                let buf = socket
+                    .send(header_len + ip_repr.payload_len())
+                    .map_err(|_| AxError::WouldBlock)?;
+                ip_repr.emit(&mut buf[..header_len], &ChecksumCapabilities::default());
+                let read = src.read(&mut buf[header_len..])?;
+                assert_eq!(read, buf.len() - header_len);
+                Ok(read)
+            })
+        })
+    }
+
+    fn recv(&self, dst: &mut impl BufMut, mut options: RecvOptions) -> AxResult<usize> {
+        let local_addr = *self.local_addr.read();
+        let peer_addr = *self.peer_addr.read();
+        let peek = options.flags.contains(RecvFlags::PEEK);
+
+        self.general.recv_poller(self).poll(|| {
+            poll_interfaces();
+            self.with_smol_socket(|socket| {
+                let packet = socket.peek().map_err(|_| AxError::WouldBlock)?;
+                let (src_addr, dst_addr, payload) = match self.version {
+                    IpVersion::Ipv4 => {
+                        let ip = Ipv4Packet::new_unchecked(packet);
+                        // Linux delivers IPv4 packets with their header.
+                        (ip.src_addr().into(), ip.dst_addr().into(), packet)
+                    }
+                    IpVersion::Ipv6 => {
+                        let ip = Ipv6Packet::new_unchecked(packet);
+                        let header_len = packet.len() - ip.payload_len() as usize;
+                        (
+                            IpAddress::from(ip.src_addr()),
+                            IpAddress::from(ip.dst_addr()),
+                            &packet[header_len..],
+                        )
+                    }
+                };
+                if local_addr.is_some_and(|addr| addr != dst_addr)
+                    || peer_addr.is_some_and(|addr| addr != src_addr)
+                {
+                    // Not for us, drop it.
+                    let _ = socket.recv();
+                    return Err(AxError::WouldBlock);
+                }
+                if let Some(from) = options.from.as_deref_mut() {
+                    *from = SocketAddrEx::Ip(SocketAddr::new(IpAddr::from(src_addr), 0));
+                }
+
+                let read = dst.write(payload)?;
+                let len = payload.len();
+                if !peek {
+                    let _ = socket.recv();
+                }
+                Ok(if options.flags.contains(RecvFlags::TRUNCATE) {
+                    len
+                } else {
+                    read
+                })
+            })
+        })
+    }
+
+    fn local_addr(&self) -> AxResult<SocketAddrEx> {
+        let addr = match (*self.local_addr.read(), self.version) {
+            (Some(addr), _) => addr.into(),
+            (None, IpVersion::Ipv4) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (None, IpVersion::Ipv6) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        Ok(SocketAddrEx::Ip(SocketAddr::new(addr, 0)))
+    }
+
+    fn peer_addr(&self) -> AxResult<SocketAddrEx> {
+        self.peer_addr
+            .read()
+            .map(|addr| SocketAddrEx::Ip(SocketAddr::new(addr.into(), 0)))
+            .ok_or(AxError::NotConnected)
+    }
+
+    fn shutdown(&self, _how: Shutdown) -> AxResult {
+        poll_interfaces();
+        Ok(())
+    }
+}
+
+impl Pollable for RawSocket {
+    fn poll(&self) -> IoEvents {
+        poll_interfaces();
+        let mut events = IoEvents::empty();
+        self.with_smol_socket(|socket| {
+            events.set(IoEvents::IN, socket.can_recv());
+            events.set(IoEvents::OUT, socket.can_send());
+        });
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.intersects(IoEvents::IN | IoEvents::OUT) {
+            self.general.register_waker(context.waker());
+        }
+    }
+}
+
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        SOCKET_SET.remove(self.handle);
+    }
+}
@@ -1,4 +1,5 @@
 use alloc::{boxed::Box, vec, vec::Vec};
+use core::cmp::Reverse;
 
 use smoltcp::{
     iface::SocketSet,
@@ -11,7 +12,8 @@ use smoltcp::{
 use crate::{
     LISTEN_TABLE,
     consts::{SOCKET_BUFFER_SIZE, STANDARD_MTU},
-    device::Device,
+    device::{Device, Direction},
+    stats,
 };
 
 #[derive(Debug)]
@@ -20,6 +22,8 @@ pub struct Rule {
     pub via: Option<IpAddress>,
     pub dev: usize,
     pub src: IpAddress,
+    /// Preference among rules with the same prefix length, lower first.
+    pub metric: u32,
 }
 
 impl Rule {
@@ -29,8 +33,14 @@ impl Rule {
             via,
             dev,
             src,
+            metric: 0,
         }
     }
+
+    pub fn with_metric(mut self, metric: u32) -> Self {
+        self.metric = metric;
+        self
+    }
 }
 
 type PacketBuffer = smoltcp::storage::PacketBuffer<'static, ()>;
@@ -45,17 +55,29 @@ impl RouteTable {
     }
 
     pub fn add_rule(&mut self, rule: Rule) {
-        let idx = self
-            .rules
-            .binary_search_by(|it| rule.filter.prefix_len().cmp(&it.filter.prefix_len()))
-            .unwrap_or_else(|idx| idx);
+        // Longest prefix first, then lowest metric. Rules that compare equal
+        // keep their insertion order.
+        let idx = self.rules.partition_point(|it| {
+            (it.filter.prefix_len(), Reverse(it.metric))
+                >= (rule.filter.prefix_len(), Reverse(rule.metric))
+        });
         self.rules.insert(idx, rule);
     }
 
-    pub fn lookup(&self, dst: &IpAddress) -> Option<&Rule> {
+    pub fn remove_rules(&mut self, f: impl Fn(&Rule) -> bool) {
+        self.rules.retain(|rule| !f(rule));
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Finds the rule for `dst`, ignoring the rules of the devices for which
+    /// `up` is `false`.
+    pub fn lookup(&self, dst: &IpAddress, up: &[bool]) -> Option<&Rule> {
         self.rules
             .iter()
-            .find(|rule| rule.filter.contains_addr(dst))
+            .find(|rule| up[rule.dev] && rule.filter.contains_addr(dst))
     }
 }
 
@@ -63,6 +85,8 @@ pub struct Router {
     rx_buffer: PacketBuffer,
     tx_buffer: PacketBuffer,
     pub(crate) devices: Vec<Box<dyn Device>>,
+    /// Whether each device is up. Down devices neither receive nor send.
+    up: Vec<bool>,
     pub(crate) table: RouteTable,
 }
 impl Router {
@@ -79,6 +103,7 @@ impl Router {
             rx_buffer,
             tx_buffer,
             devices: Vec::new(),
+            up: Vec::new(),
             table: RouteTable::new(),
         }
     }
@@ -87,13 +112,33 @@ impl Router {
         self.table.add_rule(rule);
     }
 
+    pub fn remove_rules(&mut self, f: impl Fn(&Rule) -> bool) {
+        self.table.remove_rules(f);
+    }
+
     pub fn add_device(&mut self, device: Box<dyn Device>) -> usize {
         self.devices.push(device);
+        self.up.push(true);
         self.devices.len() - 1
     }
 
+    pub fn is_up(&self, dev: usize) -> bool {
+        self.up[dev]
+    }
+
+    pub fn set_up(&mut self, dev: usize, up: bool) {
+        self.up[dev] = up;
+    }
+
+    pub fn lookup(&self, dst: &IpAddress) -> Option<&Rule> {
+        self.table.lookup(dst, &self.up)
+    }
+
     pub fn poll(&mut self, timestamp: Instant) {
-        for dev in &mut self.devices {
+        for (dev, up) in self.devices.iter_mut().zip(&self.up) {
+            if !*up {
+                continue;
+            }
             while !self.rx_buffer.is_full() && dev.recv(&mut self.rx_buffer, timestamp) {}
         }
     }
@@ -101,6 +146,7 @@ impl Router {
     pub fn dispatch(&mut self, timestamp: Instant) -> bool {
         let mut poll_next = false;
         while let Ok(((), packet)) = self.tx_buffer.dequeue() {
+            stats::account_packet(Direction::Tx, packet);
             match IpVersion::of_packet(packet).expect("got invalid IP packet") {
                 IpVersion::Ipv4 => {
                     let packet = smoltcp::wire::Ipv4Packet::new_checked(packet)
@@ -108,15 +154,17 @@ impl Router {
                     let dst_addr = IpAddress::Ipv4(packet.dst_addr());
                     if packet.dst_addr().is_broadcast() {
                         let buf = packet.into_inner();
-                        for dev in &mut self.devices {
-                            poll_next |= dev.send(dst_addr, buf, timestamp);
+                        for (dev, up) in self.devices.iter_mut().zip(&self.up) {
+                            if *up {
+                                poll_next |= dev.send(dst_addr, buf, timestamp);
+                            }
                         }
                     } else {
-                        let Some(rule) = self.table.lookup(&dst_addr) else {
+                        let Some(rule) = self.table.lookup(&dst_addr, &self.up) else {
                             warn!("No route found for destination: {}", dst_addr);
+                            stats::account_no_route(dst_addr.version());
                             continue;
                         };
-                        assert_eq!(rule.src, IpAddress::Ipv4(packet.src_addr()));
 
                         let next_hop = rule.via.unwrap_or(dst_addr);
                         let dev = &mut self.devices[rule.dev];
@@ -129,15 +177,17 @@ impl Router {
                     let dst_addr = IpAddress::Ipv6(packet.dst_addr());
                     if packet.dst_addr().is_multicast() {
                         let buf = packet.into_inner();
-                        for dev in &mut self.devices {
-                            poll_next |= dev.send(dst_addr, buf, timestamp);
+                        for (dev, up) in self.devices.iter_mut().zip(&self.up) {
+                            if *up {
+                                poll_next |= dev.send(dst_addr, buf, timestamp);
+                            }
                         }
                     } else {
-                        let Some(rule) = self.table.lookup(&dst_addr) else {
+                        let Some(rule) = self.table.lookup(&dst_addr, &self.up) else {
                             warn!("No route found for destination: {}", dst_addr);
+                            stats::account_no_route(dst_addr.version());
                             continue;
                         };
-                        assert_eq!(rule.src, IpAddress::Ipv6(packet.src_addr()));
 
                         let next_hop = rule.via.unwrap_or(dst_addr);
                         let dev = &mut self.devices[rule.dev];
@@ -207,6 +257,7 @@ impl<'a> smoltcp::phy::RxToken for RxToken<'a> {
     }
 
     fn preprocess(&self, sockets: &mut SocketSet) {
+        stats::account_packet(Direction::Rx, self.0);
         snoop_tcp_packet(self.0, sockets);
     }
 }
@@ -1,57 +1,321 @@
+use alloc::{string::ToString, vec::Vec};
 use core::{
     pin::pin,
     task::{Context, Waker},
     time::Duration,
 };
 
-use axhal::time::{NANOS_PER_MICROS, wall_time_nanos};
-use axtask::future::sleep_until;
+use axerrno::{AxError, AxResult, LinuxError, ax_bail};
+use axhal::time::{NANOS_PER_MICROS, monotonic_time_nanos};
+use axtask::future::sleep_until_monotonic;
 use smoltcp::{
     iface::{Interface, SocketSet},
-    time::Instant,
-    wire::{HardwareAddress, IpAddress, IpListenEndpoint},
+    time::{Duration, Instant},
+    wire::{
+        HardwareAddress, IpAddress, IpCidr, IpListenEndpoint, Ipv4Address, Ipv4Cidr, Ipv6Address,
+        Ipv6Cidr,
+    },
 };
 
-use crate::{SOCKET_SET, router::Router};
+use crate::{
+    SOCKET_SET,
+    device::NeighborEntry,
+    iface::InterfaceInfo,
+    router::{Router, Rule},
+};
+
+/// Returns `cidr` with the host part of its address cleared.
+pub(crate) fn network_of(cidr: IpCidr) -> IpCidr {
+    match cidr {
+        IpCidr::Ipv4(cidr) => cidr.network().into(),
+        IpCidr::Ipv6(cidr) => {
+            let mask = u128::MAX
+                .checked_shl(128 - cidr.prefix_len() as u32)
+                .unwrap_or(0);
+            let addr = Ipv6Address::from_bits(cidr.address().to_bits() & mask);
+            Ipv6Cidr::new(addr, cidr.prefix_len()).into()
+        }
+    }
+}
 
-fn now() -> Instant {
-    Instant::from_micros_const((wall_time_nanos() / NANOS_PER_MICROS) as i64)
+pub(crate) fn now() -> Instant {
+    Instant::from_micros_const((monotonic_time_nanos() / NANOS_PER_MICROS) as i64)
 }
 
 pub struct Service {
     pub iface: Interface,
     router: Router,
+    /// Addresses assigned to the devices, with the device index.
+    addrs: Vec<(usize, IpCidr)>,
+    /// Default routers learnt from router advertisements, with the device
+    /// index and when they expire.
+    default_routers: Vec<(usize, Ipv6Address, Instant)>,
 }
 impl Service {
     pub fn new(mut router: Router) -> Self {
         let config = smoltcp::iface::Config::new(HardwareAddress::Ip);
         let iface = Interface::new(config, &mut router, now());
 
-        Self { iface, router }
+        Self {
+            iface,
+            router,
+            addrs: Vec::new(),
+            default_routers: Vec::new(),
+        }
+    }
+
+    /// Assigns `ip` to the device at index `dev`, together with an on-link
+    /// route for its subnet.
+    pub fn add_address(&mut self, dev: usize, ip: IpCidr) -> AxResult {
+        if self.addrs.contains(&(dev, ip)) {
+            ax_bail!(AlreadyExists, "address already assigned");
+        }
+        let mut pushed = true;
+        self.iface.update_ip_addrs(|ip_addrs| {
+            if !ip_addrs.contains(&ip) && ip_addrs.push(ip).is_err() {
+                pushed = false;
+            }
+        });
+        if !pushed {
+            warn!("Too many interface addresses, dropping {}", ip);
+            ax_bail!(NoMemory, "too many interface addresses");
+        }
+        self.addrs.push((dev, ip));
+
+        self.router.devices[dev].add_ip_addr(ip);
+        self.router
+            .add_rule(Rule::new(network_of(ip), None, dev, ip.address()));
+        Ok(())
+    }
+
+    /// Removes `ip` from the device at index `dev`, together with all the
+    /// routes using it as source address.
+    pub fn remove_address(&mut self, dev: usize, ip: IpCidr) -> AxResult {
+        if !self.addrs.contains(&(dev, ip)) {
+            ax_bail!(NotFound, "address not assigned");
+        }
+        let src = ip.address();
+        self.router
+            .remove_rules(|rule| rule.dev == dev && rule.src == src);
+        self.router.devices[dev].remove_ip_addr(ip);
+
+        self.addrs.retain(|it| *it != (dev, ip));
+        // The same address may still be assigned to another device.
+        if !self.addrs.iter().any(|(_, it)| *it == ip) {
+            self.iface
+                .update_ip_addrs(|ip_addrs| ip_addrs.retain(|it| *it != ip));
+        }
+        Ok(())
+    }
+
+    pub fn has_address(&self, dev: usize, ip: &IpAddress) -> bool {
+        self.addrs
+            .iter()
+            .any(|(it, cidr)| *it == dev && cidr.address() == *ip)
+    }
+
+    pub fn device_index(&self, name: &str) -> Option<usize> {
+        self.router
+            .devices
+            .iter()
+            .position(|device| device.name() == name)
+    }
+
+    pub fn device_name(&self, dev: usize) -> &str {
+        self.router.devices[dev].name()
+    }
+
+    pub fn set_device_up(&mut self, dev: usize, up: bool) {
+        self.router.set_up(dev, up);
+    }
+
+    pub fn neighbors(&self, dev: usize) -> Vec<NeighborEntry> {
+        self.router.devices[dev].neighbors()
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        self.router.table.rules()
+    }
+
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.router.add_rule(rule);
+    }
+
+    /// Removes the rules matching `f`, returning whether there were any.
+    pub fn remove_rules(&mut self, f: impl Fn(&Rule) -> bool) -> bool {
+        let before = self.router.table.rules().len();
+        self.router.remove_rules(f);
+        self.router.table.rules().len() != before
+    }
+
+    pub fn interfaces(&self) -> Vec<InterfaceInfo> {
+        self.router
+            .devices
+            .iter()
+            .enumerate()
+            .map(|(index, device)| InterfaceInfo {
+                index,
+                name: device.name().to_string(),
+                mac: device.mac_address().map(|mac| mac.0),
+                addrs: self
+                    .addrs
+                    .iter()
+                    .filter(|(dev, _)| *dev == index)
+                    .map(|(_, ip)| (*ip).into())
+                    .collect(),
+                up: self.router.is_up(index),
+                stats: device.stats(),
+            })
+            .collect()
     }
 
     pub fn poll(&mut self, sockets: &mut SocketSet) -> bool {
         let timestamp = now();
 
         self.router.poll(timestamp);
+        self.install_autoconf(timestamp);
         self.iface.poll(timestamp, &mut self.router, sockets);
         self.router.dispatch(timestamp)
     }
 
-    pub fn get_source_address(&self, dst_addr: &IpAddress) -> IpAddress {
-        let Some(rule) = self.router.table.lookup(dst_addr) else {
-            panic!("no route to destination: {dst_addr}");
+    /// Replaces the IPv4 address of the device at index `dev`.
+    ///
+    /// The address and routes installed for `old` are removed. If `new` is
+    /// given, it is added with a default route through `gateway` if there is
+    /// one.
+    pub fn set_ipv4_config(
+        &mut self,
+        dev: usize,
+        old: Option<Ipv4Cidr>,
+        new: Option<Ipv4Cidr>,
+        gateway: Option<Ipv4Address>,
+    ) {
+        if let Some(old) = old {
+            let _ = self.remove_address(dev, old.into());
+        }
+        let Some(ip) = new else {
+            return;
         };
-        rule.src
+        if self.add_address(dev, ip.into()).is_err() {
+            return;
+        }
+        if let Some(gateway) = gateway {
+            self.router.add_rule(Rule::new(
+                Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0).into(),
+                Some(gateway.into()),
+                dev,
+                ip.address().into(),
+            ));
+        }
+    }
+
+    /// Adds an IPv6 address to the device at index `dev`, with a default route
+    /// through `gateway` if there is one.
+    pub fn add_ipv6_addr(&mut self, dev: usize, ip: Ipv6Cidr, gateway: Option<Ipv6Address>) {
+        if self.add_address(dev, ip.into()).is_err() {
+            return;
+        }
+        if let Some(gateway) = gateway {
+            self.router.add_rule(Rule::new(
+                Ipv6Cidr::new(Ipv6Address::UNSPECIFIED, 0).into(),
+                Some(gateway.into()),
+                dev,
+                ip.address().into(),
+            ));
+        }
+    }
+
+    fn install_autoconf(&mut self, now: Instant) {
+        for dev in 0..self.router.devices.len() {
+            while let Some(conf) = self.router.devices[dev].poll_autoconf() {
+                let new_ip = conf
+                    .ip
+                    .filter(|ip| !self.has_address(dev, &ip.address().into()));
+                if let Some(ip) = new_ip {
+                    info!("IPv6 address autoconfigured: {}", ip);
+                    self.add_ipv6_addr(dev, ip, None);
+                }
+                self.update_default_router(dev, conf.router, conf.router_lifetime, now);
+            }
+        }
+
+        let mut expired = Vec::new();
+        self.default_routers.retain(|&(dev, router, expires_at)| {
+            if expires_at > now {
+                return true;
+            }
+            expired.push((dev, router));
+            false
+        });
+        for (dev, router) in expired {
+            info!("IPv6 default router {} expired", router);
+            self.remove_default_route(dev, router);
+        }
+    }
+
+    /// Records that `router` on the device at index `dev` is a default router
+    /// for `lifetime`, or no longer one if `lifetime` is zero.
+    fn update_default_router(
+        &mut self,
+        dev: usize,
+        router: Ipv6Address,
+        lifetime: Duration,
+        now: Instant,
+    ) {
+        let index = self
+            .default_routers
+            .iter()
+            .position(|&(it_dev, it, _)| it_dev == dev && it == router);
+        match index {
+            Some(index) if lifetime == Duration::ZERO => {
+                self.default_routers.remove(index);
+                self.remove_default_route(dev, router);
+            }
+            Some(index) => self.default_routers[index].2 = now + lifetime,
+            None if lifetime == Duration::ZERO => {}
+            None => {
+                // Reach the router from a global address, if there is one.
+                let Some(src) = self
+                    .addrs
+                    .iter()
+                    .filter(|(it, _)| *it == dev)
+                    .filter_map(|(_, ip)| match ip {
+                        IpCidr::Ipv6(ip) => Some(ip.address()),
+                        _ => None,
+                    })
+                    .min_by_key(|ip| ip.is_unicast_link_local())
+                else {
+                    return;
+                };
+                self.router.add_rule(Rule::new(
+                    Ipv6Cidr::new(Ipv6Address::UNSPECIFIED, 0).into(),
+                    Some(router.into()),
+                    dev,
+                    src.into(),
+                ));
+                self.default_routers.push((dev, router, now + lifetime));
+            }
+        }
+    }
+
+    fn remove_default_route(&mut self, dev: usize, router: Ipv6Address) {
+        let default = IpCidr::from(Ipv6Cidr::new(Ipv6Address::UNSPECIFIED, 0));
+        let via = Some(IpAddress::from(router));
+        self.router
+            .remove_rules(|rule| rule.dev == dev && rule.filter == default && rule.via == via);
+    }
+
+    pub fn get_source_address(&self, dst_addr: &IpAddress) -> AxResult<IpAddress> {
+        let Some(rule) = self.router.lookup(dst_addr) else {
+            warn!("no route to destination: {dst_addr}");
+            return Err(AxError::Other(LinuxError::ENETUNREACH));
+        };
+        Ok(rule.src)
     }
 
     pub fn device_mask_for(&self, endpoint: &IpListenEndpoint) -> u32 {
         match endpoint.addr {
-            Some(addr) => self
-                .router
-                .table
-                .lookup(&addr)
-                .map_or(0, |it| 1u32 << it.dev),
+            Some(addr) => self.router.lookup(&addr).map_or(0, |it| 1u32 << it.dev),
             None => u32::MAX,
         }
     }
@@ -73,8 +337,8 @@ impl Service {
                 if let Some(next) = next {
                     let mut cx = Context::from_waker(waker);
                     let deadline = Duration::from_micros(next.total_micros() as _);
-                    // warn!("WAIT {:?}", deadline .checked_sub(wall_time()));
-                    let _ = pin!(sleep_until(deadline)).poll(&mut cx);
+                    // warn!("WAIT {:?}", deadline .checked_sub(monotonic_time()));
+                    let _ = pin!(sleep_until_monotonic(deadline)).poll(&mut cx);
                 } else {
                     // warn!("WAIT INDEF");
                 }
@@ -82,3 +346,59 @@ impl Service {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::device::LoopbackDevice;
+
+    fn default_routes(service: &Service) -> Vec<IpAddress> {
+        service
+            .rules()
+            .iter()
+            .filter(|rule| rule.filter.prefix_len() == 0)
+            .filter_map(|rule| rule.via)
+            .collect()
+    }
+
+    #[test]
+    fn default_router_expires() {
+        let mut router = Router::new();
+        let dev = router.add_device(Box::new(LoopbackDevice::new()));
+        let mut service = Service::new(router);
+        let ip = Ipv6Cidr::new("2001:db8::1".parse().unwrap(), 64);
+        service.add_ipv6_addr(dev, ip, None);
+
+        let gateway: Ipv6Address = "fe80::1".parse().unwrap();
+        let start = Instant::from_secs(100);
+        service.update_default_router(dev, gateway, Duration::from_secs(30), start);
+        assert_eq!(default_routes(&service), [IpAddress::from(gateway)]);
+        assert_eq!(service.rules().last().unwrap().src, ip.address().into());
+
+        // A later advertisement refreshes the lifetime.
+        let refreshed = start + Duration::from_secs(20);
+        service.update_default_router(dev, gateway, Duration::from_secs(30), refreshed);
+        service.install_autoconf(start + Duration::from_secs(40));
+        assert_eq!(default_routes(&service), [IpAddress::from(gateway)]);
+
+        service.install_autoconf(refreshed + Duration::from_secs(30));
+        assert!(default_routes(&service).is_empty());
+    }
+
+    #[test]
+    fn default_router_withdrawn() {
+        let mut router = Router::new();
+        let dev = router.add_device(Box::new(LoopbackDevice::new()));
+        let mut service = Service::new(router);
+        service.add_ipv6_addr(dev, Ipv6Cidr::new("2001:db8::1".parse().unwrap(), 64), None);
+
+        let gateway: Ipv6Address = "fe80::1".parse().unwrap();
+        let now = Instant::from_secs(100);
+        service.update_default_router(dev, gateway, Duration::from_secs(30), now);
+        // A zero router lifetime means the router is no longer a default one.
+        service.update_default_router(dev, gateway, Duration::ZERO, now);
+        assert!(default_routes(&service).is_empty());
+    }
+}
@@ -2,7 +2,7 @@ use alloc::{boxed::Box, vec::Vec};
 use core::{
     any::Any,
     fmt::{self, Debug},
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     task::Context,
 };
 
@@ -13,7 +13,9 @@ use bitflags::bitflags;
 use enum_dispatch::enum_dispatch;
 
 use crate::{
+    icmp::IcmpSocket,
     options::{Configurable, GetSocketOption, SetSocketOption},
+    raw::RawSocket,
     tcp::TcpSocket,
     udp::UdpSocket,
     unix::{UnixSocket, UnixSocketAddr},
@@ -59,6 +61,21 @@ impl SocketAddrEx {
     }
 }
 
+/// Returns `addr` with an IPv4-mapped IPv6 address converted to the IPv4
+/// address it maps, which is what routing and the interfaces work with.
+pub(crate) fn routable_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// Returns `addr` as reported by a socket, with IPv4 addresses mapped to IPv6
+/// ones if the socket uses IPv6 addresses.
+pub(crate) fn socket_family_addr(addr: SocketAddr, ipv6: bool) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ipv6 => SocketAddr::new(ip.to_ipv6_mapped().into(), addr.port()),
+        _ => addr,
+    }
+}
+
 bitflags! {
     /// Flags for sending data to a socket.
     ///
@@ -166,6 +183,8 @@ pub trait SocketOps: Configurable {
 pub enum Socket {
     Udp(UdpSocket),
     Tcp(TcpSocket),
+    Icmp(IcmpSocket),
+    Raw(RawSocket),
     Unix(UnixSocket),
     #[cfg(feature = "vsock")]
     Vsock(VsockSocket),
@@ -176,6 +195,8 @@ impl Pollable for Socket {
         match self {
             Socket::Tcp(tcp) => tcp.poll(),
             Socket::Udp(udp) => udp.poll(),
+            Socket::Icmp(icmp) => icmp.poll(),
+            Socket::Raw(raw) => raw.poll(),
             Socket::Unix(unix) => unix.poll(),
             #[cfg(feature = "vsock")]
             Socket::Vsock(vsock) => vsock.poll(),
@@ -186,6 +207,8 @@ impl Pollable for Socket {
         match self {
             Socket::Tcp(tcp) => tcp.register(context, events),
             Socket::Udp(udp) => udp.register(context, events),
+            Socket::Icmp(icmp) => icmp.register(context, events),
+            Socket::Raw(raw) => raw.register(context, events),
             Socket::Unix(unix) => unix.register(context, events),
             #[cfg(feature = "vsock")]
             Socket::Vsock(vsock) => vsock.register(context, events),
@@ -0,0 +1,365 @@
+//! Network statistics.
+//!
+//! Besides the per-interface counters (see [`LinkStats`]), the IP packets
+//! going through the router are accounted per protocol, like in Linux's
+//! `/proc/net/snmp`, and TCP segments are tracked per connection to fill
+//! [`TcpInfo`].
+
+use core::time::Duration;
+
+use axhal::time::monotonic_time;
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use smoltcp::wire::{
+    IpAddress, IpEndpoint, IpProtocol, IpVersion, Ipv4Packet, Ipv6Packet, TcpOption, TcpPacket,
+    TcpSeqNumber,
+};
+use spin::Mutex;
+
+pub use crate::device::LinkStats;
+use crate::{consts::TCP_CONN_TABLE_SIZE, device::Direction, options::TcpInfo};
+
+/// IP-level counters.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IpStats {
+    /// Packets received from the devices.
+    pub in_receives: u64,
+    /// Packets sent to the devices.
+    pub out_requests: u64,
+    /// Packets dropped because there was no route to their destination.
+    pub out_no_routes: u64,
+}
+
+/// ICMP counters, for ICMPv4 or ICMPv6.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IcmpStats {
+    pub in_msgs: u64,
+    pub in_dest_unreachs: u64,
+    pub in_echos: u64,
+    pub in_echo_reps: u64,
+    pub out_msgs: u64,
+    pub out_dest_unreachs: u64,
+    pub out_echos: u64,
+    pub out_echo_reps: u64,
+}
+
+/// TCP counters.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TcpStats {
+    /// Connections initiated locally.
+    pub active_opens: u64,
+    /// Connections accepted from a peer.
+    pub passive_opens: u64,
+    pub in_segs: u64,
+    pub out_segs: u64,
+    pub retrans_segs: u64,
+    pub in_rsts: u64,
+    pub out_rsts: u64,
+}
+
+/// UDP counters.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UdpStats {
+    pub in_datagrams: u64,
+    pub out_datagrams: u64,
+}
+
+/// Per-protocol counters, see [`protocol_stats`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProtocolStats {
+    pub ipv4: IpStats,
+    pub ipv6: IpStats,
+    pub icmp: IcmpStats,
+    pub icmp6: IcmpStats,
+    pub tcp: TcpStats,
+    pub udp: UdpStats,
+}
+
+/// TCP segments seen on a connection.
+#[derive(Default)]
+struct Connection {
+    bytes_sent: u64,
+    bytes_received: u64,
+    segs_out: u64,
+    segs_in: u64,
+    retransmits: u32,
+    /// End of the highest segment sent.
+    snd_max: Option<TcpSeqNumber>,
+    /// Segment being timed for RTT measurement, with the time it was sent.
+    rtt_probe: Option<(TcpSeqNumber, Duration)>,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    /// Window advertised by the peer, in bytes.
+    snd_wnd: u32,
+    /// Window scale advertised by the peer in its SYN.
+    snd_wscale: u8,
+    last_seen: Duration,
+}
+
+impl Connection {
+    /// Updates the RTT estimate as in RFC 6298.
+    fn sample_rtt(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+    }
+
+    fn on_send(&mut self, packet: &TcpPacket<&[u8]>, payload_len: usize, now: Duration) {
+        self.segs_out += 1;
+        let seg_len = packet.segment_len();
+        if seg_len == 0 {
+            return;
+        }
+        let end = packet.seq_number() + seg_len;
+        if self.snd_max.is_some_and(|snd_max| end <= snd_max) {
+            self.retransmits += 1;
+            // Karn's algorithm: do not time retransmitted segments.
+            self.rtt_probe = None;
+            return;
+        }
+        self.bytes_sent += payload_len as u64;
+        self.snd_max = Some(end);
+        if self.rtt_probe.is_none() {
+            self.rtt_probe = Some((end, now));
+        }
+    }
+
+    fn on_recv(&mut self, packet: &TcpPacket<&[u8]>, payload_len: usize, now: Duration) {
+        self.segs_in += 1;
+        self.bytes_received += payload_len as u64;
+        if packet.syn() {
+            self.snd_wscale = window_scale(packet).unwrap_or(0);
+        }
+        if !packet.ack() {
+            return;
+        }
+        // The window of SYN segments is never scaled.
+        let scale = if packet.syn() { 0 } else { self.snd_wscale };
+        self.snd_wnd = (packet.window_len() as u32) << scale;
+        if let Some((seq, sent_at)) = self.rtt_probe {
+            if packet.ack_number() >= seq {
+                self.sample_rtt(now.saturating_sub(sent_at));
+                self.rtt_probe = None;
+            }
+        }
+    }
+}
+
+fn window_scale(packet: &TcpPacket<&[u8]>) -> Option<u8> {
+    let mut options = packet.options();
+    while !options.is_empty() {
+        let (rest, option) = TcpOption::parse(options).ok()?;
+        match option {
+            TcpOption::EndOfList => break,
+            TcpOption::WindowScale(scale) => return Some(scale.min(14)),
+            _ => {}
+        }
+        options = rest;
+    }
+    None
+}
+
+type ConnectionTable = HashMap<(IpEndpoint, IpEndpoint), Connection>;
+
+lazy_static! {
+    static ref PROTOCOL_STATS: Mutex<ProtocolStats> = Mutex::new(ProtocolStats::default());
+    /// TCP connections, by local and remote endpoint.
+    static ref CONNECTIONS: Mutex<ConnectionTable> = Mutex::new(HashMap::new());
+}
+
+/// Accounts an IP packet going through the router.
+pub(crate) fn account_packet(direction: Direction, packet: &[u8]) {
+    let Ok(version) = IpVersion::of_packet(packet) else {
+        return;
+    };
+    let (protocol, src_addr, dst_addr, payload) = match version {
+        IpVersion::Ipv4 => {
+            let Ok(packet) = Ipv4Packet::new_checked(packet) else {
+                return;
+            };
+            (
+                packet.next_header(),
+                IpAddress::Ipv4(packet.src_addr()),
+                IpAddress::Ipv4(packet.dst_addr()),
+                packet.payload(),
+            )
+        }
+        IpVersion::Ipv6 => {
+            let Ok(packet) = Ipv6Packet::new_checked(packet) else {
+                return;
+            };
+            (
+                packet.next_header(),
+                IpAddress::Ipv6(packet.src_addr()),
+                IpAddress::Ipv6(packet.dst_addr()),
+                packet.payload(),
+            )
+        }
+    };
+
+    let mut stats = PROTOCOL_STATS.lock();
+    let ip = match version {
+        IpVersion::Ipv4 => &mut stats.ipv4,
+        IpVersion::Ipv6 => &mut stats.ipv6,
+    };
+    match direction {
+        Direction::Rx => ip.in_receives += 1,
+        Direction::Tx => ip.out_requests += 1,
+    }
+
+    match protocol {
+        IpProtocol::Icmp | IpProtocol::Icmpv6 => {
+            let (icmp, dest_unreach, echo, echo_reply) = match version {
+                IpVersion::Ipv4 => (&mut stats.icmp, 3, 8, 0),
+                IpVersion::Ipv6 => (&mut stats.icmp6, 1, 128, 129),
+            };
+            let Some(&msg_type) = payload.first() else {
+                return;
+            };
+            let (msgs, dest_unreachs, echos, echo_reps) = match direction {
+                Direction::Rx => (
+                    &mut icmp.in_msgs,
+                    &mut icmp.in_dest_unreachs,
+                    &mut icmp.in_echos,
+                    &mut icmp.in_echo_reps,
+                ),
+                Direction::Tx => (
+                    &mut icmp.out_msgs,
+                    &mut icmp.out_dest_unreachs,
+                    &mut icmp.out_echos,
+                    &mut icmp.out_echo_reps,
+                ),
+            };
+            *msgs += 1;
+            match msg_type {
+                t if t == dest_unreach => *dest_unreachs += 1,
+                t if t == echo => *echos += 1,
+                t if t == echo_reply => *echo_reps += 1,
+                _ => {}
+            }
+        }
+        IpProtocol::Udp => match direction {
+            Direction::Rx => stats.udp.in_datagrams += 1,
+            Direction::Tx => stats.udp.out_datagrams += 1,
+        },
+        IpProtocol::Tcp => {
+            let Ok(tcp) = TcpPacket::new_checked(payload) else {
+                return;
+            };
+            let payload_len = tcp.payload().len();
+            let src = IpEndpoint::new(src_addr, tcp.src_port());
+            let dst = IpEndpoint::new(dst_addr, tcp.dst_port());
+            let now = monotonic_time();
+
+            let key = match direction {
+                Direction::Rx => {
+                    stats.tcp.in_segs += 1;
+                    stats.tcp.in_rsts += tcp.rst() as u64;
+                    (dst, src)
+                }
+                Direction::Tx => {
+                    stats.tcp.out_segs += 1;
+                    stats.tcp.out_rsts += tcp.rst() as u64;
+                    if tcp.syn() {
+                        if tcp.ack() {
+                            stats.tcp.passive_opens += 1;
+                        } else {
+                            stats.tcp.active_opens += 1;
+                        }
+                    }
+                    (src, dst)
+                }
+            };
+            drop(stats);
+
+            let mut connections = CONNECTIONS.lock();
+            if !connections.contains_key(&key) {
+                if tcp.rst() {
+                    return;
+                }
+                evict_oldest(&mut connections);
+            }
+            let conn = connections.entry(key).or_default();
+            conn.last_seen = now;
+            match direction {
+                Direction::Rx => conn.on_recv(&tcp, payload_len, now),
+                Direction::Tx => {
+                    let retransmits = conn.retransmits;
+                    conn.on_send(&tcp, payload_len, now);
+                    if conn.retransmits != retransmits {
+                        PROTOCOL_STATS.lock().tcp.retrans_segs += 1;
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Accounts a packet dropped for lack of route.
+pub(crate) fn account_no_route(version: IpVersion) {
+    let mut stats = PROTOCOL_STATS.lock();
+    match version {
+        IpVersion::Ipv4 => stats.ipv4.out_no_routes += 1,
+        IpVersion::Ipv6 => stats.ipv6.out_no_routes += 1,
+    }
+}
+
+fn evict_oldest(connections: &mut ConnectionTable) {
+    if connections.len() < TCP_CONN_TABLE_SIZE {
+        return;
+    }
+    let oldest = connections
+        .iter()
+        .min_by_key(|(_, conn)| conn.last_seen)
+        .map(|(key, _)| *key);
+    if let Some(key) = oldest {
+        connections.remove(&key);
+    }
+}
+
+/// Fills the fields of `info` tracked for the connection from `local` to
+/// `remote`.
+pub(crate) fn fill_tcp_info(local: IpEndpoint, remote: IpEndpoint, info: &mut TcpInfo) {
+    let connections = CONNECTIONS.lock();
+    let Some(conn) = connections.get(&(local, remote)) else {
+        return;
+    };
+    info.rtt = conn.srtt.unwrap_or_default();
+    info.rttvar = conn.rttvar;
+    info.snd_wnd = conn.snd_wnd;
+    // Congestion control is disabled, so the send window is only limited by
+    // the window of the peer.
+    info.snd_cwnd = conn.snd_wnd;
+    info.total_retrans = conn.retransmits;
+    info.bytes_sent = conn.bytes_sent;
+    info.bytes_received = conn.bytes_received;
+    info.segs_out = conn.segs_out;
+    info.segs_in = conn.segs_in;
+}
+
+/// Forgets the connection from `local` to `remote`.
+pub(crate) fn remove_tcp_connection(local: IpEndpoint, remote: IpEndpoint) {
+    CONNECTIONS.lock().remove(&(local, remote));
+}
+
+/// Returns the per-protocol counters.
+pub fn protocol_stats() -> ProtocolStats {
+    *PROTOCOL_STATS.lock()
+}
+
+/// Returns the counters of the interface named `iface`.
+pub fn interface_stats(iface: &str) -> Option<LinkStats> {
+    crate::iface::interfaces()
+        .into_iter()
+        .find(|info| info.name == iface)
+        .map(|info| info.stats)
+}
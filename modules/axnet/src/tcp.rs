@@ -1,6 +1,6 @@
 use alloc::vec;
 use core::{
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     sync::atomic::{AtomicBool, Ordering},
     task::Context,
 };
@@ -21,9 +21,11 @@ use crate::{
     RecvFlags, RecvOptions, SERVICE, SendOptions, Shutdown, Socket, SocketAddrEx, SocketOps,
     consts::{TCP_RX_BUF_LEN, TCP_TX_BUF_LEN},
     general::GeneralOptions,
-    options::{Configurable, GetSocketOption, SetSocketOption},
+    options::{Configurable, GetSocketOption, SetSocketOption, TcpInfo},
     poll_interfaces,
+    socket::{routable_addr, socket_family_addr},
     state::*,
+    stats,
 };
 
 pub(crate) fn new_tcp_socket() -> smol::Socket<'static> {
@@ -41,6 +43,8 @@ pub struct TcpSocket {
     general: GeneralOptions,
     rx_closed: AtomicBool,
     poll_rx_closed: PollSet,
+    /// Whether the socket was bound or connected with an IPv6 address.
+    ipv6: AtomicBool,
 }
 
 unsafe impl Sync for TcpSocket {}
@@ -55,6 +59,7 @@ impl TcpSocket {
             general: GeneralOptions::new(),
             rx_closed: AtomicBool::new(false),
             poll_rx_closed: PollSet::new(),
+            ipv6: AtomicBool::new(false),
         }
     }
 
@@ -67,6 +72,7 @@ impl TcpSocket {
             general: GeneralOptions::new(),
             rx_closed: AtomicBool::new(false),
             poll_rx_closed: PollSet::new(),
+            ipv6: AtomicBool::new(false),
         };
         result.with_smol_socket(|socket| {
             result
@@ -92,6 +98,14 @@ impl TcpSocket {
         SOCKET_SET.with_socket_mut::<smol::Socket, _, _>(self.handle, f)
     }
 
+    fn unspecified_addr(&self) -> IpAddr {
+        if self.ipv6.load(Ordering::Relaxed) {
+            Ipv6Addr::UNSPECIFIED.into()
+        } else {
+            Ipv4Addr::UNSPECIFIED.into()
+        }
+    }
+
     fn bound_endpoint(&self) -> AxResult<IpListenEndpoint> {
         let endpoint = self.with_smol_socket(|socket| socket.get_bound_endpoint());
         if endpoint.port == 0 {
@@ -172,8 +186,22 @@ impl Configurable for TcpSocket {
             O::ReceiveBuffer(size) => {
                 **size = TCP_RX_BUF_LEN;
             }
-            O::TcpInfo(_) => {
-                // TODO(mivik): implement TCP_INFO
+            O::TcpInfo(info) => {
+                **info = TcpInfo::default();
+                if self.is_listening() {
+                    info.state = TCP_LISTEN;
+                    return Ok(true);
+                }
+                self.with_smol_socket(|socket| {
+                    info.state = linux_tcp_state(socket.state());
+                    info.unacked = socket.send_queue();
+                    info.unread = socket.recv_queue();
+                    if let (Some(local), Some(remote)) =
+                        (socket.local_endpoint(), socket.remote_endpoint())
+                    {
+                        stats::fill_tcp_info(local, remote, info);
+                    }
+                });
             }
             _ => return Ok(false),
         }
@@ -205,7 +233,9 @@ impl Configurable for TcpSocket {
 }
 impl SocketOps for TcpSocket {
     fn bind(&self, local_addr: SocketAddrEx) -> AxResult {
-        let mut local_addr = local_addr.into_ip()?;
+        let local_addr = local_addr.into_ip()?;
+        let ipv6 = local_addr.is_ipv6();
+        let mut local_addr = routable_addr(local_addr);
         self.state
             .lock(State::Idle)
             .map_err(|_| ax_err_type!(InvalidInput, "already bound"))?
@@ -235,6 +265,7 @@ impl SocketOps for TcpSocket {
                         .set_device_mask(SERVICE.lock().device_mask_for(&endpoint));
                     Ok(())
                 })?;
+                self.ipv6.store(ipv6, Ordering::Relaxed);
                 debug!("TCP socket {}: binding to {}", self.handle, local_addr);
                 Ok(())
             })
@@ -255,13 +286,14 @@ impl SocketOps for TcpSocket {
             .transit(State::Connecting, || {
                 // TODO: check remote addr unreachable
                 // let (bound_endpoint, remote_endpoint) = self.get_endpoint_pair(remote_addr)?;
-                let remote_endpoint = IpEndpoint::from(remote_addr);
+                let remote_endpoint = IpEndpoint::from(routable_addr(remote_addr));
                 let mut bound_endpoint =
                     self.with_smol_socket(|socket| socket.get_bound_endpoint());
                 if bound_endpoint.addr.is_none() {
                     bound_endpoint.addr =
-                        Some(SERVICE.lock().get_source_address(&remote_endpoint.addr));
+                        Some(SERVICE.lock().get_source_address(&remote_endpoint.addr)?);
                 }
+                self.ipv6.store(remote_addr.is_ipv6(), Ordering::Relaxed);
                 if bound_endpoint.port == 0 {
                     bound_endpoint.port = get_ephemeral_port()?;
                 }
@@ -333,6 +365,9 @@ impl SocketOps for TcpSocket {
             poll_interfaces();
             LISTEN_TABLE.accept(bound_port).map(|handle| {
                 let socket = TcpSocket::new_connected(handle);
+                socket
+                    .ipv6
+                    .store(self.ipv6.load(Ordering::Relaxed), Ordering::Relaxed);
                 debug!(
                     "accepted connection from {}, {}",
                     handle,
@@ -404,23 +439,29 @@ impl SocketOps for TcpSocket {
     fn local_addr(&self) -> AxResult<SocketAddrEx> {
         self.with_smol_socket(|socket| {
             let endpoint = socket.get_bound_endpoint();
-            Ok(SocketAddrEx::Ip(SocketAddr::new(
+            let addr = SocketAddr::new(
                 endpoint
                     .addr
-                    .map_or_else(|| Ipv4Addr::UNSPECIFIED.into(), Into::into),
+                    .map_or_else(|| self.unspecified_addr(), Into::into),
                 endpoint.port,
+            );
+            Ok(SocketAddrEx::Ip(socket_family_addr(
+                addr,
+                self.ipv6.load(Ordering::Relaxed),
             )))
         })
     }
 
     fn peer_addr(&self) -> AxResult<SocketAddrEx> {
         self.with_smol_socket(|socket| {
-            Ok(SocketAddrEx::Ip(
-                socket
-                    .remote_endpoint()
-                    .ok_or(AxError::NotConnected)?
-                    .into(),
-            ))
+            let addr = socket
+                .remote_endpoint()
+                .ok_or(AxError::NotConnected)?
+                .into();
+            Ok(SocketAddrEx::Ip(socket_family_addr(
+                addr,
+                self.ipv6.load(Ordering::Relaxed),
+            )))
         })
     }
 
@@ -484,6 +525,11 @@ impl Pollable for TcpSocket {
 
 impl Drop for TcpSocket {
     fn drop(&mut self) {
+        let endpoints =
+            self.with_smol_socket(|socket| (socket.local_endpoint(), socket.remote_endpoint()));
+        if let (Some(local), Some(remote)) = endpoints {
+            stats::remove_tcp_connection(local, remote);
+        }
         if let Err(err) = self.shutdown(Shutdown::Both) {
             warn!("TCP socket {}: shutdown failed: {}", self.handle, err);
         }
@@ -493,6 +539,25 @@ impl Drop for TcpSocket {
     }
 }
 
+const TCP_LISTEN: u8 = 10;
+
+/// Converts a smoltcp TCP state to the number of the Linux `TCP_*` state.
+fn linux_tcp_state(state: smol::State) -> u8 {
+    match state {
+        smol::State::Established => 1,
+        smol::State::SynSent => 2,
+        smol::State::SynReceived => 3,
+        smol::State::FinWait1 => 4,
+        smol::State::FinWait2 => 5,
+        smol::State::TimeWait => 6,
+        smol::State::Closed => 7,
+        smol::State::CloseWait => 8,
+        smol::State::LastAck => 9,
+        smol::State::Listen => TCP_LISTEN,
+        smol::State::Closing => 11,
+    }
+}
+
 fn get_ephemeral_port() -> AxResult<u16> {
     const PORT_START: u16 = 0xc000;
     const PORT_END: u16 = 0xffff;
@@ -1,13 +1,14 @@
 use alloc::vec;
 use core::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicBool, Ordering},
     task::Context,
 };
 
 use axerrno::{AxError, AxResult, ax_bail, ax_err_type};
 use axio::{Buf, BufMut};
 use axpoll::{IoEvents, Pollable};
-use axsync::Mutex;
+use axsync::{Mutex, RwLock};
 use smoltcp::{
     iface::SocketHandle,
     phy::PacketMeta,
@@ -15,7 +16,6 @@ use smoltcp::{
     storage::PacketMetadata,
     wire::{IpAddress, IpEndpoint, IpListenEndpoint},
 };
-use spin::RwLock;
 
 use crate::{
     RecvFlags, RecvOptions, SERVICE, SOCKET_SET, SendOptions, Shutdown, SocketAddrEx, SocketOps,
@@ -23,6 +23,7 @@ use crate::{
     general::GeneralOptions,
     options::{Configurable, GetSocketOption, SetSocketOption},
     poll_interfaces,
+    socket::{routable_addr, socket_family_addr},
 };
 
 pub(crate) fn new_udp_socket() -> smol::Socket<'static> {
@@ -38,6 +39,8 @@ pub struct UdpSocket {
     handle: SocketHandle,
     local_addr: RwLock<Option<IpEndpoint>>,
     peer_addr: RwLock<Option<(IpEndpoint, IpAddress)>>,
+    /// Whether the socket was bound with an IPv6 address.
+    ipv6: AtomicBool,
 
     general: GeneralOptions,
 }
@@ -53,6 +56,7 @@ impl UdpSocket {
             handle,
             local_addr: RwLock::new(None),
             peer_addr: RwLock::new(None),
+            ipv6: AtomicBool::new(false),
 
             general: GeneralOptions::new(),
         }
@@ -62,6 +66,27 @@ impl UdpSocket {
         SOCKET_SET.with_socket_mut::<smol::Socket, _, _>(self.handle, f)
     }
 
+    /// Binds to an ephemeral port on the unspecified address of the same
+    /// family as `remote`, if not bound yet.
+    fn bind_implicitly(&self, remote: &SocketAddr) -> AxResult {
+        if self.local_addr.read().is_some() {
+            return Ok(());
+        }
+        let ip = match remote {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        self.bind(SocketAddrEx::Ip(SocketAddr::new(ip, 0)))
+    }
+
+    /// Returns `endpoint` as reported to the user of the socket.
+    fn user_addr(&self, endpoint: IpEndpoint) -> SocketAddrEx {
+        SocketAddrEx::Ip(socket_family_addr(
+            endpoint.into(),
+            self.ipv6.load(Ordering::Relaxed),
+        ))
+    }
+
     fn remote_endpoint(&self) -> AxResult<(IpEndpoint, IpAddress)> {
         match self.peer_addr.try_read() {
             Some(addr) => addr.ok_or(AxError::NotConnected),
@@ -113,7 +138,9 @@ impl Configurable for UdpSocket {
 }
 impl SocketOps for UdpSocket {
     fn bind(&self, local_addr: SocketAddrEx) -> AxResult {
-        let mut local_addr = local_addr.into_ip()?;
+        let local_addr = local_addr.into_ip()?;
+        let ipv6 = local_addr.is_ipv6();
+        let mut local_addr = routable_addr(local_addr);
         let mut guard = self.local_addr.write();
 
         if local_addr.port() == 0 {
@@ -144,6 +171,7 @@ impl SocketOps for UdpSocket {
             .set_device_mask(SERVICE.lock().device_mask_for(&endpoint));
 
         *guard = Some(local_endpoint);
+        self.ipv6.store(ipv6, Ordering::Relaxed);
         info!("UDP socket {}: bound on {}", self.handle, endpoint);
         Ok(())
     }
@@ -151,15 +179,10 @@ impl SocketOps for UdpSocket {
     fn connect(&self, remote_addr: SocketAddrEx) -> AxResult {
         let remote_addr = remote_addr.into_ip()?;
         let mut guard = self.peer_addr.write();
-        if self.local_addr.read().is_none() {
-            self.bind(SocketAddrEx::Ip(SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-                0,
-            )))?;
-        }
+        self.bind_implicitly(&remote_addr)?;
+        let remote_addr = IpEndpoint::from(routable_addr(remote_addr));
 
-        let remote_addr = IpEndpoint::from(remote_addr);
-        let src = SERVICE.lock().get_source_address(&remote_addr.addr);
+        let src = SERVICE.lock().get_source_address(&remote_addr.addr)?;
         *guard = Some((remote_addr, src));
         debug!("UDP socket {}: connected to {}", self.handle, remote_addr);
         Ok(())
@@ -168,8 +191,10 @@ impl SocketOps for UdpSocket {
     fn send(&self, src: &mut impl Buf, options: SendOptions) -> AxResult<usize> {
         let (remote_addr, source_addr) = match options.to {
             Some(addr) => {
-                let addr = IpEndpoint::from(addr.into_ip()?);
-                let src = SERVICE.lock().get_source_address(&addr.addr);
+                let addr = addr.into_ip()?;
+                self.bind_implicitly(&addr)?;
+                let addr = IpEndpoint::from(routable_addr(addr));
+                let src = SERVICE.lock().get_source_address(&addr.addr)?;
                 (addr, src)
             }
             None => self.remote_endpoint()?,
@@ -178,12 +203,6 @@ impl SocketOps for UdpSocket {
             ax_bail!(InvalidInput, "invalid address");
         }
 
-        if self.local_addr.read().is_none() {
-            self.bind(SocketAddrEx::Ip(SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-                0,
-            )))?;
-        }
         self.general.send_poller(self).poll(|| {
             poll_interfaces();
             self.with_smol_socket(|socket| {
@@ -248,7 +267,7 @@ impl SocketOps for UdpSocket {
                         Ok((src, meta)) => {
                             match &mut expected_remote {
                                 ExpectedRemote::Any(remote_addr) => {
-                                    **remote_addr = SocketAddrEx::Ip(meta.endpoint.into());
+                                    **remote_addr = self.user_addr(meta.endpoint);
                                 }
                                 ExpectedRemote::Expecting(expected) => {
                                     if (!expected.addr.is_unspecified()
@@ -285,17 +304,14 @@ impl SocketOps for UdpSocket {
     fn local_addr(&self) -> AxResult<SocketAddrEx> {
         match self.local_addr.try_read() {
             Some(addr) => addr
-                .map(Into::into)
-                .map(SocketAddrEx::Ip)
+                .map(|addr| self.user_addr(addr))
                 .ok_or(AxError::NotConnected),
             None => Err(AxError::NotConnected),
         }
     }
 
     fn peer_addr(&self) -> AxResult<SocketAddrEx> {
-        self.remote_endpoint()
-            .map(|it| it.0.into())
-            .map(SocketAddrEx::Ip)
+        self.remote_endpoint().map(|it| self.user_addr(it.0))
     }
 
     fn shutdown(&self, _how: Shutdown) -> AxResult {
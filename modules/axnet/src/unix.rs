@@ -2,7 +2,10 @@ pub(crate) mod dgram;
 pub(crate) mod stream;
 
 use alloc::{boxed::Box, sync::Arc};
-use core::task::Context;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
 
 use async_trait::async_trait;
 use axerrno::{AxError, AxResult};
@@ -73,6 +76,11 @@ pub struct BindSlot {
     stream: Mutex<Option<stream::Bind>>,
     dgram: Mutex<Option<dgram::Bind>>,
 }
+impl BindSlot {
+    fn is_empty(&self) -> bool {
+        self.stream.lock().is_none() && self.dgram.lock().is_none()
+    }
+}
 
 lazy_static! {
     static ref ABSTRACT_BINDS: Mutex<HashMap<Arc<[u8]>, BindSlot>> = Mutex::new(HashMap::new());
@@ -113,7 +121,12 @@ fn with_slot_or_insert<R>(
         UnixSocketAddr::Unnamed => Err(AxError::InvalidInput),
         UnixSocketAddr::Abstract(name) => {
             let mut binds = ABSTRACT_BINDS.lock();
-            f(binds.entry(name.clone()).or_default())
+            let slot = binds.entry(name.clone()).or_default();
+            let result = f(slot);
+            if slot.is_empty() {
+                binds.remove(name);
+            }
+            result
         }
         UnixSocketAddr::Path(path) => {
             let loc = OpenOptions::new()
@@ -133,10 +146,20 @@ fn with_slot_or_insert<R>(
     }
 }
 
+/// Type of a Unix socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnixSocketKind {
+    Stream,
+    Dgram,
+}
+
 pub struct UnixSocket {
     transport: Transport,
     local_addr: Mutex<UnixSocketAddr>,
     remote_addr: Mutex<UnixSocketAddr>,
+    /// Whether this socket owns the binding of `local_addr`, as opposed to
+    /// sockets accepted from it.
+    bound: AtomicBool,
 }
 impl UnixSocket {
     pub fn new(transport: impl Into<Transport>) -> Self {
@@ -144,6 +167,24 @@ impl UnixSocket {
             transport: transport.into(),
             local_addr: Mutex::new(UnixSocketAddr::Unnamed),
             remote_addr: Mutex::new(UnixSocketAddr::Unnamed),
+            bound: AtomicBool::new(false),
+        }
+    }
+
+    /// Creates a pair of connected unnamed sockets, like `socketpair(2)`.
+    ///
+    /// `pid` is the process creating the sockets, which both ends report as
+    /// their peer.
+    pub fn pair(kind: UnixSocketKind, pid: u32) -> (Self, Self) {
+        match kind {
+            UnixSocketKind::Stream => {
+                let (a, b) = StreamTransport::new_pair(pid);
+                (Self::new(a), Self::new(b))
+            }
+            UnixSocketKind::Dgram => {
+                let (a, b) = DgramTransport::new_pair(pid);
+                (Self::new(a), Self::new(b))
+            }
         }
     }
 }
@@ -163,6 +204,7 @@ impl SocketOps for UnixSocket {
         if matches!(&*guard, UnixSocketAddr::Unnamed) {
             with_slot_or_insert(&local_addr, |slot| self.transport.bind(slot, &local_addr))?;
             *guard = local_addr;
+            self.bound.store(true, Ordering::Release);
         } else {
             return Err(AxError::InvalidInput);
         }
@@ -194,6 +236,7 @@ impl SocketOps for UnixSocket {
             transport,
             local_addr: Mutex::new(self.local_addr.lock().clone()),
             remote_addr: Mutex::new(peer_addr),
+            bound: AtomicBool::new(false),
         }))
     }
 
@@ -227,3 +270,213 @@ impl Pollable for UnixSocket {
         self.transport.register(context, events);
     }
 }
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if !self.bound.load(Ordering::Acquire) {
+            return;
+        }
+        // Abstract names vanish with the last socket bound to them.
+        if let UnixSocketAddr::Abstract(name) = &*self.local_addr.lock() {
+            let mut binds = ABSTRACT_BINDS.lock();
+            if let Some(slot) = binds.get(name) {
+                match &self.transport {
+                    Transport::Stream(_) => *slot.stream.lock() = None,
+                    Transport::Dgram(_) => *slot.dgram.lock() = None,
+                }
+                if slot.is_empty() {
+                    binds.remove(name);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use super::*;
+    use crate::{CMsgData, options::UnixCredentials};
+
+    static INIT: Once = Once::new();
+
+    fn abstract_addr(name: &str) -> SocketAddrEx {
+        SocketAddrEx::Unix(UnixSocketAddr::Abstract(name.as_bytes().into()))
+    }
+
+    fn peer_pid(socket: &UnixSocket) -> u32 {
+        let mut cred = UnixCredentials::default();
+        socket
+            .get_option(GetSocketOption::PeerCredentials(&mut cred))
+            .unwrap();
+        cred.pid
+    }
+
+    fn set_nonblocking(socket: &UnixSocket) {
+        socket
+            .set_option(SetSocketOption::NonBlocking(&true))
+            .unwrap();
+    }
+
+    fn roundtrip(a: &UnixSocket, b: &UnixSocket, msg: &[u8]) {
+        assert_eq!(
+            a.send(&mut &msg[..], SendOptions::default()).unwrap(),
+            msg.len()
+        );
+        let mut buf = [0u8; 64];
+        let n = b.recv(&mut &mut buf[..], RecvOptions::default()).unwrap();
+        assert_eq!(&buf[..n], msg);
+    }
+
+    fn send_with(socket: &UnixSocket, msg: &[u8], cmsg: Vec<CMsgData>) {
+        let options = SendOptions {
+            cmsg,
+            ..Default::default()
+        };
+        assert_eq!(socket.send(&mut &msg[..], options).unwrap(), msg.len());
+    }
+
+    fn recv_with(socket: &UnixSocket, buf: &mut [u8]) -> (usize, Vec<CMsgData>) {
+        let mut cmsg = Vec::new();
+        let options = RecvOptions {
+            cmsg: Some(&mut cmsg),
+            ..Default::default()
+        };
+        let n = socket.recv(&mut &mut buf[..], options).unwrap();
+        (n, cmsg)
+    }
+
+    #[test]
+    fn stream_pair() {
+        INIT.call_once(axtask::init_scheduler);
+
+        let (a, b) = UnixSocket::pair(UnixSocketKind::Stream, 42);
+        roundtrip(&a, &b, b"ping");
+        roundtrip(&b, &a, b"pong");
+        assert_eq!(peer_pid(&a), 42);
+        assert_eq!(peer_pid(&b), 42);
+    }
+
+    #[test]
+    fn dgram_pair() {
+        INIT.call_once(axtask::init_scheduler);
+
+        let (a, b) = UnixSocket::pair(UnixSocketKind::Dgram, 7);
+        roundtrip(&a, &b, b"hello");
+        roundtrip(&b, &a, b"world");
+        assert_eq!(peer_pid(&a), 7);
+        assert_eq!(peer_pid(&b), 7);
+
+        set_nonblocking(&b);
+        let mut buf = [0u8; 8];
+        assert!(matches!(
+            b.recv(&mut &mut buf[..], RecvOptions::default()),
+            Err(AxError::WouldBlock)
+        ));
+    }
+
+    #[test]
+    fn stream_cmsg_boundary() {
+        INIT.call_once(axtask::init_scheduler);
+
+        let (a, b) = UnixSocket::pair(UnixSocketKind::Stream, 1);
+        send_with(&a, b"first", Vec::new());
+        send_with(&a, b"second", vec![Box::new(7u32)]);
+
+        // The first read stops short of the bytes sent with ancillary data.
+        let mut buf = [0u8; 64];
+        let (n, cmsg) = recv_with(&b, &mut buf);
+        assert_eq!(&buf[..n], b"first");
+        assert!(cmsg.is_empty());
+
+        // The ancillary data comes with the first of its bytes only.
+        let (n, cmsg) = recv_with(&b, &mut buf[..3]);
+        assert_eq!(&buf[..n], b"sec");
+        assert_eq!(cmsg.len(), 1);
+        assert_eq!(cmsg[0].downcast_ref::<u32>(), Some(&7));
+        let (n, cmsg) = recv_with(&b, &mut buf);
+        assert_eq!(&buf[..n], b"ond");
+        assert!(cmsg.is_empty());
+    }
+
+    #[test]
+    fn stream_pass_object() {
+        INIT.call_once(axtask::init_scheduler);
+
+        let (a, b) = UnixSocket::pair(UnixSocketKind::Stream, 1);
+        let object = Arc::new(42u32);
+        send_with(&a, b"x", vec![Box::new(object.clone())]);
+
+        let mut buf = [0u8; 8];
+        let (n, mut cmsg) = recv_with(&b, &mut buf);
+        assert_eq!(&buf[..n], b"x");
+        assert_eq!(cmsg.len(), 1);
+        let received = cmsg.pop().unwrap().downcast::<Arc<u32>>().unwrap();
+        assert!(Arc::ptr_eq(&received, &object));
+        drop(received);
+        assert_eq!(Arc::strong_count(&object), 1);
+    }
+
+    #[test]
+    fn stream_peer_credentials() {
+        INIT.call_once(axtask::init_scheduler);
+
+        let addr = abstract_addr("test-peercred");
+        let server = UnixSocket::new(StreamTransport::new(1));
+        server.bind(addr.clone()).unwrap();
+        server.listen().unwrap();
+
+        let client = UnixSocket::new(StreamTransport::new(2));
+        client.connect(addr).unwrap();
+        let accepted = server.accept().unwrap();
+
+        assert_eq!(peer_pid(&client), 1);
+        let Socket::Unix(accepted) = accepted else {
+            panic!("accepted a non-Unix socket");
+        };
+        assert_eq!(peer_pid(&accepted), 2);
+        roundtrip(&client, &accepted, b"request");
+        roundtrip(&accepted, &client, b"response");
+    }
+
+    #[test]
+    fn abstract_name_released_on_drop() {
+        INIT.call_once(axtask::init_scheduler);
+
+        let addr = abstract_addr("test-release");
+        let server = UnixSocket::new(StreamTransport::new(1));
+        server.bind(addr.clone()).unwrap();
+
+        // The name is taken while the socket lives.
+        let other = UnixSocket::new(StreamTransport::new(1));
+        assert!(matches!(other.bind(addr.clone()), Err(AxError::AddrInUse)));
+        drop(other);
+
+        // A socket accepted from the listener does not own the name.
+        let client = UnixSocket::new(StreamTransport::new(2));
+        client.connect(addr.clone()).unwrap();
+        drop(server.accept().unwrap());
+        assert!(
+            ABSTRACT_BINDS
+                .lock()
+                .contains_key(b"test-release".as_slice())
+        );
+
+        drop(server);
+        assert!(
+            !ABSTRACT_BINDS
+                .lock()
+                .contains_key(b"test-release".as_slice())
+        );
+        let client = UnixSocket::new(StreamTransport::new(2));
+        assert!(matches!(
+            client.connect(addr.clone()),
+            Err(AxError::NotFound)
+        ));
+
+        // The name can be bound again.
+        let server = UnixSocket::new(DgramTransport::new(3));
+        server.bind(addr).unwrap();
+    }
+}
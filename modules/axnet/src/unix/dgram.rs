@@ -6,8 +6,7 @@ use async_trait::async_trait;
 use axerrno::{AxError, AxResult};
 use axio::{Buf, BufMut};
 use axpoll::{IoEvents, PollSet, Pollable};
-use axsync::Mutex;
-use spin::RwLock;
+use axsync::{Mutex, RwLock};
 
 use crate::{
     CMsgData, RecvFlags, RecvOptions, SendOptions, SocketAddrEx,
@@ -25,11 +24,13 @@ struct Packet {
 struct Channel {
     data_tx: async_channel::Sender<Packet>,
     poll_update: Arc<PollSet>,
+    peer_pid: u32,
 }
 
 pub struct Bind {
     data_tx: async_channel::Sender<Packet>,
     poll_update: Arc<PollSet>,
+    pid: u32,
 }
 impl Bind {
     fn connect(&self) -> Channel {
@@ -37,6 +38,7 @@ impl Bind {
         Channel {
             data_tx: tx,
             poll_update: self.poll_update.clone(),
+            peer_pid: self.pid,
         }
     }
 }
@@ -86,6 +88,7 @@ impl DgramTransport {
             Channel {
                 data_tx: tx2,
                 poll_update: poll2.clone(),
+                peer_pid: pid,
             },
             pid,
         );
@@ -94,6 +97,7 @@ impl DgramTransport {
             Channel {
                 data_tx: tx1,
                 poll_update: poll1.clone(),
+                peer_pid: pid,
             },
             pid,
         );
@@ -112,10 +116,15 @@ impl Configurable for DgramTransport {
         match opt {
             O::PassCredentials(_) => {}
             O::PeerCredentials(cred) => {
-                // Datagram sockets are stateless and do not have a peer, so we
+                // Unconnected datagram sockets do not have a peer, so we
                 // return the credentials of the process that created the
                 // socket.
-                **cred = UnixCredentials::new(self.pid);
+                let peer_pid = self
+                    .connected
+                    .read()
+                    .as_ref()
+                    .map_or(self.pid, |chan| chan.peer_pid);
+                **cred = UnixCredentials::new(peer_pid);
             }
             _ => return Ok(false),
         }
@@ -152,6 +161,7 @@ impl TransportOps for DgramTransport {
         *slot = Some(Bind {
             data_tx: tx,
             poll_update: poll_update.clone(),
+            pid: self.pid,
         });
         *guard = Some((rx, poll_update));
         self.local_addr.write().clone_from(local_addr);
@@ -1,4 +1,4 @@
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
 use core::{
     sync::atomic::{AtomicBool, Ordering},
     task::Context,
@@ -15,7 +15,7 @@ use ringbuf::{
 };
 
 use crate::{
-    RecvOptions, SendOptions, Shutdown,
+    CMsgData, RecvOptions, SendOptions, Shutdown,
     general::GeneralOptions,
     options::{Configurable, GetSocketOption, SetSocketOption, UnixCredentials},
     unix::{Transport, TransportOps, UnixSocketAddr},
@@ -23,24 +23,41 @@ use crate::{
 
 const BUF_SIZE: usize = 64 * 1024;
 
-fn new_uni_channel() -> (HeapProd<u8>, HeapCons<u8>) {
+/// Ancillary data attached to a position of the byte stream.
+struct CMsgRecord {
+    pos: u64,
+    cmsg: Vec<CMsgData>,
+}
+
+type CMsgQueue = Arc<Mutex<VecDeque<CMsgRecord>>>;
+
+fn new_uni_channel() -> (HeapProd<u8>, HeapCons<u8>, CMsgQueue) {
     let rb = HeapRb::new(BUF_SIZE);
-    rb.split()
+    let (tx, rx) = rb.split();
+    (tx, rx, CMsgQueue::default())
 }
 fn new_channels(pid: u32) -> (Channel, Channel) {
-    let (client_tx, server_rx) = new_uni_channel();
-    let (server_tx, client_rx) = new_uni_channel();
+    let (client_tx, server_rx, client_cmsg) = new_uni_channel();
+    let (server_tx, client_rx, server_cmsg) = new_uni_channel();
     let poll_update = Arc::new(PollSet::new());
     (
         Channel {
             tx: client_tx,
             rx: client_rx,
+            tx_cmsg: client_cmsg.clone(),
+            rx_cmsg: server_cmsg.clone(),
+            tx_pos: 0,
+            rx_pos: 0,
             poll_update: poll_update.clone(),
             peer_pid: pid,
         },
         Channel {
             tx: server_tx,
             rx: server_rx,
+            tx_cmsg: server_cmsg,
+            rx_cmsg: client_cmsg,
+            tx_pos: 0,
+            rx_pos: 0,
             poll_update,
             peer_pid: pid,
         },
@@ -50,6 +67,14 @@ fn new_channels(pid: u32) -> (Channel, Channel) {
 struct Channel {
     tx: HeapProd<u8>,
     rx: HeapCons<u8>,
+    /// Ancillary data sent along with `tx`, received by the peer in order.
+    tx_cmsg: CMsgQueue,
+    /// Ancillary data sent by the peer along with `rx`.
+    rx_cmsg: CMsgQueue,
+    /// Number of bytes written to `tx` so far.
+    tx_pos: u64,
+    /// Number of bytes read from `rx` so far.
+    rx_pos: u64,
     // TODO: granularity
     poll_update: Arc<PollSet>,
     peer_pid: u32,
@@ -84,12 +109,19 @@ struct ConnRequest {
     pid: u32,
 }
 
+/// Transport of Unix stream sockets.
+///
+/// Ancillary data (e.g. objects or [`UnixCredentials`]) sent with some bytes
+/// is received by the read that returns the first of them. Reads stop short
+/// of bytes sent with ancillary data, so that it is not merged with earlier
+/// data.
 pub struct StreamTransport {
     channel: Mutex<Option<Channel>>,
     conn_rx: Mutex<Option<(async_channel::Receiver<ConnRequest>, Arc<PollSet>)>>,
     poll_state: PollSet,
     general: GeneralOptions,
     pid: u32,
+    pass_credentials: AtomicBool,
     rx_closed: AtomicBool,
     tx_closed: AtomicBool,
 }
@@ -105,6 +137,7 @@ impl StreamTransport {
             poll_state: PollSet::new(),
             general: GeneralOptions::default(),
             pid,
+            pass_credentials: AtomicBool::new(false),
             rx_closed: AtomicBool::new(false),
             tx_closed: AtomicBool::new(false),
         }
@@ -130,7 +163,9 @@ impl Configurable for StreamTransport {
             O::SendBuffer(size) => {
                 **size = BUF_SIZE;
             }
-            O::PassCredentials(_) => {}
+            O::PassCredentials(pass) => {
+                **pass = self.pass_credentials.load(Ordering::Relaxed);
+            }
             O::PeerCredentials(cred) => {
                 let peer_pid = self
                     .channel
@@ -152,7 +187,9 @@ impl Configurable for StreamTransport {
         }
 
         match opt {
-            O::PassCredentials(_) => {}
+            O::PassCredentials(pass) => {
+                self.pass_credentials.store(*pass, Ordering::Relaxed);
+            }
             _ => return Ok(false),
         }
         Ok(true)
@@ -219,6 +256,8 @@ impl TransportOps for StreamTransport {
         }
         let size = src.remaining();
         let mut total = 0;
+        // Attached to the first byte sent.
+        let mut cmsg = Some(options.cmsg).filter(|cmsg| !cmsg.is_empty());
         let non_blocking = self.general.nonblocking();
         self.general.send_poller(self).poll(|| {
             let mut guard = self.channel.lock();
@@ -235,7 +274,18 @@ impl TransportOps for StreamTransport {
                 if count >= left.len() {
                     count += src.read(unsafe { right.assume_init_mut() })?;
                 }
+                if count > 0 {
+                    if let Some(cmsg) = cmsg.take() {
+                        // Queue the ancillary data before the bytes become
+                        // visible to the peer.
+                        chan.tx_cmsg.lock().push_back(CMsgRecord {
+                            pos: chan.tx_pos,
+                            cmsg,
+                        });
+                    }
+                }
                 unsafe { chan.tx.advance_write_index(count) };
+                chan.tx_pos += count as u64;
                 count
             };
             total += count;
@@ -251,15 +301,31 @@ impl TransportOps for StreamTransport {
         })
     }
 
-    fn recv(&self, dst: &mut impl BufMut, _options: RecvOptions) -> AxResult<usize> {
+    fn recv(&self, dst: &mut impl BufMut, mut options: RecvOptions) -> AxResult<usize> {
+        let pass_credentials = self.pass_credentials.load(Ordering::Relaxed);
         self.general.recv_poller(self).poll(|| {
             let mut guard = self.channel.lock();
             let Some(chan) = guard.as_mut() else {
                 return Err(AxError::NotConnected);
             };
+            if chan.rx.occupied_len() == 0 {
+                return Err(AxError::WouldBlock);
+            }
+
+            // A single read never crosses the start of data that was sent
+            // with ancillary data, so that it is received together with the
+            // first byte it was sent with.
+            let limit = chan
+                .rx_cmsg
+                .lock()
+                .iter()
+                .find(|it| it.pos > chan.rx_pos)
+                .map_or(usize::MAX, |it| (it.pos - chan.rx_pos) as usize);
 
             let count = {
                 let (left, right) = chan.rx.as_slices();
+                let left = &left[..left.len().min(limit)];
+                let right = &right[..right.len().min(limit - left.len())];
                 let mut count = dst.write(left)?;
                 if count >= left.len() {
                     count += dst.write(right)?;
@@ -267,12 +333,25 @@ impl TransportOps for StreamTransport {
                 unsafe { chan.rx.advance_read_index(count) };
                 count
             };
-            if count > 0 {
-                chan.poll_update.wake();
-                Ok(count)
-            } else {
-                Err(AxError::WouldBlock)
+            if count == 0 {
+                return Ok(0);
             }
+
+            let mut rx_cmsg = chan.rx_cmsg.lock();
+            let mut cmsg = Vec::new();
+            while rx_cmsg.front().is_some_and(|it| it.pos <= chan.rx_pos) {
+                cmsg.extend(rx_cmsg.pop_front().unwrap().cmsg);
+            }
+            drop(rx_cmsg);
+            chan.rx_pos += count as u64;
+            if let Some(dst) = options.cmsg.as_mut() {
+                if pass_credentials {
+                    dst.push(Box::new(UnixCredentials::new(chan.peer_pid)));
+                }
+                dst.extend(cmsg);
+            }
+            chan.poll_update.wake();
+            Ok(count)
         })
     }
 
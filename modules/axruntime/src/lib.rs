@@ -17,6 +17,7 @@
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
+//! - `tickless`: Stop the timer tick on idle CPUs.
 //! - `fs`: Enable filesystem support.
 //! - `net`: Enable networking support.
 //! - `display`: Enable graphics support.
@@ -35,6 +36,9 @@ mod lang_items;
 #[cfg(feature = "smp")]
 mod mp;
 
+#[cfg(all(feature = "multitask", feature = "paging"))]
+mod stack_guard;
+
 #[cfg(feature = "smp")]
 pub use self::mp::rust_main_secondary;
 
@@ -188,8 +192,15 @@ pub fn rust_main(cpu_id: usize, arg: usize) -> ! {
         axbacktrace::init(ip_range, fp_range);
     }
 
+    // Before the kernel address space is marked as loaded on this CPU, so
+    // that TLB shootdowns can be queued for it.
+    #[cfg(feature = "ipi")]
+    axipi::init();
+
     #[cfg(feature = "paging")]
     axmm::init_memory_management();
+    #[cfg(all(feature = "multitask", feature = "paging"))]
+    stack_guard::init_percpu();
 
     info!("Initialize platform devices...");
     axhal::init_later(cpu_id, arg);
@@ -339,6 +350,9 @@ fn init_interrupt() {
     axhal::irq::register(axhal::irq::IPI_IRQ, |_| {
         axipi::ipi_handler();
     });
+    // Idle CPUs are woken up by IPIs when their tick is stopped.
+    #[cfg(all(feature = "tickless", not(feature = "ipi")))]
+    axhal::irq::register(axhal::irq::IPI_IRQ, |_| {});
 
     // Enable IRQs before starting app
     axhal::asm::enable_irqs();
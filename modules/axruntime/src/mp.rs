@@ -40,17 +40,21 @@ pub fn rust_main_secondary(cpu_id: usize) -> ! {
     ENTERED_CPUS.fetch_add(1, Ordering::Release);
     info!("Secondary CPU {} started.", cpu_id);
 
+    // Before the kernel address space is marked as loaded here, so that TLB
+    // shootdowns can be queued for this CPU.
+    #[cfg(feature = "ipi")]
+    axipi::init();
+
     #[cfg(feature = "paging")]
     axmm::init_memory_management_secondary();
+    #[cfg(all(feature = "multitask", feature = "paging"))]
+    super::stack_guard::init_percpu();
 
     axhal::init_later_secondary(cpu_id);
 
     #[cfg(feature = "multitask")]
     axtask::init_scheduler_secondary();
 
-    #[cfg(feature = "ipi")]
-    axipi::init();
-
     info!("Secondary CPU {:x} init OK.", cpu_id);
     super::INITED_CPUS.fetch_add(1, Ordering::Release);
 
@@ -0,0 +1,407 @@
+//! Guard pages below the kernel stacks of tasks.
+//!
+//! Task stacks are mapped by [`axmm`], with an unmapped page below each of
+//! them. An overflow must be caught when entering the trap it causes, before
+//! the trap frame is pushed onto the overflowed stack, which would fault
+//! again:
+//!
+//! - On x86_64, pushing the frame of the page fault itself fails, which raises
+//!   a double fault. Double faults run on an interrupt stack (IST) of the CPU.
+//! - On the other architectures, the trap vector of axcpu is preceded by ours,
+//!   which checks that the trap frame fits below the interrupted stack pointer
+//!   by reading its lowest word. If the read faults, the trap is handled on an
+//!   overflow stack instead.
+//!
+//! Page faults themselves stay on the stack of the task, as they may sleep.
+//!
+//! The overflow is reported by [`axtask::check_stack_overflow`], which panics
+//! naming the overflowing task. A frame record is made for the interrupted
+//! code, so that the backtrace goes on through the overflowed stack.
+
+use axhal::mem::VirtAddr;
+
+/// The size of an exception stack.
+const EXCEPTION_STACK_SIZE: usize = 0x4000;
+
+#[repr(align(16))]
+struct ExceptionStack([u8; EXCEPTION_STACK_SIZE]);
+
+struct TaskStackIfImpl;
+
+#[crate_interface::impl_interface]
+impl axtask::TaskStackIf for TaskStackIfImpl {
+    fn alloc_stack(size: usize) -> Option<VirtAddr> {
+        axmm::alloc_kernel_stack(size).ok()
+    }
+
+    fn dealloc_stack(bottom: VirtAddr, size: usize) {
+        axmm::dealloc_kernel_stack(bottom, size).expect("failed to unmap task stack");
+    }
+}
+
+/// Installs the overflow checks on the current CPU.
+///
+/// It must be called after axcpu has set up the traps of the CPU.
+pub fn init_percpu() {
+    unsafe { imp::install() };
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use axconfig::plat::CPU_NUM;
+    use axhal::{mem::VirtAddr, percpu::this_cpu_id};
+
+    use super::{EXCEPTION_STACK_SIZE, ExceptionStack};
+
+    /// The vector of double faults.
+    const DOUBLE_FAULT: usize = 8;
+
+    /// The IST entry used for double faults.
+    const DOUBLE_FAULT_IST: u8 = 1;
+
+    static mut EXCEPTION_STACKS: [ExceptionStack; CPU_NUM] =
+        [const { ExceptionStack([0; EXCEPTION_STACK_SIZE]) }; CPU_NUM];
+
+    /// The double fault handler of axcpu, which [`stack_guard_double_fault`]
+    /// goes on with when the fault is not a stack overflow.
+    static mut AXCPU_DOUBLE_FAULT: usize = 0;
+
+    #[repr(C, packed)]
+    struct DescriptorTablePointer {
+        limit: u16,
+        base: u64,
+    }
+
+    unsafe extern "C" {
+        fn stack_guard_double_fault();
+    }
+
+    core::arch::global_asm!(
+        ".section .text",
+        ".balign 16",
+        ".global stack_guard_double_fault",
+        "stack_guard_double_fault:",
+        // The CPU pushed the error code, RIP, CS, RFLAGS, RSP and SS onto the
+        // interrupt stack, 16-byte aligned.
+        "push qword ptr [rsp + 8]",
+        "push rbp",
+        "mov rbp, rsp",
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "sub rsp, 8",
+        "mov rdi, cr2",
+        "call {handler}",
+        "add rsp, 8",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+        "pop rbp",
+        "add rsp, 8",
+        "jmp qword ptr [rip + {axcpu}]",
+        handler = sym double_fault,
+        axcpu = sym AXCPU_DOUBLE_FAULT,
+    );
+
+    /// Panics if the page fault that could not be delivered, at `cr2`, hit the
+    /// guard page of the current task.
+    extern "C" fn double_fault(cr2: usize) {
+        axtask::check_stack_overflow(VirtAddr::from(cr2));
+    }
+
+    fn sgdt() -> usize {
+        let mut ptr = DescriptorTablePointer { limit: 0, base: 0 };
+        unsafe { core::arch::asm!("sgdt [{}]", in(reg) &raw mut ptr, options(nostack)) };
+        ptr.base as usize
+    }
+
+    fn sidt() -> usize {
+        let mut ptr = DescriptorTablePointer { limit: 0, base: 0 };
+        unsafe { core::arch::asm!("sidt [{}]", in(reg) &raw mut ptr, options(nostack)) };
+        ptr.base as usize
+    }
+
+    /// Returns the address of the TSS of the current CPU, from its descriptor
+    /// in the GDT.
+    unsafe fn tss_base() -> usize {
+        let selector: u16;
+        unsafe {
+            core::arch::asm!("str {0:x}", out(reg) selector, options(nomem, nostack));
+            let desc = (sgdt() + (selector & !7) as usize) as *const u8;
+            let low = desc.cast::<u64>().read_unaligned();
+            let high = desc.add(8).cast::<u32>().read_unaligned();
+            (((low >> 16) & 0xff_ffff) | (((low >> 56) & 0xff) << 24) | ((high as u64) << 32))
+                as usize
+        }
+    }
+
+    /// Points IST1 of the TSS of the current CPU to its exception stack, and
+    /// routes double faults through it and [`stack_guard_double_fault`].
+    pub unsafe fn install() {
+        unsafe {
+            let bottom = &raw mut EXCEPTION_STACKS[this_cpu_id()] as usize;
+            // IST1 follows the reserved field after RSP0-2.
+            let ist1 = (tss_base() + 0x24) as *mut u64;
+            ist1.write_unaligned((bottom + EXCEPTION_STACK_SIZE) as u64);
+
+            // The IDT is shared by all CPUs, and patched by the first one.
+            let gate = (sidt() + DOUBLE_FAULT * 16) as *mut u8;
+            let gate_offset = |gate: *mut u8| {
+                (gate.cast::<u16>().read_unaligned() as usize)
+                    | (gate.add(6).cast::<u16>().read_unaligned() as usize) << 16
+                    | (gate.add(8).cast::<u32>().read_unaligned() as usize) << 32
+            };
+            let entry = stack_guard_double_fault as usize;
+            if gate_offset(gate) != entry {
+                AXCPU_DOUBLE_FAULT = gate_offset(gate);
+                gate.cast::<u16>().write_unaligned(entry as u16);
+                gate.add(6)
+                    .cast::<u16>()
+                    .write_unaligned((entry >> 16) as u16);
+                gate.add(8)
+                    .cast::<u32>()
+                    .write_unaligned((entry >> 32) as u32);
+            }
+            let ist = gate.add(4);
+            ist.write_volatile((ist.read_volatile() & !7) | DOUBLE_FAULT_IST);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod imp {
+    use core::{mem::size_of, sync::atomic::AtomicU32};
+
+    use axhal::{context::TrapFrame, mem::VirtAddr};
+
+    use super::{EXCEPTION_STACK_SIZE, ExceptionStack};
+
+    /// The size of the trap frame pushed by axcpu on entry.
+    const TRAP_FRAME_SIZE: usize = size_of::<TrapFrame>();
+
+    /// The stack overflows are handled on, by one CPU at a time: the first
+    /// one panics, which stops the system.
+    static mut OVERFLOW_STACK: ExceptionStack = ExceptionStack([0; EXCEPTION_STACK_SIZE]);
+
+    /// Taken by the CPU handling an overflow on [`OVERFLOW_STACK`].
+    static OVERFLOW_STACK_TAKEN: AtomicU32 = AtomicU32::new(0);
+
+    /// Reports the overflow of the stack whose pointer was `sp`.
+    extern "C" fn stack_overflow(sp: usize) -> ! {
+        // The stack pointer is either in the guard page already, or just
+        // above it with no room left for a trap frame.
+        axtask::check_stack_overflow(VirtAddr::from(sp - 1));
+        axtask::check_stack_overflow(VirtAddr::from(sp - TRAP_FRAME_SIZE));
+        panic!("kernel stack overflow at sp {sp:#x}");
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    core::arch::global_asm!(
+        ".section .text",
+        ".balign 4",
+        ".global stack_guard_vector",
+        "stack_guard_vector:",
+        // sscratch is 0 on traps from S-mode, the kernel stack pointer on
+        // traps from U-mode, and 1 while the probe below runs.
+        "csrrw sp, sscratch, sp",
+        "beqz sp, 1f",
+        "addi sp, sp, -1",
+        "beqz sp, 2f",
+        "addi sp, sp, 1",
+        "csrrw sp, sscratch, sp",
+        "j trap_vector_base",
+        // From S-mode: probe the lowest word of the trap frame.
+        "1:",
+        "csrr sp, sscratch",
+        "csrwi sscratch, 1",
+        "ld zero, -{frame}(sp)",
+        "csrwi sscratch, 0",
+        "j trap_vector_base",
+        // The probe faulted: sscratch is the interrupted stack pointer.
+        "2:",
+        "lla t0, {taken}",
+        "3:",
+        "li t1, 1",
+        "amoswap.w.aq t1, t1, (t0)",
+        "bnez t1, 3b",
+        "lla sp, {stack}",
+        "li t0, {stack_size}",
+        "add sp, sp, t0",
+        "csrrw a0, sscratch, zero",
+        "addi sp, sp, -16",
+        "sd ra, 8(sp)",
+        "sd s0, 0(sp)",
+        "addi s0, sp, 16",
+        "call {report}",
+        frame = const TRAP_FRAME_SIZE,
+        taken = sym OVERFLOW_STACK_TAKEN,
+        stack = sym OVERFLOW_STACK,
+        stack_size = const EXCEPTION_STACK_SIZE,
+        report = sym stack_overflow,
+    );
+
+    #[cfg(target_arch = "riscv64")]
+    pub unsafe fn install() {
+        unsafe extern "C" {
+            fn trap_vector_base();
+            fn stack_guard_vector();
+        }
+        let vector: usize;
+        unsafe { core::arch::asm!("csrr {}, stvec", out(reg) vector) };
+        assert_eq!(vector, trap_vector_base as usize, "unexpected trap vector");
+        unsafe { core::arch::asm!("csrw stvec, {}", in(reg) stack_guard_vector as usize) };
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    core::arch::global_asm!(
+        ".section .text",
+        ".p2align 11",
+        ".global stack_guard_vector",
+        "stack_guard_vector:",
+        ".irp offset, 0x000, 0x080, 0x100, 0x180",
+        ".p2align 7",
+        "b exception_vector_base + \\offset",
+        ".endr",
+        // Synchronous exceptions from EL1 with SP_EL1: check that the trap
+        // frame fits, by translating its lowest address.
+        ".p2align 7",
+        "msr tpidrro_el0, x0",
+        "mov x0, sp",
+        "sub x0, x0, #{frame}",
+        "at s1e1w, x0",
+        "isb",
+        "mrs x0, par_el1",
+        "tbnz x0, #0, 1f",
+        "mrs x0, tpidrro_el0",
+        "msr tpidrro_el0, xzr",
+        "b exception_vector_base + 0x200",
+        ".irp offset, 0x280, 0x300, 0x380, 0x400, 0x480, 0x500, 0x580, 0x600, 0x680, 0x700, 0x780",
+        ".p2align 7",
+        "b exception_vector_base + \\offset",
+        ".endr",
+        // The trap frame does not fit.
+        "1:",
+        "adrp x0, {taken}",
+        "add x0, x0, :lo12:{taken}",
+        "2:",
+        "ldaxr w1, [x0]",
+        "cbnz w1, 2b",
+        "mov w1, #1",
+        "stxr w2, w1, [x0]",
+        "cbnz w2, 2b",
+        "mov x1, sp",
+        "adrp x0, {stack}",
+        "add x0, x0, :lo12:{stack}",
+        "mov x2, #{stack_size}",
+        "add sp, x0, x2",
+        "mrs x2, elr_el1",
+        "stp x29, x2, [sp, #-16]!",
+        "mov x29, sp",
+        "mov x0, x1",
+        "bl {report}",
+        frame = const TRAP_FRAME_SIZE,
+        taken = sym OVERFLOW_STACK_TAKEN,
+        stack = sym OVERFLOW_STACK,
+        stack_size = const EXCEPTION_STACK_SIZE,
+        report = sym stack_overflow,
+    );
+
+    #[cfg(target_arch = "aarch64")]
+    pub unsafe fn install() {
+        unsafe extern "C" {
+            fn exception_vector_base();
+            fn stack_guard_vector();
+        }
+        let vector: usize;
+        unsafe { core::arch::asm!("mrs {}, vbar_el1", out(reg) vector) };
+        assert_eq!(
+            vector, exception_vector_base as usize,
+            "unexpected exception vector"
+        );
+        unsafe { core::arch::asm!("msr vbar_el1, {}", "isb", in(reg) stack_guard_vector as usize) };
+    }
+
+    /// A KSave CSR not used by axcpu, holding `$t0` while it is used.
+    #[cfg(target_arch = "loongarch64")]
+    const KSAVE_SCRATCH: usize = 0x36;
+
+    /// A KSave CSR not used by axcpu, set while the probe runs.
+    #[cfg(target_arch = "loongarch64")]
+    const KSAVE_PROBING: usize = 0x37;
+
+    #[cfg(target_arch = "loongarch64")]
+    core::arch::global_asm!(
+        ".section .text",
+        ".balign 4096",
+        ".global stack_guard_vector",
+        "stack_guard_vector:",
+        "csrwr $t0, {scratch}",
+        "csrrd $t0, {probing}",
+        "bnez $t0, 2f",
+        // PRMD.PPLV: nothing to check on traps from user mode.
+        "csrrd $t0, 0x1",
+        "andi $t0, $t0, 0x3",
+        "bnez $t0, 1f",
+        // Probe the lowest word of the trap frame.
+        "addi.d $t0, $zero, 1",
+        "csrwr $t0, {probing}",
+        "ld.d $zero, $sp, -{frame}",
+        "csrwr $zero, {probing}",
+        "1:",
+        "csrrd $t0, {scratch}",
+        "b exception_entry_base",
+        // The probe faulted: $sp is the interrupted stack pointer.
+        "2:",
+        "la.pcrel $t0, {taken}",
+        "3:",
+        "addi.w $t1, $zero, 1",
+        "amswap_db.w $t2, $t1, $t0",
+        "bnez $t2, 3b",
+        "move $a0, $sp",
+        "la.pcrel $t0, {stack}",
+        "li.d $t1, {stack_size}",
+        "add.d $sp, $t0, $t1",
+        "addi.d $sp, $sp, -16",
+        "st.d $ra, $sp, 8",
+        "st.d $fp, $sp, 0",
+        "addi.d $fp, $sp, 16",
+        "bl {report}",
+        scratch = const KSAVE_SCRATCH,
+        probing = const KSAVE_PROBING,
+        frame = const TRAP_FRAME_SIZE,
+        taken = sym OVERFLOW_STACK_TAKEN,
+        stack = sym OVERFLOW_STACK,
+        stack_size = const EXCEPTION_STACK_SIZE,
+        report = sym stack_overflow,
+    );
+
+    #[cfg(target_arch = "loongarch64")]
+    pub unsafe fn install() {
+        unsafe extern "C" {
+            fn exception_entry_base();
+            fn stack_guard_vector();
+        }
+        let vector: usize;
+        unsafe {
+            core::arch::asm!("csrrd {}, 0xc", out(reg) vector);
+            core::arch::asm!("csrwr $zero, {}", const KSAVE_PROBING);
+        }
+        assert_eq!(
+            vector, exception_entry_base as usize,
+            "unexpected exception entry"
+        );
+        unsafe { core::arch::asm!("csrwr {}, 0xc", inout(reg) stack_guard_vector as usize => _) };
+    }
+}
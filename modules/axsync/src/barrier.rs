@@ -0,0 +1,169 @@
+//! A barrier synchronizing a fixed number of tasks.
+
+use axtask::future::block_on;
+
+use crate::{Condvar, Mutex, MutexExt};
+
+struct BarrierState {
+    count: usize,
+    generation: usize,
+}
+
+/// A barrier, which lets a number of tasks wait for all of them to reach a
+/// point of their execution.
+pub struct Barrier {
+    state: Mutex<BarrierState>,
+    cvar: Condvar,
+    num_tasks: usize,
+}
+
+/// Returned by [`Barrier::wait`] when all tasks have reached the barrier.
+#[derive(Debug)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` for exactly one of the tasks released together.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Creates a [`Barrier`] that blocks tasks until `n` of them wait on it.
+    pub const fn new(n: usize) -> Self {
+        Self {
+            state: Mutex::new(BarrierState {
+                count: 0,
+                generation: 0,
+            }),
+            cvar: Condvar::new(),
+            num_tasks: n,
+        }
+    }
+
+    /// Blocks the current task until all tasks have reached this point.
+    ///
+    /// The barrier can be reused once all tasks are released.
+    pub fn wait(&self) -> BarrierWaitResult {
+        block_on(self.wait_async())
+    }
+
+    /// The asynchronous version of [`Barrier::wait`].
+    ///
+    /// If the future is dropped before all tasks have reached the barrier,
+    /// the current task is not counted anymore.
+    pub async fn wait_async(&self) -> BarrierWaitResult {
+        let mut state = self.state.lock_async().await;
+        let generation = state.generation;
+        state.count += 1;
+        if state.count < self.num_tasks {
+            let arrival = Arrival {
+                barrier: self,
+                generation,
+            };
+            while generation == state.generation {
+                state = self.cvar.wait_async(state).await;
+            }
+            core::mem::forget(arrival);
+            BarrierWaitResult(false)
+        } else {
+            state.count = 0;
+            state.generation = state.generation.wrapping_add(1);
+            self.cvar.notify_all();
+            BarrierWaitResult(true)
+        }
+    }
+}
+
+/// The arrival of a task at a [`Barrier`], given back if its wait is given up
+/// before the round is over.
+struct Arrival<'a> {
+    barrier: &'a Barrier,
+    generation: usize,
+}
+
+impl Drop for Arrival<'_> {
+    fn drop(&mut self) {
+        // The wait only gives up while the lock is released.
+        let mut state = self.barrier.state.lock();
+        if state.generation == self.generation {
+            state.count -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use axtask::{
+        self as thread,
+        future::{block_on, timeout},
+    };
+
+    use crate::{Barrier, tests::may_interrupt};
+
+    #[test]
+    fn rounds() {
+        let _lock = crate::tests::init();
+
+        const NUM_TASKS: usize = 10;
+        const NUM_ROUNDS: usize = 5;
+        static B: Barrier = Barrier::new(NUM_TASKS + 1);
+        static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+        static LEADERS: AtomicUsize = AtomicUsize::new(0);
+
+        for _ in 0..NUM_TASKS {
+            thread::spawn(
+                || {
+                    for _ in 0..NUM_ROUNDS {
+                        may_interrupt();
+                        ARRIVED.fetch_add(1, Ordering::AcqRel);
+                        if B.wait().is_leader() {
+                            LEADERS.fetch_add(1, Ordering::AcqRel);
+                        }
+                    }
+                },
+                "".into(),
+            );
+        }
+
+        for round in 1..=NUM_ROUNDS {
+            if B.wait().is_leader() {
+                LEADERS.fetch_add(1, Ordering::AcqRel);
+            }
+            // Nobody passes the barrier before all tasks have arrived.
+            assert!(ARRIVED.load(Ordering::Acquire) >= round * NUM_TASKS);
+        }
+        while LEADERS.load(Ordering::Acquire) < NUM_ROUNDS {
+            thread::yield_now();
+        }
+        assert_eq!(LEADERS.load(Ordering::Acquire), NUM_ROUNDS);
+        println!("Barrier test OK");
+    }
+
+    #[test]
+    fn give_up() {
+        let _lock = crate::tests::init();
+
+        static B: Barrier = Barrier::new(2);
+        let wait = || block_on(timeout(Some(Duration::from_millis(10)), B.wait_async()));
+
+        // The task giving up is not counted anymore, so the next one waits
+        // alone as well.
+        assert!(wait().is_err());
+        assert!(wait().is_err());
+
+        // Both tasks are released together then.
+        thread::spawn(
+            || {
+                B.wait();
+            },
+            "".into(),
+        );
+        B.wait();
+    }
+}
@@ -0,0 +1,190 @@
+//! A condition variable working with the sleeping [`Mutex`](crate::Mutex).
+
+use core::time::Duration;
+
+use axtask::future::{block_on, timeout};
+use event_listener::{Event, listener};
+
+use crate::{MutexExt, MutexGuard};
+
+/// Whether a timed wait on a [`Condvar`] returned because of the timeout.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait timed out.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// A condition variable.
+///
+/// Tasks block on it while releasing a [`Mutex`](crate::Mutex), until they
+/// are notified. As with any condition variable, wake-ups may be spurious, so
+/// the condition should be checked again in a loop, or with
+/// [`Condvar::wait_while`].
+pub struct Condvar {
+    event: Event,
+}
+
+impl Condvar {
+    /// Creates a [`Condvar`].
+    pub const fn new() -> Self {
+        Self {
+            event: Event::new(),
+        }
+    }
+
+    /// Releases the lock of `guard` and blocks the current task until it is
+    /// notified, then acquires the lock again.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        block_on(self.wait_async(guard))
+    }
+
+    /// Blocks the current task as long as `condition` returns `true`.
+    pub fn wait_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Like [`Condvar::wait`], but gives up waiting after `dur`.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        block_on(self.wait_timeout_async(guard, dur))
+    }
+
+    /// Like [`Condvar::wait_while`], but gives up waiting after `dur`.
+    ///
+    /// The result tells whether `condition` was still `true` on the timeout.
+    pub fn wait_timeout_while<'a, T, F>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+        condition: F,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult)
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        block_on(self.wait_timeout_while_async(guard, dur, condition))
+    }
+
+    /// The asynchronous version of [`Condvar::wait`].
+    pub async fn wait_async<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = MutexGuard::mutex(&guard);
+        // Listen before unlocking, so that a notification in between is not
+        // missed.
+        listener!(self.event => listener);
+        drop(guard);
+        listener.await;
+        mutex.lock_async().await
+    }
+
+    /// The asynchronous version of [`Condvar::wait_timeout`].
+    pub async fn wait_timeout_async<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        let mutex = MutexGuard::mutex(&guard);
+        listener!(self.event => listener);
+        drop(guard);
+        let timed_out = timeout(Some(dur), listener).await.is_err();
+        (mutex.lock_async().await, WaitTimeoutResult(timed_out))
+    }
+
+    /// The asynchronous version of [`Condvar::wait_timeout_while`].
+    pub async fn wait_timeout_while_async<'a, T, F>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+        mut condition: F,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult)
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let mutex = MutexGuard::mutex(&guard);
+        let wait = async {
+            let mut guard = guard;
+            while condition(&mut *guard) {
+                guard = self.wait_async(guard).await;
+            }
+            guard
+        };
+        // The wait is only suspended with the lock released, so giving it up
+        // never leaves the lock held.
+        let result = timeout(Some(dur), wait).await;
+        match result {
+            Ok(guard) => (guard, WaitTimeoutResult(false)),
+            Err(_) => {
+                let mut guard = mutex.lock_async().await;
+                let timed_out = condition(&mut *guard);
+                (guard, WaitTimeoutResult(timed_out))
+            }
+        }
+    }
+
+    /// Wakes up one task blocked on this condition variable.
+    pub fn notify_one(&self) {
+        self.event.notify(1);
+    }
+
+    /// Wakes up all tasks blocked on this condition variable.
+    pub fn notify_all(&self) {
+        self.event.notify(usize::MAX);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axtask as thread;
+
+    use crate::{Condvar, Mutex};
+
+    #[test]
+    fn wait_and_notify() {
+        let _lock = crate::tests::init();
+
+        const NUM_TASKS: u32 = 10;
+        static M: Mutex<u32> = Mutex::new(0);
+        static CV: Condvar = Condvar::new();
+
+        for _ in 0..NUM_TASKS {
+            thread::spawn(
+                || {
+                    let guard = CV.wait_while(M.lock(), |started| *started == 0);
+                    drop(guard);
+                    let mut guard = M.lock();
+                    *guard += 1;
+                    CV.notify_all();
+                },
+                "".into(),
+            );
+        }
+
+        thread::yield_now();
+        *M.lock() = 1;
+        CV.notify_all();
+        let guard = CV.wait_while(M.lock(), |val| *val < NUM_TASKS + 1);
+        assert_eq!(*guard, NUM_TASKS + 1);
+        println!("Condvar test OK");
+    }
+}
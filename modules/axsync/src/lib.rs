@@ -3,26 +3,87 @@
 //! Currently supported primitives:
 //!
 //! - [`Mutex`]: A mutual exclusion primitive.
+//! - `PiMutex`: A mutex with priority inheritance, for real-time tasks.
+//! - [`RwLock`]: A writer-preferring readers-writer lock.
+//! - `Condvar`: A condition variable, to wait for an event with a [`Mutex`].
+//! - `Semaphore`: A counting semaphore.
+//! - `Barrier`: A barrier, to wait for a number of tasks to reach a point.
+//! - `Once` and `OnceLock`: One-time initialization.
 //! - mod [`spin`]: spinlocks imported from the [`kspin`] crate.
 //!
+//! The blocking primitives put the waiting tasks to sleep, and have
+//! asynchronous variants to be used in futures, such as the ones run by
+//! [`axtask::future::block_on`].
+//!
 //! # Cargo Features
 //!
 //! - `multitask`: For use in the multi-threaded environments. If the feature is
-//!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`]. This
-//!   feature is enabled by default.
+//!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`], and
+//!   [`RwLock`] a spinning readers-writer lock, while the other primitives are
+//!   not available. This feature is enabled by default.
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
 
+#[cfg(feature = "multitask")]
+extern crate alloc;
+
 pub use kspin as spin;
 
+#[cfg(feature = "multitask")]
+mod barrier;
+#[cfg(feature = "multitask")]
+mod condvar;
 #[cfg(feature = "multitask")]
 mod mutex;
+#[cfg(feature = "multitask")]
+mod once;
+#[cfg(feature = "multitask")]
+mod pi_mutex;
+#[cfg(feature = "multitask")]
+mod rwlock;
+#[cfg(feature = "multitask")]
+mod semaphore;
 
 #[cfg(feature = "multitask")]
 #[doc(cfg(feature = "multitask"))]
-pub use self::mutex::{Mutex, MutexGuard, RawMutex};
+pub use self::{
+    barrier::{Barrier, BarrierWaitResult},
+    condvar::{Condvar, WaitTimeoutResult},
+    mutex::{Mutex, MutexExt, MutexGuard, RawMutex},
+    once::{Once, OnceLock},
+    pi_mutex::{PiMutex, PiMutexExt, PiMutexGuard, RawPiMutex},
+    rwlock::{RawRwLock, RwLock, RwLockExt, RwLockReadGuard, RwLockWriteGuard},
+    semaphore::{Semaphore, SemaphoreGuard},
+};
 
+#[cfg(not(feature = "multitask"))]
+#[doc(cfg(not(feature = "multitask")))]
+pub use ::spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 #[cfg(not(feature = "multitask"))]
 #[doc(cfg(not(feature = "multitask")))]
 pub use kspin::{SpinNoIrq as Mutex, SpinNoIrqGuard as MutexGuard};
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, MutexGuard, Once};
+
+    static INIT: Once = Once::new();
+
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// Initializes the scheduler, and makes the tests using it run one at a
+    /// time.
+    pub fn init() -> MutexGuard<'static, ()> {
+        let lock = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        INIT.call_once(axtask::init_scheduler);
+        lock
+    }
+
+    pub fn may_interrupt() {
+        // simulate interrupts
+        if rand::random::<u32>() % 3 == 0 {
+            axtask::yield_now();
+        }
+    }
+}
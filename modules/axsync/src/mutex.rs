@@ -1,6 +1,9 @@
 //! A naïve sleeping mutex.
 
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::{
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use axtask::{current, future::block_on, yield_now};
 use event_listener::{Event, listener};
@@ -24,6 +27,17 @@ impl RawMutex {
             owner_id: AtomicU64::new(0),
         }
     }
+
+    /// Acquires the mutex, waiting asynchronously if it is locked.
+    pub async fn lock_async(&self) {
+        while !lock_api::RawMutex::try_lock(self) {
+            listener!(self.event => listener);
+            if lock_api::RawMutex::try_lock(self) {
+                return;
+            }
+            listener.await;
+        }
+    }
 }
 
 struct Spin(u32);
@@ -134,16 +148,28 @@ pub type Mutex<T> = lock_api::Mutex<RawMutex, T>;
 /// An alias of [`lock_api::MutexGuard`].
 pub type MutexGuard<'a, T> = lock_api::MutexGuard<'a, RawMutex, T>;
 
+/// Asynchronous locking of a [`Mutex`], for use in futures.
+pub trait MutexExt<T: ?Sized> {
+    /// Acquires this mutex, waiting asynchronously.
+    fn lock_async(&self) -> impl Future<Output = MutexGuard<'_, T>>;
+}
+
+impl<T: ?Sized> MutexExt<T> for Mutex<T> {
+    async fn lock_async(&self) -> MutexGuard<'_, T> {
+        // SAFETY: the lock is acquired before the guard is made.
+        unsafe {
+            self.raw().lock_async().await;
+            self.make_guard_unchecked()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::sync::Once;
-
     use axtask as thread;
 
     use crate::Mutex;
 
-    static INIT: Once = Once::new();
-
     fn may_interrupt() {
         // simulate interrupts
         if rand::random::<u32>() % 3 == 0 {
@@ -153,7 +179,7 @@ mod tests {
 
     #[test]
     fn lots_and_lots() {
-        INIT.call_once(thread::init_scheduler);
+        let _lock = crate::tests::init();
 
         const NUM_TASKS: u32 = 10;
         const NUM_ITERS: u32 = 10_000;
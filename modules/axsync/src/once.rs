@@ -0,0 +1,286 @@
+//! One-time initialization, with the other tasks sleeping until it is done.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use axtask::future::block_on;
+use event_listener::{Event, listener};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// A synchronization primitive to run a one-time initialization.
+///
+/// Tasks arriving while the initialization runs sleep until it is done,
+/// instead of spinning.
+pub struct Once {
+    state: AtomicU8,
+    event: Event,
+}
+
+/// Publishes the state of the initialization when it ends, even if it is
+/// cancelled or panics, in which case another task may run it again.
+struct Completion<'a> {
+    once: &'a Once,
+    state: u8,
+}
+
+impl Drop for Completion<'_> {
+    fn drop(&mut self) {
+        self.once.state.store(self.state, Ordering::Release);
+        self.once.event.notify(usize::MAX);
+    }
+}
+
+impl Once {
+    /// Creates a [`Once`].
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+            event: Event::new(),
+        }
+    }
+
+    /// Returns `true` if an initialization has completed.
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Runs `f` if no initialization has completed yet.
+    ///
+    /// If another task is running its initialization, the current task blocks
+    /// until it is done.
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        if !self.is_completed() {
+            block_on(self.call_once_async(async { f() }));
+        }
+    }
+
+    /// The asynchronous version of [`Once::call_once`], with the
+    /// initialization being a future itself.
+    pub async fn call_once_async<F: Future<Output = ()>>(&self, f: F) {
+        loop {
+            match self.state.compare_exchange(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    let mut completion = Completion {
+                        once: self,
+                        state: INCOMPLETE,
+                    };
+                    f.await;
+                    completion.state = COMPLETE;
+                    return;
+                }
+                Err(COMPLETE) => return,
+                Err(_) => {
+                    listener!(self.event => listener);
+                    if self.state.load(Ordering::Acquire) != RUNNING {
+                        continue;
+                    }
+                    listener.await;
+                }
+            }
+        }
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Once {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Once")
+            .field("completed", &self.is_completed())
+            .finish()
+    }
+}
+
+/// A cell written only once, by the first task that initializes it.
+pub struct OnceLock<T> {
+    once: Once,
+    value: UnsafeCell<MaybeUninit<T>>,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send + Sync> Sync for OnceLock<T> {}
+unsafe impl<T: Send> Send for OnceLock<T> {}
+
+impl<T> OnceLock<T> {
+    /// Creates an empty [`OnceLock`].
+    pub const fn new() -> Self {
+        Self {
+            once: Once::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the value, or `None` if the cell is not initialized yet.
+    pub fn get(&self) -> Option<&T> {
+        if self.once.is_completed() {
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the value, or `None` if the cell is not
+    /// initialized yet.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if self.once.is_completed() {
+            Some(unsafe { self.value.get_mut().assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// Initializes the cell with `value`, or gives it back if the cell is
+    /// already initialized.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    /// Returns the value, initializing the cell with `f` if it is empty.
+    ///
+    /// If another task is initializing the cell, the current task blocks until
+    /// it is done.
+    pub fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        self.once.call_once(|| unsafe {
+            (*self.value.get()).write(f());
+        });
+        unsafe { (*self.value.get()).assume_init_ref() }
+    }
+
+    /// The asynchronous version of [`OnceLock::get_or_init`], with the value
+    /// produced by a future.
+    pub async fn get_or_init_async<F: Future<Output = T>>(&self, f: F) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        self.once
+            .call_once_async(async {
+                let value = f.await;
+                unsafe { (*self.value.get()).write(value) };
+            })
+            .await;
+        unsafe { (*self.value.get()).assume_init_ref() }
+    }
+
+    /// Takes the value out, leaving the cell empty.
+    pub fn take(&mut self) -> Option<T> {
+        if self.once.is_completed() {
+            self.once = Once::new();
+            Some(unsafe { self.value.get_mut().assume_init_read() })
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the cell, returning its value if it is initialized.
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+}
+
+impl<T> Default for OnceLock<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_tuple("OnceLock");
+        match self.get() {
+            Some(value) => d.field(value),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+impl<T> Drop for OnceLock<T> {
+    fn drop(&mut self) {
+        if self.once.is_completed() {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use axtask as thread;
+
+    use crate::{Once, OnceLock, tests::may_interrupt};
+
+    #[test]
+    fn init_once() {
+        let _lock = crate::tests::init();
+
+        const NUM_TASKS: usize = 10;
+        static O: Once = Once::new();
+        static CELL: OnceLock<usize> = OnceLock::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+        for i in 0..NUM_TASKS {
+            thread::spawn(
+                move || {
+                    O.call_once(|| {
+                        // Let the other tasks find the initialization running.
+                        may_interrupt();
+                        thread::yield_now();
+                        CALLS.fetch_add(1, Ordering::AcqRel);
+                    });
+                    assert!(O.is_completed());
+                    let value = *CELL.get_or_init(|| {
+                        thread::yield_now();
+                        i
+                    });
+                    assert_eq!(CELL.get(), Some(&value));
+                    FINISHED.fetch_add(1, Ordering::Release);
+                },
+                "".into(),
+            );
+        }
+
+        while FINISHED.load(Ordering::Acquire) < NUM_TASKS {
+            thread::yield_now();
+        }
+        assert_eq!(CALLS.load(Ordering::Acquire), 1);
+        assert!(CELL.set(usize::MAX).is_err());
+
+        let mut cell = OnceLock::new();
+        assert_eq!(
+            thread::future::block_on(cell.get_or_init_async(async { 42 })),
+            &42
+        );
+        assert_eq!(cell.take(), Some(42));
+        assert!(cell.get().is_none());
+        println!("Once test OK");
+    }
+}
@@ -0,0 +1,465 @@
+//! A sleeping mutex with priority inheritance.
+//!
+//! While a task waits for a [`PiMutex`], the owner of the mutex is boosted to
+//! the real-time priority of the waiter, so that tasks of priorities in
+//! between cannot hold off the waiter indefinitely by starving the owner
+//! (priority inversion). The boost follows chains of tasks blocked on mutexes
+//! owned by tasks that are themselves blocked, and is dropped on unlock, or
+//! lowered when a waiter gives up.
+//!
+//! On unlock, the mutex is handed over to its waiter of the highest priority,
+//! which is the only one woken up.
+//!
+//! The owners and waiters of all PI mutexes are recorded under a single global
+//! lock, which keeps the chains consistent while they are walked.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    cmp::Reverse,
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axtask::{
+    AxTaskRef, current,
+    future::{block_on, timeout, timeout_at_monotonic},
+    set_priority_boost,
+};
+use event_listener::{Event, listener};
+use kspin::SpinNoIrq;
+
+/// The maximum number of owners boosted along a chain of blocked tasks.
+const MAX_CHAIN_DEPTH: usize = 32;
+
+/// A pointer to a mutex, valid while it is recorded in the [`PiState`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct MutexPtr(*const RawPiMutex);
+
+// Safety: the pointer is only dereferenced under the lock of the `PiState`,
+// while the mutex is known to be alive.
+unsafe impl Send for MutexPtr {}
+
+impl MutexPtr {
+    fn owner_id(&self) -> u64 {
+        unsafe { &*self.0 }.owner_id.load(Ordering::Acquire)
+    }
+}
+
+struct Waiter {
+    task: AxTaskRef,
+    mutex: MutexPtr,
+    /// Notified when the mutex is handed over to the task.
+    event: Arc<Event>,
+    /// The order of arrival, waiters of the same priority being served first
+    /// come, first served.
+    seq: u64,
+}
+
+struct Owner {
+    task: AxTaskRef,
+    mutexes: Vec<MutexPtr>,
+}
+
+struct PiState {
+    /// The tasks blocked on a PI mutex, by task ID.
+    waiters: BTreeMap<u64, Waiter>,
+    /// The tasks owning PI mutexes, by task ID.
+    owners: BTreeMap<u64, Owner>,
+    /// The order of arrival of the next waiter.
+    next_seq: u64,
+}
+
+static PI_STATE: SpinNoIrq<PiState> = SpinNoIrq::new(PiState {
+    waiters: BTreeMap::new(),
+    owners: BTreeMap::new(),
+    next_seq: 0,
+});
+
+impl PiState {
+    /// Returns the highest real-time priority of the tasks waiting for
+    /// `mutex`.
+    fn top_waiter_priority(&self, mutex: MutexPtr) -> u8 {
+        self.waiters
+            .values()
+            .filter(|waiter| waiter.mutex == mutex)
+            .map(|waiter| waiter.task.effective_rt_priority())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the ID of the task to hand `mutex` over to: the one of the
+    /// highest priority, and the first one to arrive among them.
+    fn top_waiter(&self, mutex: MutexPtr) -> Option<u64> {
+        self.waiters
+            .iter()
+            .filter(|(_, waiter)| waiter.mutex == mutex)
+            .max_by_key(|(_, waiter)| (waiter.task.effective_rt_priority(), Reverse(waiter.seq)))
+            .map(|(&id, _)| id)
+    }
+
+    /// Boosts the task `owner_id` to the highest priority of the tasks waiting
+    /// for the mutexes it owns, or restores its priority if there is none.
+    ///
+    /// Returns `false` if the task is not recorded as an owner yet.
+    fn update_boost(&self, owner_id: u64) -> bool {
+        let Some(owner) = self.owners.get(&owner_id) else {
+            return false;
+        };
+        let prio = owner
+            .mutexes
+            .iter()
+            .map(|&mutex| self.top_waiter_priority(mutex))
+            .max()
+            .unwrap_or(0);
+        set_priority_boost(&owner.task, prio);
+        true
+    }
+
+    /// Updates the boost along the chain of the owner of `mutex`, the owner of
+    /// the mutex the owner waits for, and so on, after the task `waiter_id`
+    /// started or stopped waiting for `mutex`.
+    fn propagate(&self, mut mutex: MutexPtr, waiter_id: u64) {
+        for _ in 0..MAX_CHAIN_DEPTH {
+            let owner_id = mutex.owner_id();
+            if owner_id == 0 {
+                return;
+            }
+            debug_assert_ne!(
+                owner_id, waiter_id,
+                "deadlock: task {waiter_id} waits for a PI mutex it holds via a chain of tasks"
+            );
+            if !self.update_boost(owner_id) {
+                return;
+            }
+            match self.waiters.get(&owner_id) {
+                Some(waiter) => mutex = waiter.mutex,
+                None => return,
+            }
+        }
+    }
+
+    fn acquired(&mut self, mutex: MutexPtr, task: AxTaskRef) {
+        let owner_id = task.id().as_u64();
+        self.owners
+            .entry(owner_id)
+            .or_insert_with(|| Owner {
+                task,
+                mutexes: Vec::new(),
+            })
+            .mutexes
+            .push(mutex);
+        // Inherit from the tasks that were already waiting.
+        self.update_boost(owner_id);
+    }
+
+    fn released(&mut self, mutex: MutexPtr, owner_id: u64) {
+        let Some(owner) = self.owners.get_mut(&owner_id) else {
+            return;
+        };
+        owner.mutexes.retain(|&it| it != mutex);
+        self.update_boost(owner_id);
+        if self.owners[&owner_id].mutexes.is_empty() {
+            self.owners.remove(&owner_id);
+        }
+    }
+
+    /// Unlocks `mutex` owned by the task `owner_id`, handing it over to its
+    /// top waiter, if any.
+    fn release(&mut self, mutex: &RawPiMutex, owner_id: u64) {
+        self.released(mutex.ptr(), owner_id);
+        let next = self
+            .top_waiter(mutex.ptr())
+            .and_then(|id| Some((id, self.waiters.remove(&id)?)));
+        match next {
+            Some((id, waiter)) => {
+                mutex.owner_id.store(id, Ordering::Release);
+                waiter.event.notify(1);
+                self.acquired(mutex.ptr(), waiter.task);
+            }
+            None => mutex.owner_id.store(0, Ordering::Release),
+        }
+    }
+}
+
+/// A task waiting for a PI mutex, until the mutex is handed over to it or it
+/// gives up.
+struct Waiting<'a> {
+    mutex: &'a RawPiMutex,
+    waiter_id: u64,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut pi = PI_STATE.lock();
+        if pi.waiters.remove(&self.waiter_id).is_some() {
+            // The owners may not inherit the priority of the task anymore.
+            pi.propagate(self.mutex.ptr(), self.waiter_id);
+        } else {
+            // The mutex was handed over in the meantime.
+            pi.release(self.mutex, self.waiter_id);
+        }
+    }
+}
+
+/// A [`lock_api::RawMutex`] implementation with priority inheritance.
+///
+/// When the mutex is locked, the current task blocks, and the owner of the
+/// mutex inherits its real-time priority until it unlocks the mutex. The
+/// owner is recorded by its task ID, and the mutex must be unlocked by the
+/// task that locked it.
+pub struct RawPiMutex {
+    owner_id: AtomicU64,
+}
+
+impl RawPiMutex {
+    /// Creates a [`RawPiMutex`].
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            owner_id: AtomicU64::new(0),
+        }
+    }
+
+    fn ptr(&self) -> MutexPtr {
+        MutexPtr(self)
+    }
+
+    /// Acquires the mutex, waiting asynchronously if it is locked.
+    ///
+    /// If the future is dropped before it completes, e.g. on a timeout, the
+    /// task gives up waiting, and the owner stops inheriting its priority.
+    pub async fn lock_async(&self) {
+        if lock_api::RawMutex::try_lock(self) {
+            return;
+        }
+        let curr = current().clone();
+        let curr_id = curr.id().as_u64();
+        debug_assert_ne!(
+            self.owner_id.load(Ordering::Relaxed),
+            curr_id,
+            "{} tried to acquire PI mutex it already owns.",
+            curr.id_name()
+        );
+
+        let event = Arc::new(Event::new());
+        {
+            let mut pi = PI_STATE.lock();
+            if self
+                .owner_id
+                .compare_exchange(0, curr_id, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                pi.acquired(self.ptr(), curr);
+                return;
+            }
+            let seq = pi.next_seq;
+            pi.next_seq += 1;
+            pi.waiters.insert(
+                curr_id,
+                Waiter {
+                    task: curr,
+                    mutex: self.ptr(),
+                    event: event.clone(),
+                    seq,
+                },
+            );
+            pi.propagate(self.ptr(), curr_id);
+        }
+
+        let waiting = Waiting {
+            mutex: self,
+            waiter_id: curr_id,
+        };
+        loop {
+            listener!(event => listener);
+            if self.owner_id.load(Ordering::Acquire) == curr_id {
+                break;
+            }
+            listener.await;
+        }
+        // The mutex was handed over, and is kept.
+        core::mem::forget(waiting);
+    }
+}
+
+unsafe impl lock_api::RawMutex for RawPiMutex {
+    type GuardMarker = lock_api::GuardNoSend;
+
+    /// Initial value for an unlocked mutex.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawPiMutex::new();
+
+    fn lock(&self) {
+        block_on(self.lock_async());
+    }
+
+    fn try_lock(&self) -> bool {
+        let curr = current();
+        if self
+            .owner_id
+            .compare_exchange(0, curr.id().as_u64(), Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        PI_STATE.lock().acquired(self.ptr(), curr.clone());
+        true
+    }
+
+    unsafe fn unlock(&self) {
+        let mut pi = PI_STATE.lock();
+        let owner_id = self.owner_id.load(Ordering::Relaxed);
+        assert_eq!(
+            owner_id,
+            current().id().as_u64(),
+            "{} tried to release PI mutex it doesn't own",
+            current().id_name()
+        );
+        pi.release(self, owner_id);
+    }
+
+    #[inline(always)]
+    fn is_locked(&self) -> bool {
+        self.owner_id.load(Ordering::Relaxed) != 0
+    }
+}
+
+unsafe impl lock_api::RawMutexTimed for RawPiMutex {
+    type Duration = Duration;
+    /// A time on the monotonic clock, see `axhal::time::monotonic_time`.
+    type Instant = Duration;
+
+    fn try_lock_for(&self, dur: Duration) -> bool {
+        block_on(timeout(Some(dur), self.lock_async())).is_ok()
+    }
+
+    fn try_lock_until(&self, deadline: Duration) -> bool {
+        block_on(timeout_at_monotonic(Some(deadline), self.lock_async())).is_ok()
+    }
+}
+
+impl Drop for RawPiMutex {
+    fn drop(&mut self) {
+        // A guard may have been leaked.
+        let owner_id = *self.owner_id.get_mut();
+        if owner_id != 0 {
+            PI_STATE.lock().released(self.ptr(), owner_id);
+        }
+    }
+}
+
+/// An alias of [`lock_api::Mutex`] with priority inheritance.
+pub type PiMutex<T> = lock_api::Mutex<RawPiMutex, T>;
+/// An alias of [`lock_api::MutexGuard`] for a [`PiMutex`].
+pub type PiMutexGuard<'a, T> = lock_api::MutexGuard<'a, RawPiMutex, T>;
+
+/// Asynchronous locking of a [`PiMutex`], for use in futures.
+pub trait PiMutexExt<T: ?Sized> {
+    /// Acquires this mutex, waiting asynchronously.
+    ///
+    /// See [`RawPiMutex::lock_async`] for giving up the wait.
+    fn lock_async(&self) -> impl Future<Output = PiMutexGuard<'_, T>>;
+}
+
+impl<T: ?Sized> PiMutexExt<T> for PiMutex<T> {
+    async fn lock_async(&self) -> PiMutexGuard<'_, T> {
+        // SAFETY: the lock is acquired before the guard is made.
+        unsafe {
+            self.raw().lock_async().await;
+            self.make_guard_unchecked()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axtask::{
+        self as thread, SchedulingPolicy, TaskState,
+        future::{block_on, interruptible},
+    };
+
+    use crate::{PiMutex, PiMutexExt};
+
+    #[test]
+    fn priority_inheritance() {
+        let _lock = crate::tests::init();
+
+        static M: PiMutex<()> = PiMutex::new(());
+        static ORDER: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+        // The low priority task takes the mutex first.
+        let guard = M.lock();
+        let low = thread::current().clone();
+        let high = thread::spawn(
+            || {
+                let _guard = M.lock();
+                ORDER.lock().unwrap().push("high");
+            },
+            "high".into(),
+        );
+        assert!(thread::set_scheduling_policy(
+            &high,
+            SchedulingPolicy::Fifo,
+            30
+        ));
+        let medium = thread::spawn(|| ORDER.lock().unwrap().push("medium"), "medium".into());
+        assert!(thread::set_scheduling_policy(
+            &medium,
+            SchedulingPolicy::Fifo,
+            20
+        ));
+
+        // Let the high priority task block on the mutex.
+        while high.state() != TaskState::Blocked {
+            thread::yield_now();
+        }
+        assert_eq!(low.effective_rt_priority(), 30);
+        drop(guard);
+        assert_eq!(low.effective_rt_priority(), 0);
+
+        high.join();
+        medium.join();
+        assert_eq!(*ORDER.lock().unwrap(), ["high", "medium"]);
+    }
+
+    #[test]
+    fn boost_lowered_when_waiter_gives_up() {
+        let _lock = crate::tests::init();
+
+        static M: PiMutex<()> = PiMutex::new(());
+
+        let guard = M.lock();
+        let low = thread::current().clone();
+        let waiters = [(30, "high"), (20, "medium")].map(|(prio, name)| {
+            let task = thread::spawn(
+                || assert!(block_on(interruptible(M.lock_async())).is_err()),
+                name.into(),
+            );
+            assert!(thread::set_scheduling_policy(
+                &task,
+                SchedulingPolicy::Fifo,
+                prio
+            ));
+            task
+        });
+        while waiters
+            .iter()
+            .any(|task| task.state() != TaskState::Blocked)
+        {
+            thread::yield_now();
+        }
+        assert_eq!(low.effective_rt_priority(), 30);
+
+        // The owner inherits from the remaining waiter only.
+        waiters[0].interrupt();
+        waiters[0].join();
+        assert_eq!(low.effective_rt_priority(), 20);
+        waiters[1].interrupt();
+        waiters[1].join();
+        assert_eq!(low.effective_rt_priority(), 0);
+        drop(guard);
+    }
+}
@@ -0,0 +1,260 @@
+//! A sleeping, writer-preferring readers-writer lock.
+
+use core::{
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use axtask::future::block_on;
+use event_listener::{Event, listener};
+
+/// The lock is held by a writer.
+const WRITER: usize = 1;
+/// The count of readers holding the lock is kept above this bit.
+const READER: usize = 2;
+
+/// A [`lock_api::RawRwLock`] implementation.
+///
+/// When the lock cannot be acquired, the current task blocks until it is
+/// released. Once a writer is waiting, new readers block as well, so that a
+/// steady flow of readers cannot starve writers.
+pub struct RawRwLock {
+    state: AtomicUsize,
+    writers_waiting: AtomicUsize,
+    readers_event: Event,
+    writers_event: Event,
+}
+
+impl RawRwLock {
+    /// Creates a [`RawRwLock`].
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            readers_event: Event::new(),
+            writers_event: Event::new(),
+        }
+    }
+
+    /// Acquires a shared lock, waiting asynchronously if it is not available.
+    pub async fn lock_shared_async(&self) {
+        while !lock_api::RawRwLock::try_lock_shared(self) {
+            listener!(self.readers_event => listener);
+            if lock_api::RawRwLock::try_lock_shared(self) {
+                return;
+            }
+            listener.await;
+        }
+    }
+
+    /// Acquires an exclusive lock, waiting asynchronously if it is not
+    /// available.
+    pub async fn lock_exclusive_async(&self) {
+        if lock_api::RawRwLock::try_lock_exclusive(self) {
+            return;
+        }
+        // Keep new readers out until we get the lock.
+        let _waiting = WaitingWriter::new(self);
+        loop {
+            listener!(self.writers_event => listener);
+            if lock_api::RawRwLock::try_lock_exclusive(self) {
+                return;
+            }
+            listener.await;
+        }
+    }
+}
+
+/// Counts a writer waiting for the lock, until it gets the lock or gives up.
+struct WaitingWriter<'a>(&'a RawRwLock);
+
+impl<'a> WaitingWriter<'a> {
+    fn new(lock: &'a RawRwLock) -> Self {
+        lock.writers_waiting.fetch_add(1, Ordering::SeqCst);
+        Self(lock)
+    }
+}
+
+impl Drop for WaitingWriter<'_> {
+    fn drop(&mut self) {
+        let lock = self.0;
+        // If the writer gave up while the lock is free, wake up the readers
+        // that were held off only by it.
+        if lock.writers_waiting.fetch_sub(1, Ordering::SeqCst) == 1
+            && lock.state.load(Ordering::SeqCst) & WRITER == 0
+        {
+            lock.readers_event.notify(usize::MAX);
+        }
+    }
+}
+
+unsafe impl lock_api::RawRwLock for RawRwLock {
+    type GuardMarker = lock_api::GuardSend;
+
+    /// Initial value for an unlocked lock.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawRwLock::new();
+
+    #[inline]
+    fn lock_shared(&self) {
+        if !self.try_lock_shared() {
+            block_on(self.lock_shared_async());
+        }
+    }
+
+    #[inline]
+    fn try_lock_shared(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & WRITER != 0 || self.writers_waiting.load(Ordering::Acquire) != 0 {
+                return false;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state + READER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(x) => state = x,
+            }
+        }
+    }
+
+    #[inline]
+    unsafe fn unlock_shared(&self) {
+        let state = self.state.fetch_sub(READER, Ordering::Release);
+        debug_assert!(state >= READER, "unlocking a RwLock not read-locked");
+        if state == READER {
+            self.writers_event.notify(1);
+        }
+    }
+
+    #[inline]
+    fn lock_exclusive(&self) {
+        if !self.try_lock_exclusive() {
+            block_on(self.lock_exclusive_async());
+        }
+    }
+
+    #[inline]
+    fn try_lock_exclusive(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    #[inline]
+    unsafe fn unlock_exclusive(&self) {
+        let state = self.state.swap(0, Ordering::SeqCst);
+        debug_assert_eq!(state, WRITER, "unlocking a RwLock not write-locked");
+        if self.writers_waiting.load(Ordering::SeqCst) != 0 {
+            self.writers_event.notify(1);
+        } else {
+            self.readers_event.notify(usize::MAX);
+        }
+    }
+
+    #[inline]
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != 0
+    }
+
+    #[inline]
+    fn is_locked_exclusive(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+}
+
+/// An alias of [`lock_api::RwLock`].
+pub type RwLock<T> = lock_api::RwLock<RawRwLock, T>;
+/// An alias of [`lock_api::RwLockReadGuard`].
+pub type RwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawRwLock, T>;
+/// An alias of [`lock_api::RwLockWriteGuard`].
+pub type RwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawRwLock, T>;
+
+/// Asynchronous locking of a [`RwLock`], for use in futures.
+pub trait RwLockExt<T: ?Sized> {
+    /// Locks this lock with shared read access, waiting asynchronously.
+    fn read_async(&self) -> impl Future<Output = RwLockReadGuard<'_, T>>;
+
+    /// Locks this lock with exclusive write access, waiting asynchronously.
+    fn write_async(&self) -> impl Future<Output = RwLockWriteGuard<'_, T>>;
+}
+
+impl<T: ?Sized> RwLockExt<T> for RwLock<T> {
+    async fn read_async(&self) -> RwLockReadGuard<'_, T> {
+        // SAFETY: the shared lock is acquired before the guard is made.
+        unsafe {
+            self.raw().lock_shared_async().await;
+            self.make_read_guard_unchecked()
+        }
+    }
+
+    async fn write_async(&self) -> RwLockWriteGuard<'_, T> {
+        // SAFETY: the exclusive lock is acquired before the guard is made.
+        unsafe {
+            self.raw().lock_exclusive_async().await;
+            self.make_write_guard_unchecked()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axtask as thread;
+
+    use crate::{RwLock, RwLockExt, tests::may_interrupt};
+
+    #[test]
+    fn readers_and_writers() {
+        let _lock = crate::tests::init();
+
+        const NUM_TASKS: u32 = 10;
+        const NUM_ITERS: u32 = 1_000;
+        static L: RwLock<(u32, u32)> = RwLock::new((0, 0));
+
+        for _ in 0..NUM_TASKS {
+            thread::spawn(
+                || {
+                    for _ in 0..NUM_ITERS {
+                        let mut val = L.write();
+                        val.0 += 1;
+                        may_interrupt();
+                        val.1 += 1;
+                        drop(val);
+                        may_interrupt();
+                    }
+                },
+                "".into(),
+            );
+            thread::spawn(
+                || {
+                    for _ in 0..NUM_ITERS {
+                        let val = L.read();
+                        may_interrupt();
+                        // Writers never run while a reader holds the lock.
+                        assert_eq!(val.0, val.1);
+                        drop(val);
+                        may_interrupt();
+                    }
+                },
+                "".into(),
+            );
+        }
+
+        loop {
+            let val = thread::future::block_on(L.read_async());
+            if val.0 == NUM_ITERS * NUM_TASKS {
+                break;
+            }
+            drop(val);
+            may_interrupt();
+        }
+
+        let val = thread::future::block_on(L.write_async());
+        assert_eq!(*val, (NUM_ITERS * NUM_TASKS, NUM_ITERS * NUM_TASKS));
+        println!("RwLock test OK");
+    }
+}
@@ -0,0 +1,132 @@
+//! A counting semaphore.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::future::block_on;
+use event_listener::{Event, listener};
+
+/// A counting semaphore.
+///
+/// It holds a number of permits: [`Semaphore::acquire`] takes one, blocking
+/// the current task until one is available, and [`Semaphore::release`] gives
+/// one back.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    event: Event,
+}
+
+impl Semaphore {
+    /// Creates a [`Semaphore`] with `permits` available permits.
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            event: Event::new(),
+        }
+    }
+
+    /// Returns the number of permits currently available.
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Acquire)
+    }
+
+    /// Takes a permit if one is available, without blocking.
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Takes a permit, blocking the current task until one is available.
+    pub fn acquire(&self) {
+        if !self.try_acquire() {
+            block_on(self.acquire_async());
+        }
+    }
+
+    /// The asynchronous version of [`Semaphore::acquire`].
+    pub async fn acquire_async(&self) {
+        while !self.try_acquire() {
+            listener!(self.event => listener);
+            if self.try_acquire() {
+                return;
+            }
+            listener.await;
+        }
+    }
+
+    /// Takes a permit, and returns a guard that gives it back when dropped.
+    pub fn access(&self) -> SemaphoreGuard<'_> {
+        self.acquire();
+        SemaphoreGuard(self)
+    }
+
+    /// The asynchronous version of [`Semaphore::access`].
+    pub async fn access_async(&self) -> SemaphoreGuard<'_> {
+        self.acquire_async().await;
+        SemaphoreGuard(self)
+    }
+
+    /// Gives a permit back, waking up a task waiting for one.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.event.notify(1);
+    }
+}
+
+/// A permit taken with [`Semaphore::access`], given back when dropped.
+#[must_use = "the permit is given back right away if the guard is unused"]
+pub struct SemaphoreGuard<'a>(&'a Semaphore);
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use axtask as thread;
+
+    use crate::{Semaphore, tests::may_interrupt};
+
+    #[test]
+    fn bounded_concurrency() {
+        let _lock = crate::tests::init();
+
+        const NUM_TASKS: usize = 10;
+        const NUM_PERMITS: usize = 3;
+        static S: Semaphore = Semaphore::new(NUM_PERMITS);
+        static INSIDE: AtomicUsize = AtomicUsize::new(0);
+        static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+        for _ in 0..NUM_TASKS {
+            thread::spawn(
+                || {
+                    for _ in 0..100 {
+                        let _permit = S.access();
+                        let inside = INSIDE.fetch_add(1, Ordering::AcqRel) + 1;
+                        assert!(inside <= NUM_PERMITS);
+                        may_interrupt();
+                        INSIDE.fetch_sub(1, Ordering::AcqRel);
+                    }
+                    FINISHED.fetch_add(1, Ordering::Release);
+                },
+                "".into(),
+            );
+        }
+
+        while FINISHED.load(Ordering::Acquire) < NUM_TASKS {
+            thread::yield_now();
+        }
+        assert_eq!(S.available_permits(), NUM_PERMITS);
+        assert!(S.try_acquire());
+        thread::future::block_on(S.acquire_async());
+        S.release();
+        S.release();
+        println!("Semaphore test OK");
+    }
+}
@@ -7,12 +7,18 @@ use alloc::{
 
 use kernel_guard::NoPreemptIrqSave;
 
+pub use crate::registry::{dump_tasks, find_task, for_each_task};
+pub use crate::run_queue::{RunQueueStats, SchedSnapshot, run_queue_stats, sched_snapshot};
 pub(crate) use crate::run_queue::{current_run_queue, select_run_queue};
+pub(crate) use crate::sched::Scheduler;
+pub use crate::sched::{MAX_RT_PRIORITY, MIN_RT_PRIORITY, SchedulingPolicy};
+#[cfg(feature = "stack-guard")]
+pub use crate::stack::{TaskStackIf, check_stack_overflow};
 pub use crate::task::{CurrentTask, TaskId, TaskInner, TaskState};
 #[cfg(feature = "task-ext")]
 pub use crate::task::{TaskExt, TaskExtProxy};
 #[cfg(feature = "irq")]
-pub use crate::timers::register_timer_callback;
+pub use crate::timers::{register_timer_callback, set_wall_time};
 
 /// The reference type of a task.
 pub type AxTaskRef = Arc<AxTask>;
@@ -27,14 +33,14 @@ cfg_if::cfg_if! {
     if #[cfg(feature = "sched-rr")] {
         const MAX_TIME_SLICE: usize = 5;
         pub(crate) type AxTask = axsched::RRTask<TaskInner, MAX_TIME_SLICE>;
-        pub(crate) type Scheduler = axsched::RRScheduler<TaskInner, MAX_TIME_SLICE>;
+        pub(crate) type FairScheduler = axsched::RRScheduler<TaskInner, MAX_TIME_SLICE>;
     } else if #[cfg(feature = "sched-cfs")] {
         pub(crate) type AxTask = axsched::CFSTask<TaskInner>;
-        pub(crate) type Scheduler = axsched::CFScheduler<TaskInner>;
+        pub(crate) type FairScheduler = axsched::CFScheduler<TaskInner>;
     } else {
         // If no scheduler features are set, use FIFO as the default.
         pub(crate) type AxTask = axsched::FifoTask<TaskInner>;
-        pub(crate) type Scheduler = axsched::FifoScheduler<TaskInner>;
+        pub(crate) type FairScheduler = axsched::FifoScheduler<TaskInner>;
     }
 }
 
@@ -142,11 +148,67 @@ pub fn set_priority(prio: isize) -> bool {
     current_run_queue::<NoPreemptIrqSave>().set_current_priority(prio)
 }
 
+/// Set the priority for the given task, which may be ready, blocked or
+/// running on any CPU.
+///
+/// The priority is within the class of the scheduling policy of the task, see
+/// [`set_priority`] and [`set_scheduling_policy`].
+///
+/// Returns `true` if the priority is set successfully.
+pub fn set_task_priority(task: &AxTaskRef, prio: isize) -> bool {
+    let ok =
+        crate::run_queue::with_task_scheduler(task, |scheduler| scheduler.set_priority(task, prio));
+    // Let the current CPU pick the highest priority task again. Other CPUs do
+    // it on their next tick.
+    #[cfg(feature = "preempt")]
+    if ok {
+        current().set_preempt_pending(true);
+    }
+    ok
+}
+
+/// Sets the scheduling policy of the given task, and its priority within the
+/// class of the policy.
+///
+/// Real-time tasks ([`SchedulingPolicy::Fifo`] and
+/// [`SchedulingPolicy::RoundRobin`]) have priorities from [`MIN_RT_PRIORITY`]
+/// to [`MAX_RT_PRIORITY`], and always run before the tasks with the
+/// [`SchedulingPolicy::Normal`] policy, whose priority is the one of
+/// [`set_priority`].
+///
+/// Returns `true` if the policy is set successfully.
+pub fn set_scheduling_policy(task: &AxTaskRef, policy: SchedulingPolicy, prio: isize) -> bool {
+    let ok = crate::run_queue::with_task_scheduler(task, |scheduler| {
+        scheduler.set_policy(task, policy, prio)
+    });
+    // Let the current CPU pick the highest priority task again. Other CPUs do
+    // it on their next tick.
+    #[cfg(feature = "preempt")]
+    if ok {
+        current().set_preempt_pending(true);
+    }
+    ok
+}
+
+/// Boosts the given task to the real-time priority `prio` at least, until it
+/// is called again with `0`.
+///
+/// It is meant for priority inheritance: a task holding a lock that a task of
+/// higher priority waits for runs with the priority of the latter, so that it
+/// is not held off by the tasks of priorities in between. A task of the fair
+/// class is scheduled as a [`SchedulingPolicy::Fifo`] one while boosted.
+pub fn set_priority_boost(task: &AxTaskRef, prio: u8) {
+    let prio = prio.min(MAX_RT_PRIORITY);
+    crate::run_queue::with_task_scheduler(task, |scheduler| scheduler.set_boost(task, prio));
+    // Let the current CPU pick the highest priority task again. Other CPUs do
+    // it on their next tick.
+    #[cfg(feature = "preempt")]
+    current().set_preempt_pending(true);
+}
+
 /// Set the affinity for the current task.
 /// [`AxCpuMask`] is used to specify the CPU affinity.
 /// Returns `true` if the affinity is set successfully.
-///
-/// TODO: support set the affinity for other tasks.
 pub fn set_current_affinity(cpumask: AxCpuMask) -> bool {
     if cpumask.is_empty() {
         false
@@ -158,14 +220,8 @@ pub fn set_current_affinity(cpumask: AxCpuMask) -> bool {
         // the affinity. If not, we need to migrate the task to the correct CPU.
         #[cfg(feature = "smp")]
         if !cpumask.get(axhal::percpu::this_cpu_id()) {
-            const MIGRATION_TASK_STACK_SIZE: usize = 4096;
             // Spawn a new migration task for migrating.
-            let migration_task = TaskInner::new(
-                move || crate::run_queue::migrate_entry(curr),
-                "migration-task".into(),
-                MIGRATION_TASK_STACK_SIZE,
-            )
-            .into_arc();
+            let migration_task = crate::run_queue::new_migration_task(curr);
 
             // Migrate the current task to the correct CPU using the migration task.
             current_run_queue::<NoPreemptIrqSave>().migrate_current(migration_task);
@@ -179,6 +235,46 @@ pub fn set_current_affinity(cpumask: AxCpuMask) -> bool {
     }
 }
 
+/// Set the affinity for the given task, which may be ready, blocked or running
+/// on any CPU.
+///
+/// A task that is no longer allowed on its CPU is moved to another one: at
+/// once if it is ready, when it is woken up if it is blocked, and at its next
+/// preemption or yield if it is running on another CPU. Without the `preempt`
+/// feature, a running task is only moved when it yields.
+///
+/// Returns `true` if the affinity is set successfully.
+pub fn set_affinity(task: &AxTaskRef, cpumask: AxCpuMask) -> bool {
+    if cpumask.is_empty() {
+        return false;
+    }
+    if current().ptr_eq(task) {
+        return set_current_affinity(cpumask);
+    }
+    task.set_cpumask(cpumask);
+    #[cfg(feature = "smp")]
+    crate::run_queue::migrate_task(task);
+    true
+}
+
+/// Accounts the time from now on to the user mode of the current task.
+///
+/// It should be called right before returning to user space.
+pub fn account_user_enter() {
+    current()
+        .stats()
+        .set_user(true, axhal::time::monotonic_time_nanos());
+}
+
+/// Accounts the time from now on to the kernel mode of the current task.
+///
+/// It should be called right after trapping from user space.
+pub fn account_user_exit() {
+    current()
+        .stats()
+        .set_user(false, axhal::time::monotonic_time_nanos());
+}
+
 /// Current task gives up the CPU time voluntarily, and switches to another
 /// ready task.
 pub fn yield_now() {
@@ -193,11 +289,16 @@ pub fn exit(exit_code: i32) -> ! {
 /// The idle task routine.
 ///
 /// It runs an infinite loop that keeps calling [`yield_now()`].
+///
+/// With the `tickless` feature, the periodic tick is stopped while waiting
+/// for IRQs, and restarted once the CPU wakes up.
 pub fn run_idle() -> ! {
     loop {
         yield_now();
         trace!("idle task: waiting for IRQs...");
-        #[cfg(feature = "irq")]
+        #[cfg(feature = "tickless")]
+        crate::timers::idle();
+        #[cfg(all(feature = "irq", not(feature = "tickless")))]
         axhal::asm::wait_for_irqs();
     }
 }
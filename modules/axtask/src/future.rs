@@ -9,7 +9,7 @@ use core::{
 };
 
 use axerrno::{AxError, AxResult};
-use axhal::time::TimeValue;
+use axhal::time::{TimeValue, monotonic_time};
 use axpoll::{IoEvents, Pollable};
 use futures::FutureExt;
 use kernel_guard::NoPreemptIrqSave;
@@ -17,7 +17,7 @@ use pin_project::pin_project;
 
 use crate::{
     AxTaskRef, WeakAxTaskRef, current, current_run_queue, select_run_queue,
-    timers::{TimerKey, cancel_timer, has_timer, set_timer},
+    timers::{Clock, TimerKey, cancel_timer, has_timer, set_timer},
 };
 
 struct AxWaker {
@@ -136,12 +136,19 @@ pub fn interruptible<F: IntoFuture>(f: F) -> Interruptible<F::IntoFuture> {
 pub struct TimerFuture(Option<TimerKey>);
 
 impl TimerFuture {
-    pub fn new(deadline: Option<Duration>) -> Self {
-        if let Some(deadline) = deadline {
-            Self(set_timer(deadline, &current()))
-        } else {
-            return Self(None);
-        }
+    /// Creates a timer resolved when the wall clock reaches `deadline`, or
+    /// never if `deadline` is `None`.
+    ///
+    /// The timer follows changes of the wall clock made with
+    /// [`set_wall_time`](crate::timers::set_wall_time).
+    pub fn new(deadline: Option<TimeValue>) -> Self {
+        Self(deadline.map(|deadline| set_timer(Clock::Realtime, deadline, &current())))
+    }
+
+    /// Creates a timer resolved when the monotonic clock reaches `deadline`,
+    /// or never if `deadline` is `None`.
+    pub fn new_monotonic(deadline: Option<TimeValue>) -> Self {
+        Self(deadline.map(|deadline| set_timer(Clock::Monotonic, deadline, &current())))
     }
 }
 
@@ -149,12 +156,13 @@ impl Future for TimerFuture {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if let Some(key) = self.0
-            && !has_timer(&key)
-        {
-            Poll::Ready(())
-        } else {
-            Poll::Pending
+        match &self.0 {
+            Some(key) if !has_timer(key) => Poll::Ready(()),
+            Some(key) => {
+                key.rehome();
+                Poll::Pending
+            }
+            None => Poll::Pending,
         }
     }
 }
@@ -169,14 +177,19 @@ impl Drop for TimerFuture {
 
 /// Waits until `duration` has elapsed.
 pub fn sleep(duration: Duration) -> TimerFuture {
-    sleep_until(axhal::time::wall_time() + duration)
+    sleep_until_monotonic(monotonic_time() + duration)
 }
 
-/// Waits until `deadline` is reached.
+/// Waits until the wall clock reaches `deadline`.
 pub fn sleep_until(deadline: TimeValue) -> TimerFuture {
     TimerFuture::new(Some(deadline))
 }
 
+/// Waits until the monotonic clock reaches `deadline`.
+pub fn sleep_until_monotonic(deadline: TimeValue) -> TimerFuture {
+    TimerFuture::new_monotonic(Some(deadline))
+}
+
 /// Error returned by [`timeout`] and [`timeout_at`].
 #[derive(Debug, PartialEq, Eq)]
 pub struct Elapsed;
@@ -219,12 +232,10 @@ impl<F: Future> Future for Timeout<F> {
 }
 
 pub fn timeout<F: IntoFuture>(duration: Option<Duration>, f: F) -> Timeout<F::IntoFuture> {
-    timeout_at(
-        duration.and_then(|x| x.checked_add(axhal::time::wall_time())),
-        f,
-    )
+    timeout_at_monotonic(duration.and_then(|x| x.checked_add(monotonic_time())), f)
 }
 
+/// Like [`timeout`], but with a deadline on the wall clock.
 pub fn timeout_at<F: IntoFuture>(deadline: Option<TimeValue>, f: F) -> Timeout<F::IntoFuture> {
     Timeout {
         inner: f.into_future(),
@@ -232,6 +243,17 @@ pub fn timeout_at<F: IntoFuture>(deadline: Option<TimeValue>, f: F) -> Timeout<F
     }
 }
 
+/// Like [`timeout`], but with a deadline on the monotonic clock.
+pub fn timeout_at_monotonic<F: IntoFuture>(
+    deadline: Option<TimeValue>,
+    f: F,
+) -> Timeout<F::IntoFuture> {
+    Timeout {
+        inner: f.into_future(),
+        delay: TimerFuture::new_monotonic(deadline),
+    }
+}
+
 pub struct Poller<'a, P> {
     pollable: &'a P,
     events: IoEvents,
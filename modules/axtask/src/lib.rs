@@ -12,6 +12,15 @@
 //! - `irq`: Interrupts are enabled. If this feature is enabled, timer-based
 //!   APIs can be used, such as [`sleep`], [`sleep_until`].
 //! - `preempt`: Enable preemptive scheduling.
+//! - `ipi`: Use IPIs (through `axipi`) to make a task running on another CPU
+//!   migrate at once when its affinity changes. It also enables the `irq`
+//!   feature.
+//! - `tickless`: Stop the periodic timer tick on idle CPUs, which only wake up
+//!   for their next timer. It also enables the `irq` feature.
+//! - `stack-guard`: Map task stacks through [`TaskStackIf`], implemented by
+//!   the runtime, with a guard page below each stack to catch overflows.
+//! - `stack-canary`: Fill task stacks with a pattern to measure their maximum
+//!   usage, see [`TaskInner::stack_high_water_mark`].
 //! - `sched-fifo`: Use the [FIFO cooperative scheduler][1]. It also enables the
 //!   `multitask` feature if it is enabled. This feature is enabled by default,
 //!   and it can be overriden by other scheduler features.
@@ -40,6 +49,10 @@ cfg_if::cfg_if! {
         #[macro_use]
         mod run_queue;
         mod task;
+        mod sched;
+        mod stats;
+        mod stack;
+        mod registry;
         mod api;
 
         pub mod future;
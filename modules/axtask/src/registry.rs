@@ -0,0 +1,199 @@
+//! The registry of all tasks, to enumerate them or look them up by ID.
+//!
+//! It only holds weak references: a task is removed from the registry when it
+//! is dropped, and looking it up never extends its lifetime by itself.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::fmt::{self, Write};
+
+use kspin::SpinNoIrq;
+
+use crate::{AxTaskRef, SchedulingPolicy, TaskId, WeakAxTaskRef};
+
+static TASKS: SpinNoIrq<BTreeMap<u64, WeakAxTaskRef>> = SpinNoIrq::new(BTreeMap::new());
+
+pub(crate) fn register(task: &AxTaskRef) {
+    TASKS
+        .lock()
+        .insert(task.id().as_u64(), AxTaskRef::downgrade(task));
+}
+
+pub(crate) fn unregister(id: TaskId) {
+    TASKS.lock().remove(&id.as_u64());
+}
+
+/// Returns all live tasks, by increasing ID.
+fn live_tasks() -> Vec<AxTaskRef> {
+    // The references are dropped after the lock is released, as dropping the
+    // last one of a task unregisters it.
+    TASKS
+        .lock()
+        .values()
+        .filter_map(|task| task.upgrade())
+        .collect()
+}
+
+/// Calls `f` on all tasks that are not dropped yet, by increasing ID.
+///
+/// Exited tasks are included until their resources are recycled.
+pub fn for_each_task(mut f: impl FnMut(&AxTaskRef)) {
+    for task in live_tasks() {
+        f(&task);
+    }
+}
+
+/// Looks up a task by its ID.
+pub fn find_task(id: TaskId) -> Option<AxTaskRef> {
+    TASKS.lock().get(&id.as_u64())?.upgrade()
+}
+
+/// The number of tasks [`dump_tasks`] takes from the registry at a time.
+const DUMP_BATCH: usize = 16;
+
+/// The width of a formatted column of [`dump_tasks`], longer ones are
+/// truncated.
+const COLUMN_LEN: usize = 64;
+
+/// Writes a table of all tasks to `out`, one line per task with its ID, name,
+/// state, CPU, affinity, scheduling priority and kernel stack size.
+///
+/// It is meant for debug consoles and panic handlers: it does not allocate,
+/// and gives up if the registry is locked instead of waiting for it.
+///
+/// ```ignore
+/// let mut dump = String::new();
+/// axtask::dump_tasks(&mut dump).unwrap();
+/// ax_println!("{dump}");
+/// ```
+pub fn dump_tasks(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(
+        out,
+        "{:>6} {:<20} {:<8} {:>3} {:<12} {:<8} {:>8}",
+        "ID", "NAME", "STATE", "CPU", "CPUMASK", "PRIO", "STACK"
+    )?;
+    let mut next_id = 0;
+    loop {
+        let mut batch = [const { None }; DUMP_BATCH];
+        // The references are dropped after the lock is released, as in
+        // `live_tasks`.
+        {
+            let Some(tasks) = TASKS.try_lock() else {
+                return writeln!(out, "(the task registry is locked)");
+            };
+            let live = tasks
+                .range(next_id..)
+                .filter_map(|(_, task)| task.upgrade());
+            for (slot, task) in batch.iter_mut().zip(live) {
+                *slot = Some(task);
+            }
+        }
+        for task in batch.iter().flatten() {
+            next_id = task.id().as_u64() + 1;
+            dump_task(out, task)?;
+        }
+        if batch[DUMP_BATCH - 1].is_none() {
+            return Ok(());
+        }
+    }
+}
+
+fn dump_task(out: &mut impl fmt::Write, task: &AxTaskRef) -> fmt::Result {
+    writeln!(
+        out,
+        "{:>6} {:<20} {:<8} {:>3} {:<12} {:<8} {:>8}",
+        task.id().as_u64(),
+        Column::new(|s| task
+            .try_with_name(|name| s.write_str(name))
+            .unwrap_or_else(|| s.write_str("?"))),
+        Column::new(|s| write!(s, "{:?}", task.state())),
+        task.cpu_id(),
+        Column::new(|s| write_cpu_list(s, task)),
+        Column::new(|s| match task.scheduling_policy() {
+            SchedulingPolicy::Normal => write!(s, "normal:{}", task.priority()),
+            SchedulingPolicy::Fifo => write!(s, "fifo:{}", task.rt_priority()),
+            SchedulingPolicy::RoundRobin => write!(s, "rr:{}", task.rt_priority()),
+        }),
+        Column::new(|s| match task.kernel_stack_size() {
+            Some(size) => write!(s, "{}K", size / 1024),
+            None => s.write_str("boot"),
+        })
+    )
+}
+
+/// Writes the affinity of a task as a list of CPU ranges, like `0-3,6`, or
+/// `?` if it is being changed.
+fn write_cpu_list(out: &mut impl fmt::Write, task: &AxTaskRef) -> fmt::Result {
+    // Never waits for the lock, which may be held by the panicking CPU.
+    let Some(cpumask) = task.try_cpumask() else {
+        return out.write_char('?');
+    };
+    let mut first = true;
+    let mut cpu = 0;
+    while cpu < axconfig::plat::CPU_NUM {
+        if !cpumask.get(cpu) {
+            cpu += 1;
+            continue;
+        }
+        let start = cpu;
+        while cpu + 1 < axconfig::plat::CPU_NUM && cpumask.get(cpu + 1) {
+            cpu += 1;
+        }
+        if !first {
+            out.write_char(',')?;
+        }
+        first = false;
+        if start == cpu {
+            write!(out, "{start}")?;
+        } else {
+            write!(out, "{start}-{cpu}")?;
+        }
+        cpu += 1;
+    }
+    Ok(())
+}
+
+/// A column of [`dump_tasks`], written on the stack first so that the
+/// padding applies to it as a whole.
+struct Column<F>(F);
+
+impl<F: Fn(&mut StackStr) -> fmt::Result> Column<F> {
+    fn new(f: F) -> Self {
+        Self(f)
+    }
+}
+
+impl<F: Fn(&mut StackStr) -> fmt::Result> fmt::Display for Column<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = StackStr {
+            buf: [0; COLUMN_LEN],
+            len: 0,
+        };
+        (self.0)(&mut s)?;
+        f.pad(s.as_str())
+    }
+}
+
+/// A string of [`COLUMN_LEN`] bytes at most, truncated if longer.
+struct StackStr {
+    buf: [u8; COLUMN_LEN],
+    len: usize,
+}
+
+impl StackStr {
+    fn as_str(&self) -> &str {
+        // Only whole characters are written.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+}
+
+impl fmt::Write for StackStr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(COLUMN_LEN - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
 #[cfg(feature = "smp")]
 use alloc::sync::Weak;
-use alloc::{collections::VecDeque, sync::Arc};
-use core::{mem::MaybeUninit, task::Poll};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    task::Poll,
+    time::Duration,
+};
 
-use axhal::percpu::this_cpu_id;
-use axsched::BaseScheduler;
+use axhal::{percpu::this_cpu_id, time::monotonic_time_nanos};
 use futures::{future::poll_fn, task::AtomicWaker};
 use kernel_guard::BaseGuard;
 use kspin::SpinRaw;
@@ -56,6 +60,19 @@ static mut RUN_QUEUES: [MaybeUninit<&'static mut AxRunQueue>; axconfig::plat::CP
 #[allow(clippy::declare_interior_mutable_const)] // It's ok because it's used only for initialization `RUN_QUEUES`.
 const ARRAY_REPEAT_VALUE: MaybeUninit<&'static mut AxRunQueue> = MaybeUninit::uninit();
 
+/// Whether the run queue of each CPU in [`RUN_QUEUES`] has been initialized.
+static RUN_QUEUES_ONLINE: [AtomicBool; axconfig::plat::CPU_NUM] =
+    [const { AtomicBool::new(false) }; axconfig::plat::CPU_NUM];
+
+/// The interval of the periodic load balancing, in ticks.
+#[cfg(all(feature = "smp", feature = "irq"))]
+const BALANCE_INTERVAL_TICKS: u64 = 4;
+
+/// The minimum difference of load between the busiest run queue and the
+/// current one for the periodic load balancing to move a task.
+#[cfg(all(feature = "smp", feature = "irq"))]
+const BALANCE_MIN_IMBALANCE: usize = 2;
+
 /// Returns a reference to the current run queue in [`CurrentRunQueueRef`].
 ///
 /// ## Safety
@@ -79,16 +96,24 @@ pub(crate) fn current_run_queue<G: BaseGuard>() -> CurrentRunQueueRef<'static, G
     }
 }
 
-/// Selects the run queue index based on a CPU set bitmap and load balancing.
+/// Returns the number of ready tasks in the run queue of the current CPU.
+#[cfg(feature = "tickless")]
+pub(crate) fn nr_ready() -> usize {
+    unsafe { RUN_QUEUE.current_ref_raw() }
+        .nr_ready
+        .load(Ordering::SeqCst)
+}
+
+/// Selects the run queue index for a task becoming ready, based on its CPU
+/// affinity and the load of the run queues.
 ///
-/// This function filters the available run queues based on the provided
-/// `cpumask` and selects the run queue index for the next task. The selection
-/// is based on a round-robin algorithm.
+/// The CPU the task last ran on, whose caches may still be warm, is preferred,
+/// then the CPU of the waker, which is likely to share data with the task. If
+/// both are busy, an idle CPU is chosen, or the least loaded one.
 ///
 /// ## Arguments
 ///
-/// * `cpumask` - A bitmap representing the CPUs that are eligible for task
-///   execution.
+/// * `task` - The task to place.
 ///
 /// ## Returns
 ///
@@ -96,26 +121,51 @@ pub(crate) fn current_run_queue<G: BaseGuard>() -> CurrentRunQueueRef<'static, G
 ///
 /// ## Panics
 ///
-/// This function will panic if `cpu_mask` is empty, indicating that there are
-/// no available CPUs for task execution.
+/// This function will panic if the `cpumask` of the task is empty, indicating
+/// that there are no available CPUs for task execution.
 #[cfg(feature = "smp")]
-// The modulo operation is safe here because `axconfig::plat::CPU_NUM` is always greater than 1 with
-// "smp" enabled.
-#[allow(clippy::modulo_one)]
-#[inline]
-fn select_run_queue_index(cpumask: AxCpuMask) -> usize {
-    use core::sync::atomic::{AtomicUsize, Ordering};
-    static RUN_QUEUE_INDEX: AtomicUsize = AtomicUsize::new(0);
-
+fn select_run_queue_index(task: &AxTaskRef) -> usize {
+    let cpumask = task.cpumask();
     assert!(!cpumask.is_empty(), "No available CPU for task execution");
 
-    // Round-robin selection of the run queue index.
-    loop {
-        let index = RUN_QUEUE_INDEX.fetch_add(1, Ordering::SeqCst) % axconfig::plat::CPU_NUM;
-        if cpumask.get(index) {
-            return index;
+    let candidates = [task.cpu_id() as usize, this_cpu_id()]
+        .into_iter()
+        .chain(0..axconfig::plat::CPU_NUM);
+    let mut best: Option<(usize, usize)> = None;
+    for cpu in candidates {
+        if !cpumask.get(cpu) {
+            continue;
+        }
+        let Some(rq) = run_queue_ref(cpu) else {
+            continue;
+        };
+        let load = rq.load();
+        if best.is_none_or(|(_, best_load)| load < best_load) {
+            best = Some((cpu, load));
+            if load == 0 {
+                break;
+            }
         }
     }
+    best.map_or_else(
+        || {
+            (0..axconfig::plat::CPU_NUM)
+                .find(|&cpu| cpumask.get(cpu))
+                .unwrap()
+        },
+        |(cpu, _)| cpu,
+    )
+}
+
+/// Returns the run queue with the most ready tasks other than the one of
+/// `cpu_id`, if any has ready tasks.
+#[cfg(feature = "smp")]
+fn busiest_run_queue(cpu_id: usize) -> Option<&'static AxRunQueue> {
+    (0..axconfig::plat::CPU_NUM)
+        .filter(|&cpu| cpu != cpu_id)
+        .filter_map(run_queue_ref)
+        .filter(|rq| rq.nr_ready.load(Ordering::Relaxed) > 0)
+        .max_by_key(|rq| rq.load())
 }
 
 /// Retrieves a `'static` reference to the run queue corresponding to the given
@@ -141,12 +191,70 @@ fn get_run_queue(index: usize) -> &'static mut AxRunQueue {
     unsafe { RUN_QUEUES[index].assume_init_mut() }
 }
 
+/// Returns a shared reference to the run queue of the CPU `index`, or `None`
+/// if the CPU has not initialized it yet.
+#[inline]
+fn run_queue_ref(index: usize) -> Option<&'static AxRunQueue> {
+    RUN_QUEUES_ONLINE
+        .get(index)?
+        .load(Ordering::Acquire)
+        .then(|| unsafe { &**RUN_QUEUES[index].assume_init_ref() })
+}
+
+/// Load balancing and CPU time statistics of a run queue.
+#[derive(Debug, Clone, Copy)]
+pub struct RunQueueStats {
+    /// The ID of the CPU of the run queue.
+    pub cpu_id: usize,
+    /// The number of ready tasks waiting in the run queue.
+    pub nr_ready: usize,
+    /// Whether the CPU is running its idle task.
+    pub idle: bool,
+    /// The number of tasks that came to this run queue from another CPU.
+    pub nr_migrations: u64,
+    /// The number of tasks stolen by this CPU when it was about to idle.
+    pub nr_idle_steals: u64,
+    /// The number of tasks pulled by the periodic load balancing of this CPU.
+    pub nr_balance_pulls: u64,
+    /// The time the CPU has run its idle task.
+    pub idle_time: Duration,
+    /// The time the CPU has run other tasks.
+    pub busy_time: Duration,
+    /// The number of context switches on the CPU.
+    pub nr_switches: u64,
+}
+
+/// A snapshot of the statistics of all run queues.
+#[derive(Debug, Clone)]
+pub struct SchedSnapshot {
+    /// The monotonic time when the snapshot was taken.
+    pub timestamp: Duration,
+    /// The statistics of the run queues of the CPUs that are up.
+    pub run_queues: Vec<RunQueueStats>,
+}
+
+/// Returns the load balancing statistics of the run queue of the CPU
+/// `cpu_id`, or `None` if it is not initialized.
+pub fn run_queue_stats(cpu_id: usize) -> Option<RunQueueStats> {
+    run_queue_ref(cpu_id).map(AxRunQueue::stats)
+}
+
+/// Takes a snapshot of the statistics of all run queues.
+pub fn sched_snapshot() -> SchedSnapshot {
+    SchedSnapshot {
+        timestamp: Duration::from_nanos(monotonic_time_nanos()),
+        run_queues: (0..axconfig::plat::CPU_NUM)
+            .filter_map(run_queue_stats)
+            .collect(),
+    }
+}
+
 /// Selects the appropriate run queue for the provided task.
 ///
 /// * In a single-core system, this function always returns a reference to the
 ///   global run queue.
 /// * In a multi-core system, this function selects the run queue based on the
-///   task's CPU affinity and load balance.
+///   task's CPU affinity and load balance, see [`select_run_queue_index`].
 ///
 /// ## Arguments
 ///
@@ -156,13 +264,6 @@ fn get_run_queue(index: usize) -> &'static mut AxRunQueue {
 ///
 /// * [`AxRunQueueRef`] - a static reference to the selected [`AxRunQueue`]
 ///   (current or remote).
-///
-/// ## TODO
-///
-/// 1. Implement better load balancing across CPUs for more efficient task
-///    distribution.
-/// 2. Use a more generic load balancing algorithm that can be customized or
-///    replaced.
 #[inline]
 pub(crate) fn select_run_queue<G: BaseGuard>(task: &AxTaskRef) -> AxRunQueueRef<'static, G> {
     let irq_state = G::acquire();
@@ -180,7 +281,7 @@ pub(crate) fn select_run_queue<G: BaseGuard>(task: &AxTaskRef) -> AxRunQueueRef<
     {
         // When SMP is enabled, select the run queue based on the task's CPU affinity
         // and load balance.
-        let index = select_run_queue_index(task.cpumask());
+        let index = select_run_queue_index(task);
         AxRunQueueRef {
             inner: get_run_queue(index),
             state: irq_state,
@@ -197,6 +298,27 @@ pub(crate) struct AxRunQueue {
     /// Since irq and preempt are preserved by the kernel guard hold by
     /// `AxRunQueueRef`, we just use a simple raw spin lock here.
     scheduler: SpinRaw<Scheduler>,
+    /// The number of ready tasks in the scheduler, read by other CPUs without
+    /// locking it.
+    nr_ready: AtomicUsize,
+    /// Whether the CPU is running its idle task.
+    idle: AtomicBool,
+    /// The task running on the CPU, for other CPUs waking up tasks.
+    #[cfg(all(feature = "smp", feature = "preempt"))]
+    current: SpinRaw<crate::WeakAxTaskRef>,
+    /// The number of ticks since the run queue was created.
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    ticks: u64,
+    nr_migrations: AtomicU64,
+    nr_idle_steals: AtomicU64,
+    nr_balance_pulls: AtomicU64,
+    /// Time spent running the idle task, in nanoseconds.
+    idle_nanos: AtomicU64,
+    /// Time spent running other tasks, in nanoseconds.
+    busy_nanos: AtomicU64,
+    /// When the last context switch happened.
+    last_switch: AtomicU64,
+    nr_switches: AtomicU64,
 }
 
 /// A reference to the run queue with specific guard.
@@ -249,7 +371,13 @@ impl<G: BaseGuard> AxRunQueueRef<'_, G> {
             self.inner.cpu_id
         );
         assert!(task.is_ready());
-        self.inner.scheduler.lock().add_task(task);
+        #[cfg(feature = "smp")]
+        task.set_cpu_id(self.inner.cpu_id as _);
+        task.stats().mark_ready(monotonic_time_nanos());
+        self.inner
+            .with_scheduler(|scheduler| scheduler.add_task(task));
+        #[cfg(feature = "tickless")]
+        crate::timers::notify_enqueue(self.inner.cpu_id);
     }
 
     /// Unblock one task by inserting it into the run queue.
@@ -258,6 +386,15 @@ impl<G: BaseGuard> AxRunQueueRef<'_, G> {
     /// which means the task is already unblocked by other cores.
     pub fn unblock_task(&mut self, task: AxTaskRef, resched: bool) {
         let task_id_name = task.id_name();
+        // Compare with the task running on the CPU of this run queue, which
+        // may not be the current CPU.
+        #[cfg(feature = "preempt")]
+        let curr = self.inner.current_task();
+        #[cfg(feature = "preempt")]
+        let resched = resched
+            || curr
+                .as_ref()
+                .is_some_and(|curr| curr.is_idle() || crate::sched::should_preempt(&task, curr));
         // Try to change the state of the task from `Blocked` to `Ready`,
         // if successful, the task will be put into this run queue,
         // otherwise, the task is already unblocked by other cores.
@@ -266,16 +403,20 @@ impl<G: BaseGuard> AxRunQueueRef<'_, G> {
         // scheduling process.
         if self
             .inner
-            .put_task_with_state(task, TaskState::Blocked, resched)
+            .put_task_with_state(task, TaskState::Blocked, false)
         {
             // Since now, the task to be unblocked is in the `Ready` state.
             let cpu_id = self.inner.cpu_id;
             debug!("task unblock: {} on run_queue {}", task_id_name, cpu_id);
-            // Note: when the task is unblocked on another CPU's run queue,
-            // we just ingiore the `resched` flag.
-            if resched && cpu_id == this_cpu_id() {
+            // A task running on another CPU notices the flag on its next
+            // preemption point.
+            if resched {
                 #[cfg(feature = "preempt")]
-                crate::current().set_preempt_pending(true);
+                if cpu_id == this_cpu_id() {
+                    crate::current().set_preempt_pending(true);
+                } else if let Some(curr) = curr {
+                    curr.set_preempt_pending(true);
+                }
             }
         }
     }
@@ -286,10 +427,38 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
     #[cfg(feature = "irq")]
     pub fn scheduler_timer_tick(&mut self) {
         let curr = &self.current_task;
-        if !curr.is_idle() && self.inner.scheduler.lock().task_tick(curr) {
+        if !curr.is_idle()
+            && self
+                .inner
+                .with_scheduler(|scheduler| scheduler.task_tick(curr))
+        {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
+
+        #[cfg(feature = "smp")]
+        {
+            self.inner.ticks += 1;
+            if self.inner.ticks.is_multiple_of(BALANCE_INTERVAL_TICKS) {
+                let _pulled = self.inner.balance();
+                #[cfg(feature = "preempt")]
+                if _pulled
+                    .is_some_and(|task| curr.is_idle() || crate::sched::should_preempt(&task, curr))
+                {
+                    curr.set_preempt_pending(true);
+                }
+                // Idle CPUs with their tick stopped do not balance, wake one up
+                // to steal from this run queue.
+                #[cfg(feature = "tickless")]
+                if self.inner.nr_ready.load(Ordering::Relaxed) > 0 {
+                    let idle_cpu = (0..axconfig::plat::CPU_NUM)
+                        .find(|&cpu| run_queue_ref(cpu).is_some_and(|rq| rq.load() == 0));
+                    if let Some(cpu) = idle_cpu {
+                        crate::timers::notify_enqueue(cpu);
+                    }
+                }
+            }
+        }
     }
 
     /// Yield the current task and reschedule.
@@ -300,6 +469,13 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
         trace!("task yield: {}", curr.id_name());
         assert!(curr.is_running());
 
+        // The affinity of the task may have been changed by another task.
+        #[cfg(feature = "smp")]
+        if !curr.cpumask().get(self.inner.cpu_id) {
+            self.migrate_current(new_migration_task(curr.clone()));
+            return;
+        }
+
         self.inner
             .put_task_with_state(curr.clone(), TaskState::Running, false);
 
@@ -325,6 +501,7 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
         // Mark current task's state as `Ready`,
         // but, do not put current task to the scheduler of this run queue.
         curr.set_state(TaskState::Ready);
+        curr.stats().mark_ready(monotonic_time_nanos());
 
         // Call `switch_to` to reschedule to the migration task that performs the
         // migration directly.
@@ -360,6 +537,12 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
             can_preempt
         );
         if can_preempt {
+            // The affinity of the task may have been changed by another task.
+            #[cfg(feature = "smp")]
+            if !curr.cpumask().get(self.inner.cpu_id) {
+                self.migrate_current(new_migration_task(curr.clone()));
+                return;
+            }
             self.inner
                 .put_task_with_state(curr.clone(), TaskState::Running, true);
             self.inner.resched();
@@ -446,17 +629,16 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
     }
 
     pub fn set_current_priority(&mut self, prio: isize) -> bool {
+        let curr = &self.current_task;
         self.inner
-            .scheduler
-            .lock()
-            .set_priority(&self.current_task, prio)
+            .with_scheduler(|scheduler| scheduler.set_priority(curr, prio))
     }
 }
 
 impl AxRunQueue {
     /// Create a new run queue for the specified CPU.
     /// The run queue is initialized with a per-CPU gc task in its scheduler.
-    fn new(cpu_id: usize) -> Self {
+    pub(crate) fn new(cpu_id: usize) -> Self {
         let gc_task = TaskInner::new(
             || block_on(gc_entry()),
             "gc".into(),
@@ -470,10 +652,117 @@ impl AxRunQueue {
         scheduler.add_task(gc_task);
         Self {
             cpu_id,
+            nr_ready: AtomicUsize::new(scheduler.len()),
             scheduler: SpinRaw::new(scheduler),
+            idle: AtomicBool::new(crate::current().is_idle()),
+            #[cfg(all(feature = "smp", feature = "preempt"))]
+            current: SpinRaw::new(Weak::new()),
+            #[cfg(all(feature = "smp", feature = "irq"))]
+            ticks: 0,
+            nr_migrations: AtomicU64::new(0),
+            nr_idle_steals: AtomicU64::new(0),
+            nr_balance_pulls: AtomicU64::new(0),
+            idle_nanos: AtomicU64::new(0),
+            busy_nanos: AtomicU64::new(0),
+            last_switch: AtomicU64::new(monotonic_time_nanos()),
+            nr_switches: AtomicU64::new(0),
         }
     }
 
+    /// Calls `f` with the locked scheduler, keeping the count of ready tasks
+    /// up to date.
+    pub(crate) fn with_scheduler<R>(&self, f: impl FnOnce(&mut Scheduler) -> R) -> R {
+        let mut scheduler = self.scheduler.lock();
+        let ret = f(&mut scheduler);
+        self.nr_ready.store(scheduler.len(), Ordering::Relaxed);
+        ret
+    }
+
+    /// Returns the task running on the CPU of the run queue, if known.
+    #[cfg(feature = "preempt")]
+    fn current_task(&self) -> Option<AxTaskRef> {
+        #[cfg(feature = "smp")]
+        return self.current.lock().upgrade();
+        #[cfg(not(feature = "smp"))]
+        Some(crate::current().clone())
+    }
+
+    /// Returns the load of the run queue: its ready tasks and the running one,
+    /// unless the CPU is idle.
+    #[cfg(feature = "smp")]
+    fn load(&self) -> usize {
+        self.nr_ready.load(Ordering::Relaxed) + !self.idle.load(Ordering::Relaxed) as usize
+    }
+
+    /// Returns the load balancing and CPU time statistics of the run queue.
+    pub(crate) fn stats(&self) -> RunQueueStats {
+        let idle = self.idle.load(Ordering::Relaxed);
+        // Add the time since the last context switch.
+        let now = monotonic_time_nanos();
+        let elapsed = now.saturating_sub(self.last_switch.load(Ordering::Relaxed));
+        let (idle_pending, busy_pending) = if idle { (elapsed, 0) } else { (0, elapsed) };
+        RunQueueStats {
+            cpu_id: self.cpu_id,
+            nr_ready: self.nr_ready.load(Ordering::Relaxed),
+            idle,
+            nr_migrations: self.nr_migrations.load(Ordering::Relaxed),
+            nr_idle_steals: self.nr_idle_steals.load(Ordering::Relaxed),
+            nr_balance_pulls: self.nr_balance_pulls.load(Ordering::Relaxed),
+            idle_time: Duration::from_nanos(self.idle_nanos.load(Ordering::Relaxed) + idle_pending),
+            busy_time: Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed) + busy_pending),
+            nr_switches: self.nr_switches.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Makes `task`, coming from another run queue, belong to this one.
+    #[cfg(feature = "smp")]
+    fn claim_task(&self, task: &AxTaskRef) {
+        if task.cpu_id() as usize != self.cpu_id {
+            task.set_cpu_id(self.cpu_id as _);
+            self.nr_migrations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes a ready task of `busiest` that this CPU can run, and makes it
+    /// belong to this run queue.
+    #[cfg(feature = "smp")]
+    pub(crate) fn pull_task(&self, busiest: &AxRunQueue) -> Option<AxTaskRef> {
+        let task = busiest.with_scheduler(|scheduler| scheduler.steal_task(self.cpu_id))?;
+        debug!(
+            "task pull: {} from run_queue {} to {}",
+            task.id_name(),
+            busiest.cpu_id,
+            self.cpu_id
+        );
+        self.claim_task(&task);
+        Some(task)
+    }
+
+    /// Steals a ready task from the busiest run queue, as this CPU is about to
+    /// idle.
+    #[cfg(feature = "smp")]
+    fn steal_task(&self) -> Option<AxTaskRef> {
+        let task = self.pull_task(busiest_run_queue(self.cpu_id)?)?;
+        self.nr_idle_steals.fetch_add(1, Ordering::Relaxed);
+        Some(task)
+    }
+
+    /// Periodic load balancing: pulls a ready task from the busiest run queue
+    /// if it is busier enough than this one.
+    ///
+    /// Returns the task pulled, which is put into this run queue.
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    fn balance(&self) -> Option<AxTaskRef> {
+        let busiest = busiest_run_queue(self.cpu_id)?;
+        if busiest.load() < self.load() + BALANCE_MIN_IMBALANCE {
+            return None;
+        }
+        let task = self.pull_task(busiest)?;
+        self.nr_balance_pulls.fetch_add(1, Ordering::Relaxed);
+        self.with_scheduler(|scheduler| scheduler.put_prev_task(task.clone(), false));
+        Some(task)
+    }
+
     /// Puts target task into current run queue with `Ready` state
     /// if its state matches `current_state` (except idle task).
     ///
@@ -509,10 +798,19 @@ impl AxRunQueue {
                     core::hint::spin_loop();
                 }
             }
-            // TODO: priority
             #[cfg(feature = "smp")]
-            task.set_cpu_id(self.cpu_id as _);
-            self.scheduler.lock().put_prev_task(task, preempt);
+            self.claim_task(&task);
+            task.stats().mark_ready(monotonic_time_nanos());
+            self.with_scheduler(|scheduler| {
+                // A woken up task waits behind the ready tasks of its priority.
+                if current_state == TaskState::Blocked {
+                    scheduler.add_task(task)
+                } else {
+                    scheduler.put_prev_task(task, preempt)
+                }
+            });
+            #[cfg(feature = "tickless")]
+            crate::timers::notify_enqueue(self.cpu_id);
             true
         } else {
             false
@@ -522,14 +820,14 @@ impl AxRunQueue {
     /// Core reschedule subroutine.
     /// Pick the next task to run and switch to it.
     fn resched(&mut self) {
-        let next = self
-            .scheduler
-            .lock()
-            .pick_next_task()
-            .unwrap_or_else(|| unsafe {
-                // Safety: IRQs must be disabled at this time.
-                IDLE_TASK.current_ref_raw().get_unchecked().clone()
-            });
+        let next = self.with_scheduler(|scheduler| scheduler.pick_next_task());
+        // Look for work on the other CPUs before going idle.
+        #[cfg(feature = "smp")]
+        let next = next.or_else(|| self.steal_task());
+        let next = next.unwrap_or_else(|| unsafe {
+            // Safety: IRQs must be disabled at this time.
+            IDLE_TASK.current_ref_raw().get_unchecked().clone()
+        });
         assert!(
             next.is_ready(),
             "next {} is not ready: {:?}",
@@ -558,6 +856,23 @@ impl AxRunQueue {
             return;
         }
 
+        // Account the CPU time of the tasks and of this CPU.
+        let now = monotonic_time_nanos();
+        prev_task.stats().switch_out(now, !prev_task.is_ready());
+        next_task.stats().switch_in(now, self.cpu_id);
+        let elapsed = now.saturating_sub(self.last_switch.swap(now, Ordering::Relaxed));
+        if prev_task.is_idle() {
+            self.idle_nanos.fetch_add(elapsed, Ordering::Relaxed);
+        } else {
+            self.busy_nanos.fetch_add(elapsed, Ordering::Relaxed);
+        }
+        self.nr_switches.fetch_add(1, Ordering::Relaxed);
+        self.idle.store(next_task.is_idle(), Ordering::Relaxed);
+        #[cfg(all(feature = "smp", feature = "preempt"))]
+        {
+            *self.current.lock() = Arc::downgrade(&next_task);
+        }
+
         // Claim the task as running, we do this before switching to it
         // such that any running task will have this set.
         #[cfg(feature = "smp")]
@@ -640,17 +955,113 @@ async fn gc_entry() -> ! {
     .await
 }
 
+/// Creates the task that moves `task`, which must be the current task, to a
+/// run queue matching its CPU affinity, see
+/// [`CurrentRunQueueRef::migrate_current`].
+#[cfg(feature = "smp")]
+pub(crate) fn new_migration_task(task: AxTaskRef) -> AxTaskRef {
+    const MIGRATION_TASK_STACK_SIZE: usize = 4096;
+    TaskInner::new(
+        move || migrate_entry(task),
+        "migration-task".into(),
+        MIGRATION_TASK_STACK_SIZE,
+    )
+    .into_arc()
+}
+
 /// The task routine for migrating the current task to the correct CPU.
 ///
 /// It calls `select_run_queue` to get the correct run queue for the task, and
 /// then puts the task to the scheduler of target run queue.
 #[cfg(feature = "smp")]
 pub(crate) fn migrate_entry(migrated_task: AxTaskRef) {
-    select_run_queue::<kernel_guard::NoPreemptIrqSave>(&migrated_task)
-        .inner
-        .scheduler
-        .lock()
-        .put_prev_task(migrated_task, false)
+    let rq = select_run_queue::<kernel_guard::NoPreemptIrqSave>(&migrated_task);
+    rq.inner.claim_task(&migrated_task);
+    rq.inner
+        .with_scheduler(|scheduler| scheduler.put_prev_task(migrated_task, false));
+    #[cfg(feature = "tickless")]
+    crate::timers::notify_enqueue(rq.inner.cpu_id);
+}
+
+/// Moves `task`, which is not the current task, to a CPU allowed by its
+/// affinity.
+///
+/// A ready task is moved to another run queue at once. A task running on
+/// another CPU is preempted there, and migrates itself when it is rescheduled.
+/// Without the `preempt` feature, it is not preempted, and only migrates when
+/// it yields. A blocked task is placed on an allowed CPU when it is woken up.
+#[cfg(feature = "smp")]
+pub(crate) fn migrate_task(task: &AxTaskRef) {
+    let _guard = kernel_guard::NoPreemptIrqSave::new();
+    let allowed = |task: &AxTaskRef| task.cpumask().get(task.cpu_id() as usize);
+
+    let removed = loop {
+        // A task just preempted is ready while its CPU may still be switching
+        // away from it. Like a woken up task in `put_task_with_state`, it can
+        // only be moved once its context is saved.
+        while task.is_ready() && task.on_cpu() {
+            core::hint::spin_loop();
+        }
+        let removed = with_task_scheduler(task, |scheduler| {
+            if allowed(task) {
+                Some(None)
+            } else if task.is_ready() && task.on_cpu() {
+                // Preempted again meanwhile, wait again.
+                None
+            } else {
+                Some(scheduler.remove_task(task))
+            }
+        });
+        if let Some(removed) = removed {
+            break removed;
+        }
+    };
+    if let Some(task) = removed {
+        let rq = select_run_queue::<kernel_guard::NoOp>(&task);
+        debug!(
+            "task migrate: {} to run_queue {}",
+            task.id_name(),
+            rq.inner.cpu_id
+        );
+        rq.inner.claim_task(&task);
+        rq.inner
+            .with_scheduler(|scheduler| scheduler.put_prev_task(task, false));
+        #[cfg(feature = "tickless")]
+        crate::timers::notify_enqueue(rq.inner.cpu_id);
+    } else if task.is_running() && !allowed(task) {
+        #[cfg(all(feature = "preempt", feature = "ipi"))]
+        {
+            let task = task.clone();
+            axipi::run_on_cpu(task.cpu_id() as usize, move || {
+                task.set_preempt_pending(true)
+            });
+        }
+        #[cfg(all(feature = "preempt", not(feature = "ipi")))]
+        task.set_preempt_pending(true);
+    }
+}
+
+/// Calls `f` with the scheduler of the run queue `task` belongs to, that is the
+/// one it is ready in, or the one it last ran on.
+pub(crate) fn with_task_scheduler<R>(task: &AxTaskRef, f: impl FnOnce(&mut Scheduler) -> R) -> R {
+    let _guard = kernel_guard::NoPreemptIrqSave::new();
+    let mut f = Some(f);
+    loop {
+        let cpu_id = task.cpu_id() as usize;
+        #[cfg(feature = "smp")]
+        let rq = get_run_queue(cpu_id);
+        #[cfg(not(feature = "smp"))]
+        // Safety: IRQs and preemption are disabled by the guard.
+        let rq = unsafe { RUN_QUEUE.current_ref_mut_raw() };
+        // A task moving to another run queue gets its `cpu_id` updated before
+        // being put into the scheduler of that run queue.
+        let ret = rq.with_scheduler(|scheduler| {
+            (task.cpu_id() as usize == cpu_id).then(|| f.take().unwrap()(scheduler))
+        });
+        if let Some(ret) = ret {
+            return ret;
+        }
+    }
 }
 
 /// Clear the `on_cpu` field of previous task running on this CPU.
@@ -688,6 +1099,7 @@ pub(crate) fn init() {
     unsafe {
         RUN_QUEUES[cpu_id].write(RUN_QUEUE.current_ref_mut_raw());
     }
+    RUN_QUEUES_ONLINE[cpu_id].store(true, Ordering::Release);
 }
 
 pub(crate) fn init_secondary() {
@@ -707,4 +1119,5 @@ pub(crate) fn init_secondary() {
     unsafe {
         RUN_QUEUES[cpu_id].write(RUN_QUEUE.current_ref_mut_raw());
     }
+    RUN_QUEUES_ONLINE[cpu_id].store(true, Ordering::Release);
 }
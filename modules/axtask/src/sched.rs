@@ -0,0 +1,431 @@
+//! The scheduler of a run queue.
+//!
+//! Tasks are divided into two classes: the real-time class, with fixed
+//! priorities and the [`Fifo`](SchedulingPolicy::Fifo) and
+//! [`RoundRobin`](SchedulingPolicy::RoundRobin) policies, and the fair class,
+//! scheduled by the scheduler selected with the `sched-*` features. A ready
+//! real-time task always runs before the tasks of the fair class.
+
+use alloc::collections::VecDeque;
+#[cfg(feature = "smp")]
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicIsize, AtomicU8, AtomicUsize, Ordering};
+
+use axsched::BaseScheduler;
+
+use crate::{AxTaskRef, FairScheduler};
+
+/// The lowest priority of a real-time task.
+pub const MIN_RT_PRIORITY: u8 = 1;
+/// The highest priority of a real-time task.
+pub const MAX_RT_PRIORITY: u8 = 99;
+
+/// The time slice of [`RoundRobin`](SchedulingPolicy::RoundRobin) tasks, in
+/// ticks.
+const RT_TIME_SLICE: usize = 10;
+/// The number of ready tasks looked at when stealing one for another CPU.
+#[cfg(feature = "smp")]
+const STEAL_MAX_TRIES: usize = 8;
+
+/// The scheduling policy of a task.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// The fair class. The priority is the one of the underlying scheduler
+    /// (e.g. the nice value for CFS).
+    Normal = 0,
+    /// Real-time, running until it blocks, yields or is preempted by a task of
+    /// higher priority.
+    Fifo = 1,
+    /// Real-time, like [`Fifo`](Self::Fifo) but sharing the CPU with tasks of
+    /// the same priority in time slices.
+    RoundRobin = 2,
+}
+
+impl SchedulingPolicy {
+    /// Whether the policy belongs to the real-time class.
+    pub const fn is_realtime(self) -> bool {
+        !matches!(self, Self::Normal)
+    }
+}
+
+impl From<u8> for SchedulingPolicy {
+    #[inline]
+    fn from(policy: u8) -> Self {
+        match policy {
+            0 => Self::Normal,
+            1 => Self::Fifo,
+            2 => Self::RoundRobin,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Scheduling state of a task, besides the one kept by the fair class.
+pub(crate) struct SchedEntity {
+    policy: AtomicU8,
+    rt_priority: AtomicU8,
+    /// The priority of the task in the fair class.
+    fair_priority: AtomicIsize,
+    /// The real-time priority the task is boosted to, by priority inheritance,
+    /// or `0` if it is not boosted.
+    boost: AtomicU8,
+    /// Ticks left in the time slice of a round-robin task.
+    time_slice: AtomicUsize,
+    /// Whether the task is in the scheduler of a run queue.
+    queued: AtomicBool,
+}
+
+impl SchedEntity {
+    pub const fn new() -> Self {
+        Self {
+            policy: AtomicU8::new(SchedulingPolicy::Normal as u8),
+            rt_priority: AtomicU8::new(0),
+            fair_priority: AtomicIsize::new(0),
+            boost: AtomicU8::new(0),
+            time_slice: AtomicUsize::new(RT_TIME_SLICE),
+            queued: AtomicBool::new(false),
+        }
+    }
+
+    /// The policy set for the task.
+    #[inline]
+    pub fn base_policy(&self) -> SchedulingPolicy {
+        self.policy.load(Ordering::Acquire).into()
+    }
+
+    /// The real-time priority set for the task.
+    #[inline]
+    pub fn base_rt_priority(&self) -> u8 {
+        self.rt_priority.load(Ordering::Acquire)
+    }
+
+    /// The priority of the task in the fair class.
+    #[inline]
+    pub fn fair_priority(&self) -> isize {
+        self.fair_priority.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn boost(&self) -> u8 {
+        self.boost.load(Ordering::Acquire)
+    }
+
+    /// The policy the task is scheduled with: a boosted task of the fair class
+    /// is scheduled as a [`Fifo`](SchedulingPolicy::Fifo) one.
+    #[inline]
+    pub fn policy(&self) -> SchedulingPolicy {
+        match self.base_policy() {
+            SchedulingPolicy::Normal if self.boost() > 0 => SchedulingPolicy::Fifo,
+            policy => policy,
+        }
+    }
+
+    /// The real-time priority the task is scheduled with, including the boost.
+    #[inline]
+    pub fn rt_priority(&self) -> u8 {
+        self.base_rt_priority().max(self.boost())
+    }
+
+    fn set_policy(&self, policy: SchedulingPolicy, rt_priority: u8) {
+        self.policy.store(policy as u8, Ordering::Release);
+        self.rt_priority.store(rt_priority, Ordering::Release);
+        self.time_slice.store(RT_TIME_SLICE, Ordering::Release);
+    }
+
+    #[inline]
+    pub fn is_queued(&self) -> bool {
+        self.queued.load(Ordering::Acquire)
+    }
+
+    fn set_queued(&self, queued: bool) {
+        self.queued.store(queued, Ordering::Release)
+    }
+}
+
+/// Returns whether `task`, becoming ready, should preempt `current`.
+#[cfg(feature = "preempt")]
+pub(crate) fn should_preempt(task: &AxTaskRef, current: &AxTaskRef) -> bool {
+    let task = task.sched();
+    let current = current.sched();
+    task.policy().is_realtime()
+        && (!current.policy().is_realtime() || task.rt_priority() > current.rt_priority())
+}
+
+/// The real-time class: one FIFO queue per priority.
+struct RtScheduler {
+    queues: [VecDeque<AxTaskRef>; MAX_RT_PRIORITY as usize + 1],
+    /// Bit `n` is set if the queue of priority `n` is not empty.
+    bitmap: u128,
+}
+
+impl RtScheduler {
+    const fn new() -> Self {
+        Self {
+            queues: [const { VecDeque::new() }; MAX_RT_PRIORITY as usize + 1],
+            bitmap: 0,
+        }
+    }
+
+    fn highest_priority(&self) -> Option<u8> {
+        (self.bitmap != 0).then(|| self.bitmap.ilog2() as u8)
+    }
+
+    fn push(&mut self, task: AxTaskRef, front: bool) {
+        let prio = task.sched().rt_priority();
+        let queue = &mut self.queues[prio as usize];
+        if front {
+            queue.push_front(task);
+        } else {
+            queue.push_back(task);
+        }
+        self.bitmap |= 1 << prio;
+    }
+
+    fn pop(&mut self) -> Option<AxTaskRef> {
+        let prio = self.highest_priority()?;
+        let queue = &mut self.queues[prio as usize];
+        let task = queue.pop_front();
+        if queue.is_empty() {
+            self.bitmap &= !(1 << prio);
+        }
+        task
+    }
+
+    fn remove(&mut self, task: &AxTaskRef) -> Option<AxTaskRef> {
+        let prio = task.sched().rt_priority();
+        let queue = &mut self.queues[prio as usize];
+        let task = queue
+            .iter()
+            .position(|it| AxTaskRef::ptr_eq(it, task))
+            .and_then(|index| queue.remove(index));
+        if queue.is_empty() {
+            self.bitmap &= !(1 << prio);
+        }
+        task
+    }
+
+    /// Iterates over the ready tasks, in the order they would run.
+    #[cfg(feature = "smp")]
+    fn iter(&self) -> impl Iterator<Item = &AxTaskRef> {
+        self.queues.iter().rev().flatten()
+    }
+}
+
+/// The scheduler of a run queue, with a real-time class above the fair one.
+pub(crate) struct Scheduler {
+    rt: RtScheduler,
+    fair: FairScheduler,
+    /// The number of ready tasks in both classes.
+    len: usize,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            rt: RtScheduler::new(),
+            fair: FairScheduler::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of ready tasks.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn scheduler_name() -> &'static str {
+        FairScheduler::scheduler_name()
+    }
+
+    pub fn add_task(&mut self, task: AxTaskRef) {
+        task.sched().set_queued(true);
+        self.len += 1;
+        if task.sched().policy().is_realtime() {
+            self.rt.push(task, false);
+        } else {
+            self.fair.add_task(task);
+        }
+    }
+
+    pub fn remove_task(&mut self, task: &AxTaskRef) -> Option<AxTaskRef> {
+        if !task.sched().is_queued() {
+            return None;
+        }
+        let task = if task.sched().policy().is_realtime() {
+            self.rt.remove(task)
+        } else {
+            self.fair.remove_task(task)
+        }?;
+        task.sched().set_queued(false);
+        self.len -= 1;
+        Some(task)
+    }
+
+    pub fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        let task = self.rt.pop().or_else(|| self.fair.pick_next_task())?;
+        task.sched().set_queued(false);
+        self.len -= 1;
+        Some(task)
+    }
+
+    /// Takes a ready task for the CPU `cpu_id`, which must be allowed to run
+    /// it, and the task must have finished switching out of its last CPU.
+    ///
+    /// The tasks that would run first are looked at first.
+    #[cfg(feature = "smp")]
+    pub fn steal_task(&mut self, cpu_id: usize) -> Option<AxTaskRef> {
+        let can_steal = |task: &AxTaskRef| task.cpumask().get(cpu_id) && !task.on_cpu();
+        // The real-time queues are looked at in place.
+        let mut tries = 0;
+        let found = self
+            .rt
+            .iter()
+            .take(STEAL_MAX_TRIES)
+            .inspect(|_| tries += 1)
+            .find(|&it| can_steal(it))
+            .cloned();
+        let task = match found {
+            Some(task) => self.rt.remove(&task),
+            None => {
+                let mut skipped = Vec::new();
+                let mut stolen = None;
+                for _ in tries..STEAL_MAX_TRIES {
+                    let Some(task) = self.fair.pick_next_task() else {
+                        break;
+                    };
+                    if can_steal(&task) {
+                        stolen = Some(task);
+                        break;
+                    }
+                    skipped.push(task);
+                }
+                self.restore_fair(skipped);
+                stolen
+            }
+        }?;
+        task.sched().set_queued(false);
+        self.len -= 1;
+        Some(task)
+    }
+
+    /// Puts back the tasks of the fair class taken out by
+    /// [`steal_task`](Self::steal_task), in their original places.
+    #[cfg(feature = "smp")]
+    fn restore_fair(&mut self, skipped: Vec<AxTaskRef>) {
+        if skipped.is_empty() {
+            return;
+        }
+        cfg_if::cfg_if! {
+            if #[cfg(all(feature = "sched-cfs", not(feature = "sched-rr")))] {
+                // The tasks are ordered by their own virtual runtime.
+                for task in skipped {
+                    self.fair.put_prev_task(task, false);
+                }
+            } else {
+                // The queue is in order: requeue the remaining tasks behind
+                // the skipped ones, which keeps their time slices.
+                let remaining: Vec<_> = core::iter::from_fn(|| self.fair.pick_next_task()).collect();
+                for task in skipped.into_iter().chain(remaining) {
+                    self.fair.add_task(task);
+                }
+            }
+        }
+    }
+
+    pub fn put_prev_task(&mut self, prev: AxTaskRef, preempt: bool) {
+        let sched = prev.sched();
+        sched.set_queued(true);
+        self.len += 1;
+        match sched.policy() {
+            SchedulingPolicy::Normal => self.fair.put_prev_task(prev, preempt),
+            SchedulingPolicy::Fifo => self.rt.push(prev, preempt),
+            SchedulingPolicy::RoundRobin => {
+                // A preempted task keeps its place until its time slice is
+                // used up.
+                let front = preempt && sched.time_slice.load(Ordering::Acquire) > 0;
+                if !front {
+                    sched.time_slice.store(RT_TIME_SLICE, Ordering::Release);
+                }
+                self.rt.push(prev, front);
+            }
+        }
+    }
+
+    /// Accounts a tick to the running task `current`, returning whether it
+    /// should be preempted.
+    pub fn task_tick(&mut self, current: &AxTaskRef) -> bool {
+        let sched = current.sched();
+        match sched.policy() {
+            SchedulingPolicy::Normal => {
+                self.fair.task_tick(current) || self.rt.highest_priority().is_some()
+            }
+            policy => {
+                let expired = policy == SchedulingPolicy::RoundRobin
+                    && !sched
+                        .time_slice
+                        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |it| it.checked_sub(1))
+                        .is_ok_and(|it| it > 1);
+                expired
+                    || self
+                        .rt
+                        .highest_priority()
+                        .is_some_and(|prio| prio > sched.rt_priority())
+            }
+        }
+    }
+
+    /// Sets the priority of `task` within its class.
+    pub fn set_priority(&mut self, task: &AxTaskRef, prio: isize) -> bool {
+        match task.sched().base_policy() {
+            SchedulingPolicy::Normal => {
+                let ok = self.fair.set_priority(task, prio);
+                if ok {
+                    task.sched().fair_priority.store(prio, Ordering::Release);
+                }
+                ok
+            }
+            policy => self.set_policy(task, policy, prio),
+        }
+    }
+
+    /// Sets the scheduling policy and priority of `task`, moving it to the
+    /// queue of its new class if it is ready.
+    ///
+    /// With the [`Normal`](SchedulingPolicy::Normal) policy, `prio` is the
+    /// priority of the fair class, `0` being always accepted.
+    pub fn set_policy(&mut self, task: &AxTaskRef, policy: SchedulingPolicy, prio: isize) -> bool {
+        let valid = if policy.is_realtime() {
+            (MIN_RT_PRIORITY as isize..=MAX_RT_PRIORITY as isize).contains(&prio)
+        } else {
+            // The fair class checks the priority, and only sets it if valid.
+            self.fair.set_priority(task, prio) || prio == 0
+        };
+        if !valid {
+            return false;
+        }
+        if !policy.is_realtime() {
+            task.sched().fair_priority.store(prio, Ordering::Release);
+        }
+        let queued = self.remove_task(task);
+        let rt_priority = if policy.is_realtime() { prio as u8 } else { 0 };
+        task.sched().set_policy(policy, rt_priority);
+        if let Some(task) = queued {
+            self.add_task(task);
+        }
+        true
+    }
+
+    /// Boosts `task` to the real-time priority `prio` at least, or removes the
+    /// boost if `prio` is `0`, moving it to its new queue if it is ready.
+    pub fn set_boost(&mut self, task: &AxTaskRef, prio: u8) {
+        if task.sched().boost() == prio {
+            return;
+        }
+        let queued = self.remove_task(task);
+        task.sched().boost.store(prio, Ordering::Release);
+        if let Some(task) = queued {
+            self.add_task(task);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Kernel stacks of tasks.
+//!
+//! Stacks are allocated from the global heap, unless the `stack-guard` feature
+//! is enabled: they are then mapped by the runtime through [`TaskStackIf`],
+//! with an unmapped guard page below each of them so that an overflow faults
+//! instead of silently corrupting the memory nearby.
+//!
+//! With the `stack-canary` feature, stacks are filled with a known pattern
+//! when allocated, to measure how deep they have been used.
+
+#[cfg(not(feature = "stack-guard"))]
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+#[cfg(feature = "stack-guard")]
+use memory_addr::PAGE_SIZE_4K;
+use memory_addr::VirtAddr;
+
+/// The interface to map kernel stacks of tasks, implemented by the runtime
+/// when the `stack-guard` feature is enabled.
+#[cfg(feature = "stack-guard")]
+#[crate_interface::def_interface]
+pub trait TaskStackIf {
+    /// Maps a stack of `size` bytes, with at least one unmapped page right
+    /// below it, and returns its lowest address.
+    fn alloc_stack(size: usize) -> Option<VirtAddr>;
+
+    /// Unmaps a stack returned by [`TaskStackIf::alloc_stack`].
+    fn dealloc_stack(bottom: VirtAddr, size: usize);
+}
+
+/// The byte pattern filling unused stacks with the `stack-canary` feature.
+#[cfg(feature = "stack-canary")]
+const STACK_CANARY: u8 = 0xcc;
+
+pub(crate) struct TaskStack {
+    ptr: NonNull<u8>,
+    size: usize,
+}
+
+impl TaskStack {
+    pub fn alloc(size: usize) -> Self {
+        #[cfg(not(feature = "stack-guard"))]
+        let ptr = {
+            let layout = Layout::from_size_align(size, 16).unwrap();
+            NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap()
+        };
+        #[cfg(feature = "stack-guard")]
+        let ptr = {
+            let bottom = crate_interface::call_interface!(TaskStackIf::alloc_stack(size))
+                .expect("failed to map task stack");
+            NonNull::new(bottom.as_mut_ptr()).unwrap()
+        };
+        #[cfg(feature = "stack-canary")]
+        unsafe {
+            ptr.write_bytes(STACK_CANARY, size)
+        };
+        Self { ptr, size }
+    }
+
+    pub const fn top(&self) -> VirtAddr {
+        unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.size)) }
+    }
+
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the maximum number of bytes of the stack used so far, found
+    /// from the deepest byte not equal to the canary pattern anymore.
+    #[cfg(feature = "stack-canary")]
+    pub fn high_water_mark(&self) -> usize {
+        let stack = unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.size) };
+        let untouched = stack
+            .iter()
+            .position(|&byte| byte != STACK_CANARY)
+            .unwrap_or(self.size);
+        self.size - untouched
+    }
+}
+
+impl Drop for TaskStack {
+    fn drop(&mut self) {
+        #[cfg(not(feature = "stack-guard"))]
+        unsafe {
+            let layout = Layout::from_size_align(self.size, 16).unwrap();
+            alloc::alloc::dealloc(self.ptr.as_ptr(), layout)
+        }
+        #[cfg(feature = "stack-guard")]
+        crate_interface::call_interface!(TaskStackIf::dealloc_stack(
+            VirtAddr::from_mut_ptr_of(self.ptr.as_ptr()),
+            self.size
+        ));
+    }
+}
+
+/// Checks whether a page fault at `vaddr` in kernel mode is an overflow of the
+/// stack of the current task, in which case it panics, reporting the task;
+/// the panic handler then prints the backtrace.
+///
+/// It is called by the runtime when a trap finds the stack pointer too close
+/// to the guard page, on an exception stack of the CPU: the trap must not be
+/// handled on the overflowed stack itself to get there.
+#[cfg(feature = "stack-guard")]
+pub fn check_stack_overflow(vaddr: VirtAddr) {
+    let Some(curr) = crate::current_may_uninit() else {
+        return;
+    };
+    let (Some(top), Some(size)) = (curr.kernel_stack_top(), curr.kernel_stack_size()) else {
+        return;
+    };
+    let bottom = top - size;
+    if (bottom - PAGE_SIZE_4K..bottom).contains(&vaddr) {
+        panic!(
+            "kernel stack overflow in {} at {:#x}, stack size {:#x}",
+            curr.id_name(),
+            vaddr,
+            size
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! CPU time accounting of tasks.
+//!
+//! The time of a task is charged when it is switched out, and when it enters
+//! or leaves the user mode, see [`account_user_enter`](crate::account_user_enter)
+//! and [`account_user_exit`](crate::account_user_exit).
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// CPU time statistics of a task, in nanoseconds of the monotonic clock.
+pub(crate) struct TaskStats {
+    user_nanos: AtomicU64,
+    kernel_nanos: AtomicU64,
+    /// Time spent ready in run queues.
+    wait_nanos: AtomicU64,
+    voluntary_switches: AtomicU64,
+    involuntary_switches: AtomicU64,
+    /// When the time of the running task was last charged.
+    last_update: AtomicU64,
+    in_user: AtomicBool,
+    /// When the task became ready, or `0` if it is not waiting in a run queue.
+    ready_since: AtomicU64,
+    last_cpu: AtomicUsize,
+}
+
+impl TaskStats {
+    pub const fn new() -> Self {
+        Self {
+            user_nanos: AtomicU64::new(0),
+            kernel_nanos: AtomicU64::new(0),
+            wait_nanos: AtomicU64::new(0),
+            voluntary_switches: AtomicU64::new(0),
+            involuntary_switches: AtomicU64::new(0),
+            last_update: AtomicU64::new(0),
+            in_user: AtomicBool::new(false),
+            ready_since: AtomicU64::new(0),
+            last_cpu: AtomicUsize::new(0),
+        }
+    }
+
+    /// Charges the time since the last update to the current mode of the
+    /// running task.
+    fn update(&self, now: u64) {
+        let elapsed = now.saturating_sub(self.last_update.swap(now, Ordering::AcqRel));
+        if self.in_user.load(Ordering::Acquire) {
+            self.user_nanos.fetch_add(elapsed, Ordering::Relaxed);
+        } else {
+            self.kernel_nanos.fetch_add(elapsed, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the switch of the running task to the user mode, or back to the
+    /// kernel mode.
+    pub fn set_user(&self, in_user: bool, now: u64) {
+        self.update(now);
+        self.in_user.store(in_user, Ordering::Release);
+    }
+
+    /// Records that the task was put into a run queue.
+    pub fn mark_ready(&self, now: u64) {
+        self.ready_since.store(now, Ordering::Release);
+    }
+
+    /// Records that the task starts running on the CPU `cpu_id`.
+    pub fn switch_in(&self, now: u64, cpu_id: usize) {
+        let ready_since = self.ready_since.swap(0, Ordering::AcqRel);
+        if ready_since != 0 {
+            self.wait_nanos
+                .fetch_add(now.saturating_sub(ready_since), Ordering::Relaxed);
+        }
+        self.last_update.store(now, Ordering::Release);
+        self.last_cpu.store(cpu_id, Ordering::Release);
+    }
+
+    /// Records that the task stops running, either because it blocked or
+    /// exited (`voluntary`), or because it was preempted.
+    pub fn switch_out(&self, now: u64, voluntary: bool) {
+        self.update(now);
+        if voluntary {
+            self.voluntary_switches.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.involuntary_switches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the time not charged yet if the task is `running`.
+    fn pending_nanos(&self, running: bool, user: bool) -> u64 {
+        if running && self.in_user.load(Ordering::Acquire) == user {
+            let now = axhal::time::monotonic_time_nanos();
+            now.saturating_sub(self.last_update.load(Ordering::Acquire))
+        } else {
+            0
+        }
+    }
+
+    pub fn user_nanos(&self, running: bool) -> u64 {
+        self.user_nanos.load(Ordering::Relaxed) + self.pending_nanos(running, true)
+    }
+
+    pub fn kernel_nanos(&self, running: bool) -> u64 {
+        self.kernel_nanos.load(Ordering::Relaxed) + self.pending_nanos(running, false)
+    }
+
+    pub fn wait_nanos(&self) -> u64 {
+        self.wait_nanos.load(Ordering::Relaxed)
+    }
+
+    pub fn voluntary_switches(&self) -> u64 {
+        self.voluntary_switches.load(Ordering::Relaxed)
+    }
+
+    pub fn involuntary_switches(&self) -> u64 {
+        self.involuntary_switches.load(Ordering::Relaxed)
+    }
+
+    pub fn last_cpu(&self) -> usize {
+        self.last_cpu.load(Ordering::Acquire)
+    }
+}
@@ -2,14 +2,13 @@ use alloc::{boxed::Box, string::String, sync::Arc};
 #[cfg(feature = "preempt")]
 use core::sync::atomic::AtomicUsize;
 use core::{
-    alloc::Layout,
     cell::{Cell, UnsafeCell},
     fmt,
     future::poll_fn,
     ops::Deref,
-    ptr::NonNull,
     sync::atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicU32, AtomicU64, Ordering},
     task::{Poll, Waker},
+    time::Duration,
 };
 
 use axhal::context::TaskContext;
@@ -19,7 +18,13 @@ use futures::task::AtomicWaker;
 use kspin::SpinNoIrq;
 use memory_addr::{VirtAddr, align_up_4k};
 
-use crate::{AxCpuMask, AxTask, AxTaskRef, future::block_on};
+use crate::{
+    AxCpuMask, AxTask, AxTaskRef,
+    future::block_on,
+    sched::{SchedEntity, SchedulingPolicy},
+    stack::TaskStack,
+    stats::TaskStats,
+};
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -67,6 +72,11 @@ pub struct TaskInner {
     interrupted: AtomicBool,
     interrupt_waker: AtomicWaker,
 
+    /// Scheduling policy and real-time priority.
+    sched: SchedEntity,
+    /// CPU time statistics.
+    stats: TaskStats,
+
     /// Used to indicate the CPU ID where the task is running or will run.
     cpu_id: AtomicU32,
     /// Used to indicate whether the task is running on a CPU.
@@ -101,6 +111,12 @@ impl TaskId {
     pub const fn as_u64(&self) -> u64 {
         self.0
     }
+
+    /// Convert a `u64` to a task ID, e.g. to look the task up with
+    /// [`find_task`](crate::find_task).
+    pub const fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
 }
 
 impl From<u8> for TaskState {
@@ -159,6 +175,12 @@ impl TaskInner {
         *self.name.lock() = String::from(name);
     }
 
+    /// Calls `f` with the name of the task without waiting for it, or returns
+    /// `None` if it is being accessed.
+    pub(crate) fn try_with_name<R>(&self, f: impl FnOnce(&str) -> R) -> Option<R> {
+        self.name.try_lock().map(|name| f(&name))
+    }
+
     /// Get a combined string of the task ID and name.
     pub fn id_name(&self) -> alloc::string::String {
         alloc::format!("Task({}, {:?})", self.id.as_u64(), self.name())
@@ -205,6 +227,23 @@ impl TaskInner {
         }
     }
 
+    /// Returns the size of the kernel stack, or `None` for the tasks running
+    /// on the boot stack.
+    #[inline]
+    pub const fn kernel_stack_size(&self) -> Option<usize> {
+        match &self.kstack {
+            Some(s) => Some(s.size()),
+            None => None,
+        }
+    }
+
+    /// Returns the maximum number of bytes of the kernel stack used so far,
+    /// or `None` for the tasks running on the boot stack.
+    #[cfg(feature = "stack-canary")]
+    pub fn stack_high_water_mark(&self) -> Option<usize> {
+        self.kstack.as_ref().map(TaskStack::high_water_mark)
+    }
+
     /// Returns the CPU ID where the task is running or will run.
     ///
     /// Note: the task may not be running on the CPU, it just exists in the run
@@ -222,6 +261,12 @@ impl TaskInner {
         *self.cpumask.lock()
     }
 
+    /// Gets the cpu affinity mask of the task without waiting for it, or
+    /// returns `None` if it is being accessed.
+    pub(crate) fn try_cpumask(&self) -> Option<AxCpuMask> {
+        self.cpumask.try_lock().map(|cpumask| *cpumask)
+    }
+
     /// Sets the cpu affinity mask of the task.
     ///
     /// # Arguments
@@ -231,6 +276,75 @@ impl TaskInner {
         *self.cpumask.lock() = cpumask
     }
 
+    /// Returns the scheduling policy of the task.
+    #[inline]
+    pub fn scheduling_policy(&self) -> SchedulingPolicy {
+        self.sched.base_policy()
+    }
+
+    /// Returns the real-time priority of the task, or `0` if it is not in the
+    /// real-time class.
+    #[inline]
+    pub fn rt_priority(&self) -> u8 {
+        self.sched.base_rt_priority()
+    }
+
+    /// Returns the priority of the task in the fair class (e.g. its nice value
+    /// for CFS), which it keeps while in the real-time class.
+    #[inline]
+    pub fn priority(&self) -> isize {
+        self.sched.fair_priority()
+    }
+
+    /// Returns the real-time priority the task is scheduled with, which is
+    /// above [`rt_priority`](Self::rt_priority) while it is boosted by
+    /// [`set_priority_boost`](crate::set_priority_boost).
+    #[inline]
+    pub fn effective_rt_priority(&self) -> u8 {
+        self.sched.rt_priority()
+    }
+
+    /// Returns the time the task has run in user mode, as reported by
+    /// [`account_user_enter`](crate::account_user_enter) and
+    /// [`account_user_exit`](crate::account_user_exit).
+    #[inline]
+    pub fn user_time(&self) -> Duration {
+        Duration::from_nanos(self.stats.user_nanos(self.is_running()))
+    }
+
+    /// Returns the time the task has run in kernel mode.
+    #[inline]
+    pub fn kernel_time(&self) -> Duration {
+        Duration::from_nanos(self.stats.kernel_nanos(self.is_running()))
+    }
+
+    /// Returns the time the task has spent ready in run queues, waiting for a
+    /// CPU.
+    #[inline]
+    pub fn wait_time(&self) -> Duration {
+        Duration::from_nanos(self.stats.wait_nanos())
+    }
+
+    /// Returns the number of times the task gave up the CPU because it blocked
+    /// or exited.
+    #[inline]
+    pub fn voluntary_switches(&self) -> u64 {
+        self.stats.voluntary_switches()
+    }
+
+    /// Returns the number of times the task was preempted, or yielded the CPU
+    /// while staying ready.
+    #[inline]
+    pub fn involuntary_switches(&self) -> u64 {
+        self.stats.involuntary_switches()
+    }
+
+    /// Returns the ID of the CPU the task last ran on.
+    #[inline]
+    pub fn last_cpu(&self) -> usize {
+        self.stats.last_cpu()
+    }
+
     /// Registers a waker to be woken when the task is interrupted.
     #[inline]
     pub fn on_interrupt(&self, waker: &Waker) {
@@ -265,6 +379,8 @@ impl TaskInner {
             cpumask: SpinNoIrq::new(AxCpuMask::full()),
             interrupted: AtomicBool::new(false),
             interrupt_waker: AtomicWaker::new(),
+            sched: SchedEntity::new(),
+            stats: TaskStats::new(),
             cpu_id: AtomicU32::new(0),
             #[cfg(feature = "smp")]
             on_cpu: AtomicBool::new(false),
@@ -303,7 +419,9 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
+        let task = Arc::new(AxTask::new(self));
+        crate::registry::register(&task);
+        task
     }
 
     #[inline]
@@ -399,6 +517,16 @@ impl TaskInner {
         self.wait_for_exit.wake();
     }
 
+    #[inline]
+    pub(crate) const fn sched(&self) -> &SchedEntity {
+        &self.sched
+    }
+
+    #[inline]
+    pub(crate) const fn stats(&self) -> &TaskStats {
+        &self.stats
+    }
+
     #[inline]
     pub(crate) const unsafe fn ctx_mut_ptr(&self) -> *mut TaskContext {
         self.ctx.get()
@@ -445,31 +573,16 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
-    }
-}
-
-struct TaskStack {
-    ptr: NonNull<u8>,
-    layout: Layout,
-}
-
-impl TaskStack {
-    pub fn alloc(size: usize) -> Self {
-        let layout = Layout::from_size_align(size, 16).unwrap();
-        Self {
-            ptr: NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap(),
-            layout,
+        #[cfg(feature = "stack-canary")]
+        if let Some(used) = self.stack_high_water_mark() {
+            debug!(
+                "task stack usage: {}, {:#x} of {:#x} bytes",
+                self.id_name(),
+                used,
+                self.kernel_stack_size().unwrap_or(0)
+            );
         }
-    }
-
-    pub const fn top(&self) -> VirtAddr {
-        unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
-    }
-}
-
-impl Drop for TaskStack {
-    fn drop(&mut self) {
-        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+        crate::registry::unregister(self.id);
     }
 }
 
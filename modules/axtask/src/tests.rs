@@ -1,6 +1,12 @@
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::Poll,
+};
 use std::sync::{Mutex, Once};
 
+use futures::task::AtomicWaker;
+
 use crate::{api as axtask, current};
 
 static INIT: Once = Once::new();
@@ -33,6 +39,145 @@ fn test_sched_fifo() {
     }
 }
 
+#[test]
+fn test_sched_rt_priority() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    const POLICIES: [(axtask::SchedulingPolicy, isize); 4] = [
+        (axtask::SchedulingPolicy::Normal, 0),
+        (axtask::SchedulingPolicy::Fifo, 10),
+        (axtask::SchedulingPolicy::RoundRobin, 50),
+        (axtask::SchedulingPolicy::Fifo, 30),
+    ];
+
+    for (i, (policy, prio)) in POLICIES.into_iter().enumerate() {
+        let task = axtask::spawn(move || ORDER.lock().unwrap().push(i), format!("RT{i}"));
+        assert!(axtask::set_scheduling_policy(&task, policy, prio));
+        assert_eq!(task.scheduling_policy(), policy);
+    }
+    let task = axtask::spawn(|| {}, "RT-invalid".into());
+    assert!(!axtask::set_scheduling_policy(
+        &task,
+        axtask::SchedulingPolicy::Fifo,
+        100
+    ));
+
+    while ORDER.lock().unwrap().len() < POLICIES.len() {
+        axtask::yield_now();
+    }
+    // Real-time tasks run first, by decreasing priority.
+    assert_eq!(*ORDER.lock().unwrap(), [2, 3, 1, 0]);
+}
+
+#[test]
+fn test_set_invalid_policy() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let task = axtask::spawn(|| {}, "RT-keep".into());
+    assert!(axtask::set_scheduling_policy(
+        &task,
+        axtask::SchedulingPolicy::Fifo,
+        10
+    ));
+    // No fair scheduler accepts this priority: the task is left untouched.
+    assert!(!axtask::set_scheduling_policy(
+        &task,
+        axtask::SchedulingPolicy::Normal,
+        100
+    ));
+    assert_eq!(task.scheduling_policy(), axtask::SchedulingPolicy::Fifo);
+    assert_eq!(task.rt_priority(), 10);
+    task.join();
+}
+
+#[test]
+fn test_rt_wakeup_order() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    static READY: AtomicBool = AtomicBool::new(false);
+    static WAKER: AtomicWaker = AtomicWaker::new();
+
+    let spawn_fifo = |i: usize, f: fn()| {
+        let task = axtask::spawn(
+            move || {
+                f();
+                ORDER.lock().unwrap().push(i);
+            },
+            format!("RT{i}"),
+        );
+        assert!(axtask::set_scheduling_policy(
+            &task,
+            axtask::SchedulingPolicy::Fifo,
+            10
+        ));
+        task
+    };
+
+    let sleeper = spawn_fifo(2, || {
+        crate::future::block_on(poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            if READY.load(Ordering::Acquire) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }))
+    });
+    // Let the sleeper block.
+    axtask::yield_now();
+    let tasks = [spawn_fifo(0, || {}), spawn_fifo(1, || {})];
+
+    // The woken up task runs after the ready tasks of the same priority.
+    READY.store(true, Ordering::Release);
+    WAKER.wake();
+    sleeper.join();
+    for task in tasks {
+        task.join();
+    }
+    assert_eq!(*ORDER.lock().unwrap(), [0, 1, 2]);
+}
+
+#[test]
+fn test_set_other_task() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    let tasks: Vec<_> = [10, 20]
+        .into_iter()
+        .enumerate()
+        .map(|(i, prio)| {
+            let task = axtask::spawn(move || ORDER.lock().unwrap().push(i), format!("T{i}"));
+            assert!(axtask::set_scheduling_policy(
+                &task,
+                axtask::SchedulingPolicy::Fifo,
+                prio
+            ));
+            task
+        })
+        .collect();
+
+    // Change the tasks while they are ready.
+    assert!(axtask::set_task_priority(&tasks[0], 30));
+    assert_eq!(tasks[0].rt_priority(), 30);
+    assert!(!axtask::set_task_priority(&tasks[1], 0));
+    assert!(axtask::set_affinity(
+        &tasks[1],
+        axtask::AxCpuMask::one_shot(0)
+    ));
+    assert!(!axtask::set_affinity(&tasks[1], axtask::AxCpuMask::new()));
+
+    while ORDER.lock().unwrap().len() < tasks.len() {
+        axtask::yield_now();
+    }
+    assert_eq!(*ORDER.lock().unwrap(), [0, 1]);
+}
+
 #[test]
 fn test_fp_state_switch() {
     let _lock = SERIAL.lock();
@@ -91,3 +236,135 @@ fn test_task_join() {
         assert_eq!(task.join(), i as _);
     }
 }
+
+#[test]
+fn test_task_stats() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_YIELDS: u64 = 3;
+    let tasks: Vec<_> = (0..2)
+        .map(|i| {
+            axtask::spawn(
+                || {
+                    for _ in 0..NUM_YIELDS {
+                        axtask::yield_now();
+                    }
+                },
+                format!("S{i}"),
+            )
+        })
+        .collect();
+
+    for task in tasks {
+        task.join();
+        // The tasks take turns, and exit at last.
+        assert!(task.involuntary_switches() >= NUM_YIELDS);
+        assert_eq!(task.voluntary_switches(), 1);
+        assert_eq!(task.user_time(), core::time::Duration::ZERO);
+        assert_eq!(task.last_cpu(), 0);
+    }
+    assert!(axtask::sched_snapshot().run_queues[0].nr_switches > 0);
+}
+
+#[test]
+fn test_task_registry() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let task = axtask::spawn(|| {}, "registered".into());
+    let found = axtask::find_task(task.id()).unwrap();
+    assert!(axtask::AxTaskRef::ptr_eq(&found, &task));
+    assert!(axtask::find_task(axtask::TaskId::from_u64(u64::MAX)).is_none());
+
+    let mut seen = false;
+    axtask::for_each_task(|it| seen |= it.id() == task.id());
+    assert!(seen);
+
+    let mut dump = String::new();
+    axtask::dump_tasks(&mut dump).unwrap();
+    assert!(
+        dump.lines()
+            .any(|line| line.contains("registered") && line.contains("normal:0"))
+    );
+    task.join();
+}
+
+#[test]
+fn test_priority_boost() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    let normal = axtask::spawn(|| ORDER.lock().unwrap().push(0), "boosted".into());
+    let fifo = axtask::spawn(|| ORDER.lock().unwrap().push(1), "fifo".into());
+    assert!(axtask::set_scheduling_policy(
+        &fifo,
+        axtask::SchedulingPolicy::Fifo,
+        10
+    ));
+
+    // The boosted task runs first, while keeping its own policy.
+    axtask::set_priority_boost(&normal, 20);
+    assert_eq!(normal.effective_rt_priority(), 20);
+    assert_eq!(normal.rt_priority(), 0);
+    assert_eq!(normal.scheduling_policy(), axtask::SchedulingPolicy::Normal);
+
+    while ORDER.lock().unwrap().len() < 2 {
+        axtask::yield_now();
+    }
+    assert_eq!(*ORDER.lock().unwrap(), [0, 1]);
+    axtask::set_priority_boost(&normal, 0);
+    assert_eq!(normal.effective_rt_priority(), 0);
+}
+
+/// Needs the `smp` feature, and a configuration with 2 CPUs at least.
+#[cfg(feature = "smp")]
+#[test]
+fn test_pull_task() {
+    use crate::{run_queue::AxRunQueue, sched::Scheduler, task::TaskInner};
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+    if axconfig::plat::CPU_NUM < 2 {
+        return;
+    }
+
+    let new_task = |name: &str, pinned: bool| {
+        let task = TaskInner::new(|| {}, name.into(), 0x1000).into_arc();
+        task.set_cpu_id(1);
+        if pinned {
+            task.set_cpumask(axtask::AxCpuMask::one_shot(1));
+        }
+        task
+    };
+    // The run queue of CPU 1 starts with its gc task, pinned to it.
+    let busiest = AxRunQueue::new(1);
+    let tasks = [
+        new_task("P0", true),
+        new_task("P1", true),
+        new_task("free", false),
+        new_task("P2", true),
+    ];
+    busiest.with_scheduler(|scheduler| {
+        for task in &tasks {
+            scheduler.add_task(task.clone());
+        }
+    });
+
+    let rq = AxRunQueue::new(0);
+    let task = rq.pull_task(&busiest).unwrap();
+    assert!(axtask::AxTaskRef::ptr_eq(&task, &tasks[2]));
+    assert_eq!(task.cpu_id(), 0);
+    assert_eq!(rq.stats().nr_migrations, 1);
+    assert_eq!(busiest.stats().nr_ready, 4);
+    // No other task can run on CPU 0.
+    assert!(rq.pull_task(&busiest).is_none());
+    assert_eq!(rq.stats().nr_migrations, 1);
+
+    // The skipped tasks are still in their places.
+    let names: Vec<_> = core::iter::from_fn(|| busiest.with_scheduler(Scheduler::pick_next_task))
+        .map(|task| task.name())
+        .collect();
+    assert_eq!(names, ["gc", "P0", "P1", "P2"]);
+}
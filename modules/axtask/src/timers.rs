@@ -1,10 +1,34 @@
-use alloc::{boxed::Box, vec::Vec};
-use core::sync::atomic::{AtomicU64, Ordering};
+//! Timers for sleeping tasks.
+//!
+//! Each CPU owns a hierarchical timer wheel, advanced by [`check_events`] on
+//! every timer tick of that CPU. Deadlines are on the monotonic clock or on the
+//! wall clock (`CLOCK_REALTIME`-style). Wall-clock timers are converted to the
+//! monotonic clock when armed, and armed again whenever the wall clock is set
+//! with [`set_wall_time`].
+//!
+//! A timer is armed on the wheel of the CPU its task runs on, and follows the
+//! task when it is polled on another CPU (see [`TimerKey::rehome`]).
+//!
+//! With the `tickless` feature, an idle CPU stops its periodic tick and only
+//! wakes up for the earliest timer of its wheel, or after [`MAX_IDLE_NANOS`].
+//! Timer callbacks are not called while the tick is stopped.
 
-use axhal::time::{TimeValue, wall_time};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+#[cfg(feature = "tickless")]
+use core::sync::atomic::AtomicBool;
+use core::{
+    mem,
+    sync::atomic::{AtomicU8, AtomicU32, AtomicUsize, Ordering},
+};
+
+use axhal::{
+    percpu::this_cpu_id,
+    time::{NANOS_PER_SEC, TimeValue, epochoffset_nanos, monotonic_time, wall_time},
+};
+#[cfg(feature = "tickless")]
+use kernel_guard::{IrqSave, NoPreempt};
 use kernel_guard::{NoOp, NoPreemptIrqSave};
 use kspin::{SpinNoIrq, SpinRaw};
-use weak_map::WeakMap;
 
 use crate::{AxTaskRef, WeakAxTaskRef, select_run_queue};
 
@@ -19,38 +43,408 @@ where
     TIMER_CALLBACKS.lock().push(Box::new(callback));
 }
 
-static TIMER_KEY: AtomicU64 = AtomicU64::new(0);
+/// The resolution of the timer wheels.
+const NANOS_PER_TICK: u64 = NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+
+const LEVEL_BITS: u32 = 6;
+const LEVEL_SIZE: usize = 1 << LEVEL_BITS;
+const LEVEL_MASK: u64 = LEVEL_SIZE as u64 - 1;
+const LEVELS: usize = 6;
+/// The farthest expiry a wheel can hold, in ticks. Farther timers are put in
+/// the last slot and cascaded again when it is reached.
+const MAX_DELTA: u64 = (1 << (LEVEL_BITS * LEVELS as u32)) - 1;
+/// Wheels are compacted once they hold more stale entries than this, and more
+/// than live ones.
+const COMPACT_THRESHOLD: usize = 64;
+/// The longest time an idle CPU sleeps with its tick stopped.
+#[cfg(feature = "tickless")]
+pub const MAX_IDLE_NANOS: u64 = NANOS_PER_SEC;
+
+/// The clock a timer deadline refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Clock {
+    /// The monotonic clock, see [`monotonic_time`].
+    Monotonic,
+    /// The wall clock, see [`wall_time`].
+    Realtime,
+}
+
+const PENDING: u8 = 0;
+const FIRED: u8 = 1;
+const CANCELLED: u8 = 2;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub(crate) struct TimerKey {
+pub(crate) struct Timer {
+    task: WeakAxTaskRef,
+    clock: Clock,
     deadline: TimeValue,
-    key: u64,
+    state: AtomicU8,
+    /// The CPU whose wheel holds the timer.
+    cpu: AtomicUsize,
+    /// Bumped when the timer leaves a wheel, invalidating the entry left
+    /// behind.
+    seq: AtomicU32,
 }
 
-static TIMER_WHEEL: SpinNoIrq<WeakMap<TimerKey, WeakAxTaskRef>> = SpinNoIrq::new(WeakMap::new());
+impl Timer {
+    /// Returns the tick at which the timer expires, rounded up.
+    fn expires(&self) -> u64 {
+        let nanos = match self.clock {
+            Clock::Monotonic => self.deadline.as_nanos(),
+            Clock::Realtime => self
+                .deadline
+                .as_nanos()
+                .saturating_sub(epochoffset_nanos() as u128),
+        };
+        u64::try_from(nanos.div_ceil(NANOS_PER_TICK as u128)).unwrap_or(u64::MAX)
+    }
 
-pub(crate) fn set_timer(deadline: TimeValue, task: &AxTaskRef) -> Option<TimerKey> {
-    if deadline <= wall_time() {
-        return None;
+    fn is_pending(&self) -> bool {
+        self.state.load(Ordering::Acquire) == PENDING
     }
+}
 
-    let mut wheel = TIMER_WHEEL.lock();
-    let key = TimerKey {
-        deadline,
-        key: TIMER_KEY.fetch_add(1, Ordering::AcqRel),
-    };
-    wheel.insert(key, task);
+/// A handle to an armed timer, see [`set_timer`].
+pub(crate) struct TimerKey(Arc<Timer>);
+
+impl TimerKey {
+    /// Moves the timer to the wheel of the current CPU, if it is still
+    /// pending on another one.
+    ///
+    /// Timers are polled by their task, so this keeps them on the CPU the task
+    /// has been migrated to.
+    pub(crate) fn rehome(&self) {
+        let timer = &self.0;
+        let cpu = this_cpu_id();
+        let old = timer.cpu.load(Ordering::Acquire);
+        if old == cpu || !timer.is_pending() {
+            return;
+        }
+        {
+            let mut wheel = TIMER_WHEELS[old].lock();
+            if !timer.is_pending() {
+                return;
+            }
+            timer.seq.fetch_add(1, Ordering::AcqRel);
+            wheel.live -= 1;
+            wheel.stale += 1;
+            wheel.compact_if_needed();
+            timer.cpu.store(cpu, Ordering::Release);
+        }
+        TIMER_WHEELS[cpu].lock().insert(timer.clone());
+    }
+}
 
-    Some(key)
+struct Entry {
+    timer: Arc<Timer>,
+    seq: u32,
+    expires: u64,
+}
+
+impl Entry {
+    fn is_live(&self) -> bool {
+        self.timer.seq.load(Ordering::Acquire) == self.seq && self.timer.is_pending()
+    }
+}
+
+/// A hierarchical timer wheel.
+///
+/// Level `n` has [`LEVEL_SIZE`] slots of `LEVEL_SIZE^n` ticks each. Entries
+/// of a slot above level 0 are cascaded to lower levels when the wheel reaches
+/// the slot, so that a level 0 slot only holds entries expiring on its tick.
+///
+/// Cancelled and moved timers leave stale entries behind, which are dropped
+/// when reached, or all at once when there are too many of them.
+struct TimerWheel {
+    /// The next tick to process.
+    now: u64,
+    levels: [[Vec<Entry>; LEVEL_SIZE]; LEVELS],
+    live: usize,
+    stale: usize,
+}
+
+impl TimerWheel {
+    const fn new() -> Self {
+        Self {
+            now: 0,
+            levels: [const { [const { Vec::new() }; LEVEL_SIZE] }; LEVELS],
+            live: 0,
+            stale: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.live == 0 && self.stale == 0
+    }
+
+    fn insert(&mut self, timer: Arc<Timer>) {
+        if self.is_empty() {
+            // Skip the ticks elapsed while the wheel was empty.
+            self.now = self.now.max(current_tick());
+        }
+        let seq = timer.seq.load(Ordering::Acquire);
+        let expires = timer.expires();
+        self.live += 1;
+        self.insert_entry(Entry {
+            timer,
+            seq,
+            expires,
+        });
+    }
+
+    fn insert_entry(&mut self, entry: Entry) {
+        let delta = entry.expires.saturating_sub(self.now);
+        let (level, expires) = if delta > MAX_DELTA {
+            (LEVELS - 1, self.now + MAX_DELTA)
+        } else if delta < LEVEL_SIZE as u64 {
+            (0, entry.expires.max(self.now))
+        } else {
+            ((delta.ilog2() / LEVEL_BITS) as usize, entry.expires)
+        };
+        let index = (expires >> (LEVEL_BITS * level as u32)) & LEVEL_MASK;
+        self.levels[level][index as usize].push(entry);
+    }
+
+    /// Re-inserts the entries of a slot relative to the current tick.
+    fn cascade(&mut self, level: usize, index: usize) {
+        for entry in mem::take(&mut self.levels[level][index]) {
+            if entry.is_live() {
+                self.insert_entry(entry);
+            } else {
+                self.stale -= 1;
+            }
+        }
+    }
+
+    /// Processes the ticks up to `tick` (included), collecting the tasks of
+    /// the expired timers.
+    fn advance(&mut self, tick: u64, expired: &mut Vec<AxTaskRef>) {
+        if self.is_empty() {
+            self.now = self.now.max(tick + 1);
+            return;
+        }
+        while self.now <= tick {
+            let now = self.now;
+            let mut level = 1;
+            while level < LEVELS && (now >> (LEVEL_BITS * (level as u32 - 1))) & LEVEL_MASK == 0 {
+                let index = (now >> (LEVEL_BITS * level as u32)) & LEVEL_MASK;
+                self.cascade(level, index as usize);
+                level += 1;
+            }
+
+            for entry in mem::take(&mut self.levels[0][(now & LEVEL_MASK) as usize]) {
+                if entry.is_live()
+                    && entry
+                        .timer
+                        .state
+                        .compare_exchange(PENDING, FIRED, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok()
+                {
+                    self.live -= 1;
+                    if let Some(task) = entry.timer.task.upgrade() {
+                        expired.push(task);
+                    }
+                } else {
+                    self.stale -= 1;
+                }
+            }
+            self.now += 1;
+        }
+    }
+
+    /// Returns the first tick at which the wheel has work to do: a level 0
+    /// slot with timers to fire, or a higher level slot to cascade.
+    #[cfg(feature = "tickless")]
+    fn next_expiry(&self) -> Option<u64> {
+        if self.live == 0 {
+            return None;
+        }
+        let mut next: Option<u64> = None;
+        for (level, slots) in self.levels.iter().enumerate() {
+            let shift = LEVEL_BITS * level as u32;
+            // The first slot boundary of this level not yet reached.
+            let base = self.now.div_ceil(1 << shift);
+            for offset in 0..LEVEL_SIZE as u64 {
+                let tick = (base + offset) << shift;
+                if next.is_some_and(|next| tick >= next) {
+                    break;
+                }
+                if slots[((base + offset) & LEVEL_MASK) as usize]
+                    .iter()
+                    .any(Entry::is_live)
+                {
+                    next = Some(tick);
+                    break;
+                }
+            }
+        }
+        next
+    }
+
+    fn compact_if_needed(&mut self) {
+        if self.stale <= COMPACT_THRESHOLD || self.stale <= self.live {
+            return;
+        }
+        for slot in self.levels.iter_mut().flatten() {
+            slot.retain(Entry::is_live);
+        }
+        self.stale = 0;
+    }
+
+    /// Arms the wall-clock timers again, after the wall clock is set.
+    fn rearm_realtime(&mut self) {
+        let mut rearmed = Vec::new();
+        for slot in self.levels.iter_mut().flatten() {
+            slot.retain_mut(|entry| {
+                if entry.timer.clock == Clock::Realtime && entry.is_live() {
+                    rearmed.push(Entry {
+                        timer: entry.timer.clone(),
+                        seq: entry.seq,
+                        expires: entry.timer.expires(),
+                    });
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        for entry in rearmed {
+            self.insert_entry(entry);
+        }
+    }
+}
+
+static TIMER_WHEELS: [SpinNoIrq<TimerWheel>; axconfig::plat::CPU_NUM] =
+    [const { SpinNoIrq::new(TimerWheel::new()) }; axconfig::plat::CPU_NUM];
+
+fn current_tick() -> u64 {
+    monotonic_time().as_nanos() as u64 / NANOS_PER_TICK
+}
+
+/// Arms a timer waking up `task` when `clock` reaches `deadline`.
+///
+/// The timer has already fired if the deadline is reached.
+pub(crate) fn set_timer(clock: Clock, deadline: TimeValue, task: &AxTaskRef) -> TimerKey {
+    let now = match clock {
+        Clock::Monotonic => monotonic_time(),
+        Clock::Realtime => wall_time(),
+    };
+    let cpu = this_cpu_id();
+    let timer = Arc::new(Timer {
+        task: Arc::downgrade(task),
+        clock,
+        deadline,
+        state: AtomicU8::new(if deadline <= now { FIRED } else { PENDING }),
+        cpu: AtomicUsize::new(cpu),
+        seq: AtomicU32::new(0),
+    });
+    if timer.is_pending() {
+        TIMER_WHEELS[cpu].lock().insert(timer.clone());
+    }
+    TimerKey(timer)
 }
 
 pub(crate) fn cancel_timer(key: &TimerKey) {
-    let mut wheel = TIMER_WHEEL.lock();
-    wheel.remove(key);
+    let timer = &key.0;
+    if !timer.is_pending() {
+        return;
+    }
+    let mut wheel = TIMER_WHEELS[timer.cpu.load(Ordering::Acquire)].lock();
+    if timer
+        .state
+        .compare_exchange(PENDING, CANCELLED, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+    {
+        wheel.live -= 1;
+        wheel.stale += 1;
+        wheel.compact_if_needed();
+    }
 }
 
 pub(crate) fn has_timer(key: &TimerKey) -> bool {
-    TIMER_WHEEL.lock().contains_key(key)
+    key.0.is_pending()
+}
+
+/// Sets the wall clock to `now`, and re-evaluates the wall-clock timers of all
+/// CPUs against it.
+///
+/// Timers on the monotonic clock are not affected.
+pub fn set_wall_time(now: TimeValue) {
+    axhal::time::set_wall_time(now);
+    for wheel in &TIMER_WHEELS {
+        wheel.lock().rearm_realtime();
+    }
+    // Idle CPUs have to program their timer again.
+    #[cfg(feature = "tickless")]
+    for cpu in 0..axconfig::plat::CPU_NUM {
+        kick_cpu(cpu);
+    }
+}
+
+/// Whether the tick of each CPU is stopped.
+#[cfg(feature = "tickless")]
+static TICK_STOPPED: [AtomicBool; axconfig::plat::CPU_NUM] =
+    [const { AtomicBool::new(false) }; axconfig::plat::CPU_NUM];
+/// Whether a task has been queued on each CPU since it last went idle.
+#[cfg(feature = "tickless")]
+static WAKEUP_PENDING: [AtomicBool; axconfig::plat::CPU_NUM] =
+    [const { AtomicBool::new(false) }; axconfig::plat::CPU_NUM];
+
+/// Wakes up `cpu` with an IPI if its tick is stopped.
+#[cfg(feature = "tickless")]
+fn kick_cpu(cpu: usize) {
+    if cpu != this_cpu_id() && TICK_STOPPED[cpu].load(Ordering::SeqCst) {
+        axhal::irq::send_ipi(
+            axhal::irq::IPI_IRQ,
+            axhal::irq::IpiTarget::Other { cpu_id: cpu },
+        );
+    }
+}
+
+/// Notifies `cpu` that a task has been put on its run queue, so that it
+/// leaves idle.
+#[cfg(feature = "tickless")]
+pub(crate) fn notify_enqueue(cpu: usize) {
+    WAKEUP_PENDING[cpu].store(true, Ordering::SeqCst);
+    kick_cpu(cpu);
+}
+
+/// Idles the current CPU until an IRQ arrives, with its periodic tick stopped
+/// and the timer programmed for the earliest timer of its wheel instead.
+///
+/// IRQs stay disabled from the check for queued tasks until the CPU waits, so
+/// that a wakeup IPI, or an IRQ queuing a task, cannot be handled in between
+/// and leave the CPU waiting with a ready task. Returns at once, leaving the
+/// tick running, if a task has been queued on the CPU meanwhile.
+#[cfg(feature = "tickless")]
+pub(crate) fn idle() {
+    let _g = NoPreempt::new();
+    axhal::asm::disable_irqs();
+    let cpu = this_cpu_id();
+    // Pairs with `notify_enqueue`: either we see the pending wakeup, or the
+    // waker sees the stopped tick and sends an IPI.
+    TICK_STOPPED[cpu].store(true, Ordering::SeqCst);
+    if WAKEUP_PENDING[cpu].swap(false, Ordering::SeqCst) || crate::run_queue::nr_ready() > 0 {
+        TICK_STOPPED[cpu].store(false, Ordering::SeqCst);
+        axhal::asm::enable_irqs();
+        return;
+    }
+
+    let max_deadline = monotonic_time().as_nanos() as u64 + MAX_IDLE_NANOS;
+    let deadline = TIMER_WHEELS[cpu]
+        .lock()
+        .next_expiry()
+        .map_or(max_deadline, |tick| {
+            tick.saturating_mul(NANOS_PER_TICK).min(max_deadline)
+        });
+    trace!("CPU {cpu}: tick stopped until {deadline}ns");
+    axhal::time::set_oneshot_timer(deadline);
+    axhal::irq::enable_and_wait_for_irqs();
+
+    // Restart the tick.
+    let _irq = IrqSave::new();
+    if TICK_STOPPED[cpu].swap(false, Ordering::SeqCst) {
+        axhal::time::set_oneshot_timer(monotonic_time().as_nanos() as u64 + NANOS_PER_TICK);
+    }
 }
 
 pub(crate) fn check_events() {
@@ -58,15 +452,11 @@ pub(crate) fn check_events() {
         callback(wall_time());
     }
 
-    let mut wheel = TIMER_WHEEL.lock();
-    for (key, maybe_task) in &mut *wheel {
-        if key.deadline <= wall_time() {
-            if let Some(task) = maybe_task.upgrade() {
-                select_run_queue::<NoOp>(&task).unblock_task(task, true);
-                core::mem::take(maybe_task);
-            }
-        } else {
-            break;
-        }
+    let mut expired = Vec::new();
+    TIMER_WHEELS[this_cpu_id()]
+        .lock()
+        .advance(current_tick(), &mut expired);
+    for task in expired {
+        select_run_queue::<NoOp>(&task).unblock_task(task, true);
     }
 }